@@ -0,0 +1,43 @@
+//! Map the rooms reachable from the start, and write them as a graphviz file
+//!
+//! `cargo run --release --example map_maze -- 50 maze.dot`, then `dot -Tsvg maze.dot`
+
+use std::path::{Path, PathBuf};
+
+use synacor_challenge::solver::{ExploreOptions, GameSolver, Maze};
+use synacor_challenge::Game;
+
+/// Explore at most `max_rooms` rooms from the current room of `game`, write the graphviz file
+/// to `path` if any
+pub fn map(
+    game: &Game,
+    max_rooms: usize,
+    path: Option<&Path>,
+) -> Result<Maze, Box<dyn std::error::Error>> {
+    let options = ExploreOptions {
+        max_rooms: Some(max_rooms),
+        graphviz: path.map(Path::to_path_buf),
+        ..Default::default()
+    };
+    GameSolver::explore_maze(game.vm(), &options).ok_or_else(|| "Exploration failed".into())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let max_rooms = match args.next() {
+        Some(max_rooms) => max_rooms.parse()?,
+        None => 20,
+    };
+    let path = PathBuf::from(args.next().unwrap_or_else(|| "maze.dot".to_string()));
+
+    let mut game = Game::new()?;
+    game.act("take tablet")?;
+    let maze = map(&game, max_rooms, Some(&path))?;
+    println!(
+        "{} rooms explored, {} left to explore",
+        maze.explored.len(),
+        maze.queue.len()
+    );
+
+    Ok(())
+}
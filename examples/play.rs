@@ -0,0 +1,43 @@
+//! A minimal player: the lines of stdin are fed to the game, its answers are printed
+//!
+//! `cargo run --example play`, or `cargo run --example play < walkthrough.txt`
+
+use std::io::{BufRead, Write};
+
+use synacor_challenge::{Game, GameResponse};
+
+/// Play the lines of `input` until the game ends or there is no more input
+///
+/// Returns the number of inputs played.
+pub fn play(
+    game: &mut Game,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    write!(output, "{}", game.output())?;
+
+    let mut played = 0;
+    for line in input.lines() {
+        let line = line?;
+        let response = game.act(line.trim())?;
+        played += 1;
+        write!(output, "{}", game.output())?;
+        if let GameResponse::Terminal(kind) = response {
+            writeln!(output, "*** {} ***", kind)?;
+        }
+        if game.is_over() {
+            break;
+        }
+        output.flush()?;
+    }
+
+    Ok(played)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut game = Game::new()?;
+    let played = play(&mut game, std::io::stdin().lock(), std::io::stdout())?;
+    println!("\n{} inputs, holding {:?}", played, game.inventory()?);
+
+    Ok(())
+}
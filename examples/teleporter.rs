@@ -0,0 +1,120 @@
+//! Find the value of `r7` the teleporter checks, and use it to reach the beach
+//!
+//! `cargo run --release --example teleporter`, trying all the values is slow in debug builds.
+
+use std::ops::RangeInclusive;
+
+use synacor_challenge::emulator::NativeImpl;
+use synacor_challenge::solver::GameSolver;
+use synacor_challenge::{Game, GameResponse};
+
+/// From the boot to Synacor Headquarters, with the first use of the teleporter
+pub const TO_TELEPORTER: [&str; 50] = [
+    "take tablet",
+    "doorway",
+    "north",
+    "north",
+    "bridge",
+    "continue",
+    "down",
+    "east",
+    "take empty lantern",
+    "west",
+    "west",
+    "passage",
+    "ladder",
+    "west",
+    "south",
+    "north",
+    "take can",
+    "west",
+    "ladder",
+    "darkness",
+    "use can",
+    "use lantern",
+    "continue",
+    "west",
+    "west",
+    "west",
+    "west",
+    "north",
+    "take red coin",
+    "north",
+    "east",
+    "take concave coin",
+    "down",
+    "take corroded coin",
+    "up",
+    "west",
+    "west",
+    "take blue coin",
+    "up",
+    "take shiny coin",
+    "down",
+    "east",
+    "use blue coin",
+    "use red coin",
+    "use shiny coin",
+    "use concave coin",
+    "use corroded coin",
+    "north",
+    "take teleporter",
+    "use teleporter",
+];
+
+/// The function at 6027 called with `r0 = 4`, `r1 = 1`, modulo 32768
+///
+/// `f(0, n) = n + 1`, `f(m, 0) = f(m - 1, r7)`, `f(m, n) = f(m - 1, f(m, n - 1))`, computed
+/// one row of `m` at a time.
+pub fn check(r7: u16) -> u16 {
+    let mut row: Vec<u16> = (0..32768).map(|n| (n + 1) % 32768).collect();
+    for _ in 1..=3 {
+        let mut next = vec![0; 32768];
+        next[0] = row[r7 as usize];
+        for n in 1..32768 {
+            next[n] = row[next[n - 1] as usize];
+        }
+        row = next;
+    }
+
+    // f(4, 1) = f(3, f(4, 0)) = f(3, f(3, r7))
+    row[row[r7 as usize] as usize]
+}
+
+/// The first value making the check return 6
+pub fn find_r7(candidates: RangeInclusive<u16>) -> Option<u16> {
+    candidates.into_iter().find(|&r7| check(r7) == 6)
+}
+
+/// Use the teleporter with `r7` set, the check runs natively instead of for ages
+pub fn use_teleporter(r7: u16) -> Result<Game, Box<dyn std::error::Error>> {
+    let mut game = Game::new()?;
+    for input in TO_TELEPORTER {
+        game.act(input)?;
+    }
+
+    let mut vm = game.into_vm();
+    vm.set_register(7, r7);
+    vm.set_patching(true);
+    vm.set_native_impl(NativeImpl::Table);
+    let mut game = Game::from_vm(vm)?;
+    game.act("use teleporter")?;
+
+    Ok(game)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let r7 = find_r7(1..=32767).ok_or("No value of r7 passes the check")?;
+    println!("r7 = {}", r7);
+
+    let game = use_teleporter(r7)?;
+    match GameResponse::from_vm(game.vm()) {
+        GameResponse::Room(room) => println!("Teleported to {}", room.name),
+        response => return Err(format!("Not teleported: {:?}", response).into()),
+    }
+    for code in GameSolver::find_codes(game.output()) {
+        println!("Code: {}", code);
+    }
+
+    Ok(())
+}
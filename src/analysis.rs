@@ -0,0 +1,311 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::{Opcode, Val, Vm};
+use crate::report::{self, BasicBlock};
+
+/// Directory of the analysis databases, one per binary
+pub const ANALYSIS_DIR: &str = "analysis";
+
+/// A function and what the static analysis found about it
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Function {
+    pub start: usize,
+    pub label: String,
+    pub blocks: Vec<BasicBlock>,
+    /// Registers read before being written: the arguments
+    pub args: BTreeSet<usize>,
+    /// Registers preserved by the prologue
+    pub saved: Vec<usize>,
+    /// Registers written by the function or its callees, and not saved
+    pub clobbered: BTreeSet<usize>,
+    /// Literal call targets
+    pub calls: BTreeSet<usize>,
+    /// No memory write, I/O or indirect call in the function itself
+    pub local_pure: bool,
+    /// `local_pure`, and all the callees are pure
+    pub pure: bool,
+    /// MD5 of the addresses and words of the instructions, to detect changes
+    pub hash: String,
+    /// The code was modified since the analysis, it's redone by the next `update`
+    #[serde(default)]
+    pub stale: bool,
+}
+
+impl Function {
+    pub fn analyze(vm: &Vm, start: usize) -> Self {
+        let instructions = report::walk_function(vm.get_memory(), start);
+        let usage = vm.register_usage(start);
+        let prologue_end = start + usage.saved.len() * Opcode::Push(Val::Num(0)).size();
+
+        let mut args = BTreeSet::new();
+        let mut written = BTreeSet::new();
+        let mut calls = BTreeSet::new();
+        let mut local_pure = true;
+        for (ip, opcode) in &instructions {
+            if *ip < prologue_end {
+                continue;
+            }
+            let (sources, destination) = operands(opcode);
+            for r in sources.iter().filter_map(register) {
+                if !written.contains(&r) {
+                    args.insert(r);
+                }
+            }
+            if let Some(r) = destination.as_ref().and_then(register) {
+                written.insert(r);
+            }
+            match opcode {
+                Opcode::Call(Val::Num(target)) => {
+                    calls.insert(*target as usize);
+                }
+                Opcode::Call(_) | Opcode::Wmem(_, _) | Opcode::Out(_) | Opcode::In(_) => {
+                    local_pure = false
+                }
+                _ => {}
+            }
+        }
+
+        Function {
+            start,
+            label: format!("fn_{}", start),
+            blocks: report::basic_blocks(&instructions),
+            args,
+            saved: usage.saved,
+            clobbered: usage.clobbered,
+            calls,
+            local_pure,
+            pure: local_pure,
+            hash: hash_instructions(&instructions),
+            stale: false,
+        }
+    }
+
+    /// Addresses of the words of the instructions
+    pub fn addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        self.blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .flat_map(|(ip, opcode)| *ip..*ip + opcode.size())
+    }
+
+    pub fn print(&self) {
+        let list = |regs: &mut dyn Iterator<Item = &usize>| {
+            let regs = regs.map(|r| format!("r{}", r)).collect::<Vec<_>>();
+            if regs.is_empty() {
+                "none".to_string()
+            } else {
+                regs.join(" ")
+            }
+        };
+        println!("{} ({})", self.label, self.start);
+        println!("  args: {}", list(&mut self.args.iter()));
+        println!("  saved: {}", list(&mut self.saved.iter()));
+        println!("  clobbered: {}", list(&mut self.clobbered.iter()));
+        println!(
+            "  calls: {}",
+            self.calls
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        println!("  pure: {}", self.pure);
+        println!("  blocks: {}", self.blocks.len());
+        for block in &self.blocks {
+            println!("    {} -> {:?}", block.start, block.successors);
+        }
+    }
+}
+
+/// Registers read and written by an instruction
+fn operands(opcode: &Opcode) -> (Vec<Val>, Option<Val>) {
+    match *opcode {
+        Opcode::Set(a, b) | Opcode::Not(a, b) | Opcode::Rmem(a, b) => (vec![b], Some(a)),
+        Opcode::Eq(a, b, c)
+        | Opcode::Gt(a, b, c)
+        | Opcode::Add(a, b, c)
+        | Opcode::Mult(a, b, c)
+        | Opcode::Mod(a, b, c)
+        | Opcode::And(a, b, c)
+        | Opcode::Or(a, b, c) => (vec![b, c], Some(a)),
+        Opcode::Pop(a) | Opcode::In(a) => (vec![], Some(a)),
+        Opcode::Push(a) | Opcode::Jmp(a) | Opcode::Call(a) | Opcode::Out(a) => (vec![a], None),
+        Opcode::Jt(a, b) | Opcode::Jf(a, b) | Opcode::Wmem(a, b) => (vec![a, b], None),
+        Opcode::Halt | Opcode::Ret | Opcode::Noop => (vec![], None),
+    }
+}
+
+fn register(v: &Val) -> Option<usize> {
+    match v {
+        Val::Reg(r) => Some(*r),
+        _ => None,
+    }
+}
+
+fn hash_instructions(instructions: &[(usize, Opcode)]) -> String {
+    let mut bytes = Vec::new();
+    for (ip, opcode) in instructions {
+        bytes.write_u16::<LittleEndian>(*ip as u16).unwrap();
+        for word in opcode.machine_code() {
+            bytes.write_u16::<LittleEndian>(word).unwrap();
+        }
+    }
+    format!("{:x}", md5::compute(bytes))
+}
+
+/// Analyzed functions of a binary, saved between sessions
+///
+/// Only the functions whose code changed are analyzed again by `update`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AnalysisDb {
+    /// MD5 of the binary the analysis started from
+    pub binary: String,
+    pub functions: BTreeMap<usize, Function>,
+    /// Names of addresses, used as `@name` in the commands
+    #[serde(default)]
+    pub bookmarks: BTreeMap<String, usize>,
+}
+
+impl AnalysisDb {
+    pub fn new(binary: &[u16]) -> Self {
+        AnalysisDb {
+            binary: binary_hash(binary),
+            functions: BTreeMap::new(),
+            bookmarks: BTreeMap::new(),
+        }
+    }
+
+    /// `analysis/<md5 of the binary>.json`
+    pub fn default_path(binary: &[u16]) -> PathBuf {
+        Path::new(ANALYSIS_DIR).join(format!("{}.json", binary_hash(binary)))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let f = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let f = std::fs::File::create(path)?;
+        serde_json::to_writer(f, self)?;
+        Ok(())
+    }
+
+    /// Analyze the new and modified functions, and return how many were analyzed
+    ///
+    /// The callers of a modified function are analyzed again too, as they inherit its clobbered
+    /// registers.
+    pub fn update(&mut self, vm: &Vm) -> usize {
+        let discovered = report::discover_functions(vm);
+        let mut stale: BTreeSet<usize> = discovered
+            .iter()
+            .filter(|(start, instructions)| {
+                self.functions
+                    .get(start)
+                    .is_none_or(|f| f.stale || f.hash != hash_instructions(instructions))
+            })
+            .map(|(start, _)| *start)
+            .collect();
+        loop {
+            let callers: Vec<usize> = self
+                .functions
+                .values()
+                .filter(|f| !stale.contains(&f.start) && f.calls.iter().any(|c| stale.contains(c)))
+                .map(|f| f.start)
+                .collect();
+            if callers.is_empty() {
+                break;
+            }
+            stale.extend(callers);
+        }
+
+        let mut functions = BTreeMap::new();
+        for start in discovered.keys() {
+            let function = match self.functions.remove(start) {
+                Some(function) if !stale.contains(start) => function,
+                _ => Function::analyze(vm, *start),
+            };
+            functions.insert(*start, function);
+        }
+        self.functions = functions;
+        self.update_purity();
+
+        stale.iter().filter(|s| discovered.contains_key(s)).count()
+    }
+
+    /// Addresses of all the analyzed instructions, to track with `Vm::set_tracked_code`
+    pub fn code_addresses(&self) -> BTreeSet<usize> {
+        self.functions
+            .values()
+            .flat_map(Function::addresses)
+            .collect()
+    }
+
+    /// Mark the functions containing `addresses` as stale, and return the new ones
+    pub fn mark_modified(&mut self, addresses: &BTreeSet<usize>) -> Vec<usize> {
+        let mut marked = Vec::new();
+        for function in self.functions.values_mut() {
+            if !function.stale && function.addresses().any(|addr| addresses.contains(&addr)) {
+                function.stale = true;
+                marked.push(function.start);
+            }
+        }
+
+        marked
+    }
+
+    /// Functions containing `addr`
+    pub fn functions_at(&self, addr: usize) -> impl Iterator<Item = &Function> {
+        self.functions
+            .values()
+            .filter(move |f| f.addresses().any(|a| a == addr))
+    }
+
+    /// Stale functions containing `addr`
+    pub fn stale_at(&self, addr: usize) -> impl Iterator<Item = &Function> {
+        self.functions
+            .values()
+            .filter(move |f| f.stale && f.addresses().any(|a| a == addr))
+    }
+
+    /// A function is pure if it and all its callees are locally pure
+    fn update_purity(&mut self) {
+        for function in self.functions.values_mut() {
+            function.pure = function.local_pure;
+        }
+        loop {
+            let impure: Vec<usize> = self
+                .functions
+                .values()
+                .filter(|f| {
+                    f.pure
+                        && f.calls
+                            .iter()
+                            .any(|c| !self.functions.get(c).is_some_and(|callee| callee.pure))
+                })
+                .map(|f| f.start)
+                .collect();
+            if impure.is_empty() {
+                break;
+            }
+            for start in impure {
+                self.functions.get_mut(&start).unwrap().pure = false;
+            }
+        }
+    }
+}
+
+pub(crate) fn binary_hash(binary: &[u16]) -> String {
+    let mut bytes = Vec::new();
+    for word in binary {
+        bytes.write_u16::<LittleEndian>(*word).unwrap();
+    }
+    format!("{:x}", md5::compute(bytes))
+}
@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Default folder of the session folders, see `artifacts_dir` in config.json
+pub const ARTIFACTS_DIR: &str = "artifacts";
+
+/// Folder of the files generated by a session, like `artifacts/2026-10-15_14-03-22/`
+///
+/// The folder is only created with the first artifact, so sessions that write nothing leave
+/// nothing behind.
+#[derive(Debug)]
+pub struct Artifacts {
+    root: PathBuf,
+    started: SystemTime,
+    /// Created folder of the session
+    session: Option<PathBuf>,
+}
+
+impl Artifacts {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Artifacts {
+            root: root.as_ref().to_path_buf(),
+            started: SystemTime::now(),
+            session: None,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Folder of this session, if an artifact was written
+    pub fn session(&self) -> Option<&Path> {
+        self.session.as_deref()
+    }
+
+    /// `name` in the session folder, if it was written
+    pub fn find<P: AsRef<Path>>(&self, name: P) -> Option<PathBuf> {
+        let path = self.session.as_ref()?.join(name);
+        path.exists().then_some(path)
+    }
+
+    /// Path of the artifact `name` in the session folder, created if needed
+    pub fn path<P: AsRef<Path>>(&mut self, name: P) -> std::io::Result<PathBuf> {
+        let session = match &self.session {
+            Some(session) => session.clone(),
+            None => {
+                let stamp = utc_timestamp(self.started);
+                // another session started in the same second
+                let session = (1..)
+                    .map(|n| match n {
+                        1 => self.root.join(&stamp),
+                        n => self.root.join(format!("{}-{}", stamp, n)),
+                    })
+                    .find(|dir| !dir.exists())
+                    .unwrap();
+                std::fs::create_dir_all(&session)?;
+                self.session = Some(session.clone());
+                session
+            }
+        };
+
+        Ok(session.join(name))
+    }
+
+    /// Session folders, oldest first, with their files
+    pub fn sessions(&self) -> std::io::Result<Vec<(PathBuf, Vec<PathBuf>)>> {
+        let mut sessions = Vec::new();
+        if !self.root.exists() {
+            return Ok(sessions);
+        }
+        for entry in std::fs::read_dir(&self.root)? {
+            let dir = entry?.path();
+            if dir.is_dir() {
+                let files = list_files(&dir)?;
+                sessions.push((dir, files));
+            }
+        }
+        sessions.sort();
+
+        Ok(sessions)
+    }
+}
+
+/// Files under `dir`, recursively, sorted
+fn list_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(list_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// `2026-10-15_14-03-22`, in UTC, sortable and valid in file names
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
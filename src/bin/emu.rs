@@ -7,36 +7,154 @@ use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::io::IsTerminal;
 use std::path::Path;
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use rustyline::completion::Completer;
 use rustyline::error::ReadlineError;
-use rustyline::{DefaultEditor, Editor};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::{DefaultHistory, History};
+use rustyline::validate::Validator;
+use rustyline::{Context, DefaultEditor, Editor, Helper};
 
 use synacor_challenge::cli::*;
 use synacor_challenge::emulator::*;
 
+/// Lines of the debugger, and the game inputs of the `game` mode
+const HISTORY: &str = "history.txt";
+const GAME_HISTORY: &str = "game_history.txt";
+
+/// Completes the game inputs in the `game` mode, nothing otherwise
+#[derive(Default)]
+struct GameHelper {
+    words: Option<GameWords>,
+}
+
+impl Completer for GameHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(match &self.words {
+            Some(words) => words.complete(&line[..pos]),
+            None => (pos, Vec::new()),
+        })
+    }
+}
+
+impl Hinter for GameHelper {
+    type Hint = String;
+}
+
+impl Highlighter for GameHelper {}
+
+impl Validator for GameHelper {}
+
+impl Helper for GameHelper {}
+
 fn main() {
-    let vm = Vm::default();
+    let args = clap::Command::new("emu")
+        .about("Synacor challenge emulator and debugger")
+        .arg(
+            clap::Arg::new("binary")
+                .long("binary")
+                .help("path of challenge.bin, else config.json, $SYNACOR_BIN, or the usual places")
+                .value_parser(clap::value_parser!(std::path::PathBuf)),
+        )
+        .get_matches();
 
-    let mut rl = DefaultEditor::new().unwrap();
-    let _ = rl.load_history("history.txt");
+    let config = synacor_challenge::config::Config::load_default();
+    let binary = Vm::find_binary(
+        args.get_one::<std::path::PathBuf>("binary")
+            .map(|p| p.as_path()),
+        config.binary.as_deref(),
+    );
+    let mut vm = Vm::new();
+    let binary = match binary {
+        Ok(path) => match vm.load_program_from_file(&path) {
+            Ok(()) => Some(path),
+            Err(e) => {
+                println!("Unable to load {}: {}", path.display(), e);
+                None
+            }
+        },
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
+    };
+    if binary.is_none() {
+        println!("No program loaded, `load <path>` to start");
+    }
+
+    let mut rl: Editor<GameHelper, DefaultHistory> = Editor::new().unwrap();
+    rl.set_helper(Some(GameHelper::default()));
+    let _ = rl.load_history(HISTORY);
+    // the history of the other mode
+    let mut parked = DefaultHistory::new();
+    let _ = parked.load(Path::new(GAME_HISTORY));
+    let mut in_game = false;
     let mut cli = Cli::new(vm);
+    cli.set_binary(binary);
+    // when scripted, the exit code tells if a command failed
+    let interactive = io::stdin().is_terminal();
+    // a script must not end at a prompt, `set watchdog on` to check the sourced ones
+    cli.watchdog = !interactive;
+    let mut failed = false;
 
     loop {
-        let readline = rl.readline(">> ");
+        if cli.game_mode != in_game {
+            std::mem::swap(rl.history_mut(), &mut parked);
+            in_game = cli.game_mode;
+        }
+        let words = (in_game && interactive).then(|| cli.game_words());
+        rl.helper_mut().unwrap().words = words;
+
+        let readline = rl.readline(&cli.prompt());
         match readline {
             Ok(line) => {
-                rl.add_history_entry(&line).unwrap();
-                if let Err(e) = rl.save_history("history.txt") {
+                let saved = if in_game && line.trim_start().starts_with(':') {
+                    // debugger commands stay out of the game inputs
+                    parked
+                        .add(&line)
+                        .and_then(|_| parked.save(Path::new(HISTORY)))
+                } else {
+                    rl.add_history_entry(&line).unwrap();
+                    rl.save_history(if in_game { GAME_HISTORY } else { HISTORY })
+                };
+                if let Err(e) = saved {
                     println!("Can't save history {:?}", e);
                 }
                 match cli.parse_command(&line) {
                     Ok(_) => (),
-                    Err(x) => println!("{:?}", x),
+                    Err(x) => {
+                        println!("{:?}", x);
+                        failed = true;
+                    }
                 }
+                if cli.quit {
+                    break;
+                }
+            }
+            _ => {
+                if !interactive {
+                    if let Err(e) = cli.starvation() {
+                        println!("{}", e);
+                        failed = true;
+                    }
+                }
+                break;
             }
-            _ => break,
         }
     }
+
+    if failed && !interactive {
+        std::process::exit(1);
+    }
 }
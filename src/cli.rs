@@ -1,7 +1,37 @@
-use crate::{emulator::*, solver::GameSolver};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use itertools::Itertools;
+use regex::Regex;
+
+use crate::{
+    analysis::{binary_hash, AnalysisDb},
+    artifacts::{Artifacts, ARTIFACTS_DIR},
+    codes::{CodeRegistry, CodeSlot},
+    config::Config,
+    diff,
+    driver::{self, Event, VmHandle},
+    emulator::*,
+    expr::{Expr, Watch},
+    help,
+    hints::{self, HintContext},
+    planner::{self, Planner},
+    report::{self, Analysis, CallGraphOptions, StaticCallGraph, StringDecoder},
+    solver::{
+        BruteforceOptions, ExploreOptions, GameResponse, GameSolver, GlossaryEntry,
+        GraphvizOptions, Level, Maze, TextEntry, Variable, CURRENT_ROOM,
+    },
+    viz::Gradient,
+};
 use clap::builder::BoolishValueParser;
 //use clap::{App, AppSettings, Arg, SubCommand};
-use clap::{builder::RangedU64ValueParser, Arg, Command};
+use clap::{builder::RangedU64ValueParser, Arg, ArgMatches, Command};
 
 use serde::Deserialize;
 use serde::Serialize;
@@ -10,13 +40,754 @@ use serde::Serialize;
 pub struct Snapshot {
     name: String,
     vm: Vm,
+    /// When it was taken, the load time for older dumps
+    #[serde(default = "SystemTime::now")]
+    taken: SystemTime,
+}
+
+/// First bytes of a zstd frame, to detect the compressed saves
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// A snapshot saved with its memory as the words that differ from the program
+#[derive(Serialize, Deserialize)]
+struct DeltaSnapshot {
+    /// MD5 of the program of the delta
+    base: String,
+    /// Words of memory, banks included
+    len: usize,
+    delta: Vec<(usize, Vec<u16>)>,
+    /// The snapshot with an empty memory
+    snapshot: Snapshot,
+}
+
+/// Write `snap` to `path` as a delta versus the program `base`, or in full without program,
+/// compressed with zstd if `compress`. Returns the size of the file.
+fn save_state_to_disk(
+    path: &str,
+    snap: &Snapshot,
+    base: Option<&[u16]>,
+    compress: bool,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let json = match base {
+        Some(base) => {
+            let mut vm = snap.vm.clone();
+            let memory = vm.take_memory();
+            serde_json::to_vec(&DeltaSnapshot {
+                base: binary_hash(base),
+                len: memory.len(),
+                delta: diff::memory_delta(base, &memory),
+                snapshot: Snapshot {
+                    name: snap.name.clone(),
+                    vm,
+                    taken: snap.taken,
+                },
+            })?
+        }
+        None => serde_json::to_vec(snap)?,
+    };
+    let bytes = if compress {
+        zstd::encode_all(&json[..], 0)?
+    } else {
+        json
+    };
+    std::fs::write(path, &bytes)?;
+
+    Ok(bytes.len())
+}
+
+/// Read a save of `save_state_to_disk`, compressed or not, or a full dump of older versions
+///
+/// A delta needs `base`, the program it was saved with.
+fn load_state_from_disk(
+    path: &str,
+    base: Option<&[u16]>,
+) -> Result<Snapshot, Box<dyn std::error::Error>> {
+    let mut bytes = std::fs::read(path)?;
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        bytes = zstd::decode_all(&bytes[..])?;
+    }
+    let value: serde_json::Value = serde_json::from_slice(&bytes)?;
+    if value.get("delta").is_none() {
+        return Ok(serde_json::from_value(value)?);
+    }
+
+    let saved: DeltaSnapshot = serde_json::from_value(value)?;
+    let base = base.ok_or_else(|| format!("{} is a delta versus a program, see `load`", path))?;
+    if binary_hash(base) != saved.base {
+        return Err(format!("{} is a delta versus another program", path).into());
+    }
+    let mut snap = saved.snapshot;
+    snap.vm
+        .set_memory(diff::apply_memory_delta(base, saved.len, &saved.delta)?);
+
+    Ok(snap)
+}
+
+/// Folder of the saved snapshots
+///
+/// Not a session folder of `artifacts`: `snap load`, `state` and `state gc` find the saves
+/// of the previous sessions by name.
+const SNAPS_DIR: &str = "snaps";
+
+/// A snapshot in memory or a file of snaps/, for `state list`
+struct SavedState {
+    name: String,
+    in_memory: bool,
+    time: SystemTime,
+    /// In bytes: the file size, the words of the memory for the snapshots in memory
+    size: u64,
+    /// Room and instruction count of the snapshots in memory
+    details: String,
+}
+
+/// Transcript file written by `tee`, in the format of `verify transcript`
+struct Tee {
+    path: String,
+    file: std::fs::File,
+    /// Messages of the VM already written
+    written: usize,
+}
+
+/// Interactive view reading the next lines as keys instead of commands
+enum View {
+    /// `dis graph -i`: the block at `block` of the function at `from`
+    Graph { from: usize, block: usize },
+    /// `mem edit`: the word at `offset`, and the number of words written
+    MemEdit { offset: usize, written: usize },
+}
+
+/// Keys of `mem edit`
+const MEM_EDIT_KEYS: &str = "Enter: next word, -: previous, a value or 'c': write it, q: quit";
+
+/// Keys of `dis graph -i`
+const GRAPH_KEYS: &str = "n/p: next/previous block, 1-9: follow an edge, s or Enter: step, \
+                          i: block of ip, :command: run a command, q: quit";
+
+/// Prompt of the REPL, without `prompt` in config.json
+const DEFAULT_PROMPT: &str = "{state} {room} >> ";
+
+/// Game inputs that `undo` can rewind
+const UNDO_DEPTH: usize = 100;
+
+/// Words of the game parser, never mistaken for a typo of a command
+const GAME_VERBS: [&str; 14] = [
+    "go", "look", "take", "drop", "use", "inv", "help", "north", "south", "east", "west", "up",
+    "down", "continue",
+];
+
+/// Words completing the game inputs of the `game` mode, see `Cli::game_words`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GameWords {
+    pub verbs: Vec<String>,
+    /// Things in the current room
+    pub things: Vec<String>,
+    pub inventory: Vec<String>,
+    pub exits: Vec<String>,
+}
+
+impl GameWords {
+    /// Start of the text to replace in `line`, and its completions
+    ///
+    /// The first word is a verb or an exit, the rest of the line an object of the verb: the
+    /// exits after `go`, the things of the room after `take`, the inventory after `use` and
+    /// `drop`. Objects like `empty lantern` are completed as a whole.
+    pub fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        let start = line.len() - line.trim_start().len();
+        let (verb, object) = match line[start..].split_once(' ') {
+            Some((verb, object)) => (Some(verb), object.trim_start()),
+            None => (None, &line[start..]),
+        };
+        let candidates: Vec<&String> = match verb {
+            None => self.verbs.iter().chain(&self.exits).collect(),
+            Some("go") => self.exits.iter().collect(),
+            Some("take") => self.things.iter().collect(),
+            Some("use" | "drop") => self.inventory.iter().collect(),
+            Some(_) => self.things.iter().chain(&self.inventory).collect(),
+        };
+
+        let completions = candidates
+            .into_iter()
+            .filter(|word| word.starts_with(object))
+            .cloned()
+            .sorted()
+            .dedup()
+            .collect();
+        (line.len() - object.len(), completions)
+    }
+}
+
+/// `--scaling` and `--palette` of the colored outputs
+fn gradient_arg(matches: &ArgMatches) -> Result<Gradient, Box<dyn std::error::Error>> {
+    Ok(Gradient {
+        scale: matches.get_one::<String>("scaling").unwrap().parse()?,
+        palette: matches.get_one::<String>("palette").unwrap().parse()?,
+    })
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions and transpositions
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    d[0] = (0..=b.len()).collect();
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+/// Split a command line on whitespace, keeping "quoted" or 'quoted' arguments together
+pub(crate) fn split_args(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quote: Option<char> = None;
+
+    for c in line.chars() {
+        match c {
+            '"' | '\'' if quote.is_none() => {
+                quote = Some(c);
+                current.get_or_insert_with(String::new);
+            }
+            c if quote == Some(c) => quote = None,
+            c if c.is_whitespace() && quote.is_none() => {
+                if let Some(arg) = current.take() {
+                    args.push(arg);
+                }
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+
+    args
+}
+
+/// Run a program in a fresh VM, feeding `inputs` to its prompts, for at most `max_steps`
+///
+/// Returns the outputs, and why it stopped, malformed instructions included.
+pub fn run_file<P: AsRef<std::path::Path>>(
+    path: P,
+    max_steps: usize,
+    inputs: &[String],
+) -> Result<(Vec<String>, String), Box<dyn std::error::Error>> {
+    let mut vm = load_file(path)?;
+    let mut inputs = inputs.iter();
+    let reason = run_limited(&mut vm, max_steps, |vm| match inputs.next() {
+        Some(line) => vm.feed(line).map(|_| true),
+        None => Ok(false),
+    })?;
+    if vm.get_state() != VmState::Halted && vm.get_state() != VmState::WaitingForInput {
+        vm.flush_output();
+    }
+
+    Ok((vm.get_messages().to_vec(), reason))
+}
+
+/// Like `run_file`, with byte I/O: `In` reads `input` then halts, `Out` writes bytes
+pub fn run_file_raw<P: AsRef<std::path::Path>>(
+    path: P,
+    max_steps: usize,
+    input: &[u8],
+) -> Result<(Vec<u8>, String), Box<dyn std::error::Error>> {
+    let mut vm = load_file(path)?;
+    vm.set_raw_io(true);
+    vm.feed_bytes(input, true)?;
+    let reason = run_limited(&mut vm, max_steps, |_| Ok(false))?;
+
+    Ok((vm.take_raw_output(), reason))
+}
+
+fn load_file<P: AsRef<std::path::Path>>(path: P) -> Result<Vm, Box<dyn std::error::Error>> {
+    let mut vm = Vm::new();
+    let program = Vm::read_binary(path)?;
+    if program.len() > MEM_SIZE {
+        return Err(format!("Program too big: {} words", program.len()).into());
+    }
+    vm.load_program_from_mem(&program);
+
+    Ok(vm)
+}
+
+/// Step `vm` at most `max_steps` times, `feed` is called on prompts and false if there's no
+/// more input. Returns why it stopped.
+fn run_limited(
+    vm: &mut Vm,
+    max_steps: usize,
+    mut feed: impl FnMut(&mut Vm) -> Result<bool, Box<dyn std::error::Error>>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    for _ in 0..max_steps {
+        match vm.get_state() {
+            VmState::WaitingForInput => {
+                if !feed(vm)? {
+                    return Ok("waiting for input".to_string());
+                }
+            }
+            VmState::Halted => return Ok(format!("halted after {} steps", vm.get_pc())),
+            VmState::Running | VmState::HitBreakPoint => {
+                if let Err(e) = vm.step() {
+                    return Ok(format!("crashed after {} steps: {}", vm.get_pc(), e));
+                }
+            }
+        }
+    }
+
+    Ok(format!("step limit of {} reached", max_steps))
+}
+
+/// Parse ranges of values like `0..32768` or `1..=10`
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end, inclusive) = match s.split_once("..=") {
+        Some((start, end)) => (start, end, true),
+        None => {
+            let (start, end) = s
+                .split_once("..")
+                .ok_or_else(|| format!("Invalid range {:?}, expected a..b or a..=b", s))?;
+            (start, end, false)
+        }
+    };
+    let start: u16 = start.parse().map_err(|e| format!("{}: {:?}", e, start))?;
+    let end: u32 = end.parse().map_err(|e| format!("{}: {:?}", e, end))?;
+    let end = if inclusive {
+        Some(end)
+    } else {
+        end.checked_sub(1)
+    };
+
+    match end
+        .filter(|&end| end <= u16::MAX as u32)
+        .map(|end| end as u16)
+    {
+        Some(end) if start <= end => Ok(start..=end),
+        _ => Err(format!("Empty or too large range {:?}", s)),
+    }
+}
+
+/// Offset, words and code of a line of `patch apply`
+type PatchLine = (Addr, Vec<u16>, String);
+
+/// Evaluate `12`, `0x1a` or sums like `6027+12` or `0x100-1`, the `@name` being resolved
+/// before the parsing
+fn parse_expr(s: &str) -> Result<u64, String> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    if s.is_empty() {
+        return Err("Empty expression".to_string());
+    }
+
+    let mut total: i64 = 0;
+    let mut rest = s.as_str();
+    let mut negative = false;
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = &rest[..end];
+        if term.starts_with('@') {
+            return Err(format!("Unknown name {:?}", term));
+        }
+        let value = match term.strip_prefix("0x") {
+            Some(hex) => i64::from_str_radix(hex, 16),
+            None => term.parse(),
+        }
+        .map_err(|e| format!("{}: {:?} in {:?}", e, term, s))?;
+        total = if negative {
+            total.checked_sub(value)
+        } else {
+            total.checked_add(value)
+        }
+        .ok_or_else(|| format!("Overflow in {:?}", s))?;
+
+        match rest[end..].chars().next() {
+            Some(op) => {
+                negative = op == '-';
+                rest = &rest[end + 1..];
+            }
+            None => break,
+        }
+    }
+
+    u64::try_from(total).map_err(|_| format!("Negative value {} for {:?}", total, s))
+}
+
+/// Code address, see `parse_expr`
+fn parse_addr(s: &str) -> Result<Addr, String> {
+    Addr::try_from(parse_expr(s)? as usize)
+}
+
+/// Memory offset, see `parse_expr`
+///
+/// The size of the memory depends on the extended banks, see `Cli::check_offset`.
+fn parse_offset(s: &str) -> Result<usize, String> {
+    Ok(parse_expr(s)? as usize)
+}
+
+/// End of a memory range, excluded, see `parse_expr` and `Cli::check_end`
+fn parse_end(s: &str) -> Result<usize, String> {
+    Ok(parse_expr(s)? as usize)
+}
+
+/// Memory value, see `parse_expr`
+fn parse_word(s: &str) -> Result<u16, String> {
+    let value = parse_expr(s)?;
+    u16::try_from(value).map_err(|_| format!("Invalid value {}, must be <= {}", value, u16::MAX))
+}
+
+/// What `x` shows of the memory
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum ExamineKind {
+    Words,
+    Instructions,
+    Strings,
+}
+
+/// `16w`, `8i` or `s` after `x/`: a count, then words, instructions or strings
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct ExamineFormat {
+    count: usize,
+    kind: ExamineKind,
+}
+
+impl std::str::FromStr for ExamineFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid format {:?}, expected like 16w, 8i or s", s);
+        let (count, kind) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let count = match count {
+            "" => 1,
+            count => count.parse().ok().filter(|&n| n > 0).ok_or_else(invalid)?,
+        };
+        let kind = match kind {
+            "" | "w" => ExamineKind::Words,
+            "i" => ExamineKind::Instructions,
+            "s" => ExamineKind::Strings,
+            _ => return Err(invalid()),
+        };
+
+        Ok(ExamineFormat { count, kind })
+    }
+}
+
+/// Rows of `width` words in hex, then the characters `out` would print, like `xxd`
+///
+/// `start` is the address of the first word. Words that aren't printable ASCII are shown as `.`.
+pub fn dump_words(words: &[u16], start: usize, width: usize) -> Vec<String> {
+    words
+        .chunks(width)
+        .enumerate()
+        .map(|(n, row)| {
+            let hex = row.iter().map(|word| format!("{:04x}", word)).join(" ");
+            let ascii: String = row
+                .iter()
+                .map(|&word| match u8::try_from(word) {
+                    Ok(c) if c.is_ascii_graphic() || c == b' ' => c as char,
+                    _ => '.',
+                })
+                .collect();
+            format!(
+                "{:>5}: {:<hex_width$}  |{}|",
+                start + n * width,
+                hex,
+                ascii,
+                hex_width = width * 5 - 1
+            )
+        })
+        .collect()
+}
+
+/// The instruction the words encode, else the words
+fn format_words(words: &[u16]) -> String {
+    match Opcode::decode(words) {
+        Ok(opcode) if opcode.size() == words.len() => format!("{:?}", opcode),
+        _ => format!("{:?}", words),
+    }
+}
+
+/// `950`, `12.3k` or `2.3M`
+fn format_count(n: usize) -> String {
+    match n {
+        0..=999 => n.to_string(),
+        1_000..=999_999 => format!("{:.1}k", n as f64 / 1e3),
+        _ => format!("{:.1}M", n as f64 / 1e6),
+    }
+}
+
+/// Value of a `watch`, `-` if it can't be evaluated
+fn format_value(value: Option<i64>) -> String {
+    value.map_or("-".to_string(), |value| value.to_string())
+}
+
+/// `12s ago`, `5m ago`, `2h ago` or `3d ago`
+fn format_age(time: SystemTime) -> String {
+    let secs = time.elapsed().map(|age| age.as_secs()).unwrap_or(0);
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// Lowercase words separated by `-`, for file and snapshot names
+fn slug(s: &str) -> String {
+    s.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Name of the last room described by the game
+fn room_name(vm: &Vm) -> Option<String> {
+    vm.get_messages()
+        .iter()
+        .rev()
+        .find_map(|message| Level::from(message).ok())
+        .map(|level| level.name)
+}
+
+/// Parse durations like `90`, `30s`, `5m`, `1h` or `7d`
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, "s"),
+    };
+    let value: u64 = value.parse().map_err(|e| format!("{}", e))?;
+    let seconds = match unit {
+        "s" => value,
+        "m" => value * 60,
+        "h" => value * 3600,
+        "d" => value * 86400,
+        x => return Err(format!("Unknown duration unit {:?}", x)),
+    };
+
+    Ok(Duration::from_secs(seconds))
+}
+
+/// A check of the state of the VM, for the `assert` command of scripts
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Assertion {
+    /// `reg r0 == 6`
+    Register(usize, String, u16),
+    /// `mem[3952] == 22`
+    Memory(usize, String, u16),
+    /// `output contains "self-test complete"`
+    OutputContains(String),
+}
+
+impl Assertion {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        let invalid = || {
+            format!(
+                "Invalid assertion {:?}, expected `reg r0 == 6`, `mem[3952] == 22` or \
+                 `output contains \"text\"`",
+                args.join(" ")
+            )
+        };
+        let op = |op: &str| match op {
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => Ok(op.to_string()),
+            _ => Err(invalid()),
+        };
+        let value = |v: &str| v.parse::<u16>().map_err(|_| invalid());
+
+        match args[..] {
+            ["reg", reg, o, v] => {
+                let reg = reg
+                    .strip_prefix('r')
+                    .and_then(|r| r.parse().ok())
+                    .filter(|&r: &usize| r < 8)
+                    .ok_or_else(invalid)?;
+                Ok(Assertion::Register(reg, op(o)?, value(v)?))
+            }
+            ["output", "contains", ref text @ ..] if !text.is_empty() => {
+                Ok(Assertion::OutputContains(text.join(" ")))
+            }
+            ["mem", addr, o, v] => {
+                Self::parse(&[format!("mem[{}]", addr), o.to_string(), v.to_string()])
+            }
+            [mem, o, v] => {
+                let addr = mem
+                    .strip_prefix("mem[")
+                    .and_then(|m| m.strip_suffix(']'))
+                    .and_then(|addr| addr.parse().ok())
+                    .filter(|&addr: &usize| addr < MEM_SIZE)
+                    .ok_or_else(invalid)?;
+                Ok(Assertion::Memory(addr, op(o)?, value(v)?))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Ok, or why the assertion is false
+    pub fn check(&self, vm: &Vm) -> Result<(), String> {
+        if self.holds(vm) {
+            return Ok(());
+        }
+        match self {
+            Assertion::Register(reg, op, expected) => Err(format!(
+                "Assertion failed: r{} {} {}, r{} is {}",
+                reg,
+                op,
+                expected,
+                reg,
+                vm.get_registers()[*reg]
+            )),
+            Assertion::Memory(addr, op, expected) => Err(format!(
+                "Assertion failed: mem[{}] {} {}, mem[{}] is {}",
+                addr,
+                op,
+                expected,
+                addr,
+                vm.get_memory()[*addr]
+            )),
+            Assertion::OutputContains(text) => Err(format!(
+                "Assertion failed: the output doesn't contain {:?}, it is:\n{}",
+                text,
+                Self::output(vm)
+            )),
+        }
+    }
+
+    /// Like `check` without the explanation, cheap enough to be checked at each step
+    pub fn holds(&self, vm: &Vm) -> bool {
+        let compare = |actual: u16, op: &str, expected: u16| match op {
+            "==" => actual == expected,
+            "!=" => actual != expected,
+            "<" => actual < expected,
+            ">" => actual > expected,
+            "<=" => actual <= expected,
+            _ => actual >= expected,
+        };
+        match self {
+            Assertion::Register(reg, op, expected) => {
+                compare(vm.get_registers()[*reg], op, *expected)
+            }
+            Assertion::Memory(addr, op, expected) => compare(vm.get_memory()[*addr], op, *expected),
+            Assertion::OutputContains(text) => Self::output(vm).contains(text.as_str()),
+        }
+    }
+
+    /// The last message, and what's printed since
+    fn output(vm: &Vm) -> String {
+        let mut output = vm.get_messages().last().cloned().unwrap_or_default();
+        output.extend(vm.get_output_buffer());
+        output
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Assertion::Register(reg, op, value) => write!(f, "reg r{} {} {}", reg, op, value),
+            Assertion::Memory(addr, op, value) => write!(f, "mem[{}] {} {}", addr, op, value),
+            Assertion::OutputContains(text) => write!(f, "output contains {:?}", text),
+        }
+    }
+}
+
+/// Run a command when a condition becomes true, added with `on <condition> do <command>`
+pub struct Rule {
+    pub condition: Assertion,
+    pub command: String,
+    /// The condition was true at the last check
+    was_true: bool,
+}
+
+/// Commands of the rules whose condition became true since the last check: (condition, command)
+fn triggered_rules(rules: &mut [Rule], vm: &Vm) -> Vec<(String, String)> {
+    let mut commands = Vec::new();
+    for rule in rules {
+        let is_true = rule.condition.holds(vm);
+        if is_true && !rule.was_true {
+            commands.push((rule.condition.to_string(), rule.command.clone()));
+        }
+        rule.was_true = is_true;
+    }
+
+    commands
+}
+
+/// Activity of the REPL, for `session stats`
+pub struct SessionStats {
+    pub started: Instant,
+    pub game_commands: usize,
+    /// Values of `CURRENT_ROOM` seen around game inputs
+    pub rooms: HashSet<u16>,
+    /// Names of the rooms described after game inputs, for `hint`
+    pub room_names: BTreeSet<String>,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            game_commands: 0,
+            rooms: HashSet::new(),
+            room_names: BTreeSet::new(),
+        }
+    }
 }
 
 pub struct Cli {
     pub cli: Command,
 
     pub vm: Vm,
+    /// Path of the program in the VM, `None` until `load`
+    pub binary: Option<PathBuf>,
+    /// Where the generated files go, see `artifacts`
+    pub artifacts: Artifacts,
+    /// Where the snapshots are saved, and found by name
+    pub snaps_dir: PathBuf,
     pub snapshots: Vec<Snapshot>,
+    pub codes: CodeRegistry,
+    /// Ask before destructive commands, `set confirm off` for scripts
+    pub confirm: bool,
+    /// Set by `quit`, the REPL should stop
+    pub quit: bool,
+    /// Describe each stepped instruction, `set explain on`
+    pub explain: bool,
+    /// Template of the REPL prompt, `set prompt`
+    pub prompt: String,
+    /// Check `starvation` at the end of scripts: piped stdin, `source`, `play` and `runfile`
+    /// with inputs. On when stdin is piped, `set watchdog off` to stop at prompts.
+    pub watchdog: bool,
+    /// Lines go to the game, `:command` runs a command, see `game`
+    pub game_mode: bool,
+    /// `game_words` at a pc, probed once per prompt
+    game_words: Option<(usize, GameWords)>,
+    pub session: SessionStats,
+    /// Patterns searched in the output of the game, with `alert add`
+    pub alerts: Vec<Regex>,
+    /// Shell command run on alerts, with the matching line in `$ALERT_MATCH`
+    pub alert_command: Option<String>,
+    /// Messages already searched for alerts
+    alerts_checked: usize,
+    /// Hook violations already reported
+    hooks_checked: usize,
+    /// Path and content of the analysis database, see `analysis`
+    analysis: Option<(PathBuf, AnalysisDb)>,
+    /// Last reported `Vm::io_problems`, to warn once
+    io_problems: Vec<String>,
+    pub rules: Vec<Rule>,
+    /// The VM while it runs on another thread, `bg run`
+    background: Option<VmHandle>,
+    /// Items seen by `solver explore`
+    pub glossary: BTreeMap<String, GlossaryEntry>,
+    /// The VM before each of the last game inputs, for `undo`
+    undo: VecDeque<Checkpoint>,
+    tee: Option<Tee>,
+    view: Option<View>,
 }
 
 impl Cli {
@@ -24,323 +795,3664 @@ impl Cli {
         let cli = Command::new("cli")
             .subcommand_required(true)
             .no_binary_name(true)
-            .subcommand(Command::new("helpme"))
+            .disable_help_subcommand(true)
             .subcommand(
-                Command::new("bp")
-                    .subcommand(Command::new("list"))
-                    .subcommand(
-                        Command::new("set").arg(
-                            Arg::new("offset").value_parser(RangedU64ValueParser::<usize>::new()),
-                        ),
+                Command::new("help")
+                    .about("list the commands, or show the examples of one")
+                    .arg(Arg::new("command")),
+            )
+            .subcommand(Command::new("tutorial").about("first steps with the emulator"))
+            .subcommand(
+                Command::new("quit")
+                    .alias("exit")
+                    .about("stop the emulator")
+                    .arg(
+                        Arg::new("save")
+                            .long("save")
+                            .help("dump the current state to snaps/ before quitting"),
                     )
-                    .subcommand(Command::new("unset").arg(
-                        Arg::new("offset").value_parser(RangedU64ValueParser::<usize>::new()),
-                    )),
+                    .arg(
+                        Arg::new("compress")
+                            .long("compress")
+                            .requires("save")
+                            .action(clap::ArgAction::SetTrue),
+                    ),
             )
             .subcommand(
-                Command::new("patch")
-                    .arg(Arg::new("opcode"))
-                    .arg(Arg::new("offset").value_parser(RangedU64ValueParser::<usize>::new())),
+                Command::new("game")
+                    .about("send the lines to the game, `:command` for the debugger")
+                    .arg(
+                        Arg::new("on")
+                            .default_value("on")
+                            .value_parser(BoolishValueParser::new()),
+                    ),
             )
             .subcommand(
-                Command::new("dis")
+                Command::new("artifacts")
+                    .about("files generated by the session, in a timestamped folder")
+                    .subcommand_required(true)
                     .subcommand(
-                        Command::new("at")
-                            .arg(
-                                Arg::new("from")
-                                    .required(true)
-                                    .value_parser(RangedU64ValueParser::<usize>::new()),
-                            )
-                            .arg(
-                                Arg::new("count")
-                                    .required(true)
-                                    .value_parser(RangedU64ValueParser::<usize>::new()),
-                            ),
+                        Command::new("list")
+                            .about("files of this session, or the folders of all the sessions")
+                            .arg(Arg::new("all").long("all").action(clap::ArgAction::SetTrue)),
                     )
                     .subcommand(
-                        Command::new("fn").arg(
-                            Arg::new("from")
-                                .required(true)
-                                .value_parser(RangedU64ValueParser::<usize>::new()),
-                        ),
+                        Command::new("open")
+                            .about("open an artifact, or the folder of the session")
+                            .arg(Arg::new("name")),
                     ),
             )
             .subcommand(
-                Command::new("vm")
-                    .subcommand(
-                        Command::new("patch").arg(
-                            Arg::new("patch")
-                                .required(true)
-                                .value_parser(BoolishValueParser::new()),
-                        ),
-                    )
-                    .subcommand(
-                        Command::new("register").subcommand(
-                            Command::new("set")
-                                .arg(
-                                    Arg::new("register")
-                                        .required(true)
-                                        .value_parser(RangedU64ValueParser::<usize>::new()),
-                                )
-                                .arg(
-                                    Arg::new("value")
-                                        .required(true)
-                                        .value_parser(RangedU64ValueParser::<u16>::new()),
-                                ),
-                        ),
+                Command::new("load")
+                    .about("start over with a program, found like at startup without a path")
+                    .arg(
+                        Arg::new("path")
+                            .help("default: config.json, $SYNACOR_BIN, then challenge.bin")
+                            .value_parser(clap::value_parser!(PathBuf)),
                     ),
             )
             .subcommand(
-                Command::new("mem")
-                    .subcommand(Command::new("init"))
-                    .subcommand(Command::new("list"))
+                Command::new("set")
                     .subcommand(
-                        Command::new("get").arg(
-                            Arg::new("offset").value_parser(RangedU64ValueParser::<usize>::new()),
-                        ),
+                        Command::new("confirm")
+                            .about("ask before destructive commands")
+                            .arg(
+                                Arg::new("confirm")
+                                    .required(true)
+                                    .value_parser(BoolishValueParser::new()),
+                            ),
                     )
                     .subcommand(
-                        Command::new("set")
-                            .arg(
-                                Arg::new("offset")
-                                    .value_parser(RangedU64ValueParser::<usize>::new()),
-                            )
+                        Command::new("prompt")
+                            .about("template of the prompt: {state}, {room}, {ip} and {pc}")
+                            .arg(Arg::new("template").required(true)),
+                    )
+                    .subcommand(
+                        Command::new("explain")
+                            .about("describe each stepped instruction in plain English")
                             .arg(
-                                Arg::new("value").value_parser(RangedU64ValueParser::<u16>::new()),
+                                Arg::new("explain")
+                                    .required(true)
+                                    .value_parser(BoolishValueParser::new()),
                             ),
                     )
                     .subcommand(
-                        Command::new("filter")
-                            .alias("f")
-                            .subcommand(Command::new("=").arg(
-                                Arg::new("value").value_parser(RangedU64ValueParser::<u16>::new()),
-                            ))
-                            .subcommand(Command::new("!=").arg(
-                                Arg::new("value").value_parser(RangedU64ValueParser::<u16>::new()),
-                            ))
-                            .subcommand(Command::new(">").arg(
-                                Arg::new("value").value_parser(RangedU64ValueParser::<u16>::new()),
-                            ))
-                            .subcommand(Command::new(">=").arg(
-                                Arg::new("value").value_parser(RangedU64ValueParser::<u16>::new()),
-                            ))
-                            .subcommand(Command::new("<").arg(
-                                Arg::new("value").value_parser(RangedU64ValueParser::<u16>::new()),
-                            ))
-                            .subcommand(Command::new("<=").arg(
-                                Arg::new("value").value_parser(RangedU64ValueParser::<u16>::new()),
-                            )),
-                    ),
-            )
-            .subcommand(Command::new("run").alias("r"))
-            .subcommand(Command::new("input").alias("i").arg(Arg::new("line")))
+                        Command::new("watchdog")
+                            .about(
+                                "fail a script ending while the game waits for input, \
+                                 on when stdin is piped",
+                            )
+                            .arg(
+                                Arg::new("watchdog")
+                                    .required(true)
+                                    .value_parser(BoolishValueParser::new()),
+                            ),
+                    ),
+            )
+            .subcommand(Command::new("stack").about("show the stack grouped in call frames"))
             .subcommand(
-                Command::new("solver")
-                    .subcommand(Command::new("explore"))
-                    .subcommand(Command::new("teleporter")),
+                Command::new("bt")
+                    .alias("backtrace")
+                    .about("the calls leading to ip, named with the labels"),
             )
             .subcommand(
-                Command::new("snap")
-                    .subcommand(Command::new("load").arg(Arg::new("dump_path").required(true)))
-                    .subcommand(
-                        Command::new("dump")
-                            .arg(Arg::new("name").required(true))
-                            .arg(Arg::new("dump_path").required(true)),
+                Command::new("messages")
+                    .about("outputs of the game, numbered by prompt")
+                    .arg(
+                        Arg::new("n")
+                            .value_parser(RangedU64ValueParser::<usize>::new())
+                            .help("show this message in full"),
                     )
-                    .subcommand(Command::new("take").arg(Arg::new("name").required(true)))
-                    .subcommand(Command::new("remove").arg(Arg::new("name").required(true)))
-                    .subcommand(Command::new("restore").arg(Arg::new("name").required(true)))
-                    .subcommand(Command::new("list")),
+                    .arg(
+                        Arg::new("last")
+                            .long("last")
+                            .value_parser(RangedU64ValueParser::<usize>::new())
+                            .default_value("10"),
+                    ),
+            )
+            .subcommand(
+                Command::new("session")
+                    .subcommand_required(true)
+                    .subcommand(Command::new("stats").about("overview of the solving session")),
+            )
+            .subcommand(
+                Command::new("bruteforce")
+                    .about("try each value of a register or memory cell, keep the matching outputs")
+                    .arg(
+                        Arg::new("var")
+                            .long("var")
+                            .required(true)
+                            .value_parser(|s: &str| s.parse::<Variable>())
+                            .help("r0 to r7, or a memory address"),
+                    )
+                    .arg(
+                        Arg::new("range")
+                            .long("range")
+                            .required(true)
+                            .value_parser(parse_range)
+                            .help("a..b or a..=b"),
+                    )
+                    .arg(
+                        Arg::new("input")
+                            .long("input")
+                            .action(clap::ArgAction::Append)
+                            .help("game input fed to each candidate, repeat for several"),
+                    )
+                    .arg(
+                        Arg::new("success")
+                            .long("success")
+                            .required(true)
+                            .help("regex of the output of a successful candidate"),
+                    )
+                    .arg(
+                        Arg::new("max-steps")
+                            .long("max-steps")
+                            .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                            .default_value("10000000")
+                            .help("steps per candidate, `vm patch true` makes 6027 fast"),
+                    ),
+            )
+            .subcommand(
+                Command::new("profile")
+                    .about("count the executions of each address and opcode")
+                    .subcommand_required(true)
+                    .subcommand(Command::new("start").about("forget the last profile, and count"))
+                    .subcommand(Command::new("stop"))
+                    .subcommand(
+                        Command::new("report")
+                            .about("the hottest addresses, and the opcode totals")
+                            .arg(
+                                Arg::new("top")
+                                    .long("top")
+                                    .value_parser(RangedU64ValueParser::<usize>::new())
+                                    .default_value("20"),
+                            ),
+                    ),
+            )
+            .subcommand(
+                Command::new("trace")
+                    .about("record the calls and returns, to print the call tree")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("start")
+                            .about("forget the last trace, and record")
+                            .arg(
+                                Arg::new("strings")
+                                    .long("strings")
+                                    .help("also record the strings of the printing routine")
+                                    .action(clap::ArgAction::SetTrue),
+                            )
+                            .arg(
+                                Arg::new("routine")
+                                    .long("routine")
+                                    .requires("strings")
+                                    .help("found in the code by default")
+                                    .value_parser(parse_addr),
+                            ),
+                    )
+                    .subcommand(Command::new("stop"))
+                    .subcommand(
+                        Command::new("profile")
+                            .about("calls and instructions of each function, the slowest first")
+                            .arg(
+                                Arg::new("top")
+                                    .long("top")
+                                    .value_parser(RangedU64ValueParser::<usize>::new())
+                                    .default_value("20"),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("strings")
+                            .about("calls of the string printing routine, with the printed text"),
+                    )
+                    .subcommand(Command::new("decode").about(
+                        "decode the strings of every call of the printing routine, like the trace",
+                    ))
+                    .subcommand(
+                        Command::new("tree")
+                            .about("calls with their instruction counts, repeats collapsed as ×N")
+                            .arg(
+                                Arg::new("depth")
+                                    .long("depth")
+                                    .value_parser(RangedU64ValueParser::<usize>::new())
+                                    .default_value("10"),
+                            ),
+                    ),
+            )
+            .subcommand(
+                Command::new("state")
+                    .about("compare states: `live`, a snapshot name or a file of snaps/")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("hash")
+                            .about("short hash, equal for identical states")
+                            .arg(Arg::new("name").default_value("live")),
+                    )
+                    .subcommand(
+                        Command::new("same")
+                            .about("whether two states are identical")
+                            .arg(Arg::new("a").required(true))
+                            .arg(Arg::new("b").required(true)),
+                    )
+                    .subcommand(
+                        Command::new("list")
+                            .about("the snapshots in memory and the files of snaps/")
+                            .arg(
+                                Arg::new("sort")
+                                    .long("sort")
+                                    .value_parser(["time", "name"])
+                                    .default_value("time"),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("find")
+                            .about("the states whose name or room match a regex")
+                            .arg(Arg::new("pattern").required(true)),
+                    )
+                    .subcommand(
+                        Command::new("gc")
+                            .about("size of the saved states, and drop the old or duplicated ones")
+                            .arg(
+                                Arg::new("older-than")
+                                    .long("older-than")
+                                    .value_parser(parse_duration)
+                                    .help("drop the snapshots and files of snaps/ older than this"),
+                            )
+                            .arg(
+                                Arg::new("duplicates")
+                                    .long("duplicates")
+                                    .action(clap::ArgAction::SetTrue)
+                                    .help("drop the snapshots identical to a newer one"),
+                            )
+                            .arg(
+                                Arg::new("dry-run")
+                                    .long("dry-run")
+                                    .action(clap::ArgAction::SetTrue),
+                            ),
+                    ),
+            )
+            .subcommand(
+                Command::new("strings")
+                    .about("printable runs, `Out` sequences and decoded strings of the memory")
+                    .arg(Arg::new("pattern").help("regex searched in the strings"))
+                    .arg(
+                        Arg::new("min")
+                            .long("min")
+                            .value_parser(RangedU64ValueParser::<usize>::new())
+                            .default_value("4"),
+                    ),
+            )
+            .subcommand(
+                Command::new("compare-input")
+                    .about("feed two inputs to clones of the VM, and diff the results")
+                    .arg(
+                        Arg::new("a")
+                            .required(true)
+                            .help("quote inputs with spaces"),
+                    )
+                    .arg(Arg::new("b").required(true)),
+            )
+            .subcommand(
+                Command::new("timetravel")
+                    .about("snapshot the VM periodically, to jump back with goto-pc")
+                    .subcommand(
+                        Command::new("enable").arg(
+                            Arg::new("every")
+                                .help("million instructions between snapshots")
+                                .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                                .default_value("1"),
+                        ),
+                    )
+                    .subcommand(Command::new("disable"))
+                    .subcommand(Command::new("list")),
+            )
+            .subcommand(
+                Command::new("goto-pc")
+                    .about("go to an instruction count, using the timetravel snapshots")
+                    .arg(
+                        Arg::new("count")
+                            .required(true)
+                            .value_parser(RangedU64ValueParser::<usize>::new()),
+                    ),
+            )
+            .subcommand(
+                Command::new("bp")
+                    .subcommand(Command::new("list"))
+                    .subcommand(
+                        Command::new("set")
+                            .arg(Arg::new("offset").value_parser(parse_addr))
+                            .arg(
+                                Arg::new("if")
+                                    .long("if")
+                                    .visible_alias("cond")
+                                    .help("only stop when it's true, like \"r7 != 0\", see `print`")
+                                    .value_parser(clap::value_parser!(Expr)),
+                            )
+                            .arg(Arg::new("desc").long("desc")),
+                    )
+                    .subcommand(
+                        Command::new("unset").arg(Arg::new("offset").value_parser(parse_addr)),
+                    )
+                    .subcommand(
+                        Command::new("enable")
+                            .arg(Arg::new("offset").required(true).value_parser(parse_addr)),
+                    )
+                    .subcommand(
+                        Command::new("disable")
+                            .arg(Arg::new("offset").required(true).value_parser(parse_addr)),
+                    )
+                    .subcommand(
+                        Command::new("export")
+                            .about("write the breakpoints to a JSON file")
+                            .arg(
+                                Arg::new("path")
+                                    .required(true)
+                                    .value_parser(clap::value_parser!(PathBuf)),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("import")
+                            .about("add the breakpoints of a JSON file")
+                            .arg(
+                                Arg::new("path")
+                                    .required(true)
+                                    .value_parser(clap::value_parser!(PathBuf)),
+                            ),
+                    ),
+            )
+            .subcommand(
+                Command::new("report")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("html")
+                            .about(
+                                "static site with the functions, their graphs, xrefs and strings",
+                            )
+                            .arg(
+                                Arg::new("dir")
+                                    .help("default: report/ in the artifacts")
+                                    .value_parser(clap::value_parser!(PathBuf)),
+                            )
+                            .arg(
+                                Arg::new("scaling")
+                                    .long("scaling")
+                                    .help("coloring of the execution counts: linear or log")
+                                    .default_value("log"),
+                            )
+                            .arg(
+                                Arg::new("palette")
+                                    .long("palette")
+                                    .help("greens, heat, or viridis for colorblind-safe colors")
+                                    .default_value("greens"),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("r2")
+                            .about("radare2 script of the functions, flags and comments")
+                            .arg(
+                                Arg::new("path")
+                                    .required(true)
+                                    .value_parser(clap::value_parser!(PathBuf)),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("ghidra")
+                            .about("Ghidra script of the functions, labels and comments")
+                            .arg(
+                                Arg::new("path")
+                                    .required(true)
+                                    .value_parser(clap::value_parser!(PathBuf)),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("callgraph")
+                            .about("static call graph of all the functions, graphviz or JSON")
+                            .arg(
+                                Arg::new("path")
+                                    .required(true)
+                                    .value_parser(clap::value_parser!(PathBuf)),
+                            )
+                            .arg(
+                                Arg::new("json")
+                                    .long("json")
+                                    .action(clap::ArgAction::SetTrue),
+                            ),
+                    ),
+            )
+            .subcommand(
+                Command::new("analysis")
+                    .about("functions analyzed once and saved, per binary")
+                    .subcommand_required(true)
+                    .arg(
+                        Arg::new("db")
+                            .long("db")
+                            .global(true)
+                            .help("default: analysis/<md5 of the program>.json")
+                            .value_parser(clap::value_parser!(PathBuf)),
+                    )
+                    .subcommand(
+                        Command::new("update").about("analyze the new and modified functions"),
+                    )
+                    .subcommand(Command::new("list"))
+                    .subcommand(
+                        Command::new("show")
+                            .arg(Arg::new("addr").required(true).value_parser(parse_addr)),
+                    ),
+            )
+            .subcommand(
+                Command::new("bookmark")
+                    .about("labels saved with the analysis, used as @name in the commands")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("add")
+                            .arg(Arg::new("name").required(true))
+                            .arg(Arg::new("addr").required(true).value_parser(parse_addr)),
+                    )
+                    .subcommand(Command::new("remove").arg(Arg::new("name").required(true)))
+                    .subcommand(Command::new("list")),
+            )
+            .subcommand(
+                Command::new("label")
+                    .about("names of addresses, shown by `dis` and the graphs, used as @name")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("set")
+                            .arg(Arg::new("addr").required(true).value_parser(parse_addr))
+                            .arg(Arg::new("name").required(true)),
+                    )
+                    .subcommand(
+                        Command::new("remove")
+                            .arg(Arg::new("addr").required(true).value_parser(parse_addr)),
+                    )
+                    .subcommand(Command::new("list")),
+            )
+            .subcommand(
+                Command::new("note")
+                    .about("comments on addresses, shown by `dis`")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("add")
+                            .about("replaces the note of the address")
+                            .arg(Arg::new("addr").required(true).value_parser(parse_addr))
+                            .arg(Arg::new("text").required(true)),
+                    )
+                    .subcommand(
+                        Command::new("remove")
+                            .arg(Arg::new("addr").required(true).value_parser(parse_addr)),
+                    )
+                    .subcommand(Command::new("list")),
+            )
+            .subcommand(
+                Command::new("print")
+                    .about("evaluate an expression, like `mem[r1 + 2] == 5489 && ip > 6000`")
+                    .arg(Arg::new("expr").required(true).num_args(1..)),
+            )
+            .subcommand(
+                Command::new("watch")
+                    .about("stop running when the value of an expression changes")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("add").arg(Arg::new("expr").required(true).num_args(1..)),
+                    )
+                    .subcommand(
+                        Command::new("remove").arg(
+                            Arg::new("idx")
+                                .required(true)
+                                .value_parser(RangedU64ValueParser::<usize>::new()),
+                        ),
+                    )
+                    .subcommand(Command::new("list")),
+            )
+            .subcommand(
+                Command::new("alert")
+                    .about("get notified when the game prints something")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("add")
+                            .about("regex searched in every line of the output")
+                            .arg(Arg::new("pattern").required(true)),
+                    )
+                    .subcommand(
+                        Command::new("remove").arg(
+                            Arg::new("idx")
+                                .required(true)
+                                .value_parser(RangedU64ValueParser::<usize>::new()),
+                        ),
+                    )
+                    .subcommand(Command::new("list"))
+                    .subcommand(
+                        Command::new("sink")
+                            .about("shell command run on matches, `off` to disable")
+                            .arg(
+                                Arg::new("command")
+                                    .required(true)
+                                    .help("$ALERT_MATCH holds the line, $ALERT_PATTERN the regex"),
+                            ),
+                    ),
+            )
+            .subcommand(
+                Command::new("var")
+                    .about("find and pin the memory cells that could make runs vary")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("watch")
+                            .about("log the reads of the cells from..=to")
+                            .arg(Arg::new("addr").required(true).value_parser(parse_addr))
+                            .arg(Arg::new("to").value_parser(parse_addr)),
+                    )
+                    .subcommand(
+                        Command::new("unwatch")
+                            .arg(Arg::new("addr").required(true).value_parser(parse_addr)),
+                    )
+                    .subcommand(
+                        Command::new("reads").about("cells read since watched, varying ones first"),
+                    )
+                    .subcommand(
+                        Command::new("pin")
+                            .about("force a value in a cell before every step")
+                            .arg(Arg::new("addr").required(true).value_parser(parse_addr))
+                            .arg(
+                                Arg::new("value")
+                                    .required(true)
+                                    .value_parser(clap::value_parser!(u16)),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("unpin")
+                            .arg(Arg::new("addr").required(true).value_parser(parse_addr)),
+                    )
+                    .subcommand(Command::new("list").about("watched and pinned cells")),
+            )
+            .subcommand(
+                Command::new("patch")
+                    .args_conflicts_with_subcommands(true)
+                    .arg(Arg::new("opcode"))
+                    .arg(Arg::new("offset").value_parser(parse_addr))
+                    .arg(
+                        Arg::new("dry-run")
+                            .long("dry-run")
+                            .action(clap::ArgAction::SetTrue),
+                    )
+                    .subcommand(Command::new("list").about("the patches applied since the load"))
+                    .subcommand(
+                        Command::new("undo")
+                            .about("write back the words replaced by a patch of `patch list`")
+                            .arg(
+                                Arg::new("idx")
+                                    .required(true)
+                                    .value_parser(RangedU64ValueParser::<usize>::new()),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("apply")
+                            .about(
+                                "apply the `<offset>: <opcode>` lines of a file, or `.const` lines",
+                            )
+                            .arg(Arg::new("path").required(true))
+                            .arg(
+                                Arg::new("dry-run")
+                                    .long("dry-run")
+                                    .action(clap::ArgAction::SetTrue),
+                            ),
+                    ),
+            )
+            .subcommand(
+                Command::new("x")
+                    .about("examine the memory: x/16w for words, x/8i instructions, x/s strings")
+                    .arg(
+                        Arg::new("format")
+                            .long("format")
+                            .help("what follows `x/`")
+                            .default_value("w")
+                            .value_parser(clap::value_parser!(ExamineFormat)),
+                    )
+                    .arg(Arg::new("addr").required(true).value_parser(parse_addr)),
+            )
+            .subcommand(
+                Command::new("dis")
+                    .subcommand(
+                        Command::new("at")
+                            .arg(Arg::new("from").required(true).value_parser(parse_addr))
+                            .arg(
+                                Arg::new("count")
+                                    .required(true)
+                                    .value_parser(RangedU64ValueParser::<usize>::new()),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("fn")
+                            .arg(Arg::new("from").required(true).value_parser(parse_addr)),
+                    )
+                    .subcommand(
+                        Command::new("graph")
+                            .about("basic blocks of a function, the current one by default")
+                            .arg(Arg::new("from").value_parser(parse_addr))
+                            .arg(
+                                Arg::new("interactive")
+                                    .short('i')
+                                    .long("interactive")
+                                    .help("one block at a time, the lines are keys until `q`")
+                                    .action(clap::ArgAction::SetTrue),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("callgraph")
+                            .about("graphviz call graph of the calls recorded by `trace start`")
+                            .arg(
+                                Arg::new("min-calls")
+                                    .long("min-calls")
+                                    .help("drop the edges with fewer calls")
+                                    .value_parser(RangedU64ValueParser::<usize>::new())
+                                    .default_value("1"),
+                            )
+                            .arg(
+                                Arg::new("merge")
+                                    .long("merge")
+                                    .help("one edge per caller and callee, not per call site")
+                                    .action(clap::ArgAction::SetTrue),
+                            )
+                            .arg(
+                                Arg::new("scale")
+                                    .long("scale")
+                                    .help("node size grows with the instructions, callees included")
+                                    .action(clap::ArgAction::SetTrue),
+                            )
+                            .arg(
+                                Arg::new("color")
+                                    .long("color")
+                                    .help("fill the nodes with the color of their instructions")
+                                    .action(clap::ArgAction::SetTrue),
+                            )
+                            .arg(
+                                Arg::new("scaling")
+                                    .long("scaling")
+                                    .help("linear or log")
+                                    .default_value("log"),
+                            )
+                            .arg(
+                                Arg::new("palette")
+                                    .long("palette")
+                                    .help("greens, heat, or viridis for colorblind-safe colors")
+                                    .default_value("greens"),
+                            )
+                            .arg(
+                                Arg::new("out")
+                                    .long("out")
+                                    .help("default: callgraph.dot in the artifacts")
+                                    .value_parser(clap::value_parser!(PathBuf)),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("file")
+                            .about("disassemble a binary without loading it")
+                            .arg(Arg::new("path").required(true))
+                            .arg(
+                                Arg::new("offset")
+                                    .long("offset")
+                                    .value_parser(parse_offset)
+                                    .default_value("0"),
+                            )
+                            .arg(
+                                Arg::new("count")
+                                    .long("count")
+                                    .value_parser(RangedU64ValueParser::<usize>::new())
+                                    .default_value("20"),
+                            ),
+                    ),
+            )
+            .subcommand(
+                Command::new("asm")
+                    .about("encode or decode instructions, without touching memory")
+                    .disable_help_subcommand(true)
+                    .subcommand(
+                        Command::new("file")
+                            .about("assemble a source into a binary for `runfile` or `dis file`")
+                            .arg(Arg::new("source").required(true))
+                            .arg(Arg::new("output").required(true)),
+                    )
+                    .subcommand(
+                        Command::new("encode").arg(
+                            Arg::new("instr")
+                                .required(true)
+                                .num_args(1..)
+                                .help("e.g. Set(Reg(1), 1531)"),
+                        ),
+                    )
+                    .subcommand(
+                        Command::new("decode").arg(
+                            Arg::new("words")
+                                .required(true)
+                                .num_args(1..=4)
+                                .value_parser(RangedU64ValueParser::<u16>::new()),
+                        ),
+                    )
+                    .subcommand(
+                        Command::new("help")
+                            .about("describe the instruction set")
+                            .arg(Arg::new("opcode").help("mnemonic or number")),
+                    ),
+            )
+            .subcommand(
+                Command::new("vm")
+                    .subcommand(
+                        Command::new("io")
+                            .about("input and output buffers, to debug stuck sessions"),
+                    )
+                    .subcommand(
+                        Command::new("patch").arg(
+                            Arg::new("patch")
+                                .required(true)
+                                .value_parser(BoolishValueParser::new()),
+                        ),
+                    )
+                    .subcommand(
+                        Command::new("banks")
+                            .about("extended mode: number of data banks, selected with wmem 32767")
+                            .arg(
+                                Arg::new("banks")
+                                    .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("journal")
+                            .about("record the last steps, to undo them with `step back`")
+                            .arg(Arg::new("journal").value_parser(BoolishValueParser::new()))
+                            .arg(
+                                Arg::new("size")
+                                    .long("size")
+                                    .help("number of steps kept")
+                                    .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                                    .default_value("100000"),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("costs")
+                            .about("count cycles, with the `opcode_costs` of config.json")
+                            .arg(Arg::new("costs").value_parser(BoolishValueParser::new())),
+                    )
+                    .subcommand(
+                        Command::new("coverage")
+                            .about("count the executions of each address, for `report html`")
+                            .arg(
+                                Arg::new("coverage")
+                                    .required(true)
+                                    .value_parser(BoolishValueParser::new()),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("register").subcommand(
+                            Command::new("set")
+                                .arg(
+                                    Arg::new("register")
+                                        .required(true)
+                                        .value_parser(RangedU64ValueParser::<usize>::new()),
+                                )
+                                .arg(
+                                    Arg::new("value")
+                                        .required(true)
+                                        .value_parser(RangedU64ValueParser::<u16>::new()),
+                                ),
+                        ),
+                    ),
+            )
+            .subcommand(
+                Command::new("mem")
+                    .subcommand(Command::new("init"))
+                    .subcommand(Command::new("list"))
+                    .subcommand(
+                        Command::new("dumpbin")
+                            .about("write the memory as a binary like challenge.bin")
+                            .arg(Arg::new("path").required(true)),
+                    )
+                    .subcommand(
+                        Command::new("dirty")
+                            .about("list the pages written since the load")
+                            .arg(
+                                Arg::new("clean")
+                                    .long("clean")
+                                    .help("count the writes from now on")
+                                    .action(clap::ArgAction::SetTrue),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("get").arg(Arg::new("offset").value_parser(parse_offset)),
+                    )
+                    .subcommand(
+                        Command::new("dump")
+                            .about("rows of words in hex, with the characters they print")
+                            .arg(Arg::new("offset").required(true).value_parser(parse_offset))
+                            .arg(
+                                Arg::new("count")
+                                    .required(true)
+                                    .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+                            )
+                            .arg(
+                                Arg::new("width")
+                                    .long("width")
+                                    .help("words per row")
+                                    .default_value("8")
+                                    .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("protect")
+                            .about("stop before a Wmem into start..end, list the ranges without")
+                            .arg(Arg::new("start").value_parser(parse_offset))
+                            .arg(Arg::new("end").requires("start").value_parser(parse_end)),
+                    )
+                    .subcommand(
+                        Command::new("unprotect")
+                            .about("remove a range of `mem protect`")
+                            .arg(
+                                Arg::new("idx")
+                                    .required(true)
+                                    .value_parser(clap::value_parser!(usize)),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("edit")
+                            .about("step through the words from offset and type new values")
+                            .arg(Arg::new("offset").required(true).value_parser(parse_offset)),
+                    )
+                    .subcommand(
+                        Command::new("fill")
+                            .about("write the same value in from..to")
+                            .arg(Arg::new("from").required(true).value_parser(parse_offset))
+                            .arg(Arg::new("to").required(true).value_parser(parse_end))
+                            .arg(Arg::new("value").required(true).value_parser(parse_word))
+                            .arg(
+                                Arg::new("dry-run")
+                                    .long("dry-run")
+                                    .action(clap::ArgAction::SetTrue),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("set")
+                            .arg(Arg::new("offset").value_parser(parse_offset))
+                            .arg(Arg::new("value").value_parser(parse_word)),
+                    )
+                    .subcommand(
+                        Command::new("filter")
+                            .alias("f")
+                            .subcommand(
+                                Command::new("=").arg(Arg::new("value").value_parser(parse_word)),
+                            )
+                            .subcommand(
+                                Command::new("!=").arg(Arg::new("value").value_parser(parse_word)),
+                            )
+                            .subcommand(
+                                Command::new(">").arg(Arg::new("value").value_parser(parse_word)),
+                            )
+                            .subcommand(
+                                Command::new(">=").arg(Arg::new("value").value_parser(parse_word)),
+                            )
+                            .subcommand(
+                                Command::new("<").arg(Arg::new("value").value_parser(parse_word)),
+                            )
+                            .subcommand(
+                                Command::new("<=").arg(Arg::new("value").value_parser(parse_word)),
+                            ),
+                    ),
+            )
+            .subcommand(
+                Command::new("continue")
+                    .alias("c")
+                    .about("run past the breakpoint the VM stopped on"),
+            )
+            .subcommand(
+                Command::new("run")
+                    .alias("r")
+                    .arg(
+                        Arg::new("until-call")
+                            .long("until-call")
+                            .value_parser(parse_addr)
+                            .help("stop before calling this address"),
+                    )
+                    .arg(
+                        Arg::new("max-cycles")
+                            .long("max-cycles")
+                            .value_parser(clap::value_parser!(u64))
+                            .help("stop once the cycles reach this, see `vm costs`"),
+                    )
+                    .arg(
+                        Arg::new("until-depth")
+                            .long("until-depth")
+                            .value_parser(clap::value_parser!(StopCondition))
+                            .allow_hyphen_values(true)
+                            .help("stop at this call stack depth: `2`, `<2` or `>2`"),
+                    ),
+            )
+            .subcommand(Command::new("input").alias("i").arg(Arg::new("line")))
+            .subcommand(
+                Command::new("undo")
+                    .about("rewind the VM and its patches before the last game inputs")
+                    .arg(
+                        Arg::new("count")
+                            .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                            .default_value("1"),
+                    ),
+            )
+            .subcommand(
+                Command::new("bg")
+                    .about("run on a background thread, the other commands wait for `bg fg`")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("run")
+                            .about("start or continue running, answering the prompt with the line")
+                            .arg(Arg::new("line")),
+                    )
+                    .subcommand(Command::new("status").about("print the output and events so far"))
+                    .subcommand(Command::new("pause"))
+                    .subcommand(
+                        Command::new("break")
+                            .arg(Arg::new("address").required(true).value_parser(parse_addr)),
+                    )
+                    .subcommand(
+                        Command::new("clear")
+                            .arg(Arg::new("address").required(true).value_parser(parse_addr)),
+                    )
+                    .subcommand(
+                        Command::new("snap")
+                            .about("copy the running VM to a snapshot")
+                            .arg(Arg::new("name").required(true)),
+                    )
+                    .subcommand(Command::new("fg").about("pause, and get the VM back")),
+            )
+            .subcommand(
+                Command::new("bench").subcommand_required(true).subcommand(
+                    Command::new("patch")
+                        .about(
+                            "run a scenario unpatched, with the hook, and with the table of 6027",
+                        )
+                        .arg(
+                            Arg::new("name")
+                                .required(true)
+                                .value_parser(["2125", "6027"])
+                                .help("patched function"),
+                        )
+                        .arg(
+                            Arg::new("input").long("input").help(
+                                "game input starting the scenario, `use teleporter` for 6027",
+                            ),
+                        )
+                        .arg(
+                            Arg::new("max-steps")
+                                .long("max-steps")
+                                .value_parser(RangedU64ValueParser::<usize>::new())
+                                .default_value("100000000"),
+                        ),
+                ),
+            )
+            .subcommand(
+                Command::new("runfile")
+                    .about("run another program in a fresh VM, with limits")
+                    .arg(Arg::new("program").required(true))
+                    .arg(
+                        Arg::new("max-steps")
+                            .long("max-steps")
+                            .value_parser(RangedU64ValueParser::<usize>::new())
+                            .default_value("10000000"),
+                    )
+                    .arg(
+                        Arg::new("input")
+                            .long("input")
+                            .help("file with a line per prompt of the program")
+                            .value_parser(clap::value_parser!(PathBuf)),
+                    )
+                    .arg(
+                        Arg::new("raw")
+                            .long("raw")
+                            .help("byte I/O: the input file is read until EOF, then `in` halts")
+                            .action(clap::ArgAction::SetTrue),
+                    ),
+            )
+            .subcommand(
+                Command::new("source")
+                    .about("run a file of commands, stop at the first failing one")
+                    .arg(Arg::new("path").required(true)),
+            )
+            .subcommand(
+                Command::new("tee")
+                    .about("append the inputs and outputs of the game to a transcript file")
+                    .arg(Arg::new("path"))
+                    .arg(
+                        Arg::new("stop")
+                            .long("stop")
+                            .action(clap::ArgAction::SetTrue)
+                            .conflicts_with("path"),
+                    ),
+            )
+            .subcommand(
+                Command::new("verify").subcommand_required(true).subcommand(
+                    Command::new("transcript")
+                        .about("replay the inputs of a transcript, and compare the outputs")
+                        .arg(Arg::new("path").required(true))
+                        .arg(
+                            Arg::new("update")
+                                .long("update")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("write the outputs to the transcript instead"),
+                        ),
+                ),
+            )
+            .subcommand(
+                Command::new("assert")
+                    .about("fail the script if the VM isn't in this state")
+                    .arg(
+                        Arg::new("assertion")
+                            .required(true)
+                            .num_args(1..)
+                            .trailing_var_arg(true)
+                            .allow_hyphen_values(true),
+                    ),
+            )
+            .subcommand(
+                Command::new("on")
+                    .about("run a command when a condition becomes true, or list/remove rules")
+                    .arg(
+                        Arg::new("rule")
+                            .required(true)
+                            .num_args(1..)
+                            .trailing_var_arg(true)
+                            .allow_hyphen_values(true),
+                    ),
+            )
+            .subcommand(
+                Command::new("play").arg(
+                    Arg::new("path")
+                        .required(true)
+                        .help("file with one game input per line, `#` for comments"),
+                ),
+            )
+            .subcommand(
+                Command::new("glossary")
+                    .about("items seen by `solver explore`, with their rooms and descriptions")
+                    .arg(Arg::new("item").help("regex matching the item names"))
+                    .arg(
+                        Arg::new("maze")
+                            .long("maze")
+                            .value_parser(clap::value_parser!(PathBuf))
+                            .help("add the glossary of a maze database"),
+                    )
+                    .arg(
+                        Arg::new("texts")
+                            .long("texts")
+                            .action(clap::ArgAction::SetTrue)
+                            .help("only the items with something written on them"),
+                    ),
+            )
+            .subcommand(
+                Command::new("solver")
+                    .subcommand(
+                        Command::new("graphviz")
+                            .about("render a maze database")
+                            .arg(
+                                Arg::new("maze")
+                                    .required(true)
+                                    .value_parser(clap::value_parser!(PathBuf)),
+                            )
+                            .arg(
+                                Arg::new("cluster")
+                                    .long("cluster")
+                                    .default_value("name")
+                                    .help("name, area, or prefix:<depth>"),
+                            )
+                            .arg(
+                                Arg::new("collapse")
+                                    .long("collapse")
+                                    .help("regex of the cluster labels to draw as one node"),
+                            )
+                            .arg(
+                                Arg::new("out")
+                                    .long("out")
+                                    .help("default: graphviz.dot in the artifacts")
+                                    .value_parser(clap::value_parser!(PathBuf)),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("explore")
+                            .arg(
+                                Arg::new("max-rooms")
+                                    .long("max-rooms")
+                                    .value_parser(RangedU64ValueParser::<usize>::new()),
+                            )
+                            .arg(
+                                Arg::new("max-time")
+                                    .long("max-time")
+                                    .value_parser(parse_duration)
+                                    .help("e.g. 30s, 5m, 1h"),
+                            )
+                            .arg(
+                                Arg::new("resume")
+                                    .long("resume")
+                                    .value_parser(clap::value_parser!(PathBuf))
+                                    .help("maze database to resume from and save to"),
+                            ),
+                    )
+                    .subcommand(Command::new("teleporter"))
+                    .subcommand(
+                        Command::new("tablet").about("get the tablet code from a fresh boot"),
+                    )
+                    .subcommand(
+                        Command::new("fuzz-verbs")
+                            .about("try words at the current prompt, to find hidden commands")
+                            .arg(
+                                Arg::new("wordlist")
+                                    .long("wordlist")
+                                    .value_parser(clap::value_parser!(PathBuf))
+                                    .help("file with one candidate word per line"),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("walkthrough")
+                            .arg(
+                                Arg::new("room")
+                                    .required(true)
+                                    .help("regex matching the name of the target room"),
+                            )
+                            .arg(
+                                Arg::new("item")
+                                    .long("item")
+                                    .action(clap::ArgAction::Append)
+                                    .help("regex matching an item to hold when arriving"),
+                            )
+                            .arg(
+                                Arg::new("out")
+                                    .long("out")
+                                    .value_parser(clap::value_parser!(PathBuf))
+                                    .help("write the inputs to a file readable by `play`"),
+                            )
+                            .arg(
+                                Arg::new("max-states")
+                                    .long("max-states")
+                                    .value_parser(RangedU64ValueParser::<usize>::new()),
+                            )
+                            .arg(
+                                Arg::new("maze")
+                                    .long("maze")
+                                    .value_parser(clap::value_parser!(PathBuf))
+                                    .help(
+                                        "maze database from `solver explore`, to guide the search",
+                                    ),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("vault").arg(
+                            Arg::new("inputs")
+                                .required(true)
+                                .num_args(1..)
+                                .help("game commands to feed, one per argument"),
+                        ),
+                    ),
+            )
+            .subcommand(
+                Command::new("hint")
+                    .about("suggest the next objective, from the items, rooms and codes")
+                    .arg(
+                        Arg::new("full")
+                            .long("full")
+                            .action(clap::ArgAction::SetTrue)
+                            .help("give the answers, spoilers"),
+                    ),
+            )
+            .subcommand(
+                Command::new("codes")
+                    .about("codes captured during the session")
+                    .subcommand(Command::new("status")),
+            )
+            .subcommand(
+                Command::new("copy")
+                    .about("copy to the clipboard: last-output, codes, or the content of a file")
+                    .arg(Arg::new("what").required(true)),
+            )
+            .subcommand(
+                Command::new("snap")
+                    .subcommand(
+                        Command::new("load")
+                            .arg(Arg::new("dump_path").required(true))
+                            .arg(
+                                Arg::new("dry-run")
+                                    .long("dry-run")
+                                    .action(clap::ArgAction::SetTrue),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("dump")
+                            .about("save a snapshot to snaps/, as a delta versus the program")
+                            .arg(Arg::new("name").required(true))
+                            .arg(Arg::new("dump_path").required(true))
+                            .arg(
+                                Arg::new("compress")
+                                    .long("compress")
+                                    .help("zstd compression, detected by `snap load`")
+                                    .action(clap::ArgAction::SetTrue),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("take")
+                            .arg(Arg::new("name").help("default: room, tag and instruction count"))
+                            .arg(
+                                Arg::new("tag")
+                                    .long("tag")
+                                    .conflicts_with("name")
+                                    .help("added to the default name, like `coins`"),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("remove")
+                            .arg(Arg::new("name").required(true))
+                            .arg(
+                                Arg::new("dry-run")
+                                    .long("dry-run")
+                                    .action(clap::ArgAction::SetTrue),
+                            ),
+                    )
+                    .subcommand(
+                        Command::new("restore")
+                            .arg(Arg::new("name").required(true))
+                            .arg(
+                                Arg::new("dry-run")
+                                    .long("dry-run")
+                                    .action(clap::ArgAction::SetTrue),
+                            ),
+                    )
+                    .subcommand(Command::new("list")),
             )
             .subcommand(
-                Command::new("step").alias("s").arg(
-                    Arg::new("count")
-                        .value_parser(RangedU64ValueParser::<u32>::new())
-                        .default_value("1"),
+                Command::new("step")
+                    .alias("s")
+                    .args_conflicts_with_subcommands(true)
+                    .arg(
+                        Arg::new("count")
+                            .value_parser(RangedU64ValueParser::<u32>::new())
+                            .default_value("1"),
+                    )
+                    .subcommand(
+                        Command::new("back")
+                            .about("undo the last steps, recorded by `vm journal on`")
+                            .arg(
+                                Arg::new("count")
+                                    .value_parser(RangedU64ValueParser::<usize>::new())
+                                    .default_value("1"),
+                            ),
+                    ),
+            );
+
+        let config = Config::load_default();
+        let mut codes = CodeRegistry::with_hashes(&config.code_hashes);
+        if let Ok(spec) = std::fs::read_to_string("arch-spec") {
+            codes.capture(&spec);
+        }
+
+        Self {
+            cli,
+            vm,
+            binary: None,
+            artifacts: Artifacts::new(
+                config
+                    .artifacts_dir
+                    .unwrap_or_else(|| PathBuf::from(ARTIFACTS_DIR)),
+            ),
+            snaps_dir: PathBuf::from(SNAPS_DIR),
+            snapshots: Vec::new(),
+            codes,
+            confirm: true,
+            quit: false,
+            explain: false,
+            prompt: config.prompt.unwrap_or_else(|| DEFAULT_PROMPT.to_string()),
+            watchdog: false,
+            game_mode: false,
+            game_words: None,
+            session: SessionStats::default(),
+            alerts: Vec::new(),
+            alert_command: config.alert_command,
+            alerts_checked: 0,
+            hooks_checked: 0,
+            background: None,
+            glossary: BTreeMap::new(),
+            undo: VecDeque::new(),
+            tee: None,
+            view: None,
+            analysis: None,
+            io_problems: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// Remember the path of the program in the VM, and track the functions analyzed by a
+    /// previous session
+    pub fn set_binary(&mut self, binary: Option<PathBuf>) {
+        self.binary = binary;
+        self.analysis = None;
+        let Ok(program) = self.program() else {
+            return;
+        };
+        let path = AnalysisDb::default_path(&program);
+        if path.exists() {
+            if let Err(e) = self.load_analysis(path) {
+                println!("Can't load the analysis: {}", e);
+            }
+        }
+    }
+
+    /// Words of the program, read again from `binary`
+    fn program(&self) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
+        let path = self
+            .binary
+            .as_ref()
+            .ok_or("No program loaded, see `load`")?;
+        Vm::read_binary(path)
+    }
+
+    /// Load the analysis database at `path`, and track the writes to its functions
+    fn load_analysis(&mut self, path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+        let db = if path.exists() {
+            AnalysisDb::load(&path)?
+        } else {
+            AnalysisDb::new(&self.program()?)
+        };
+        self.vm.set_tracked_code(db.code_addresses());
+        // the bookmarks are the labels saved with the analysis
+        let mut bookmarks = SymbolTable::default();
+        for (name, &addr) in &db.bookmarks {
+            if let Err(e) = Addr::try_from(addr).and_then(|addr| bookmarks.set(addr, name)) {
+                println!("Bookmark {} skipped: {}", name, e);
+            }
+        }
+        for (addr, name) in self.vm.get_symbols_mut().merge(&bookmarks) {
+            println!("Bookmark {} skipped, {} is labeled", name, addr);
+        }
+        self.analysis = Some((path, db));
+
+        Ok(())
+    }
+
+    /// Load the analysis database at `path` if it's not the current one, or the default one
+    /// if none is loaded
+    fn open_analysis(&mut self, path: Option<&PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+        let path = match (path, &self.analysis) {
+            (Some(path), Some((loaded, _))) if path == loaded => return Ok(()),
+            (None, Some(_)) => return Ok(()),
+            (Some(path), _) => path.clone(),
+            (None, None) => AnalysisDb::default_path(&self.program()?),
+        };
+
+        self.load_analysis(path)
+    }
+
+    /// Replace `@name` by the address of the label, also in operands like `Call(@name)`, and
+    /// the `fn_<addr>` words by the address. The "quoted" text is left alone.
+    fn resolve_names(&self, line: &str) -> String {
+        let names = Regex::new(r"@(\w+)|\bfn_(\d+)\b").unwrap();
+        let resolve = |text: &str| {
+            names
+                .replace_all(text, |caps: &regex::Captures| {
+                    let name = caps.get(1).map_or(&caps[0], |name| name.as_str());
+                    match self.vm.get_symbols().resolve(name) {
+                        Some(addr) => addr.to_string(),
+                        None => caps[0].to_string(),
+                    }
+                })
+                .into_owned()
+        };
+
+        let mut resolved = String::new();
+        let mut rest = line;
+        while let Some(start) = rest.find(['"', '\'']) {
+            let quote = rest[start..].chars().next().unwrap();
+            let end = rest[start + 1..]
+                .find(quote)
+                .map_or(rest.len(), |end| start + end + 2);
+            resolved.push_str(&resolve(&rest[..start]));
+            resolved.push_str(&rest[start..end]);
+            rest = &rest[end..];
+        }
+        resolved.push_str(&resolve(rest));
+
+        resolved
+    }
+
+    /// Run the commands of the rules whose condition became true
+    fn check_rules(&mut self) {
+        let commands = triggered_rules(&mut self.rules, &self.vm);
+        self.run_rules(commands);
+    }
+
+    /// Run the commands of triggered rules: (condition, command)
+    fn run_rules(&mut self, commands: Vec<(String, String)>) {
+        // the rules triggered by these commands are checked after the next command
+        for (condition, command) in commands {
+            println!("on {}: {}", condition, command);
+            if let Err(e) = self.parse_single_command(&command) {
+                println!("{}", e);
+            }
+        }
+    }
+
+    /// `Vm::run_until`, running the commands of the rules when their condition becomes true
+    ///
+    /// The VM stops after the step, runs the commands, then continues if they didn't move it.
+    fn run_until(&mut self, conditions: &[StopCondition]) -> Option<StopCondition> {
+        loop {
+            let mut commands = Vec::new();
+            let rules = &mut self.rules;
+            let stopped = self.vm.run_until_with(conditions, |vm| {
+                commands = triggered_rules(rules, vm);
+                !commands.is_empty()
+            });
+            if commands.is_empty() {
+                return stopped;
+            }
+            self.run_rules(commands);
+            if self.vm.get_state() != VmState::HitBreakPoint {
+                return None;
+            }
+        }
+    }
+
+    /// Warn about the hooks and patched functions that broke the stack during the last command
+    fn check_hooks(&mut self) {
+        let violations = self.vm.get_hook_violations();
+        // restoring a snapshot can forget violations
+        let start = self.hooks_checked.min(violations.len());
+        self.hooks_checked = violations.len();
+        for violation in &violations[start..] {
+            println!("WARNING: {}", violation);
+        }
+    }
+
+    /// Warn about new inconsistencies of the input path
+    fn check_io(&mut self) {
+        let problems = self.vm.io_problems();
+        for problem in problems.iter().filter(|p| !self.io_problems.contains(p)) {
+            println!("WARNING: {}, see `vm io`", problem);
+        }
+        self.io_problems = problems;
+    }
+
+    /// Mark the analyzed functions modified by the last command as stale
+    fn check_code_changes(&mut self) {
+        let modified = self.vm.take_modified_code();
+        if let Some((_, db)) = &mut self.analysis {
+            if !modified.is_empty() {
+                db.mark_modified(&modified);
+            }
+        }
+    }
+
+    /// `offset` if it's in the memory of the VM, extended banks included
+    fn check_offset(&self, offset: usize) -> Result<usize, String> {
+        let size = self.vm.get_memory().len();
+        if offset >= size {
+            return Err(format!("Invalid offset {}, must be < {}", offset, size));
+        }
+        Ok(offset)
+    }
+
+    /// `end` if it's at most the size of the memory of the VM
+    fn check_end(&self, end: usize) -> Result<usize, String> {
+        let size = self.vm.get_memory().len();
+        if end > size {
+            return Err(format!("Invalid end {}, must be <= {}", end, size));
+        }
+        Ok(end)
+    }
+
+    /// Warn about the listed addresses in functions modified since their analysis
+    fn warn_stale(&self, addresses: impl IntoIterator<Item = usize>) {
+        let Some((_, db)) = &self.analysis else {
+            return;
+        };
+        let stale: BTreeSet<&str> = addresses
+            .into_iter()
+            .flat_map(|addr| db.stale_at(addr).map(|f| f.label.as_str()))
+            .collect();
+        for label in stale {
+            println!(
+                "WARNING: {} was modified since its analysis, the listing may have changed",
+                label
+            );
+        }
+    }
+
+    fn get_snap_by_name(&self, name: &str) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|snap| snap.name == name)
+    }
+
+    /// Path of the saved snapshot `name`
+    fn snap_path(&self, name: &str) -> String {
+        self.snaps_dir.join(name).to_string_lossy().into_owned()
+    }
+
+    fn dump_snapshot(
+        &mut self,
+        name: &str,
+        dump_path: &str,
+        compress: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let snap = self
+            .get_snap_by_name(name)
+            .ok_or_else(|| format!("Snap {:?} not found", name))?;
+        let program = self.program().ok();
+        let size = save_state_to_disk(dump_path, snap, program.as_deref(), compress)?;
+        println!("Saved {}, {} KB", dump_path, size / 1024);
+
+        Ok(())
+    }
+
+    fn load_snapshot(&mut self, dump_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut snap = load_state_from_disk(dump_path, self.program().ok().as_deref())?;
+        snap.vm.clean_pages();
+        let name = snap.name.clone();
+
+        match self.snapshots.iter().find(|s| s.name == name) {
+            Some(_) => (),
+            None => self.snapshots.push(snap),
+        };
+        self.restore_snapshot(&name);
+
+        Ok(())
+    }
+
+    fn take_snapshot(&mut self, name: &str) {
+        self.snapshots.push(Snapshot {
+            name: name.to_string(),
+            vm: self.vm.clone(),
+            taken: SystemTime::now(),
+        });
+    }
+
+    /// `<room>[-<tag>]-<nn>_<instructions>instr`, like `ruins-coins-01_2.3Minstr`
+    pub fn suggest_snapshot_name(&self, tag: Option<&str>) -> String {
+        let mut prefix = room_name(&self.vm)
+            .map(|room| slug(&room))
+            .unwrap_or_else(|| "start".to_string());
+        if let Some(tag) = tag {
+            prefix = format!("{}-{}", prefix, slug(tag));
+        }
+        let suffix = format!("{}instr", format_count(self.vm.get_pc()));
+
+        (1..)
+            .map(|n| format!("{}-{:02}_{}", prefix, n, suffix))
+            .find(|name| self.get_snap_by_name(name).is_none())
+            .unwrap()
+    }
+
+    /// The snapshots in memory, then the files of snaps/
+    fn saved_states(&self) -> Vec<SavedState> {
+        let mut states: Vec<SavedState> = self
+            .snapshots
+            .iter()
+            .map(|snap| SavedState {
+                name: snap.name.clone(),
+                in_memory: true,
+                time: snap.taken,
+                size: 2 * snap.vm.get_memory().len() as u64,
+                details: format!(
+                    "{}, {} instructions",
+                    room_name(&snap.vm).unwrap_or_else(|| "-".to_string()),
+                    format_count(snap.vm.get_pc())
                 ),
+            })
+            .collect();
+
+        let files = std::fs::read_dir(&self.snaps_dir)
+            .into_iter()
+            .flatten()
+            .flatten();
+        for entry in files {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if metadata.is_file() && !name.starts_with('.') {
+                states.push(SavedState {
+                    name,
+                    in_memory: false,
+                    time: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    size: metadata.len(),
+                    details: String::new(),
+                });
+            }
+        }
+
+        states
+    }
+
+    fn print_saved_states(states: &[SavedState]) {
+        for state in states {
+            println!(
+                "{:>8} {:6} {:>6} KB {:40} {}",
+                format_age(state.time),
+                if state.in_memory { "memory" } else { "file" },
+                state.size / 1024,
+                state.name,
+                state.details
             );
+        }
+        println!("{} states", states.len());
+    }
 
-        Self {
-            cli,
-            vm,
-            snapshots: Vec::new(),
+    /// Merge the items of a maze in `glossary`
+    fn add_glossary(&mut self, glossary: BTreeMap<String, GlossaryEntry>) {
+        for (item, entry) in glossary {
+            let known = self.glossary.entry(item).or_default();
+            known.rooms.extend(entry.rooms);
+            known.look = known.look.take().or(entry.look);
+            // the mazes explored before the texts were parsed only have the look
+            known.text = known
+                .text
+                .take()
+                .or(entry.text)
+                .or_else(|| known.look.as_deref().and_then(TextEntry::parse));
+        }
+    }
+
+    /// The item whose text describes the check of the teleporter, like the strange book
+    fn algorithm_source(&self) -> Option<(&String, &GlossaryEntry, &TextEntry)> {
+        self.glossary.iter().find_map(|(item, entry)| {
+            let text = entry.text.as_ref().filter(|text| text.algorithm)?;
+            Some((item, entry, text))
+        })
+    }
+
+    /// A text of the glossary: the item, what is written, and the codes in it
+    fn print_text(text: &TextEntry) {
+        println!("{}", text.description);
+        if text.algorithm {
+            println!("(describes the check of the teleporter, see `solver teleporter`)");
+        }
+        for paragraph in &text.paragraphs {
+            println!("\n{}", paragraph);
+        }
+        if !text.codes.is_empty() {
+            println!("\nCodes: {}", text.codes.join(", "));
+        }
+        println!();
+    }
+
+    /// `state gc`: the states older than `max_age`, and with `duplicates` the snapshots
+    /// identical to a newer one, with the reason to drop them
+    fn gc_candidates(
+        &self,
+        states: Vec<SavedState>,
+        max_age: Option<Duration>,
+        duplicates: bool,
+    ) -> Vec<(SavedState, String)> {
+        let mut candidates = Vec::new();
+        for state in states {
+            let age = state.time.elapsed().unwrap_or_default();
+            if max_age.is_some_and(|max_age| age > max_age) {
+                let reason = format!("taken {}", format_age(state.time));
+                candidates.push((state, reason));
+                continue;
+            }
+            if !duplicates || !state.in_memory {
+                continue;
+            }
+            let snap = self.get_snap_by_name(&state.name).unwrap();
+            let newer = self
+                .snapshots
+                .iter()
+                .find(|other| other.taken > snap.taken && other.vm == snap.vm);
+            if let Some(newer) = newer {
+                let reason = format!("same as {:?}", newer.name);
+                candidates.push((state, reason));
+            }
+        }
+
+        candidates
+    }
+
+    fn remove_snapshot(&mut self, name: &str) {
+        let mut idx = None;
+        for (i, snap) in self.snapshots.iter().enumerate() {
+            if snap.name == name {
+                idx = Some(i);
+                break;
+            }
+        }
+
+        match idx {
+            Some(idx) => {
+                self.snapshots.remove(idx);
+            }
+            None => println!("Not found"),
+        }
+    }
+
+    fn restore_snapshot(&mut self, name: &str) {
+        match self.get_snap_by_name(name) {
+            Some(snap) => {
+                let vm = snap.vm.clone();
+                let tracked_code = self.vm.get_tracked_code().clone();
+                // the labels describe the program, not the state: the current ones win
+                let mut symbols = self.vm.get_symbols().clone();
+                let skipped = symbols.merge(vm.get_symbols());
+                self.vm = vm;
+                // the checkpoints only restore the VM they were taken from
+                self.undo.clear();
+                self.vm.set_tracked_code(tracked_code);
+                *self.vm.get_symbols_mut() = symbols;
+                for (addr, label) in skipped {
+                    println!(
+                        "Label {} of the snapshot skipped, {} is labeled",
+                        label, addr
+                    );
+                }
+            }
+            None => println!("Snap not found"),
+        }
+    }
+
+    /// Ask `question` on stdin, unless confirmations are disabled
+    fn confirm(&self, question: &str) -> bool {
+        if !self.confirm {
+            return true;
+        }
+
+        println!("{} [y/N]", question);
+        let mut answer = String::new();
+        match std::io::stdin().read_line(&mut answer) {
+            Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+            Err(_) => false,
+        }
+    }
+
+    /// `live` for the current VM, else a snapshot, else a file of snaps/
+    fn state_by_name(&self, name: &str) -> Result<Cow<'_, Vm>, Box<dyn std::error::Error>> {
+        if name == "live" {
+            return Ok(Cow::Borrowed(&self.vm));
+        }
+        if let Some(snap) = self.get_snap_by_name(name) {
+            return Ok(Cow::Borrowed(&snap.vm));
+        }
+
+        let path = self.snap_path(name);
+        let snap = load_state_from_disk(&path, self.program().ok().as_deref())
+            .map_err(|e| format!("No snapshot {:?}, and can't read {}: {}", name, path, e))?;
+        Ok(Cow::Owned(snap.vm))
+    }
+
+    /// The current VM is not in any snapshot
+    fn has_unsaved_progress(&self) -> bool {
+        !self.snapshots.iter().any(|snap| snap.vm == self.vm)
+    }
+
+    /// Record the codes of the last message
+    fn capture_codes(&mut self) {
+        if let Some(message) = self.vm.get_messages().last() {
+            for (slot, code) in self.codes.capture(message) {
+                println!("Captured {} code: {}", slot, code);
+                if self.codes.verify(slot) == Some(false) {
+                    println!(
+                        "WARNING: {} code doesn't match its hash, emulator bug?",
+                        slot
+                    );
+                }
+            }
+        }
+    }
+
+    fn print_session_stats(&self) {
+        let elapsed = self.session.started.elapsed().as_secs();
+        let codes = CodeSlot::ALL
+            .iter()
+            .filter(|slot| self.codes.get(**slot).is_some())
+            .count();
+        let items = match planner::inventory(&self.vm) {
+            Ok(items) => items.join(", "),
+            Err(_) => "unknown, the game isn't waiting for input".to_string(),
+        };
+
+        println!("Instructions executed: {}", self.vm.get_pc());
+        if self.vm.get_costs().is_some() {
+            println!("Cycles: {}", self.vm.get_cycles());
+        }
+        println!("Game commands: {}", self.session.game_commands);
+        println!("Rooms discovered: {}", self.session.rooms.len());
+        println!("Items held: {}", items);
+        println!("Codes captured: {}/{}", codes, CodeSlot::ALL.len());
+        println!("Snapshots taken: {}", self.snapshots.len());
+        println!(
+            "Time spent: {}h{:02}m{:02}s",
+            elapsed / 3600,
+            elapsed / 60 % 60,
+            elapsed % 60
+        );
+    }
+
+    fn background_command(&mut self, sub: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(("run", sub)) = sub.subcommand() {
+            self.undo.clear();
+            let vm = &mut self.vm;
+            let handle = self
+                .background
+                .get_or_insert_with(|| VmHandle::spawn(std::mem::replace(vm, Vm::new())));
+            match sub.get_one::<String>("line") {
+                Some(line) => handle.send(driver::Command::Input(line.clone()))?,
+                None => handle.send(driver::Command::Run)?,
+            }
+            return Ok(());
+        }
+
+        let handle = self
+            .background
+            .as_ref()
+            .ok_or("Nothing runs in the background, see `bg run`")?;
+        match sub.subcommand() {
+            Some(("status", _)) => {
+                handle.poll().iter().for_each(Self::print_event);
+                if !handle.is_alive() {
+                    // it only stops on its own by panicking
+                    let (vm, events) = self.background.take().unwrap().join()?;
+                    events.iter().for_each(Self::print_event);
+                    self.vm = vm;
+                }
+            }
+            Some(("pause", _)) => handle.send(driver::Command::Pause)?,
+            Some(("break", sub)) => {
+                let addr = *sub.get_one::<Addr>("address").unwrap();
+                handle.send(driver::Command::SetBreakpoint(addr))?;
+            }
+            Some(("clear", sub)) => {
+                let addr = *sub.get_one::<Addr>("address").unwrap();
+                handle.send(driver::Command::UnsetBreakpoint(addr))?;
+            }
+            Some(("snap", sub)) => {
+                let name = sub.get_one::<String>("name").unwrap();
+                handle.send(driver::Command::Snapshot)?;
+                // the events before the snapshot are still shown
+                let vm = loop {
+                    match handle.recv().ok_or("The VM thread stopped")? {
+                        Event::Snapshot(vm) => break vm,
+                        event => Self::print_event(&event),
+                    }
+                };
+                self.snapshots.retain(|snap| snap.name != *name);
+                self.snapshots.push(Snapshot {
+                    name: name.clone(),
+                    vm: *vm,
+                    taken: SystemTime::now(),
+                });
+            }
+            Some(("fg", _)) => {
+                let (vm, events) = self.background.take().unwrap().join()?;
+                events.iter().for_each(Self::print_event);
+                self.vm = vm;
+                println!(
+                    "Back at {} after {} steps",
+                    self.vm.get_ip(),
+                    self.vm.get_pc()
+                );
+            }
+            Some((_, _)) => return Err("unreachable?".into()),
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    fn print_event(event: &Event) {
+        match event {
+            Event::Output(text) => println!("{}", text),
+            Event::Stopped(VmState::Halted) => println!("\n\nHalted"),
+            Event::Stopped(VmState::HitBreakPoint) => println!("Hit a breakpoint"),
+            Event::Stopped(VmState::WaitingForInput) => {
+                println!("Waiting for input, `bg run <line>`")
+            }
+            Event::Stopped(VmState::Running) => (),
+            Event::Paused { ip, pc } => println!("Paused at {} after {} steps", ip, pc),
+            Event::Snapshot(_) => (),
+            Event::Error(e) => println!("Error: {}", e),
+        }
+    }
+
+    /// The `<offset>: <code>` lines of a patch file, with the words to write, see `patch apply`
+    fn read_patches(&self, path: &str) -> Result<Vec<PatchLine>, Box<dyn std::error::Error>> {
+        let mut constants = HashMap::new();
+        let mut patches = Vec::new();
+        for (n, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parse = || -> Result<(), Box<dyn std::error::Error>> {
+                if line.starts_with(".const") {
+                    return Opcode::assemble_line(line, &mut constants, &mut Vec::new());
+                }
+                let (offset, code) = line
+                    .split_once(':')
+                    .ok_or("Expected `<offset>: <opcode>`")?;
+                let offset = parse_addr(&self.resolve_names(offset.trim()))?;
+                let mut words = Vec::new();
+                Opcode::assemble_line(code.trim(), &mut constants, &mut words)?;
+                if usize::from(offset) + words.len() > MEM_SIZE {
+                    return Err(format!("{} words don't fit at {}", words.len(), offset).into());
+                }
+                patches.push((offset, words, code.trim().to_string()));
+                Ok(())
+            };
+            parse().map_err(|e| format!("{}:{}: {}", path, n + 1, e))?;
+        }
+
+        Ok(patches)
+    }
+
+    /// A line read by `mem edit`: move, or write the value and go to the next word
+    fn mem_edit_key(&mut self, mut offset: usize, mut written: usize, key: &str) {
+        match key {
+            "q" => {
+                println!("Wrote {} words", written);
+                self.view = None;
+                return;
+            }
+            "" => offset += 1,
+            "-" => offset = offset.saturating_sub(1),
+            value => {
+                let value = match value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+                    Some(c) if c.len() == 1 && c.is_ascii() => Ok(c.as_bytes()[0] as u16),
+                    _ => parse_word(&self.resolve_names(value)),
+                };
+                match value {
+                    Ok(value) => {
+                        self.vm.mem_set(offset, value);
+                        written += 1;
+                        offset += 1;
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+        }
+        if offset >= self.vm.get_memory().len() {
+            println!("End of the memory, wrote {} words", written);
+            self.view = None;
+            return;
+        }
+        self.view = Some(View::MemEdit { offset, written });
+        println!("{}", self.memory_word(offset));
+    }
+
+    /// A word with its ASCII character and the instruction it starts, for `mem edit`
+    fn memory_word(&self, offset: usize) -> String {
+        let value = self.vm.mem_get(offset);
+        let c = char::from_u32(value as u32)
+            .filter(|c| c.is_ascii_graphic() || *c == ' ' || *c == '\n')
+            .map(|c| format!("{:?}", c))
+            .unwrap_or_default();
+        // the extended banks hold no code
+        let instruction = match Addr::try_from(offset).map(|addr| self.vm.disassemble(addr, 1)) {
+            Ok(Ok(instructions)) => format!("{:?}", instructions[0].1),
+            _ => "-".to_string(),
+        };
+
+        format!(
+            "{}: {:5} {:#06x} {:5} {}",
+            self.describe(offset),
+            value,
+            value,
+            c,
+            instruction
+        )
+    }
+
+    /// `addr`, with its label if it has one
+    fn describe(&self, addr: usize) -> String {
+        match self.vm.get_symbols().label(addr) {
+            Some(label) => format!("{} ({})", addr, label),
+            None => addr.to_string(),
+        }
+    }
+
+    /// Say why running stopped, if it's not a prompt
+    fn print_stop(&self, stopped: Option<StopCondition>) {
+        match self.vm.get_state() {
+            VmState::Halted => println!("\n\nHalted"),
+            VmState::HitBreakPoint => match stopped {
+                Some(condition) => println!("Stopped at {}: {}", self.vm.get_ip(), condition),
+                None if self.vm.get_fault().is_some() => {
+                    let fault = self.vm.get_fault().unwrap();
+                    println!(
+                        "Protected write: {} writes {} to {}",
+                        self.vm
+                            .get_symbols()
+                            .format_instruction(fault.ip.into(), &fault.instruction),
+                        fault.value,
+                        self.describe(fault.addr)
+                    );
+                }
+                None => {
+                    let changed: Vec<(usize, &Watch)> = self
+                        .vm
+                        .get_watches()
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, watch)| watch.changed_at == Some(self.vm.get_pc()))
+                        .collect();
+                    if changed.is_empty() {
+                        println!(
+                            "Hit breakpoint at {}",
+                            self.describe(self.vm.get_ip().into())
+                        );
+                    }
+                    for (idx, watch) in changed {
+                        println!(
+                            "Watch {} at {}: {} changed from {} to {}",
+                            idx,
+                            self.describe(self.vm.get_ip().into()),
+                            watch.expr,
+                            format_value(watch.previous),
+                            format_value(watch.value)
+                        );
+                    }
+                }
+            },
+            VmState::Running | VmState::WaitingForInput => (),
+        }
+    }
+
+    /// Make deaths and the end of the game stand out
+    fn print_terminal_banner(&self) {
+        if let GameResponse::Terminal(kind) = GameResponse::from_vm(&self.vm) {
+            let line = "*".repeat(kind.to_string().len() + 8);
+            println!("{}\n*** {} ***\n{}", line, kind, line);
+        }
+    }
+
+    /// The prompt of the REPL, with the variables of the template replaced:
+    /// `{state}`, `{room}`, `{ip}` and `{pc}`, the instruction count
+    pub fn prompt(&self) -> String {
+        if self.background.is_some() {
+            // `self.vm` is a placeholder
+            return "bg >> ".to_string();
+        }
+        match self.view {
+            Some(View::Graph { block, .. }) => return format!("graph {} > ", block),
+            Some(View::MemEdit { offset, .. }) => return format!("mem {} > ", offset),
+            None => (),
+        }
+        let state = match self.vm.get_state() {
+            VmState::Running => "running",
+            VmState::WaitingForInput => "input",
+            VmState::Halted => "halted",
+            VmState::HitBreakPoint => "break",
+        };
+
+        let mode = if self.game_mode { "game " } else { "" };
+        mode.to_string()
+            + &self
+                .prompt
+                .replace("{state}", state)
+                .replace("{ip}", &self.vm.get_ip().to_string())
+                .replace("{pc}", &format_count(self.vm.get_pc()))
+                .replace(
+                    "{room}",
+                    &room_name(&self.vm).unwrap_or_else(|| "-".to_string()),
+                )
+    }
+
+    /// Words to complete the next game input, from the room and the inventory
+    ///
+    /// They are found by `look` and `inv` on copies of the VM, the state doesn't change.
+    /// Cached until the pc moves.
+    pub fn game_words(&mut self) -> GameWords {
+        let mut words = GameWords {
+            verbs: GAME_VERBS.iter().map(|verb| verb.to_string()).collect(),
+            ..Default::default()
+        };
+        if self.background.is_some() || self.vm.get_state() != VmState::WaitingForInput {
+            return words;
+        }
+        let pc = self.vm.get_pc();
+        if let Some((_, words)) = self.game_words.as_ref().filter(|(at, _)| *at == pc) {
+            return words.clone();
+        }
+
+        let mut probe = self.vm.clone();
+        if probe.feed("look").is_ok() {
+            probe.run();
+            if let GameResponse::Room(room) = GameResponse::from_vm(&probe) {
+                words.things = room.things;
+                words.exits = room.exits;
+            }
+        }
+        words.inventory = planner::inventory(&self.vm).unwrap_or_default();
+        self.game_words = Some((pc, words.clone()));
+
+        words
+    }
+
+    /// At the end of a script: error if the game waits for an input the script doesn't give
+    ///
+    /// The game never halts by itself, so a script stopping at a prompt ends with `quit`.
+    pub fn starvation(&self) -> Result<(), String> {
+        if !self.watchdog
+            || self.quit
+            || self.background.is_some()
+            || self.vm.get_state() != VmState::WaitingForInput
+            || !self.vm.get_input_buffer().is_empty()
+        {
+            return Ok(());
+        }
+
+        let prompt = self
+            .vm
+            .get_messages()
+            .last()
+            .map(|message| message.trim())
+            .filter(|message| !message.is_empty())
+            .unwrap_or("(no output)");
+        Err(format!(
+            "The script ended, but the game waits for input after:\n{}\n\
+             End the script with `quit` to stop at this prompt",
+            prompt
+        ))
+    }
+
+    /// Run a REPL line: `;` separates commands, `#` starts a comment
+    ///
+    /// Stops at the first failing command. A line starting with `!` is run by the shell.
+    pub fn parse_command(&mut self, input_line: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.view.is_some() {
+            return self.view_key(input_line.trim());
+        }
+        let line = match input_line.split_once('#') {
+            Some((line, _comment)) => line,
+            None => input_line,
+        };
+
+        if let Some(command) = input_line.trim_start().strip_prefix('!') {
+            // the rest of the line, `;` and `#` included
+            return self.shell(command.trim());
+        }
+        for command in line.split(';') {
+            // pasted transcripts can have extra spaces
+            let mut command = command.split_whitespace().collect::<Vec<_>>().join(" ");
+            if self.game_mode && !command.is_empty() && !command.starts_with(':') {
+                command.insert(0, '>');
+            }
+            let result = self.parse_single_command(&command);
+            if self.background.is_some() {
+                // `self.vm` is a placeholder
+                result?;
+                continue;
+            }
+            self.check_alerts();
+            self.check_hooks();
+            self.check_code_changes();
+            self.check_io();
+            self.check_rules();
+            self.check_tee();
+            result?;
+        }
+
+        Ok(())
+    }
+
+    /// A line read by the interactive view
+    fn view_key(&mut self, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match self.view {
+            Some(View::Graph { from, block }) => self.graph_key(from, block, key),
+            Some(View::MemEdit { offset, written }) => {
+                self.mem_edit_key(offset, written, key);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// A line read by `dis graph -i`, `:command` runs a command
+    fn graph_key(
+        &mut self,
+        from: usize,
+        block: usize,
+        key: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let blocks = report::basic_blocks(&report::walk_function(self.vm.get_memory(), from));
+        let index = blocks.iter().position(|b| b.start == block).unwrap_or(0);
+        let select = |index: usize| blocks.get(index).map(|b| b.start);
+        let selected = match key {
+            "q" => {
+                self.view = None;
+                return Ok(());
+            }
+            "n" => select(index + 1),
+            "p" => index.checked_sub(1).and_then(select),
+            "s" | "i" | "" => {
+                let result = match key {
+                    "i" => Ok(()),
+                    _ => self.view_command("step"),
+                };
+                self.sync_graph_view();
+                self.print_view();
+                return result;
+            }
+            _ => match key.strip_prefix(':') {
+                Some(command) => {
+                    let result = self.view_command(command);
+                    self.sync_graph_view();
+                    self.print_view();
+                    return result;
+                }
+                None => match key.parse::<usize>() {
+                    Ok(n) if n > 0 => blocks[index].successors.get(n - 1).copied(),
+                    _ => {
+                        println!("{}", GRAPH_KEYS);
+                        return Ok(());
+                    }
+                },
+            },
+        };
+        match selected {
+            Some(block) => self.view = Some(View::Graph { from, block }),
+            None => println!("No such block"),
+        }
+        self.print_view();
+
+        Ok(())
+    }
+
+    /// Run `command` from the interactive view, which stays open unless it opens another
+    fn view_command(&mut self, command: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let view = self.view.take();
+        let result = self.parse_command(command);
+        if self.view.is_none() {
+            self.view = view;
+        }
+
+        result
+    }
+
+    /// Select the block of ip, in the function of ip if it left the viewed function
+    fn sync_graph_view(&mut self) {
+        let Some(View::Graph { from, .. }) = self.view else {
+            return;
+        };
+        let ip = usize::from(self.vm.get_ip());
+        let current = self
+            .vm
+            .get_call_stack()
+            .last()
+            .map_or(ip, |f| f.target.into());
+        let synced = match self.block_of(from, ip) {
+            Some(block) => Some((from, block)),
+            None => self.block_of(current, ip).map(|block| (current, block)),
+        };
+        if let Some((from, block)) = synced {
+            self.view = Some(View::Graph { from, block });
+        }
+    }
+
+    /// Start of the basic block of `addr`, in the function at `from`
+    fn block_of(&self, from: usize, addr: usize) -> Option<usize> {
+        report::basic_blocks(&report::walk_function(self.vm.get_memory(), from))
+            .into_iter()
+            .find(|b| b.instructions.iter().any(|(a, _)| *a == addr))
+            .map(|b| b.start)
+    }
+
+    fn print_view(&self) {
+        let Some(View::Graph { from, block }) = self.view else {
+            return;
+        };
+        let blocks = report::basic_blocks(&report::walk_function(self.vm.get_memory(), from));
+        println!("{}: block {}", self.vm.get_symbols().name(from), block);
+        print!(
+            "{}",
+            report::text_block(
+                &blocks,
+                block,
+                Some(self.vm.get_ip().into()),
+                self.vm.get_symbols()
+            )
+        );
+    }
+
+    /// `x`: the words, instructions or length prefixed strings at `addr`
+    fn examine(
+        &self,
+        addr: usize,
+        format: ExamineFormat,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let memory = self.vm.get_memory();
+        if addr >= memory.len() {
+            return Err(format!("Invalid address {}", addr).into());
+        }
+
+        match format.kind {
+            ExamineKind::Words => {
+                let end = (addr + format.count).min(memory.len());
+                for row in dump_words(&memory[addr..end], addr, 8) {
+                    println!("{}", row);
+                }
+            }
+            ExamineKind::Instructions => {
+                let instructions =
+                    Opcode::disassemble(memory, addr, format.count, InvalidPolicy::Skip)?;
+                for (ip, instr) in instructions.iter() {
+                    println!("{}", self.vm.get_symbols().format_instruction(*ip, instr));
+                }
+                self.warn_stale(instructions.iter().map(|(ip, _)| *ip));
+            }
+            ExamineKind::Strings => {
+                // the encrypted strings are readable once their decoder is traced
+                let decoded = StringDecoder::from_calls(self.vm.get_print_calls())
+                    .map(|decoder| decoder.decode(memory))
+                    .unwrap_or_default();
+                let mut addr = addr;
+                for _ in 0..format.count {
+                    let len = *memory.get(addr).ok_or("End of the memory")? as usize;
+                    let words = memory.get(addr + 1..addr + 1 + len).ok_or_else(|| {
+                        format!("String of {} words at {} out of memory", len, addr)
+                    })?;
+                    match decoded.get(&addr) {
+                        Some(text) => println!("{:>5}: {:?}", addr, text),
+                        None => {
+                            let text: String = words.iter().map(|&w| w as u8 as char).collect();
+                            println!("{:>5}: {:?}", addr, text);
+                        }
+                    }
+                    addr += 1 + len;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run `command` with `sh -c`, in the directory of the emulator
+    fn shell(&self, command: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .status()
+            .map_err(|e| format!("Can't run `{}`: {}", command, e))?;
+        if !status.success() {
+            return Err(format!("`{}` failed: {}", command, status).into());
+        }
+
+        Ok(())
+    }
+
+    /// Text for `copy`: `last-output` of the game, the `codes` found, or the content of a file
+    pub(crate) fn artifact(&self, what: &str) -> Result<String, Box<dyn std::error::Error>> {
+        match what {
+            "last-output" => match self.vm.get_messages().last() {
+                Some(message) => Ok(message.clone()),
+                None => Err("The game has no output yet".into()),
+            },
+            "codes" => {
+                let codes: Vec<String> = CodeSlot::ALL
+                    .iter()
+                    .filter_map(|&slot| {
+                        let code = self.codes.get(slot)?;
+                        Some(format!("{} {}\n", slot, code))
+                    })
+                    .collect();
+                if codes.is_empty() {
+                    return Err("No code found yet".into());
+                }
+                Ok(codes.concat())
+            }
+            // the artifacts of the session first
+            path => std::fs::read_to_string(
+                self.artifacts
+                    .find(path)
+                    .unwrap_or_else(|| PathBuf::from(path)),
+            )
+            .map_err(|e| format!("Can't read {}: {}", path, e).into()),
+        }
+    }
+
+    /// Search the new messages of the game for the alerts, and run the sink command
+    fn check_alerts(&mut self) {
+        let messages = self.vm.get_messages();
+        // restoring a snapshot can shorten the history
+        let start = self.alerts_checked.min(messages.len());
+        self.alerts_checked = messages.len();
+
+        for message in &messages[start..] {
+            for alert in &self.alerts {
+                let Some(line) = message.lines().find(|line| alert.is_match(line)) else {
+                    continue;
+                };
+                println!("ALERT {}: {}", alert, line);
+
+                if let Some(command) = &self.alert_command {
+                    let status = std::process::Command::new("sh")
+                        .arg("-c")
+                        .arg(command)
+                        .env("ALERT_PATTERN", alert.as_str())
+                        .env("ALERT_MATCH", line)
+                        .status();
+                    match status {
+                        Ok(status) if status.success() => (),
+                        Ok(status) => println!("Alert command failed: {}", status),
+                        Err(e) => println!("Can't run alert command: {}", e),
+                    }
+                }
+            }
         }
     }
 
-    fn get_snap_by_name(&self, name: &str) -> Option<&Snapshot> {
-        self.snapshots.iter().find(|snap| snap.name == name)
-    }
+    /// Append the new messages of the game to the `tee` file
+    fn check_tee(&mut self) {
+        let Some(tee) = &mut self.tee else {
+            return;
+        };
+        let messages = self.vm.get_messages();
+        // restoring a snapshot can shorten the history
+        let start = tee.written.min(messages.len());
+        tee.written = messages.len();
 
-    fn dump_snapshot(&mut self, name: &str, dump_path: &str) {
-        match self.get_snap_by_name(name) {
-            Some(snap) => {
-                let mut f = std::fs::File::create(dump_path).unwrap();
-                serde_json::to_writer(&mut f, &snap).unwrap();
+        let mut text = String::new();
+        for message in &messages[start..] {
+            text.push_str(message);
+            if !message.is_empty() && !message.ends_with('\n') {
+                text.push('\n');
             }
-            None => println!("Snap not found"),
         }
+        self.tee_write(&text);
     }
 
-    fn load_snapshot(&mut self, dump_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let f = std::fs::File::open(dump_path)?;
-        let snap: Snapshot = serde_json::from_reader(f)?;
-        let name = snap.name.clone();
+    /// Append a game input to the `tee` file, after the output it answers
+    fn tee_input(&mut self, line: &str) {
+        self.check_tee();
+        self.tee_write(&format!("> {}\n", line));
+    }
 
-        match self.snapshots.iter().find(|s| s.name == name) {
-            Some(_) => (),
-            None => self.snapshots.push(snap),
+    fn tee_write(&mut self, text: &str) {
+        let Some(tee) = &mut self.tee else {
+            return;
         };
-        self.restore_snapshot(&name);
-
-        Ok(())
+        if let Err(e) = tee.file.write_all(text.as_bytes()) {
+            println!("Can't write to {}: {}, stopping tee", tee.path, e);
+            self.tee = None;
+        }
     }
 
-    fn take_snapshot(&mut self, name: &str) {
-        self.snapshots.push(Snapshot {
-            name: name.to_string(),
-            vm: self.vm.clone(),
-        });
+    /// Keep the current VM for `undo`, before a game input
+    fn save_undo(&mut self) {
+        if self.vm.get_state() != VmState::WaitingForInput {
+            return;
+        }
+        if self.undo.len() == UNDO_DEPTH {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(self.vm.checkpoint());
     }
 
-    fn remove_snapshot(&mut self, name: &str) {
-        let mut idx = None;
-        for (i, snap) in self.snapshots.iter().enumerate() {
-            if snap.name == name {
-                idx = Some(i);
-                break;
+    /// Feed a line to the game, and show the answer
+    fn feed_game(&mut self, input_line: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.save_undo();
+        self.tee_input(input_line);
+        // the room we're leaving, to count the first one
+        self.session
+            .rooms
+            .insert(self.vm.get_memory()[CURRENT_ROOM]);
+        match self.vm.feed(input_line) {
+            Ok(_) => {
+                self.run_until(&[]);
+                self.session.game_commands += 1;
+                self.session
+                    .rooms
+                    .insert(self.vm.get_memory()[CURRENT_ROOM]);
+                self.session.room_names.extend(room_name(&self.vm));
+                println!("{}", self.vm.get_messages().last().unwrap());
+                self.print_stop(None);
+                self.capture_codes();
+                self.print_terminal_banner();
+                Ok(())
             }
-        }
-
-        match idx {
-            Some(idx) => {
-                self.snapshots.remove(idx);
+            Err(e) => {
+                println!("Invalid command, tried feeding, but didn't work either");
+                Err(e)
             }
-            None => println!("Not found"),
         }
     }
 
-    fn restore_snapshot(&mut self, name: &str) {
-        match self.get_snap_by_name(name) {
-            Some(snap) => {
-                self.vm = snap.vm.clone();
+    /// Known command closest to `word`, if it looks like a typo
+    fn suggest_command(&self, word: &str) -> Option<String> {
+        let word = word.to_lowercase();
+        if GAME_VERBS.contains(&word.as_str()) {
+            return None;
+        }
+        if let GameResponse::Room(room) = GameResponse::from_vm(&self.vm) {
+            if room.exits.contains(&word) {
+                return None;
             }
-            None => println!("Snap not found"),
         }
+
+        let max_distance = if word.chars().count() <= 4 { 1 } else { 2 };
+        self.cli
+            .get_subcommands()
+            .flat_map(|c| std::iter::once(c.get_name()).chain(c.get_all_aliases()))
+            .filter(|name| name.len() >= 3)
+            .map(|name| (edit_distance(&word, name), name))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min()
+            .map(|(_, name)| name.to_string())
     }
 
-    pub fn parse_command(&mut self, input_line: &str) -> Result<(), Box<dyn std::error::Error>> {
+    /// `>line` is always fed to the game, `:command` is never fed to the game, `! command` is
+    /// run by the shell
+    fn parse_single_command(&mut self, input_line: &str) -> Result<(), Box<dyn std::error::Error>> {
         if input_line.split_whitespace().next().is_none() {
             // empy command
             return Ok(());
         }
 
-        let argv = input_line.split_whitespace();
-        let args = match self.cli.clone().try_get_matches_from(argv.clone()) {
+        if let Some(command) = input_line.strip_prefix('!') {
+            // after a `;`
+            return self.shell(command.trim());
+        }
+        if let Some(game_input) = input_line.strip_prefix('>') {
+            return self.feed_game(game_input.trim());
+        }
+        let (forced, input_line) = match input_line.strip_prefix(':') {
+            Some(command) => (true, command.trim()),
+            None => (false, input_line),
+        };
+        if self.background.is_some() && input_line.split_whitespace().next() != Some("bg") {
+            return Err("The VM runs in the background, see `bg status` and `bg fg`".into());
+        }
+
+        let mut argv = split_args(&self.resolve_names(input_line));
+        if let Some(format) = argv.first().and_then(|word| word.strip_prefix("x/")) {
+            // gdb style
+            let format = format.to_string();
+            argv.splice(0..1, ["x".to_string(), "--format".to_string(), format]);
+        }
+        let args = match self.cli.clone().try_get_matches_from(argv) {
             Ok(args) => args,
-            Err(_) => match self.vm.feed(input_line) {
-                Ok(_) => {
-                    self.vm.run();
-                    println!("{}", self.vm.get_messages().last().unwrap());
-                    return Ok(());
+            Err(e) => {
+                let word = input_line.split_whitespace().next().unwrap_or_default();
+                let known = self.cli.find_subcommand(word).is_some();
+                if forced || known {
+                    // a command with bad arguments
+                    println!("{}", e.render());
+                    return Err(format!("Invalid command `{}`", input_line).into());
                 }
-                Err(e) => {
-                    println!("Invalid command, tried feeding, but didn't work either");
-                    return Err(e);
+                if let Some(suggestion) = self.suggest_command(word) {
+                    return Err(format!(
+                        "Unknown command `{}`, did you mean `{}`? Use `>{}` to send it to the game",
+                        word, suggestion, input_line
+                    )
+                    .into());
                 }
-            },
+                return self.feed_game(input_line);
+            }
         };
 
         match args.subcommand() {
-            Some(("run", _sub)) => {
-                self.vm.run();
+            Some(("run", sub)) => {
+                let mut conditions = Vec::new();
+                if let Some(&addr) = sub.get_one::<Addr>("until-call") {
+                    conditions.push(StopCondition::StopOnCallTo(addr));
+                }
+                if let Some(&condition) = sub.get_one::<StopCondition>("until-depth") {
+                    conditions.push(condition);
+                }
+                if let Some(&cycles) = sub.get_one::<u64>("max-cycles") {
+                    if self.vm.get_costs().is_none() {
+                        return Err("No cost model, enable it with `vm costs on`".into());
+                    }
+                    conditions.push(StopCondition::StopAtCycles(cycles));
+                }
+
+                if self.vm.get_state() == VmState::Halted {
+                    return Err("Already halted".into());
+                }
+                let stopped = self.run_until(&conditions);
+                if let VmState::WaitingForInput = self.vm.get_state() {
+                    println!("{}", self.vm.get_messages().last().unwrap());
+                }
+                self.print_stop(stopped);
+                self.capture_codes();
+                self.print_terminal_banner();
+            }
+            Some(("continue", _)) => {
+                self.vm.release_breakpoint()?;
+                self.vm.step()?;
+                if self.vm.get_state() == VmState::Running {
+                    self.run_until(&[]);
+                }
                 if let VmState::WaitingForInput = self.vm.get_state() {
                     println!("{}", self.vm.get_messages().last().unwrap());
                 }
+                self.print_stop(None);
+                self.capture_codes();
+                self.print_terminal_banner();
+            }
+            Some(("undo", sub)) => {
+                let count = *sub.get_one::<usize>("count").unwrap();
+                if count > self.undo.len() {
+                    return Err(format!("Only {} game inputs to undo", self.undo.len()).into());
+                }
+                self.undo.truncate(self.undo.len() + 1 - count);
+                let checkpoint = self.undo.pop_back().unwrap();
+                self.vm.restore(&checkpoint)?;
+                println!("{}", self.vm.get_messages().last().unwrap());
             }
+            Some(("bg", sub)) => self.background_command(sub)?,
             Some(("input", sub)) => {
-                self.vm
-                    .feed(sub.get_one::<String>("line").unwrap_or(&"".to_string()))?;
+                let line = sub
+                    .get_one::<String>("line")
+                    .map_or("", |line| line.as_str());
+                self.save_undo();
+                self.tee_input(line);
+                self.vm.feed(line)?;
                 println!("{}", self.vm.get_messages().last().unwrap());
             }
-            Some(("patch", sub)) => {
-                let opcode = sub.get_one::<String>("opcode").unwrap();
-                let opcode: Opcode = opcode.parse()?;
-                let offset = *sub.get_one::<usize>("offset").unwrap();
+            Some(("bench", sub)) => match sub.subcommand() {
+                Some(("patch", sub)) => {
+                    let name = sub.get_one::<String>("name").unwrap();
+                    let max_steps = *sub.get_one::<usize>("max-steps").unwrap();
+                    let input = match sub.get_one::<String>("input") {
+                        Some(input) => Some(input.as_str()),
+                        None if name == "6027" => Some("use teleporter"),
+                        None => None,
+                    };
+
+                    let mut variants = vec![("unpatched", false, NativeImpl::Memoized)];
+                    variants.push(("hook", true, NativeImpl::Memoized));
+                    if name == "6027" {
+                        variants.push(("table", true, NativeImpl::Table));
+                    }
+
+                    println!(
+                        "{:<10} {:>12} {:>10}  result",
+                        "variant", "instructions", "time"
+                    );
+                    for (variant, patching, native_impl) in variants {
+                        let mut vm = self.vm.clone();
+                        vm.set_patching(patching);
+                        vm.set_native_impl(native_impl);
+                        if let Some(input) = input {
+                            vm.feed(input)?;
+                        }
+
+                        let start = (Instant::now(), vm.get_pc());
+                        let mut steps = 0;
+                        while vm.get_state() == VmState::Running && steps < max_steps {
+                            vm.step()?;
+                            steps += 1;
+                        }
+                        let result = match vm.get_state() {
+                            VmState::Running => "step limit".to_string(),
+                            state => format!("{:?}", state),
+                        };
+
+                        println!(
+                            "{:<10} {:>12} {:>9.3}s  {}",
+                            variant,
+                            vm.get_pc() - start.1,
+                            start.0.elapsed().as_secs_f64(),
+                            result
+                        );
+                    }
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("runfile", sub)) => {
+                let program = sub.get_one::<String>("program").unwrap();
+                let max_steps = *sub.get_one::<usize>("max-steps").unwrap();
+                if sub.get_flag("raw") {
+                    let input = match sub.get_one::<PathBuf>("input") {
+                        Some(path) => std::fs::read(path)?,
+                        None => Vec::new(),
+                    };
+                    let (output, reason) = run_file_raw(program, max_steps, &input)?;
+                    std::io::Write::write_all(&mut std::io::stdout(), &output)?;
+                    println!("\n[{}]", reason);
+                    return Ok(());
+                }
+                let inputs: Vec<String> = match sub.get_one::<PathBuf>("input") {
+                    Some(path) => std::fs::read_to_string(path)?
+                        .lines()
+                        .map(|l| l.to_string())
+                        .collect(),
+                    None => Vec::new(),
+                };
+
+                let (messages, reason) = run_file(program, max_steps, &inputs)?;
+                for (idx, message) in messages.iter().enumerate() {
+                    print!("{}", message);
+                    if let Some(input) = inputs.get(idx).filter(|_| idx + 1 < messages.len()) {
+                        println!("{}", input);
+                    }
+                }
+                println!("\n[{}]", reason);
+                if let Some(path) = sub.get_one::<PathBuf>("input") {
+                    if self.watchdog && reason == "waiting for input" {
+                        return Err(format!(
+                            "{} ended, but the program waits for input",
+                            path.display()
+                        )
+                        .into());
+                    }
+                }
+            }
+            Some(("source", sub)) => {
+                let path = sub.get_one::<String>("path").unwrap();
+                for (n, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    println!(">> {}", line);
+                    self.parse_command(line)
+                        .map_err(|e| format!("{}:{}: {}", path, n + 1, e))?;
+                    if self.quit {
+                        break;
+                    }
+                }
+                self.starvation().map_err(|e| format!("{}: {}", path, e))?;
+            }
+            Some(("tee", sub)) => {
+                if sub.get_flag("stop") {
+                    match self.tee.take() {
+                        Some(tee) => println!("Stopped writing to {}", tee.path),
+                        None => return Err("Not writing a transcript".into()),
+                    }
+                    return Ok(());
+                }
+                let Some(path) = sub.get_one::<String>("path") else {
+                    match &self.tee {
+                        Some(tee) => println!("Writing to {}", tee.path),
+                        None => println!("Not writing a transcript"),
+                    }
+                    return Ok(());
+                };
+
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("Can't open {}: {}", path, e))?;
+                // the output before the current prompt is already answered, or lost
+                self.tee = Some(Tee {
+                    path: path.clone(),
+                    file,
+                    written: self.vm.get_messages().len(),
+                });
+                println!("Appending the game to {}", path);
+            }
+            Some(("verify", sub)) => match sub.subcommand() {
+                Some(("transcript", sub)) => {
+                    let path = sub.get_one::<String>("path").unwrap();
+                    let expected = diff::Transcript::parse(&std::fs::read_to_string(path)?);
+                    let actual = expected.replay(&self.vm)?;
+
+                    if sub.get_flag("update") {
+                        std::fs::write(path, actual.to_string())?;
+                        println!("Wrote {} inputs to {:?}", actual.inputs(), path);
+                        return Ok(());
+                    }
+
+                    match expected.first_divergence(&actual) {
+                        None => println!("{} inputs, the output matches", expected.inputs()),
+                        Some(divergence) => {
+                            let line = |line: &Option<String>| match line {
+                                Some(line) => format!("{:?}", line),
+                                None => "end of the output".to_string(),
+                            };
+                            match &divergence.after {
+                                Some(input) => println!("After `{}`:", input),
+                                None => println!("Before the first input:"),
+                            }
+                            println!("- {}", line(&divergence.expected));
+                            println!("+ {}", line(&divergence.actual));
+                            return Err(format!("{}:{}: diverges", path, divergence.line).into());
+                        }
+                    }
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("assert", sub)) => {
+                let args: Vec<String> = sub
+                    .get_many::<String>("assertion")
+                    .unwrap()
+                    .cloned()
+                    .collect();
+                Assertion::parse(&args)?.check(&self.vm)?;
+            }
+            Some(("on", sub)) => {
+                let args: Vec<String> = sub.get_many::<String>("rule").unwrap().cloned().collect();
+                match &args[..] {
+                    [list] if list == "list" => {
+                        for (idx, rule) in self.rules.iter().enumerate() {
+                            println!("{}: on {} do {:?}", idx, rule.condition, rule.command);
+                        }
+                    }
+                    [remove, idx] if remove == "remove" => {
+                        let idx: usize = idx.parse()?;
+                        if idx >= self.rules.len() {
+                            return Err(format!("No rule {}", idx).into());
+                        }
+                        self.rules.remove(idx);
+                    }
+                    _ => {
+                        let split = args
+                            .iter()
+                            .position(|a| a == "do")
+                            .ok_or("Expected `on <condition> do <command>`")?;
+                        let condition = Assertion::parse(&args[..split])?;
+                        let command = args[split + 1..].join(" ");
+                        if command.is_empty() {
+                            return Err("Missing command after `do`".into());
+                        }
+                        // only a change triggers the rule
+                        let was_true = condition.check(&self.vm).is_ok();
+                        self.rules.push(Rule {
+                            condition,
+                            command,
+                            was_true,
+                        });
+                    }
+                }
+            }
+            Some(("play", sub)) => {
+                let path = sub.get_one::<String>("path").unwrap();
+                for line in std::fs::read_to_string(path)?.lines() {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+
+                    println!(">> {}", line);
+                    self.feed_game(line)?;
+                }
+                self.starvation().map_err(|e| format!("{}: {}", path, e))?;
+            }
+            Some(("patch", sub)) => match sub.subcommand() {
+                Some(("apply", sub)) => {
+                    let path = sub.get_one::<String>("path").unwrap();
+                    let patches = self.read_patches(path)?;
 
-                self.vm.patch(opcode, offset);
+                    let mut resized = 0;
+                    for (offset, words, code) in &patches {
+                        let (old, note) = match self.vm.disassemble(*offset, 1) {
+                            Ok(old) if old[0].1.size() != words.len() && !code.starts_with('.') => {
+                                resized += 1;
+                                let note =
+                                    format!(", {} -> {} words", old[0].1.size(), words.len());
+                                (format!("{:?}", old[0].1), note)
+                            }
+                            Ok(old) => (format!("{:?}", old[0].1), String::new()),
+                            Err(_) => {
+                                let offset = usize::from(*offset);
+                                let old = &self.vm.get_memory()[offset..offset + words.len()];
+                                (format!("{:?}", old), String::new())
+                            }
+                        };
+                        let offset = usize::from(*offset);
+                        println!("{}: {} -> {}{}", self.describe(offset), old, code, note);
+                    }
+                    if sub.get_flag("dry-run")
+                        || (resized > 0
+                            && !self.confirm(&format!(
+                                "{} patches change the instruction size, apply anyway?",
+                                resized
+                            )))
+                    {
+                        return Ok(());
+                    }
+
+                    for (offset, words, _) in &patches {
+                        self.vm.patch_words(words, usize::from(*offset));
+                    }
+                    println!("Applied {} patches", patches.len());
+                }
+                Some(("list", _)) => {
+                    let patches = self.vm.get_patches();
+                    for (idx, patch) in patches.iter().enumerate() {
+                        println!(
+                            "{} {}: {} -> {}",
+                            idx,
+                            self.describe(patch.offset),
+                            format_words(&patch.old),
+                            format_words(&patch.new)
+                        );
+                    }
+                    println!("{} patches", patches.len());
+                }
+                Some(("undo", sub)) => {
+                    let idx = *sub.get_one::<usize>("idx").unwrap();
+                    let patch = self.vm.undo_patch(idx)?;
+                    println!(
+                        "Restored {} at {}",
+                        format_words(&patch.old),
+                        self.describe(patch.offset)
+                    );
+                }
+                _ => {
+                    let opcode = sub
+                        .get_one::<String>("opcode")
+                        .ok_or("Expected an opcode and an offset, or a subcommand")?;
+                    let opcode: Opcode = opcode.parse()?;
+                    let offset = *sub.get_one::<Addr>("offset").unwrap();
+
+                    let old = self.vm.disassemble(offset, 1)?;
+                    let (old_size, new_size) = (old[0].1.size(), opcode.size());
+                    if sub.get_flag("dry-run") {
+                        println!(
+                            "Would replace {:?} ({} words) with {:?} ({} words) at {}",
+                            old[0].1,
+                            old_size,
+                            opcode,
+                            new_size,
+                            self.describe(offset.into())
+                        );
+                        return Ok(());
+                    }
+                    if old_size != new_size
+                        && !self.confirm(&format!(
+                            "{:?} is {} words, {:?} is {} words, patch anyway?",
+                            old[0].1, old_size, opcode, new_size
+                        ))
+                    {
+                        return Ok(());
+                    }
+
+                    self.vm.patch(opcode, offset);
+                }
+            },
+            Some(("mem", sub)) => match sub.subcommand() {
+                Some(("init", _sub)) => {
+                    self.vm.scanmem_init();
+                }
+                Some(("list", _sub)) => {
+                    let values = self.vm.scanmem_list();
+                    for (addr, old, new) in &values {
+                        println!("{}: {} -> {}", addr, old, new);
+                    }
+                    println!("Listed {} values", values.len());
+                }
+                Some(("dumpbin", sub)) => {
+                    let path = sub.get_one::<String>("path").unwrap();
+                    self.vm.dump_binary(path)?;
+                }
+                Some(("dirty", sub)) => {
+                    let pages = match self.vm.dirty_pages() {
+                        Some(pages) => pages,
+                        None if sub.get_flag("clean") => Vec::new(),
+                        None => return Err("Writes not tracked, use --clean".into()),
+                    };
+                    for &(page, writes) in &pages {
+                        let start = page * PAGE_SIZE;
+                        println!(
+                            "page {:>3}: {:>5}..{:>5} {} writes",
+                            page,
+                            start,
+                            start + PAGE_SIZE,
+                            writes
+                        );
+                    }
+                    println!("{} dirty pages", pages.len());
+                    if sub.get_flag("clean") {
+                        self.vm.clean_pages();
+                    }
+                }
+                Some(("edit", sub)) => {
+                    let offset = self.check_offset(*sub.get_one::<usize>("offset").unwrap())?;
+                    println!("{}", MEM_EDIT_KEYS);
+                    println!("{}", self.memory_word(offset));
+                    self.view = Some(View::MemEdit { offset, written: 0 });
+                }
+                Some(("get", sub)) => {
+                    let offset = self.check_offset(*sub.get_one::<usize>("offset").unwrap())?;
+                    println!("{}: {}", offset, self.vm.mem_get(offset));
+                }
+                Some(("dump", sub)) => {
+                    let offset = self.check_offset(*sub.get_one::<usize>("offset").unwrap())?;
+                    let count = *sub.get_one::<usize>("count").unwrap();
+                    let width = *sub.get_one::<usize>("width").unwrap();
+                    let memory = self.vm.get_memory();
+                    let end = (offset + count).min(memory.len());
+                    for row in dump_words(&memory[offset..end], offset, width) {
+                        println!("{}", row);
+                    }
+                }
+                Some(("set", sub)) => {
+                    let offset = self.check_offset(*sub.get_one::<usize>("offset").unwrap())?;
+                    let value = *sub.get_one::<u16>("value").unwrap();
+                    self.vm.mem_set(offset, value);
+                }
+                Some(("protect", sub)) => {
+                    let start = sub.get_one::<usize>("start").copied();
+                    let start = start.map(|start| self.check_offset(start)).transpose()?;
+                    let end = sub.get_one::<usize>("end").copied();
+                    let end = end.map(|end| self.check_end(end)).transpose()?;
+                    match (start, end) {
+                        (Some(start), Some(end)) if start < end => self.vm.protect(start..end),
+                        (Some(start), None) => self.vm.protect(start..start + 1),
+                        (Some(start), Some(end)) => {
+                            return Err(format!("Empty range {}..{}", start, end).into())
+                        }
+                        (None, _) => {
+                            for (idx, range) in self.vm.get_protected().iter().enumerate() {
+                                println!("{}: {}..{}", idx, range.start, range.end);
+                            }
+                        }
+                    }
+                }
+                Some(("unprotect", sub)) => {
+                    let idx = *sub.get_one::<usize>("idx").unwrap();
+                    let range = self.vm.unprotect(idx)?;
+                    println!("Unprotected {}..{}", range.start, range.end);
+                }
+                Some(("fill", sub)) => {
+                    let from = *sub.get_one::<usize>("from").unwrap();
+                    let to = *sub.get_one::<usize>("to").unwrap();
+                    let value = *sub.get_one::<u16>("value").unwrap();
+                    if from > to || to > self.vm.get_memory().len() {
+                        return Err(format!("Invalid range {}..{}", from, to).into());
+                    }
+
+                    if sub.get_flag("dry-run") {
+                        println!(
+                            "Would write {} in {} words at {}..{}",
+                            value,
+                            to - from,
+                            from,
+                            to
+                        );
+                    } else if self.confirm(&format!("Overwrite {} words?", to - from)) {
+                        for offset in from..to {
+                            self.vm.mem_set(offset, value);
+                        }
+                    }
+                }
+                Some(("filter", sub)) => {
+                    if let Some((filter, sub)) = sub.subcommand() {
+                        let value = sub.get_one::<u16>("value").copied();
+                        let count = self.vm.scanmem_filter(filter, value)?;
+                        println!("Selected {} values", count);
+                    }
+                }
+                Some(_) => println!("Unknown command"),
+                None => (),
+            },
+            Some(("bp", sub)) => match sub.subcommand() {
+                Some(("list", _sub)) => {
+                    for bp in self.vm.get_breakpoints() {
+                        match self.vm.disassemble(bp.address, 1) {
+                            Ok(x) => println!("{}", Vm::pretty_dis(&x, self.vm.get_symbols())),
+                            Err(e) => println!("{}: {}", bp.address, e),
+                        }
+                        if let Some(condition) = &bp.condition {
+                            println!("    if {}", condition);
+                        }
+                        if !bp.enabled {
+                            println!("    disabled");
+                        }
+                        if !bp.description.is_empty() {
+                            println!("    # {}", bp.description);
+                        }
+                    }
+                    self.warn_stale(
+                        self.vm
+                            .get_breakpoints()
+                            .iter()
+                            .map(|bp| usize::from(bp.address)),
+                    );
+                }
+                Some(("set", sub)) => {
+                    let offset = *sub.get_one::<Addr>("offset").unwrap();
+                    self.vm.add_breakpoint(Breakpoint {
+                        condition: sub.get_one::<Expr>("if").cloned(),
+                        description: sub.get_one::<String>("desc").cloned().unwrap_or_default(),
+                        ..Breakpoint::new(offset)
+                    });
+                    self.warn_stale([offset.into()]);
+                }
+                Some(("enable", sub)) => {
+                    let offset = *sub.get_one::<Addr>("offset").unwrap();
+                    self.vm.enable_breakpoint(offset, true)?;
+                }
+                Some(("disable", sub)) => {
+                    let offset = *sub.get_one::<Addr>("offset").unwrap();
+                    self.vm.enable_breakpoint(offset, false)?;
+                }
+                Some(("export", sub)) => {
+                    let path = sub.get_one::<PathBuf>("path").unwrap();
+                    let f = std::fs::File::create(path)?;
+                    serde_json::to_writer_pretty(f, self.vm.get_breakpoints())?;
+                    println!("{} breakpoints exported", self.vm.get_breakpoints().len());
+                }
+                Some(("import", sub)) => {
+                    let path = sub.get_one::<PathBuf>("path").unwrap();
+                    let f = std::fs::File::open(path)?;
+                    let breakpoints: Vec<Breakpoint> = serde_json::from_reader(f)?;
+                    let addresses: Vec<usize> =
+                        breakpoints.iter().map(|bp| bp.address.into()).collect();
+                    println!("{} breakpoints imported", breakpoints.len());
+                    for bp in breakpoints {
+                        self.vm.add_breakpoint(bp);
+                    }
+                    self.warn_stale(addresses);
+                }
+                Some(("unset", sub)) => {
+                    let offset = *sub.get_one::<Addr>("offset").unwrap();
+                    self.vm.unset_breakpoint(offset);
+                }
+                Some(_) => (),
+
+                None => (),
+            },
+            Some(("report", sub)) => match sub.subcommand() {
+                Some(("html", sub)) => {
+                    let dir = match sub.get_one::<PathBuf>("dir") {
+                        Some(dir) => dir.clone(),
+                        None => self.artifacts.path("report")?,
+                    };
+                    let mut analysis = Analysis::new(&self.vm);
+                    analysis.gradient = gradient_arg(sub)?;
+                    analysis.write_html(&dir)?;
+                    println!(
+                        "{} functions, {} strings in {}",
+                        analysis.functions.len(),
+                        analysis.strings.len(),
+                        dir.display()
+                    );
+                }
+                Some(("r2", sub)) => {
+                    let path = sub.get_one::<PathBuf>("path").unwrap();
+                    std::fs::write(path, Analysis::new(&self.vm).to_radare2())?;
+                }
+                Some(("ghidra", sub)) => {
+                    let path = sub.get_one::<PathBuf>("path").unwrap();
+                    std::fs::write(path, Analysis::new(&self.vm).to_ghidra())?;
+                }
+                Some(("callgraph", sub)) => {
+                    let path = sub.get_one::<PathBuf>("path").unwrap();
+                    let graph = StaticCallGraph::new(&self.vm);
+                    if sub.get_flag("json") {
+                        std::fs::write(path, serde_json::to_string_pretty(&graph)?)?;
+                    } else {
+                        std::fs::write(path, graph.to_dot())?;
+                    }
+                    let indirect = graph.calls.iter().filter(|c| c.indirect).count();
+                    println!(
+                        "{} functions, {} calls ({} indirect) in {}",
+                        graph.functions.len(),
+                        graph.calls.len(),
+                        indirect,
+                        path.display()
+                    );
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("analysis", sub)) => {
+                self.open_analysis(sub.get_one::<PathBuf>("db"))?;
+                let (path, db) = self.analysis.as_mut().unwrap();
+
+                // modified functions are analyzed again when they're needed
+                if sub.subcommand_name() != Some("update") && db.functions.values().any(|f| f.stale)
+                {
+                    let analyzed = db.update(&self.vm);
+                    db.save(&path)?;
+                    self.vm.set_tracked_code(db.code_addresses());
+                    println!("{} modified functions analyzed again", analyzed);
+                }
+
+                match sub.subcommand() {
+                    Some(("update", _)) => {
+                        let analyzed = db.update(&self.vm);
+                        db.save(&path)?;
+                        self.vm.set_tracked_code(db.code_addresses());
+                        println!(
+                            "{} functions, {} analyzed, saved to {}",
+                            db.functions.len(),
+                            analyzed,
+                            path.display()
+                        );
+                    }
+                    Some(("list", _)) => {
+                        for function in db.functions.values() {
+                            println!(
+                                "{:>5} {:<10} args: {:<12} pure: {}",
+                                function.start,
+                                function.label,
+                                function.args.iter().map(|r| format!("r{}", r)).join(" "),
+                                function.pure
+                            );
+                        }
+                    }
+                    Some(("show", sub)) => {
+                        let addr = usize::from(*sub.get_one::<Addr>("addr").unwrap());
+                        match db.functions.get(&addr) {
+                            Some(function) => function.print(),
+                            None => return Err(format!("No function at {}", addr).into()),
+                        }
+                    }
+                    Some((_, _)) => return Err("unreachable?".into()),
+                    None => (),
+                }
+            }
+            Some(("bookmark", sub)) => {
+                self.open_analysis(None)?;
+                let symbols = self.vm.get_symbols_mut();
+                let (path, db) = self.analysis.as_mut().unwrap();
+                match sub.subcommand() {
+                    Some(("add", sub)) => {
+                        let name = sub.get_one::<String>("name").unwrap();
+                        let addr = *sub.get_one::<Addr>("addr").unwrap();
+                        symbols.set(addr, name)?;
+                        // a label names one address
+                        db.bookmarks.retain(|_, &mut a| a != usize::from(addr));
+                        db.bookmarks.insert(name.clone(), addr.into());
+                        db.save(&path)?;
+                    }
+                    Some(("remove", sub)) => {
+                        let name = sub.get_one::<String>("name").unwrap();
+                        let addr = db
+                            .bookmarks
+                            .remove(name)
+                            .ok_or_else(|| format!("No bookmark {}", name))?;
+                        if symbols.label(addr) == Some(name.as_str()) {
+                            symbols.remove(Addr::try_from(addr)?);
+                        }
+                        db.save(&path)?;
+                    }
+                    Some(("list", _)) => {
+                        for (name, &addr) in &db.bookmarks {
+                            let instruction = match self.vm.disassemble(Addr::try_from(addr)?, 1) {
+                                Ok(x) => format!("{:?}", x[0].1),
+                                Err(e) => e.to_string(),
+                            };
+                            print!("@{}: {}: {}", name, addr, instruction);
+                            for function in db.functions_at(addr) {
+                                print!(" in {}", function.label);
+                            }
+                            println!();
+                        }
+                    }
+                    Some((_, _)) => return Err("unreachable?".into()),
+                    None => (),
+                }
+            }
+            Some(("label", sub)) => match sub.subcommand() {
+                Some(("set", sub)) => {
+                    let addr = *sub.get_one::<Addr>("addr").unwrap();
+                    let name = sub.get_one::<String>("name").unwrap();
+                    self.vm.get_symbols_mut().set(addr, name)?;
+                }
+                Some(("remove", sub)) => {
+                    let addr = *sub.get_one::<Addr>("addr").unwrap();
+                    if self.vm.get_symbols_mut().remove(addr).is_none() {
+                        return Err(format!("No label at {}", addr).into());
+                    }
+                }
+                Some(("list", _)) => {
+                    for (addr, label) in self.vm.get_symbols().iter() {
+                        println!("{:>5}: {}", addr, label);
+                    }
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("note", sub)) => match sub.subcommand() {
+                Some(("add", sub)) => {
+                    let addr = *sub.get_one::<Addr>("addr").unwrap();
+                    let text = sub.get_one::<String>("text").unwrap();
+                    self.vm.get_symbols_mut().set_note(addr, text)?;
+                }
+                Some(("remove", sub)) => {
+                    let addr = *sub.get_one::<Addr>("addr").unwrap();
+                    if self.vm.get_symbols_mut().remove_note(addr).is_none() {
+                        return Err(format!("No note at {}", addr).into());
+                    }
+                }
+                Some(("list", _)) => {
+                    for (addr, note) in self.vm.get_symbols().notes() {
+                        println!("{:>5}: {}", self.describe(addr.into()), note);
+                    }
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("print", sub)) => {
+                let expr: Expr = sub.get_many::<String>("expr").unwrap().join(" ").parse()?;
+                println!("{} = {}", expr, expr.eval(&self.vm)?);
             }
-            Some(("mem", sub)) => match sub.subcommand() {
-                Some(("init", _sub)) => {
-                    self.vm.scanmem_init();
+            Some(("watch", sub)) => match sub.subcommand() {
+                Some(("add", sub)) => {
+                    let expr: Expr = sub.get_many::<String>("expr").unwrap().join(" ").parse()?;
+                    self.vm.add_watch(expr);
+                    let watch = self.vm.get_watches().last().unwrap();
+                    println!(
+                        "Watch {}: {} = {}",
+                        self.vm.get_watches().len() - 1,
+                        watch.expr,
+                        format_value(watch.value)
+                    );
                 }
-                Some(("list", _sub)) => {
-                    self.vm.scanmem_list();
+                Some(("remove", sub)) => {
+                    let watch = self
+                        .vm
+                        .remove_watch(*sub.get_one::<usize>("idx").unwrap())?;
+                    println!("Removed {}", watch.expr);
                 }
-                Some(("get", sub)) => {
-                    let offset = *sub.get_one::<usize>("offset").unwrap();
-                    self.vm.mem_get(offset);
+                Some(("list", _)) => {
+                    for (idx, watch) in self.vm.get_watches().iter().enumerate() {
+                        println!("{}: {} = {}", idx, watch.expr, format_value(watch.value));
+                    }
                 }
-                Some(("set", sub)) => {
-                    let offset = *sub.get_one::<usize>("offset").unwrap();
-                    let value = *sub.get_one::<u16>("value").unwrap();
-                    self.vm.mem_set(offset, value);
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("alert", sub)) => match sub.subcommand() {
+                Some(("add", sub)) => {
+                    let pattern = sub.get_one::<String>("pattern").unwrap();
+                    self.alerts.push(Regex::new(pattern)?);
                 }
-                Some(("filter", sub)) => match sub.subcommand() {
-                    Some((filter, _sub)) => {
-                        let value = sub.get_one::<u16>("value").copied();
-                        self.vm.scanmem_filter(&filter, value);
+                Some(("remove", sub)) => {
+                    let idx = *sub.get_one::<usize>("idx").unwrap();
+                    if idx >= self.alerts.len() {
+                        return Err(format!("No alert {}", idx).into());
                     }
-                    None => (),
-                },
-                Some(_) => println!("Unknown command"),
+                    self.alerts.remove(idx);
+                }
+                Some(("list", _)) => {
+                    for (idx, alert) in self.alerts.iter().enumerate() {
+                        println!("{}: {}", idx, alert);
+                    }
+                    match &self.alert_command {
+                        Some(command) => println!("Sink: {}", command),
+                        None => println!("Sink: none"),
+                    }
+                }
+                Some(("sink", sub)) => {
+                    let command = sub.get_one::<String>("command").unwrap();
+                    self.alert_command = match command.as_str() {
+                        "off" => None,
+                        _ => Some(command.clone()),
+                    };
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
                 None => (),
             },
-            Some(("bp", sub)) => match sub.subcommand() {
-                Some(("list", _sub)) => {
-                    for &bp in self.vm.get_breakpoints() {
-                        match self.vm.disassemble(bp, 1) {
-                            Ok(x) => Vm::pretty_print_dis(&x),
-                            Err(e) => println!("{}: {}", bp, e),
-                        }
+            Some(("var", sub)) => match sub.subcommand() {
+                Some(("watch", sub)) => {
+                    let from = usize::from(*sub.get_one::<Addr>("addr").unwrap());
+                    let to = sub.get_one::<Addr>("to").copied().map_or(from, usize::from);
+                    for addr in from..=to {
+                        self.vm.set_watch(addr);
                     }
                 }
-                Some(("set", sub)) => {
-                    let offset = *sub.get_one::<usize>("offset").unwrap();
-                    self.vm.set_breakpoint(offset);
+                Some(("unwatch", sub)) => {
+                    self.vm
+                        .unset_watch(usize::from(*sub.get_one::<Addr>("addr").unwrap()));
                 }
-                Some(("unset", sub)) => {
-                    let offset = *sub.get_one::<usize>("offset").unwrap();
-                    self.vm.unset_breakpoint(offset);
+                Some(("reads", _)) => {
+                    let mut reads: Vec<_> = self.vm.get_entropy().reads.iter().collect();
+                    // cells read with different values are the candidates
+                    reads.sort_by_key(|(_, reads)| reads.iter().map(|r| r.1).unique().count() == 1);
+                    for (addr, reads) in reads {
+                        let values: Vec<u16> = reads.iter().map(|r| r.1).unique().collect();
+                        let readers: Vec<usize> = reads.iter().map(|r| r.0).unique().collect();
+                        println!(
+                            "{:>5}: {} reads, values {:?}, read at {:?}",
+                            addr,
+                            reads.len(),
+                            values,
+                            readers
+                        );
+                    }
                 }
-                Some(_) => (),
-
+                Some(("pin", sub)) => {
+                    let addr = usize::from(*sub.get_one::<Addr>("addr").unwrap());
+                    let value = *sub.get_one::<u16>("value").unwrap();
+                    self.vm.set_pin(addr, value);
+                }
+                Some(("unpin", sub)) => {
+                    self.vm
+                        .unset_pin(usize::from(*sub.get_one::<Addr>("addr").unwrap()));
+                }
+                Some(("list", _)) => {
+                    let entropy = self.vm.get_entropy();
+                    println!("Watched: {:?}", entropy.watched);
+                    for (addr, value) in &entropy.pins {
+                        println!("Pinned {} = {}", addr, value);
+                    }
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
                 None => (),
             },
+            Some(("x", sub)) => {
+                let format = *sub.get_one::<ExamineFormat>("format").unwrap();
+                let addr = usize::from(*sub.get_one::<Addr>("addr").unwrap());
+                self.examine(addr, format)?;
+            }
             Some(("dis", sub)) => match sub.subcommand() {
                 Some(("at", sub)) => {
-                    let from = *sub.get_one::<usize>("from").unwrap();
+                    let from = usize::from(*sub.get_one::<Addr>("from").unwrap());
                     let count = *sub.get_one::<usize>("count").unwrap();
 
-                    let instructions = self.vm.disassemble(from, count)?;
+                    let instructions = Opcode::disassemble(
+                        self.vm.get_memory(),
+                        from,
+                        count,
+                        InvalidPolicy::Skip,
+                    )?;
                     for (ip, instr) in instructions.iter() {
-                        println!("{}: {:?}", ip, instr);
+                        println!("{}", self.vm.get_symbols().format_instruction(*ip, instr));
                     }
+                    self.warn_stale(instructions.iter().map(|(ip, _)| *ip));
                 }
                 Some(("fn", sub)) => {
-                    let from = *sub.get_one::<usize>("from").unwrap();
+                    let from = usize::from(*sub.get_one::<Addr>("from").unwrap());
                     let instructions = self.vm.disassemble_function(from)?;
 
-                    Vm::pretty_print_dis(&instructions);
+                    println!("{}", self.vm.register_usage(from));
+                    println!("{}", Vm::pretty_dis(&instructions, self.vm.get_symbols()));
+                    self.warn_stale([from]);
+                }
+                Some(("graph", sub)) => {
+                    let ip = usize::from(self.vm.get_ip());
+                    let from = match sub.get_one::<Addr>("from") {
+                        Some(&from) => from.into(),
+                        None => self
+                            .vm
+                            .get_call_stack()
+                            .last()
+                            .map_or(ip, |f| f.target.into()),
+                    };
+                    if sub.get_flag("interactive") {
+                        println!("{}", GRAPH_KEYS);
+                        let block = self.block_of(from, ip).unwrap_or(from);
+                        self.view = Some(View::Graph { from, block });
+                        self.print_view();
+                        return Ok(());
+                    }
+                    let instructions = report::walk_function(self.vm.get_memory(), from);
+
+                    print!(
+                        "{}",
+                        report::text_graph(&instructions, Some(ip), self.vm.get_symbols())
+                    );
+                    self.warn_stale([from]);
+                }
+                Some(("callgraph", sub)) => {
+                    let trace = self.vm.get_trace_buffer();
+                    if trace.is_empty() {
+                        return Err("Nothing traced, see `trace start`".into());
+                    }
+                    let options = CallGraphOptions {
+                        min_calls: *sub.get_one::<usize>("min-calls").unwrap(),
+                        merge: sub.get_flag("merge"),
+                        scale: sub.get_flag("scale"),
+                        color: sub.get_flag("color"),
+                        gradient: gradient_arg(sub)?,
+                    };
+                    let out = match sub.get_one::<PathBuf>("out") {
+                        Some(out) => out.clone(),
+                        None => self.artifacts.path("callgraph.dot")?,
+                    };
+                    std::fs::write(
+                        &out,
+                        report::call_graph(
+                            trace,
+                            self.vm.get_pc(),
+                            self.vm.get_symbols(),
+                            &options,
+                        ),
+                    )?;
+                    println!("{:?}", out);
+                }
+                Some(("file", sub)) => {
+                    let path = sub.get_one::<String>("path").unwrap();
+                    let offset = *sub.get_one::<usize>("offset").unwrap();
+                    let count = *sub.get_one::<usize>("count").unwrap();
+
+                    let words = Vm::read_binary(path)?;
+                    let instructions =
+                        Opcode::disassemble(&words, offset, count, InvalidPolicy::Skip)?;
+                    // the labels are the ones of the loaded program
+                    println!("{}", Vm::pretty_dis(&instructions, &SymbolTable::default()));
                 }
                 Some(_) => (),
 
                 None => (),
             },
+            Some(("asm", sub)) => match sub.subcommand() {
+                Some(("file", sub)) => {
+                    let source = sub.get_one::<String>("source").unwrap();
+                    let output = sub.get_one::<String>("output").unwrap();
+                    let words = Opcode::assemble(&std::fs::read_to_string(source)?)
+                        .map_err(|e| format!("{}: {}", source, e))?;
+                    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+                    std::fs::write(output, bytes)?;
+                    println!("Wrote {} words to {}", words.len(), output);
+                }
+                Some(("encode", sub)) => {
+                    let instr = sub
+                        .get_many::<String>("instr")
+                        .unwrap()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let opcode: Opcode = instr.parse()?;
+                    let words = opcode.machine_code();
+                    println!(
+                        "{:?}: {}",
+                        opcode,
+                        words
+                            .iter()
+                            .map(|w| w.to_string())
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    );
+                }
+                Some(("decode", sub)) => {
+                    let words: Vec<u16> = sub.get_many::<u16>("words").unwrap().copied().collect();
+                    let opcode = Opcode::decode(&words)?;
+                    println!("{:?}", opcode);
+                    if words.len() > opcode.size() {
+                        println!("{} unused words", words.len() - opcode.size());
+                    }
+                }
+                Some(("help", sub)) => match sub.get_one::<String>("opcode") {
+                    Some(name) => println!(
+                        "{}",
+                        OpcodeInfo::find(name).ok_or_else(|| format!("Unknown opcode {}", name))?
+                    ),
+                    None => OPCODES.iter().for_each(|info| println!("{}", info)),
+                },
+                Some(_) => (),
+                None => (),
+            },
             Some(("vm", sub)) => match sub.subcommand() {
+                Some(("io", _)) => {
+                    println!("state: {:?}", self.vm.get_state());
+                    let input: String = self.vm.get_input_buffer().iter().collect();
+                    println!("input buffer: {} {:?}", input.len(), input);
+                    let output: String = self.vm.get_output_buffer().iter().collect();
+                    println!("output buffer: {} {:?}", output.len(), output);
+                    if let Some(raw) = self.vm.get_raw_io() {
+                        println!(
+                            "raw input: {} bytes{}, raw output: {} bytes",
+                            raw.input.len(),
+                            if raw.eof { ", then EOF" } else { "" },
+                            raw.output.len()
+                        );
+                    }
+                    if let Ok(Opcode::In(_)) =
+                        Opcode::decode(&self.vm.get_memory()[usize::from(self.vm.get_ip())..])
+                    {
+                        println!("next instruction reads input");
+                    }
+                    let problems = self.vm.io_problems();
+                    if problems.is_empty() {
+                        println!("consistent");
+                    }
+                    for problem in problems {
+                        println!("WARNING: {}", problem);
+                    }
+                }
                 Some(("patch", sub)) => {
                     let patching = *sub.get_one::<bool>("patch").unwrap();
                     self.vm.set_patching(patching);
                 }
+                Some(("banks", sub)) => {
+                    if let Some(&banks) = sub.get_one::<usize>("banks") {
+                        self.vm.set_banks(banks);
+                    }
+                    println!(
+                        "{} banks of {} words, selected bank {}",
+                        self.vm.banks(),
+                        MEM_SIZE,
+                        self.vm.get_bank()
+                    );
+                }
+                Some(("journal", sub)) => {
+                    match sub.get_one::<bool>("journal") {
+                        Some(true) => {
+                            let size = *sub.get_one::<usize>("size").unwrap();
+                            self.vm.set_journal(Some(size));
+                        }
+                        Some(false) => self.vm.set_journal(None),
+                        None => (),
+                    }
+
+                    match self.vm.get_journal() {
+                        Some(journal) => {
+                            println!("journal: {}/{} steps", journal.len(), journal.capacity())
+                        }
+                        None => println!("journal: off"),
+                    }
+                }
+                Some(("costs", sub)) => {
+                    match sub.get_one::<bool>("costs") {
+                        Some(true) => {
+                            let config = Config::load_default();
+                            let costs = OpcodeInfo::cost_table(&config.opcode_costs)?;
+                            self.vm.set_costs(Some(costs));
+                        }
+                        Some(false) => self.vm.set_costs(None),
+                        None => (),
+                    }
+
+                    match self.vm.get_costs() {
+                        Some(costs) => {
+                            println!("{} cycles", self.vm.get_cycles());
+                            for (info, cost) in OPCODES.iter().zip(costs) {
+                                println!("{:>6}: {}", info.mnemonic.to_lowercase(), cost);
+                            }
+                        }
+                        None => println!("No cost model, `vm costs on`"),
+                    }
+                }
+                Some(("coverage", sub)) => {
+                    self.vm
+                        .set_coverage(*sub.get_one::<bool>("coverage").unwrap());
+                }
                 Some(("register", sub)) => match sub.subcommand() {
                     Some(("set", sub)) => {
                         let reg = *sub.get_one::<usize>("register").unwrap();
@@ -355,41 +4467,250 @@ impl Cli {
                 None => println!("{:?}", self.vm),
             },
 
+            Some(("glossary", sub)) => {
+                if let Some(path) = sub.get_one::<PathBuf>("maze") {
+                    self.add_glossary(Maze::load(path)?.glossary);
+                }
+                if self.glossary.is_empty() {
+                    return Err("Empty glossary, run `solver explore` or use --maze".into());
+                }
+
+                match sub.get_one::<String>("item") {
+                    Some(pattern) => {
+                        let pattern = Regex::new(&format!("(?i){}", pattern))?;
+                        let items: Vec<_> = self
+                            .glossary
+                            .iter()
+                            .filter(|(item, _)| pattern.is_match(item))
+                            .collect();
+                        for (item, entry) in &items {
+                            println!("{}, in {}", item, entry.rooms.iter().join(", "));
+                            match (&entry.text, &entry.look) {
+                                (Some(text), _) => Self::print_text(text),
+                                (None, Some(look)) => println!("{}\n", look),
+                                (None, None) => println!("(not looked at)\n"),
+                            }
+                        }
+                        println!("{} items", items.len());
+                    }
+                    None => {
+                        let texts = sub.get_flag("texts");
+                        let items: Vec<_> = self
+                            .glossary
+                            .iter()
+                            .filter(|(_, entry)| !texts || entry.text.is_some())
+                            .collect();
+                        for (item, entry) in &items {
+                            let marker = match &entry.text {
+                                Some(text) if text.algorithm => " [algorithm]",
+                                Some(_) => " [text]",
+                                None => "",
+                            };
+                            println!("{:30} {}{}", item, entry.rooms.iter().join(", "), marker);
+                        }
+                        println!("{} items", items.len());
+                    }
+                }
+            }
             Some(("solver", sub)) => match sub.subcommand() {
-                Some(("explore", _sub)) => {
-                    GameSolver::explore_maze(&self.vm);
+                Some(("graphviz", sub)) => {
+                    let maze = Maze::load(sub.get_one::<PathBuf>("maze").unwrap())?;
+                    let options = GraphvizOptions {
+                        cluster_by: sub.get_one::<String>("cluster").unwrap().parse()?,
+                        collapse: sub
+                            .get_one::<String>("collapse")
+                            .map(|re| Regex::new(re))
+                            .transpose()?,
+                    };
+                    let out = match sub.get_one::<PathBuf>("out") {
+                        Some(out) => out.clone(),
+                        None => self.artifacts.path("graphviz.dot")?,
+                    };
+                    std::fs::write(&out, maze.to_graphviz_with(&options))?;
+                    println!("{:?}", out);
+                }
+                Some(("explore", sub)) => {
+                    let options = ExploreOptions {
+                        max_rooms: sub.get_one::<usize>("max-rooms").copied(),
+                        max_time: sub.get_one::<Duration>("max-time").copied(),
+                        resume: sub.get_one::<PathBuf>("resume").cloned(),
+                        graphviz: Some(self.artifacts.path("graphviz.dot")?),
+                    };
+                    if let Some(maze) = GameSolver::explore_maze(&self.vm, &options) {
+                        self.add_glossary(maze.glossary);
+                    }
                 }
                 Some(("teleporter", _sub)) => {
+                    match self.algorithm_source() {
+                        Some((item, entry, text)) => {
+                            println!(
+                                "The check is described by the {}, in {}:",
+                                item,
+                                entry.rooms.iter().join(", ")
+                            );
+                            for paragraph in text.algorithm_paragraphs() {
+                                println!("{}\n", paragraph);
+                            }
+                        }
+                        None => println!("Look at the strange book to read about the check"),
+                    }
                     GameSolver::trace_teleporter(&self.vm);
                 }
+                Some(("tablet", _sub)) => {
+                    let code = GameSolver::tablet()?;
+                    println!("Tablet code: {}", code);
+                    self.codes.insert(CodeSlot::Tablet, &code);
+                    if self.codes.verify(CodeSlot::Tablet) == Some(false) {
+                        println!("WARNING: tablet code doesn't match its hash, emulator bug?");
+                    }
+                }
+                Some(("fuzz-verbs", sub)) => {
+                    let words: Vec<String> = match sub.get_one::<PathBuf>("wordlist") {
+                        Some(path) => std::fs::read_to_string(path)?
+                            .lines()
+                            .map(|line| line.trim().to_string())
+                            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                            .collect(),
+                        None => GameSolver::default_fuzz_words(),
+                    };
+                    let interesting = GameSolver::fuzz_verbs(&self.vm, &words)?;
+                    println!(
+                        "{} interesting inputs out of {} words",
+                        interesting.len(),
+                        words.len()
+                    );
+                    for (input, answer) in interesting {
+                        println!("== {}\n{}", input, answer);
+                    }
+                }
+                Some(("walkthrough", sub)) => {
+                    let room = Regex::new(sub.get_one::<String>("room").unwrap())?;
+                    let items = sub
+                        .get_many::<String>("item")
+                        .unwrap_or_default()
+                        .map(|item| Regex::new(item))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let mut planner = Planner::default();
+                    if let Some(max_states) = sub.get_one::<usize>("max-states") {
+                        planner.max_states = *max_states;
+                    }
+                    let out = sub.get_one::<PathBuf>("out");
+                    let maze = sub
+                        .get_one::<PathBuf>("maze")
+                        .map(|path| Maze::load(path))
+                        .transpose()?;
+
+                    GameSolver::walkthrough(&self.vm, &planner, &room, &items, maze.as_ref(), out)?;
+                }
+                Some(("vault", sub)) => {
+                    let inputs: Vec<String> =
+                        sub.get_many::<String>("inputs").unwrap().cloned().collect();
+                    GameSolver::solve_vault(&self.vm, &inputs);
+                }
                 Some((_, _)) => return Err("unreachable?".into()),
                 None => (),
             },
+            Some(("hint", sub)) => {
+                let inventory = planner::inventory(&self.vm)
+                    .map_err(|e| format!("Can't read the inventory: {}", e))?;
+                let mut rooms = self.session.room_names.clone();
+                rooms.extend(room_name(&self.vm));
+                let context = HintContext {
+                    inventory,
+                    rooms,
+                    codes: &self.codes,
+                    r7: self.vm.get_registers()[7],
+                };
+
+                let full = sub.get_flag("full");
+                let hints = hints::hints(&context);
+                for hint in &hints {
+                    let text = if full { hint.full } else { hint.vague };
+                    println!("{}: {}", hint.name, text);
+                }
+                match hints.len() {
+                    0 => println!("No hint left, see `codes`"),
+                    _ if !full => println!("`hint --full` for the answers"),
+                    _ => (),
+                }
+            }
+            Some(("codes", _sub)) => {
+                self.codes.print_status();
+            }
+            Some(("copy", sub)) => {
+                let what = sub.get_one::<String>("what").unwrap();
+                let text = self.artifact(what)?;
+                let mut clipboard =
+                    arboard::Clipboard::new().map_err(|e| format!("No clipboard: {}", e))?;
+                clipboard.set_text(text.as_str())?;
+                println!("Copied {}, {} lines", what, text.lines().count());
+            }
             Some(("snap", sub)) => match sub.subcommand() {
                 Some(("dump", sub)) => {
                     let name = sub.get_one::<String>("name").unwrap();
                     let dump_path = sub.get_one::<String>("dump_path").unwrap();
-                    self.dump_snapshot(name, &format!("snaps/{}", dump_path));
+                    let compress = sub.get_flag("compress");
+                    self.dump_snapshot(name, &self.snap_path(dump_path), compress)?;
                 }
                 Some(("load", subsub)) => {
                     let dump_path = subsub.get_one::<String>("dump_path").unwrap();
-                    self.load_snapshot(&format!("snaps/{}", dump_path))?;
+                    if subsub.get_flag("dry-run") {
+                        println!(
+                            "Would load {}, unsaved progress: {}",
+                            self.snap_path(dump_path),
+                            self.has_unsaved_progress()
+                        );
+                        return Ok(());
+                    }
+                    if self.has_unsaved_progress()
+                        && !self.confirm("The current state is not in a snapshot, load anyway?")
+                    {
+                        return Ok(());
+                    }
+                    self.load_snapshot(&self.snap_path(dump_path))?;
                     println!(
                         "Last message was:\n{}",
                         self.vm.get_messages().last().unwrap()
                     );
                 }
                 Some(("take", sub)) => {
-                    let name = sub.get_one::<String>("name").unwrap();
-                    self.take_snapshot(name);
+                    let name = match sub.get_one::<String>("name") {
+                        Some(name) => name.clone(),
+                        None => {
+                            let tag = sub.get_one::<String>("tag").map(|t| t.as_str());
+                            self.suggest_snapshot_name(tag)
+                        }
+                    };
+                    self.take_snapshot(&name);
+                    println!("Took {:?}", name);
                 }
                 Some(("restore", sub)) => {
                     let name = sub.get_one::<String>("name").unwrap();
+                    if sub.get_flag("dry-run") {
+                        println!(
+                            "Would restore {:?}, unsaved progress: {}",
+                            name,
+                            self.has_unsaved_progress()
+                        );
+                        return Ok(());
+                    }
+                    if self.has_unsaved_progress()
+                        && !self.confirm("The current state is not in a snapshot, restore anyway?")
+                    {
+                        return Ok(());
+                    }
                     self.restore_snapshot(name);
                 }
                 Some(("remove", sub)) => {
                     let name = sub.get_one::<String>("name").unwrap();
-                    self.remove_snapshot(name);
+                    if self.get_snap_by_name(name).is_none() {
+                        return Err(format!("Snap {:?} not found", name).into());
+                    }
+                    if sub.get_flag("dry-run") {
+                        println!("Would remove {:?}", name);
+                    } else if self.confirm(&format!("Remove snapshot {:?}?", name)) {
+                        self.remove_snapshot(name);
+                    }
                 }
                 Some(("list", _)) => {
                     println!("{} snapshots:", self.snapshots.len());
@@ -398,26 +4719,515 @@ impl Cli {
                     }
                 }
                 _ => {
-                    let name = format!("{:03}", self.snapshots.len());
+                    let name = self.suggest_snapshot_name(None);
                     self.take_snapshot(&name);
+                    println!("Took {:?}", name);
+                }
+            },
+            Some(("step", sub)) => match sub.subcommand() {
+                Some(("back", sub)) => {
+                    let count: usize = *sub.get_one("count").unwrap();
+                    let undone = self.vm.step_back(count)?;
+                    if undone < count {
+                        println!("Only {} steps recorded", undone);
+                    }
+                    println!("ip: {}, pc: {}", self.vm.get_ip(), self.vm.get_pc());
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => {
+                    let count: u32 = *sub.get_one("count").unwrap();
+                    for _ in 0..count {
+                        if self.explain && self.vm.get_state() == VmState::Running {
+                            match self.vm.explain() {
+                                Ok(explanation) => {
+                                    println!("{}: {}", self.vm.get_ip(), explanation)
+                                }
+                                Err(e) => println!("{}: {}", self.vm.get_ip(), e),
+                            }
+                        }
+                        match self.vm.step() {
+                            Ok(_) => (),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                }
+            },
+            Some(("timetravel", sub)) => match sub.subcommand() {
+                Some(("enable", sub)) => {
+                    let every = *sub.get_one::<usize>("every").unwrap();
+                    self.vm.set_timetravel(Some(every * 1_000_000));
+                }
+                Some(("disable", _)) => self.vm.set_timetravel(None),
+                Some(("list", _)) => {
+                    for pc in self.vm.timetravel_snapshots() {
+                        println!("{}", pc);
+                    }
+                    println!("current: {}", self.vm.get_pc());
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("goto-pc", sub)) => {
+                let count = *sub.get_one::<usize>("count").unwrap();
+                self.vm.goto_pc(count)?;
+                println!("At {}: {:?}", self.vm.get_pc(), self.vm.get_state());
+            }
+            Some(("set", sub)) => match sub.subcommand() {
+                Some(("confirm", sub)) => {
+                    self.confirm = *sub.get_one::<bool>("confirm").unwrap();
+                }
+                Some(("explain", sub)) => {
+                    self.explain = *sub.get_one::<bool>("explain").unwrap();
+                }
+                Some(("watchdog", sub)) => {
+                    self.watchdog = *sub.get_one::<bool>("watchdog").unwrap();
+                }
+                Some(("prompt", sub)) => {
+                    self.prompt = sub.get_one::<String>("template").unwrap().clone();
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("bruteforce", sub)) => {
+                let inputs: Vec<String> = sub
+                    .get_many::<String>("input")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                if !inputs.is_empty() && self.vm.get_state() != VmState::WaitingForInput {
+                    return Err(format!("Can't feed the inputs: {:?}", self.vm.get_state()).into());
+                }
+                let options = BruteforceOptions {
+                    variable: *sub.get_one::<Variable>("var").unwrap(),
+                    values: sub.get_one::<RangeInclusive<u16>>("range").unwrap().clone(),
+                    inputs,
+                    success: Regex::new(sub.get_one::<String>("success").unwrap())?,
+                    max_steps: *sub.get_one::<usize>("max-steps").unwrap(),
+                };
+
+                let results = GameSolver::bruteforce(&self.vm, &options);
+                println!("{} matching values:", results.len());
+                for (val, line) in &results {
+                    println!("{:>6} | {}", val, line);
+                }
+            }
+            Some(("profile", sub)) => match sub.subcommand() {
+                Some(("start", _)) => self.vm.start_profile(),
+                Some(("stop", _)) => self.vm.stop_profile(),
+                Some(("report", sub)) => {
+                    let top = *sub.get_one::<usize>("top").unwrap();
+                    let profile = self
+                        .vm
+                        .get_profile()
+                        .ok_or("No profile, see `profile start`")?;
+                    let total = profile.total();
+                    let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+                    println!("{} instructions", total);
+
+                    println!("\nhottest addresses:");
+                    let memory = self.vm.get_memory();
+                    for (addr, count) in profile.hottest(top) {
+                        let instruction = match Opcode::decode(&memory[addr..]) {
+                            Ok(opcode) => format!("{:?}", opcode),
+                            Err(e) => e.to_string(),
+                        };
+                        println!(
+                            "{:>12} {:>5.1}%  {:>5}: {}",
+                            count,
+                            percent(count),
+                            addr,
+                            instruction
+                        );
+                    }
+
+                    println!("\nopcodes:");
+                    let mut opcodes: Vec<(&OpcodeInfo, u64)> =
+                        OPCODES.iter().zip(profile.opcodes).collect();
+                    opcodes.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+                    for (info, count) in opcodes.into_iter().filter(|&(_, count)| count > 0) {
+                        println!("{:>12} {:>5.1}%  {}", count, percent(count), info.mnemonic);
+                    }
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("trace", sub)) => match sub.subcommand() {
+                Some(("start", sub)) => {
+                    let routine = match sub.get_one::<Addr>("routine") {
+                        Some(&routine) => Some(routine.into()),
+                        None if sub.get_flag("strings") => Some(
+                            report::find_print_routine(self.vm.get_memory())
+                                .ok_or("No string printing routine found")?,
+                        ),
+                        None => None,
+                    };
+                    if let Some(routine) = routine {
+                        println!("Printing routine: {}", self.describe(routine));
+                    }
+                    self.vm.clear_trace_buffer();
+                    self.vm.set_traced_opcodes(
+                        Opcode::Call(Val::Invalid).discriminant() | Opcode::Ret.discriminant(),
+                    );
+                    self.vm.set_string_routine(routine);
+                }
+                Some(("stop", _)) => {
+                    self.vm.set_traced_opcodes(0);
+                    self.vm.set_string_routine(None);
+                    println!("{} instructions traced", self.vm.get_trace_buffer().len());
+                }
+                Some(("profile", sub)) => {
+                    let top = *sub.get_one::<usize>("top").unwrap();
+                    let trace = self.vm.get_trace_buffer();
+                    if trace.is_empty() {
+                        return Err("Nothing traced, see `trace start`".into());
+                    }
+                    let profiles =
+                        report::function_profile(trace, self.vm.get_pc(), self.vm.get_symbols());
+                    println!(
+                        "{:>8} {:>12} {:>12}  function",
+                        "calls", "inclusive", "exclusive"
+                    );
+                    for (label, profile) in profiles.iter().take(top) {
+                        println!(
+                            "{:>8} {:>12} {:>12}  {}",
+                            profile.calls, profile.inclusive, profile.exclusive, label
+                        );
+                    }
+                }
+                Some(("strings", _)) => {
+                    let calls = self.vm.get_print_calls();
+                    let routine = calls
+                        .first()
+                        .ok_or("No string printed, see `trace start --strings`")?
+                        .target;
+
+                    println!("Printing routine: {}", self.describe(routine));
+                    for call in calls {
+                        println!(
+                            "{:>5}: r0={:<5} {:?}",
+                            call.call_site, call.registers[0], call.text
+                        );
+                    }
+                }
+                Some(("decode", _)) => {
+                    let memory = self.vm.get_memory();
+                    let decoder = StringDecoder::from_calls(self.vm.get_print_calls())
+                        .ok_or("No string printed, see `trace start --strings`")?;
+                    println!("Printing routine: {}", self.describe(decoder.routine));
+                    for (callback, decoder) in &decoder.decoders {
+                        println!("Callback {}: {:?}", self.describe(*callback), decoder);
+                    }
+
+                    let strings = decoder.decode(memory);
+                    for (addr, text) in &strings {
+                        println!("{:>5}: {:?}", addr, text);
+                    }
+                    println!("{} strings decoded", strings.len());
                 }
+                Some(("tree", sub)) => {
+                    let depth = *sub.get_one::<usize>("depth").unwrap();
+                    let trace = self.vm.get_trace_buffer();
+                    if trace.is_empty() {
+                        return Err("Nothing traced, see `trace start`".into());
+                    }
+                    print!(
+                        "{}",
+                        report::call_tree(trace, self.vm.get_pc(), self.vm.get_symbols(), depth)
+                    );
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("state", sub)) => match sub.subcommand() {
+                Some(("hash", sub)) => {
+                    let name = sub.get_one::<String>("name").unwrap();
+                    println!("{}", self.state_by_name(name)?.state_hash());
+                }
+                Some(("same", sub)) => {
+                    let [a, b] = ["a", "b"].map(|arg| sub.get_one::<String>(arg).unwrap());
+                    let (vm_a, vm_b) = (self.state_by_name(a)?, self.state_by_name(b)?);
+                    if vm_a == vm_b {
+                        println!("same: {}", vm_a.state_hash());
+                    } else {
+                        println!(
+                            "different: {} {}, {} memory cells differ",
+                            vm_a.state_hash(),
+                            vm_b.state_hash(),
+                            diff::vm_memory_diff(&vm_a, &vm_b).len()
+                        );
+                    }
+                }
+                Some(("list", sub)) => {
+                    let mut states = self.saved_states();
+                    match sub.get_one::<String>("sort").unwrap().as_str() {
+                        "name" => states.sort_by(|a, b| a.name.cmp(&b.name)),
+                        _ => states.sort_by_key(|state| state.time),
+                    }
+                    Self::print_saved_states(&states);
+                }
+                Some(("find", sub)) => {
+                    let pattern = Regex::new(sub.get_one::<String>("pattern").unwrap())?;
+                    let mut states = self.saved_states();
+                    states.retain(|state| {
+                        pattern.is_match(&state.name) || pattern.is_match(&state.details)
+                    });
+                    states.sort_by_key(|state| state.time);
+                    Self::print_saved_states(&states);
+                }
+                Some(("gc", sub)) => {
+                    let states = self.saved_states();
+                    for (in_memory, source) in [(true, "memory"), (false, "snaps/")] {
+                        let sizes: Vec<u64> = states
+                            .iter()
+                            .filter(|state| state.in_memory == in_memory)
+                            .map(|state| state.size)
+                            .collect();
+                        println!(
+                            "{:7} {} states, {} KB",
+                            source,
+                            sizes.len(),
+                            sizes.iter().sum::<u64>() / 1024
+                        );
+                    }
+
+                    let max_age = sub.get_one::<Duration>("older-than").copied();
+                    let candidates =
+                        self.gc_candidates(states, max_age, sub.get_flag("duplicates"));
+                    if candidates.is_empty() {
+                        return Ok(());
+                    }
+                    for (state, reason) in &candidates {
+                        let source = if state.in_memory { "memory" } else { "file" };
+                        println!("{:6} {:40} {}", source, state.name, reason);
+                    }
+                    let freed = candidates.iter().map(|(state, _)| state.size).sum::<u64>();
+                    let question =
+                        format!("Drop {} states, {} KB?", candidates.len(), freed / 1024);
+                    if sub.get_flag("dry-run") {
+                        println!(
+                            "Would drop {} states, {} KB",
+                            candidates.len(),
+                            freed / 1024
+                        );
+                        return Ok(());
+                    }
+                    if !self.confirm(&question) {
+                        return Ok(());
+                    }
+
+                    for (state, _) in &candidates {
+                        if state.in_memory {
+                            self.remove_snapshot(&state.name);
+                        } else {
+                            std::fs::remove_file(self.snap_path(&state.name))?;
+                        }
+                    }
+                    println!("Dropped {} states", candidates.len());
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("strings", sub)) => {
+                let pattern = sub
+                    .get_one::<String>("pattern")
+                    .map(|p| Regex::new(p))
+                    .transpose()?;
+                let min = *sub.get_one::<usize>("min").unwrap();
+                let memory = self.vm.get_memory();
+                let decoder = StringDecoder::from_calls(self.vm.get_print_calls());
+
+                let mut count = 0;
+                for (addr, source, text) in report::find_text(memory, min, decoder.as_ref()) {
+                    if pattern.as_ref().is_none_or(|p| p.is_match(&text)) {
+                        println!("{:>5} {:<7} {:?}", addr, source, text);
+                        count += 1;
+                    }
+                }
+                println!("{} strings", count);
+                if decoder.is_none() {
+                    println!("Trace with `trace start --strings` to decode the encrypted ones");
+                }
+            }
+            Some(("compare-input", sub)) => {
+                let mut base = self.vm.clone();
+                // both results share a baseline, to diff only their written pages
+                base.clean_pages();
+                let mut results = Vec::new();
+                for input in ["a", "b"].map(|arg| sub.get_one::<String>(arg).unwrap()) {
+                    let mut vm = base.clone();
+                    vm.feed(input)?;
+                    vm.run();
+                    results.push(vm);
+                }
+                let (a, b) = (&results[0], &results[1]);
+
+                let answer = |vm: &Vm| vm.get_messages().last().cloned().unwrap_or_default();
+                println!("--- {}", sub.get_one::<String>("a").unwrap());
+                println!("+++ {}", sub.get_one::<String>("b").unwrap());
+                for line in diff::line_diff(&answer(a), &answer(b)) {
+                    println!("{}", line);
+                }
+
+                let cells = diff::vm_memory_diff(a, b);
+                println!("\n{} memory cells differ", cells.len());
+                for (addr, x, y) in cells.iter().take(20) {
+                    println!("{:>5}: {:>5} {:>5}", addr, x, y);
+                }
+                if cells.len() > 20 {
+                    println!("...");
+                }
+            }
+            Some(("session", sub)) => match sub.subcommand() {
+                Some(("stats", _)) => self.print_session_stats(),
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("messages", sub)) => {
+                let messages = self.vm.get_messages();
+                match sub.get_one::<usize>("n") {
+                    Some(&n) => {
+                        let message = messages.get(n).ok_or_else(|| format!("No message {}", n))?;
+                        println!("{}", message);
+                    }
+                    None => {
+                        let last = *sub.get_one::<usize>("last").unwrap();
+                        for (n, message) in messages
+                            .iter()
+                            .enumerate()
+                            .skip(messages.len().saturating_sub(last))
+                        {
+                            let first =
+                                message.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+                            println!("{:>4}: {}", n, first);
+                        }
+                    }
+                }
+            }
+            Some(("stack", _)) => {
+                println!("{}", self.vm.format_stack());
+            }
+            Some(("bt", _)) => {
+                for (n, (ip, function)) in self.vm.backtrace().into_iter().enumerate() {
+                    match function {
+                        Some(function) => println!(
+                            "#{:<3} {:>5} in {}{:+}",
+                            n,
+                            ip,
+                            self.vm.get_symbols().name(function.into()),
+                            ip.get() as isize - function.get() as isize
+                        ),
+                        None => println!("#{:<3} {:>5} outside of known calls", n, ip),
+                    }
+                }
+            }
+            Some(("help", sub)) => match sub.get_one::<String>("command") {
+                Some(name) => {
+                    let command = help::find(name).ok_or_else(|| {
+                        format!("Unknown command `{}`, `help` lists the commands", name)
+                    })?;
+                    help::print_command(&self.cli, command);
+                }
+                None => help::print_commands(),
             },
-            Some(("step", sub)) => {
-                let count: u32 = *sub.get_one("count").unwrap();
-                for _ in 0..count {
-                    match self.vm.step() {
-                        Ok(_) => (),
-                        Err(e) => println!("{}", e),
+            Some(("tutorial", _)) => help::print_tutorial(),
+            Some(("game", sub)) => {
+                self.game_mode = *sub.get_one::<bool>("on").unwrap();
+                if self.game_mode {
+                    println!("Lines go to the game, `:game off` to leave");
+                }
+            }
+            Some(("artifacts", sub)) => match sub.subcommand() {
+                Some(("list", sub)) if sub.get_flag("all") => {
+                    for (dir, files) in self.artifacts.sessions()? {
+                        println!("{} {} files", dir.display(), files.len());
+                    }
+                }
+                Some(("list", _)) => {
+                    let Some(session) = self.artifacts.session() else {
+                        println!("No artifact yet");
+                        return Ok(());
+                    };
+                    let sessions = self.artifacts.sessions()?;
+                    let files = sessions
+                        .iter()
+                        .find(|(dir, _)| dir == session)
+                        .map_or(&[][..], |(_, files)| files);
+                    for file in files {
+                        println!("{}", file.display());
                     }
                 }
+                Some(("open", sub)) => {
+                    let path = match sub.get_one::<String>("name") {
+                        Some(name) => self
+                            .artifacts
+                            .find(name)
+                            .or_else(|| {
+                                // a folder of `artifacts list --all`
+                                let dir = self.artifacts.root().join(name);
+                                dir.exists().then_some(dir)
+                            })
+                            .ok_or_else(|| format!("No artifact {}", name))?,
+                        None => self
+                            .artifacts
+                            .session()
+                            .ok_or("No artifact yet")?
+                            .to_path_buf(),
+                    };
+                    let opener = if cfg!(target_os = "macos") {
+                        "open"
+                    } else {
+                        "xdg-open"
+                    };
+                    std::process::Command::new(opener)
+                        .arg(&path)
+                        .spawn()
+                        .map_err(|e| format!("Can't run {}: {}", opener, e))?;
+                }
+                Some((_, _)) => return Err("unreachable?".into()),
+                None => (),
+            },
+            Some(("load", sub)) => {
+                let config = Config::load_default();
+                let path = Vm::find_binary(
+                    sub.get_one::<PathBuf>("path").map(PathBuf::as_path),
+                    config.binary.as_deref(),
+                )?;
+                if self.binary.is_some()
+                    && self.has_unsaved_progress()
+                    && !self.confirm("The current state is not in a snapshot, load anyway?")
+                {
+                    return Ok(());
+                }
+                let mut vm = Vm::new();
+                vm.load_program_from_file(&path)?;
+                self.vm = vm;
+                self.undo.clear();
+                println!("Loaded {}", path.display());
+                self.set_binary(Some(path));
             }
-            Some(("helpme", _)) => {
-                self.cli.print_long_help().unwrap();
+            Some(("quit", sub)) => {
+                if let Some(dump_path) = sub.get_one::<String>("save") {
+                    let snap = Snapshot {
+                        name: "autosave".to_string(),
+                        vm: self.vm.clone(),
+                        taken: SystemTime::now(),
+                    };
+                    let path = self.snap_path(dump_path);
+                    let program = self.program().ok();
+                    let size = save_state_to_disk(
+                        &path,
+                        &snap,
+                        program.as_deref(),
+                        sub.get_flag("compress"),
+                    )?;
+                    println!("Saved {}, {} KB", path, size / 1024);
+                }
+                self.quit = true;
             }
             Some((x, _sub)) => unimplemented!("Unknown command {x:?}"),
             None => (),
         }
 
         Ok(())
-    } // end fn parse_command
+    } // end fn parse_single_command
 }
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// The 8 codes of the challenge, in the order they are found
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+pub enum CodeSlot {
+    Spec,
+    Boot,
+    SelfTest,
+    Tablet,
+    TwistyPassages,
+    Teleporter,
+    Beach,
+    Mirror,
+}
+
+impl CodeSlot {
+    pub const ALL: [CodeSlot; 8] = [
+        CodeSlot::Spec,
+        CodeSlot::Boot,
+        CodeSlot::SelfTest,
+        CodeSlot::Tablet,
+        CodeSlot::TwistyPassages,
+        CodeSlot::Teleporter,
+        CodeSlot::Beach,
+        CodeSlot::Mirror,
+    ];
+
+    /// Regex matching the output containing the code, as the first group
+    fn pattern(&self) -> &'static str {
+        match self {
+            CodeSlot::Spec => r"Here's a code for the challenge website: (\w{12})",
+            CodeSlot::Boot => r"this one into the challenge website: (\w{12})",
+            CodeSlot::SelfTest => r"The self-test completion code is: (\w{12})",
+            CodeSlot::Tablet => r#"You find yourself writing "(\w{12})" on the tablet"#,
+            CodeSlot::TwistyPassages => {
+                r"Chiseled on the wall of one of the passageways, you see:\s+(\w{12})"
+            }
+            CodeSlot::Teleporter => r"you think you see a pattern in the stars\.\.\.\s+(\w{12})",
+            CodeSlot::Beach => r"drawn a message in the sand here:\s+(\w{12})",
+            CodeSlot::Mirror => r#"Through the mirror, you see "(\w{12})" scrawled"#,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            CodeSlot::Spec => "in the arch-spec",
+            CodeSlot::Boot => "first output of the VM",
+            CodeSlot::SelfTest => "VM passed the self-test",
+            CodeSlot::Tablet => "use the tablet",
+            CodeSlot::TwistyPassages => "chiseled in the twisty passages",
+            CodeSlot::Teleporter => "use the teleporter after the coins",
+            CodeSlot::Beach => "use the teleporter with the right r7",
+            CodeSlot::Mirror => "use the mirror in the vault",
+        }
+    }
+
+    /// The mirror code is seen reversed
+    fn decode(&self, raw: &str) -> String {
+        match self {
+            CodeSlot::Mirror => raw
+                .chars()
+                .rev()
+                .map(|c| match c {
+                    'b' => 'd',
+                    'd' => 'b',
+                    'p' => 'q',
+                    'q' => 'p',
+                    c => c,
+                })
+                .collect(),
+            _ => raw.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CodeSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Codes captured during the session
+#[derive(Debug, Default)]
+pub struct CodeRegistry {
+    codes: BTreeMap<CodeSlot, String>,
+    /// Expected MD5 of the codes, lowercase hex
+    hashes: BTreeMap<CodeSlot, String>,
+}
+
+impl CodeRegistry {
+    pub fn with_hashes(hashes: &BTreeMap<CodeSlot, String>) -> Self {
+        CodeRegistry {
+            codes: BTreeMap::new(),
+            hashes: hashes
+                .iter()
+                .map(|(slot, hash)| (*slot, hash.trim().to_lowercase()))
+                .collect(),
+        }
+    }
+
+    /// `None` if the code is missing or there is no hash to check against
+    pub fn verify(&self, slot: CodeSlot) -> Option<bool> {
+        let code = self.codes.get(&slot)?;
+        let expected = self.hashes.get(&slot)?;
+
+        Some(format!("{:x}", md5::compute(code)) == *expected)
+    }
+
+    /// Record the codes found in `message`, returning the ones that were not known yet
+    pub fn capture(&mut self, message: &str) -> Vec<(CodeSlot, String)> {
+        let mut captured = Vec::new();
+
+        for slot in CodeSlot::ALL {
+            let re = Regex::new(slot.pattern()).unwrap();
+            if let Some(caps) = re.captures(message) {
+                let code = slot.decode(caps.get(1).unwrap().as_str());
+                if self.codes.get(&slot) != Some(&code) {
+                    self.codes.insert(slot, code.clone());
+                    captured.push((slot, code));
+                }
+            }
+        }
+
+        captured
+    }
+
+    pub fn insert(&mut self, slot: CodeSlot, code: &str) {
+        self.codes.insert(slot, code.to_string());
+    }
+
+    pub fn get(&self, slot: CodeSlot) -> Option<&str> {
+        self.codes.get(&slot).map(|code| code.as_str())
+    }
+
+    pub fn print_status(&self) {
+        println!("{}/{} codes:", self.codes.len(), CodeSlot::ALL.len());
+        for slot in CodeSlot::ALL {
+            let code = self.get(slot).unwrap_or("-");
+            let verified = match self.verify(slot) {
+                Some(true) => "ok",
+                Some(false) => "MISMATCH",
+                None => "",
+            };
+            println!(
+                "{:>14} {:>12} {:>8}   {}",
+                slot.to_string(),
+                code,
+                verified,
+                slot.description()
+            );
+        }
+    }
+}
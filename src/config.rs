@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::codes::CodeSlot;
+
+/// Default location of the config file, in the working directory
+pub const CONFIG_PATH: &str = "config.json";
+
+/// User settings, read from `config.json`
+///
+/// ```json
+/// {
+///   "code_hashes": {
+///     "Tablet": "5a105e8b9d40e1329780d62ea2265d8a"
+///   },
+///   "alert_command": "notify-send synacor \"$ALERT_MATCH\"",
+///   "opcode_costs": { "mult": 4, "mod": 8 },
+///   "prompt": "{state} {room} {pc} >> ",
+///   "binary": "/home/me/synacor/challenge.bin",
+///   "artifacts_dir": "/tmp/synacor"
+/// }
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Published MD5 hashes of the personalized codes
+    pub code_hashes: BTreeMap<CodeSlot, String>,
+    /// Shell command run when an alert matches, see `alert sink`
+    pub alert_command: Option<String>,
+    /// Cycles of the opcodes by mnemonic for `vm costs on`, 1 if not listed
+    pub opcode_costs: BTreeMap<String, u64>,
+    /// Template of the REPL prompt, see `set prompt`
+    pub prompt: Option<String>,
+    /// Path of the program, when not `challenge.bin` in the working directory
+    pub binary: Option<PathBuf>,
+    /// Folder of the session folders of generated files, `artifacts` by default
+    pub artifacts_dir: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let f = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    /// Load `CONFIG_PATH` if it exists
+    pub fn load_default() -> Self {
+        if !Path::new(CONFIG_PATH).exists() {
+            return Config::default();
+        }
+
+        match Config::load(CONFIG_PATH) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Can't load {}: {}", CONFIG_PATH, e);
+                Config::default()
+            }
+        }
+    }
+}
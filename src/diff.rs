@@ -0,0 +1,240 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use crate::driver::VmDriver;
+use crate::emulator::{Vm, VmState, PAGE_SIZE};
+
+/// Line diff of `a` and `b`, using the longest common subsequence
+///
+/// Lines are prefixed by ` `, `-` or `+`, like a unified diff without hunks.
+pub fn line_diff(a: &str, b: &str) -> Vec<String> {
+    let a: Vec<&str> = a.lines().collect();
+    let b: Vec<&str> = b.lines().collect();
+
+    // lcs[i][j]: length of the LCS of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            diff.push(format!(" {}", a[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.push(format!("-{}", a[i]));
+            i += 1;
+        } else {
+            diff.push(format!("+{}", b[j]));
+            j += 1;
+        }
+    }
+    diff.extend(a[i..].iter().map(|line| format!("-{}", line)));
+    diff.extend(b[j..].iter().map(|line| format!("+{}", line)));
+
+    diff
+}
+
+/// Cells that differ: (address, value in `a`, value in `b`)
+pub fn memory_diff(a: &[u16], b: &[u16]) -> Vec<(usize, u16, u16)> {
+    a.iter()
+        .zip(b.iter())
+        .enumerate()
+        .filter(|(_, (x, y))| x != y)
+        .map(|(addr, (x, y))| (addr, *x, *y))
+        .collect()
+}
+
+/// Runs of consecutive words of `memory` that differ from `base`, as (offset, words)
+///
+/// `base` is padded with zeros to the length of `memory`, like a program loaded in a VM.
+pub fn memory_delta(base: &[u16], memory: &[u16]) -> Vec<(usize, Vec<u16>)> {
+    let mut runs: Vec<(usize, Vec<u16>)> = Vec::new();
+    for (addr, &word) in memory.iter().enumerate() {
+        if base.get(addr).copied().unwrap_or(0) == word {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((start, words)) if *start + words.len() == addr => words.push(word),
+            _ => runs.push((addr, vec![word])),
+        }
+    }
+
+    runs
+}
+
+/// The memory of `len` words: `base` padded with zeros, with the runs of `memory_delta`
+pub fn apply_memory_delta(
+    base: &[u16],
+    len: usize,
+    delta: &[(usize, Vec<u16>)],
+) -> Result<Vec<u16>, String> {
+    let mut memory = base.to_vec();
+    memory.resize(len, 0);
+    for (start, words) in delta {
+        let run = memory
+            .get_mut(*start..*start + words.len())
+            .ok_or_else(|| format!("Run of {} words at {} out of memory", words.len(), start))?;
+        run.copy_from_slice(words);
+    }
+
+    Ok(memory)
+}
+
+/// `memory_diff` of two VMs, only on the pages written since their common state if any
+pub fn vm_memory_diff(a: &Vm, b: &Vm) -> Vec<(usize, u16, u16)> {
+    let pages = match a.differing_pages(b) {
+        Some(pages) => pages,
+        None => return memory_diff(a.get_memory(), b.get_memory()),
+    };
+    let (a, b) = (a.get_memory(), b.get_memory());
+    pages
+        .into_iter()
+        .flat_map(|page| {
+            let start = page * PAGE_SIZE;
+            let end = (start + PAGE_SIZE).min(a.len()).min(b.len());
+            memory_diff(&a[start..end], &b[start..end])
+                .into_iter()
+                .map(move |(offset, x, y)| (start + offset, x, y))
+        })
+        .collect()
+}
+
+/// Output of a program, and the inputs between its answers
+///
+/// In the file, the input lines start with `> `, the other lines are the output. The lines
+/// before the first input are the output before the first prompt.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Transcript {
+    pub answers: Vec<Answer>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Answer {
+    /// `None` for the output before the first prompt
+    pub input: Option<String>,
+    pub output: Vec<String>,
+    /// Line of the input in the file, 0 for the first answer
+    line: usize,
+}
+
+/// First line where the output doesn't match the transcript
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    /// Line in the transcript file
+    pub line: usize,
+    /// Input answered by the diverging output
+    pub after: Option<String>,
+    /// `None` past the end of the output
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+impl Transcript {
+    pub fn parse(text: &str) -> Self {
+        let mut answers = vec![Answer::default()];
+        for (n, line) in text.lines().enumerate() {
+            let input = match line {
+                ">" => Some(""),
+                line => line.strip_prefix("> "),
+            };
+            match input {
+                Some(input) => answers.push(Answer {
+                    input: Some(input.to_string()),
+                    output: Vec::new(),
+                    line: n + 1,
+                }),
+                None => answers.last_mut().unwrap().output.push(line.to_string()),
+            }
+        }
+
+        Self { answers }
+    }
+
+    /// Feed the inputs to a copy of `vm`, and record its answers at the same lines
+    ///
+    /// Stops early if the program halts, so there are fewer answers than inputs.
+    pub fn replay(&self, vm: &Vm) -> Result<Transcript, Box<dyn std::error::Error>> {
+        let mut vm = vm.clone();
+        let answers = RefCell::new(vec![Answer::default()]);
+        let mut inputs = self.answers.iter().filter_map(|a| a.input.as_ref());
+
+        let mut driver = VmDriver::new(&mut vm)
+            .on_output(|text| {
+                let mut answers = answers.borrow_mut();
+                let output = &mut answers.last_mut().unwrap().output;
+                output.extend(text.lines().map(String::from));
+            })
+            .on_input_request(|| {
+                let input = inputs.next()?;
+                answers.borrow_mut().push(Answer {
+                    input: Some(input.clone()),
+                    ..Answer::default()
+                });
+                Some(input.clone())
+            });
+        // the breakpoints of the VM are not part of the game
+        while driver.run()? == VmState::HitBreakPoint {}
+        drop(driver);
+
+        let mut answers = answers.into_inner();
+        for (answer, expected) in answers.iter_mut().zip(&self.answers) {
+            answer.line = expected.line;
+        }
+
+        Ok(Transcript { answers })
+    }
+
+    pub fn first_divergence(&self, actual: &Transcript) -> Option<Divergence> {
+        for (idx, expected) in self.answers.iter().enumerate() {
+            let Some(answer) = actual.answers.get(idx) else {
+                // the input wasn't asked
+                return Some(Divergence {
+                    line: expected.line,
+                    after: None,
+                    expected: expected.input.as_ref().map(|input| format!("> {}", input)),
+                    actual: None,
+                });
+            };
+
+            let len = expected.output.len().max(answer.output.len());
+            if let Some(i) = (0..len).find(|&i| expected.output.get(i) != answer.output.get(i)) {
+                return Some(Divergence {
+                    line: expected.line + 1 + i,
+                    after: expected.input.clone(),
+                    expected: expected.output.get(i).cloned(),
+                    actual: answer.output.get(i).cloned(),
+                });
+            }
+        }
+
+        None
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.answers.iter().filter(|a| a.input.is_some()).count()
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for answer in &self.answers {
+            if let Some(input) = &answer.input {
+                writeln!(f, "> {}", input)?;
+            }
+            for line in &answer.output {
+                writeln!(f, "{}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::JoinHandle;
+
+use crate::emulator::{Addr, Vm, VmState};
+
+/// Steps between two checks for commands, while running in the background
+const SLICE: usize = 100_000;
+
+/// Runs a `Vm` for a host: its output, its prompts and its end are callbacks
+///
+/// ```no_run
+/// use synacor_challenge::driver::VmDriver;
+/// use synacor_challenge::emulator::Vm;
+///
+/// let mut vm = Vm::default();
+/// let mut inputs = vec!["take tablet", "use tablet"].into_iter();
+/// VmDriver::new(&mut vm)
+///     .on_output(|text| println!("{}", text))
+///     .on_input_request(|| inputs.next().map(String::from))
+///     .run()
+///     .unwrap();
+/// ```
+pub struct VmDriver<'a> {
+    vm: &'a mut Vm,
+    on_output: Box<dyn FnMut(&str) + 'a>,
+    on_input_request: Box<dyn FnMut() -> Option<String> + 'a>,
+    on_halt: Box<dyn FnMut() + 'a>,
+    /// Messages already given to `on_output`
+    delivered: usize,
+}
+
+impl<'a> VmDriver<'a> {
+    /// Without callbacks, the output is dropped and there is no input
+    pub fn new(vm: &'a mut Vm) -> Self {
+        let delivered = vm.get_messages().len();
+        Self {
+            vm,
+            on_output: Box::new(|_| ()),
+            on_input_request: Box::new(|| None),
+            on_halt: Box::new(|| ()),
+            delivered,
+        }
+    }
+
+    /// Called with each non-empty answer of the program, before its prompt or halt
+    pub fn on_output(mut self, f: impl FnMut(&str) + 'a) -> Self {
+        self.on_output = Box::new(f);
+        self
+    }
+
+    /// Called on prompts, `None` stops running
+    pub fn on_input_request(mut self, f: impl FnMut() -> Option<String> + 'a) -> Self {
+        self.on_input_request = Box::new(f);
+        self
+    }
+
+    pub fn on_halt(mut self, f: impl FnMut() + 'a) -> Self {
+        self.on_halt = Box::new(f);
+        self
+    }
+
+    pub fn vm(&self) -> &Vm {
+        self.vm
+    }
+
+    /// Run until the program halts, hits a breakpoint, or the host has no more input
+    ///
+    /// Returns the state of the VM. Running again continues after a breakpoint or a prompt.
+    pub fn run(&mut self) -> Result<VmState, Box<dyn std::error::Error>> {
+        if self.vm.get_state() == VmState::HitBreakPoint {
+            self.vm.resume()?;
+        }
+
+        loop {
+            match self.vm.get_state() {
+                VmState::Running => self.vm.run(),
+                VmState::WaitingForInput => {
+                    self.deliver_output();
+                    match (self.on_input_request)() {
+                        Some(line) => self.vm.feed(&line)?,
+                        None => return Ok(VmState::WaitingForInput),
+                    }
+                }
+                VmState::Halted => {
+                    self.deliver_output();
+                    (self.on_halt)();
+                    return Ok(VmState::Halted);
+                }
+                VmState::HitBreakPoint => return Ok(VmState::HitBreakPoint),
+            }
+        }
+    }
+
+    fn deliver_output(&mut self) {
+        let messages = self.vm.get_messages();
+        // restoring a snapshot can shorten the history
+        let start = self.delivered.min(messages.len());
+        self.delivered = messages.len();
+        for message in messages[start..].iter().filter(|m| !m.is_empty()) {
+            (self.on_output)(message);
+        }
+    }
+}
+
+/// Requests to a `VmHandle`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Run, or continue after a breakpoint or a pause
+    Run,
+    /// Answer a prompt and continue running
+    Input(String),
+    Pause,
+    /// Send a copy of the VM in an `Event::Snapshot`
+    Snapshot,
+    SetBreakpoint(Addr),
+    UnsetBreakpoint(Addr),
+}
+
+/// Reports of a `VmHandle`
+#[derive(Debug)]
+pub enum Event {
+    /// Non-empty answer of the program
+    Output(String),
+    /// Stopped by the program: waiting for input, halted, or on a breakpoint
+    Stopped(VmState),
+    Paused {
+        ip: Addr,
+        pc: usize,
+    },
+    Snapshot(Box<Vm>),
+    Error(String),
+}
+
+/// A `Vm` running on a background thread, controlled with commands
+///
+/// The worker checks for commands between slices of steps, so pausing or taking a snapshot
+/// doesn't wait for the end of a long computation.
+pub struct VmHandle {
+    commands: Sender<Command>,
+    events: Receiver<Event>,
+    worker: JoinHandle<Vm>,
+}
+
+impl VmHandle {
+    /// Move `vm` to a new thread, it waits for `Command::Run`
+    pub fn spawn(vm: Vm) -> Self {
+        let (commands, commands_rx) = mpsc::channel();
+        let (events_tx, events) = mpsc::channel();
+        // like the main thread, for the recursion of the native 6027
+        let worker = std::thread::Builder::new()
+            .name("vm".to_string())
+            .stack_size(8 * 1024 * 1024)
+            .spawn(move || Worker::new(vm, events_tx).serve(commands_rx))
+            .expect("Can't spawn the VM thread");
+
+        Self {
+            commands,
+            events,
+            worker,
+        }
+    }
+
+    pub fn send(&self, command: Command) -> Result<(), String> {
+        self.commands
+            .send(command)
+            .map_err(|_| "The VM thread stopped".to_string())
+    }
+
+    /// Events received so far, without waiting
+    pub fn poll(&self) -> Vec<Event> {
+        self.events.try_iter().collect()
+    }
+
+    /// Wait for the next event
+    pub fn recv(&self) -> Option<Event> {
+        self.events.recv().ok()
+    }
+
+    /// False once the thread stopped, which it only does on its own by panicking
+    pub fn is_alive(&self) -> bool {
+        !self.worker.is_finished()
+    }
+
+    /// Stop the thread, and get the VM back with the events not received yet
+    ///
+    /// Fails if the thread panicked, the VM is lost.
+    pub fn join(self) -> Result<(Vm, Vec<Event>), String> {
+        drop(self.commands);
+        let vm = self
+            .worker
+            .join()
+            .map_err(|_| "The VM thread panicked, its VM is lost".to_string())?;
+
+        Ok((vm, self.events.try_iter().collect()))
+    }
+}
+
+struct Worker {
+    vm: Vm,
+    events: Sender<Event>,
+    running: bool,
+    delivered: usize,
+}
+
+impl Worker {
+    fn new(vm: Vm, events: Sender<Event>) -> Self {
+        let delivered = vm.get_messages().len();
+        Self {
+            vm,
+            events,
+            running: false,
+            delivered,
+        }
+    }
+
+    /// Until the handle is dropped
+    fn serve(mut self, commands: Receiver<Command>) -> Vm {
+        loop {
+            let command = if self.running {
+                match commands.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => break,
+                }
+            };
+
+            if let Some(command) = command {
+                self.handle(command);
+            }
+            if self.running {
+                self.run_slice();
+            }
+        }
+
+        self.vm
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Run => {
+                if self.vm.get_state() == VmState::HitBreakPoint {
+                    self.vm.release_breakpoint().unwrap();
+                }
+                match self.vm.get_state() {
+                    VmState::Running => self.running = true,
+                    state => self.send(Event::Stopped(state)),
+                }
+            }
+            Command::Input(line) => match self.vm.feed(&line) {
+                Ok(()) => self.running = true,
+                Err(e) => self.send(Event::Error(e.to_string())),
+            },
+            Command::Pause => {
+                if self.running {
+                    self.running = false;
+                    self.deliver_output();
+                    self.send(Event::Paused {
+                        ip: self.vm.get_ip(),
+                        pc: self.vm.get_pc(),
+                    });
+                }
+            }
+            Command::Snapshot => self.send(Event::Snapshot(Box::new(self.vm.clone()))),
+            Command::SetBreakpoint(addr) => self.vm.set_breakpoint(addr),
+            Command::UnsetBreakpoint(addr) => self.vm.unset_breakpoint(addr),
+        }
+    }
+
+    fn run_slice(&mut self) {
+        for _ in 0..SLICE {
+            if self.vm.get_state() != VmState::Running {
+                break;
+            }
+            if let Err(e) = self.vm.step() {
+                self.running = false;
+                self.send(Event::Error(e.to_string()));
+                return;
+            }
+        }
+
+        let state = self.vm.get_state();
+        if state != VmState::Running {
+            self.running = false;
+            self.deliver_output();
+            self.send(Event::Stopped(state));
+        }
+    }
+
+    fn deliver_output(&mut self) {
+        let messages = self.vm.get_messages();
+        let start = self.delivered.min(messages.len());
+        self.delivered = messages.len();
+        for message in messages[start..].iter().filter(|m| !m.is_empty()) {
+            self.send(Event::Output(message.clone()));
+        }
+    }
+
+    fn send(&self, event: Event) {
+        // the handle may be gone, it gets the VM back anyway
+        let _ = self.events.send(event);
+    }
+}
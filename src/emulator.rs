@@ -1,15 +1,21 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    convert::TryFrom,
     fmt,
     fs::File,
     hash::Hash,
     io::Read,
-    path::Path,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use byteorder::{ByteOrder, LittleEndian};
 use serde::{Deserialize, Serialize};
-use serde_with::serde_as;
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::expr::{Expr, Watch};
 
 #[derive(Copy, Clone, Serialize, Deserialize, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub enum Val {
@@ -21,18 +27,31 @@ pub enum Val {
 impl std::str::FromStr for Val {
     type Err = Box<dyn std::error::Error>;
 
+    /// Accepts `Reg(3)`, `r3`, and numbers in decimal or hex (`0x7fff`).
+    /// Numbers in 32768..=32775 are encoded registers, like in memory dumps.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.chars().all(|c| c.is_numeric()) {
-            Ok(Val::Num(s.parse()?))
-        } else {
-            let l_par = s.find('(');
-            dbg!(l_par);
-            let size = s.chars().count();
-            let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
+        let s = s.trim();
+        let lower = s.to_lowercase();
 
-            let reg = inner.parse()?;
+        let reg = match lower.strip_prefix("reg(") {
+            Some(inner) => Some(inner.strip_suffix(')').ok_or("Missing right par")?),
+            None => lower.strip_prefix('r'),
+        };
+        if let Some(reg) = reg {
+            let reg: usize = reg.trim().parse()?;
+            if reg > 7 {
+                return Err(format!("Invalid register {}, must be in 0..=7", reg).into());
+            }
+            return Ok(Val::Reg(reg));
+        }
 
-            Ok(Val::Reg(reg))
+        let v = match lower.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16)?,
+            None => lower.parse()?,
+        };
+        match Val::new(v) {
+            Val::Invalid => Err(format!("Invalid value {}, must be in 0..=32775", v).into()),
+            val => Ok(val),
         }
     }
 }
@@ -53,7 +72,115 @@ impl Val {
             Val::Invalid => 32776,
         }
     }
+
+    /// The literal address, `None` for a register
+    pub fn addr(&self) -> Option<Addr> {
+        match self {
+            Val::Num(x) => Addr::new(*x),
+            _ => None,
+        }
+    }
+}
+
+/// Address of the 15-bit address space, where the code runs
+///
+/// Converting from a number checks the range, instead of a silent `as` cast.
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(try_from = "u16")]
+pub struct Addr(u16);
+
+impl Addr {
+    pub const MAX: Addr = Addr(MEM_SIZE as u16 - 1);
+
+    pub fn new(addr: u16) -> Option<Self> {
+        ((addr as usize) < MEM_SIZE).then_some(Addr(addr))
+    }
+
+    pub fn get(self) -> u16 {
+        self.0
+    }
+
+    /// `self + n` modulo 32768, like the arithmetic of the VM
+    pub fn wrapping_add(self, n: u16) -> Self {
+        Addr(((self.0 as usize + n as usize) % MEM_SIZE) as u16)
+    }
+
+    /// `self - n` modulo 32768
+    pub fn wrapping_sub(self, n: u16) -> Self {
+        Addr(((self.0 as usize + MEM_SIZE - n as usize % MEM_SIZE) % MEM_SIZE) as u16)
+    }
+
+    /// `self + n`, `None` past the end of the address space
+    pub fn checked_add(self, n: usize) -> Option<Self> {
+        Addr::try_from(self.0 as usize + n).ok()
+    }
+}
+
+impl TryFrom<usize> for Addr {
+    type Error = String;
+
+    fn try_from(addr: usize) -> Result<Self, Self::Error> {
+        u16::try_from(addr)
+            .ok()
+            .and_then(Addr::new)
+            .ok_or_else(|| format!("Invalid address {}, must be in 0..={}", addr, Addr::MAX))
+    }
+}
+
+impl TryFrom<u16> for Addr {
+    type Error = String;
+
+    fn try_from(addr: u16) -> Result<Self, Self::Error> {
+        Addr::try_from(addr as usize)
+    }
+}
+
+impl From<Addr> for usize {
+    fn from(addr: Addr) -> Self {
+        addr.0 as usize
+    }
+}
+
+impl From<Addr> for u16 {
+    fn from(addr: Addr) -> Self {
+        addr.0
+    }
+}
+
+impl std::str::FromStr for Addr {
+    type Err = String;
+
+    /// Decimal or hex (`0x1a2b`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let addr = match s.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => s.parse(),
+        }
+        .map_err(|e| format!("{}: {:?}", e, s))?;
+
+        Addr::try_from(addr)
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+/// `{:#}` gives the encoded number, e.g. `32771` for `Reg(3)`
+impl fmt::Display for Val {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{}", self.as_binary())
+        } else {
+            write!(f, "{:?}", self)
+        }
+    }
 }
+
 impl fmt::Debug for Val {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -96,312 +223,221 @@ pub enum Opcode {
     Noop = 1 << 21,
 }
 
+/// Documentation of an instruction, from the arch-spec
+pub struct OpcodeInfo {
+    pub number: u16,
+    pub mnemonic: &'static str,
+    pub operands: &'static [&'static str],
+    pub semantics: &'static str,
+}
+
+/// The instruction set, indexed by opcode number
+pub const OPCODES: [OpcodeInfo; 22] = [
+    OpcodeInfo {
+        number: 0,
+        mnemonic: "Halt",
+        operands: &[],
+        semantics: "stop execution and terminate the program",
+    },
+    OpcodeInfo {
+        number: 1,
+        mnemonic: "Set",
+        operands: &["a", "b"],
+        semantics: "set register <a> to the value of <b>",
+    },
+    OpcodeInfo {
+        number: 2,
+        mnemonic: "Push",
+        operands: &["a"],
+        semantics: "push <a> onto the stack",
+    },
+    OpcodeInfo {
+        number: 3,
+        mnemonic: "Pop",
+        operands: &["a"],
+        semantics:
+            "remove the top element from the stack and write it into <a>; empty stack = error",
+    },
+    OpcodeInfo {
+        number: 4,
+        mnemonic: "Eq",
+        operands: &["a", "b", "c"],
+        semantics: "set <a> to 1 if <b> is equal to <c>; set it to 0 otherwise",
+    },
+    OpcodeInfo {
+        number: 5,
+        mnemonic: "Gt",
+        operands: &["a", "b", "c"],
+        semantics: "set <a> to 1 if <b> is greater than <c>; set it to 0 otherwise",
+    },
+    OpcodeInfo {
+        number: 6,
+        mnemonic: "Jmp",
+        operands: &["a"],
+        semantics: "jump to <a>",
+    },
+    OpcodeInfo {
+        number: 7,
+        mnemonic: "Jt",
+        operands: &["a", "b"],
+        semantics: "if <a> is nonzero, jump to <b>",
+    },
+    OpcodeInfo {
+        number: 8,
+        mnemonic: "Jf",
+        operands: &["a", "b"],
+        semantics: "if <a> is zero, jump to <b>",
+    },
+    OpcodeInfo {
+        number: 9,
+        mnemonic: "Add",
+        operands: &["a", "b", "c"],
+        semantics: "assign into <a> the sum of <b> and <c> (modulo 32768)",
+    },
+    OpcodeInfo {
+        number: 10,
+        mnemonic: "Mult",
+        operands: &["a", "b", "c"],
+        semantics: "store into <a> the product of <b> and <c> (modulo 32768)",
+    },
+    OpcodeInfo {
+        number: 11,
+        mnemonic: "Mod",
+        operands: &["a", "b", "c"],
+        semantics: "store into <a> the remainder of <b> divided by <c>",
+    },
+    OpcodeInfo {
+        number: 12,
+        mnemonic: "And",
+        operands: &["a", "b", "c"],
+        semantics: "stores into <a> the bitwise and of <b> and <c>",
+    },
+    OpcodeInfo {
+        number: 13,
+        mnemonic: "Or",
+        operands: &["a", "b", "c"],
+        semantics: "stores into <a> the bitwise or of <b> and <c>",
+    },
+    OpcodeInfo {
+        number: 14,
+        mnemonic: "Not",
+        operands: &["a", "b"],
+        semantics: "stores 15-bit bitwise inverse of <b> in <a>",
+    },
+    OpcodeInfo {
+        number: 15,
+        mnemonic: "Rmem",
+        operands: &["a", "b"],
+        semantics: "read memory at address <b> and write it to <a>",
+    },
+    OpcodeInfo {
+        number: 16,
+        mnemonic: "Wmem",
+        operands: &["a", "b"],
+        semantics: "write the value from <b> into memory at address <a>",
+    },
+    OpcodeInfo {
+        number: 17,
+        mnemonic: "Call",
+        operands: &["a"],
+        semantics: "write the address of the next instruction to the stack and jump to <a>",
+    },
+    OpcodeInfo {
+        number: 18,
+        mnemonic: "Ret",
+        operands: &[],
+        semantics: "remove the top element from the stack and jump to it; empty stack = halt",
+    },
+    OpcodeInfo {
+        number: 19,
+        mnemonic: "Out",
+        operands: &["a"],
+        semantics: "write the character represented by ascii code <a> to the terminal",
+    },
+    OpcodeInfo {
+        number: 20,
+        mnemonic: "In",
+        operands: &["a"],
+        semantics: "read a character from the terminal and write its ascii code to <a>",
+    },
+    OpcodeInfo {
+        number: 21,
+        mnemonic: "Noop",
+        operands: &[],
+        semantics: "no operation",
+    },
+];
+
+impl OpcodeInfo {
+    /// Find by mnemonic (case insensitive) or by number
+    pub fn find(name: &str) -> Option<&'static OpcodeInfo> {
+        OPCODES.iter().find(|info| {
+            info.mnemonic.eq_ignore_ascii_case(name) || info.number.to_string() == name
+        })
+    }
+
+    /// Cycles of each opcode, 1 unless listed in `weights` by mnemonic or number
+    pub fn cost_table(weights: &BTreeMap<String, u64>) -> Result<[u64; 22], String> {
+        let mut costs = [1; 22];
+        for (name, &weight) in weights {
+            let info =
+                OpcodeInfo::find(name).ok_or_else(|| format!("Unknown opcode {:?}", name))?;
+            costs[info.number as usize] = weight;
+        }
+
+        Ok(costs)
+    }
+}
+
+impl fmt::Display for OpcodeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layout = std::iter::once(self.mnemonic.to_lowercase())
+            .chain(self.operands.iter().map(|o| format!("<{}>", o)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{:>2}: {:<15} {}", self.number, layout, self.semantics)
+    }
+}
+
 impl std::str::FromStr for Opcode {
     type Err = Box<dyn std::error::Error>;
 
+    /// `Mnemonic(a, b, ...)`, the parentheses are optional without operands
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        use Opcode::*;
-
-        let l_par = s.find('(');
-        let size = s.chars().count();
-        let opcode = match s.to_lowercase().split('(').next().unwrap() {
-            "halt" => Halt,
-            "set" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Set(a, b)
-            }
-            "push" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Push(a)
-            }
-            "pop" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Pop(a)
-            }
-            "eq" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let c = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Eq(a, b, c)
-            }
-            "gt" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let c = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Gt(a, b, c)
-            }
-            "jmp" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Jmp(a)
-            }
-            "jt" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Jt(a, b)
-            }
-            "jf" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Jf(a, b)
-            }
-            "add" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let c = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Add(a, b, c)
-            }
-            "mult" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let c = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Mult(a, b, c)
-            }
-            "mod" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let c = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Mod(a, b, c)
-            }
-            "and" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let c = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                And(a, b, c)
-            }
-            "or" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let c = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Or(a, b, c)
-            }
-            "not" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Not(a, b)
-            }
-            "rmem" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Rmem(a, b)
-            }
-            "wmem" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                let b = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Wmem(a, b)
-            }
-            "call" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Call(a)
-            }
-            "ret" => Opcode::Ret,
-            "out" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                Out(a)
-            }
-            "in" => {
-                let inner = &s[1 + l_par.ok_or("Missing left par")?..(size - 1)];
-                let mut split = inner.split(',');
-                let a = split
-                    .next()
-                    .ok_or("missing first operand")?
-                    .trim()
-                    .parse()?;
-                In(a)
+        let s = s.trim();
+        let (name, operands) = match s.find('(') {
+            Some(l_par) => {
+                let inner = s[l_par + 1..]
+                    .strip_suffix(')')
+                    .ok_or("Missing right par")?;
+                (&s[..l_par], inner)
             }
-            "noop" => Opcode::Noop,
-            _ => return Err("Unknown opcode".into()),
+            None => (s, ""),
         };
+        let info = OPCODES
+            .iter()
+            .find(|info| info.mnemonic.eq_ignore_ascii_case(name.trim()))
+            .ok_or("Unknown opcode")?;
+
+        let operands = operands
+            .split(',')
+            .filter(|operand| !operand.trim().is_empty())
+            .map(|operand| operand.parse::<Val>())
+            .collect::<Result<Vec<_>, _>>()?;
+        if operands.len() != info.operands.len() {
+            return Err(format!(
+                "{} takes {} operands, got {}",
+                info.mnemonic,
+                info.operands.len(),
+                operands.len()
+            )
+            .into());
+        }
 
-        Ok(opcode)
+        let words: Vec<u16> = std::iter::once(info.number)
+            .chain(operands.iter().map(Val::as_binary))
+            .collect();
+        Opcode::decode(&words)
     }
 }
 
@@ -410,30 +446,41 @@ impl Opcode {
         unsafe { *(self as *const Self as *const u32) }
     }
 
+    /// Number of the opcode, its index in `OPCODES`
+    pub fn number(&self) -> u16 {
+        self.discriminant().trailing_zeros() as u16
+    }
+
+    pub fn info(&self) -> &'static OpcodeInfo {
+        &OPCODES[self.number() as usize]
+    }
+
     pub fn size(&self) -> usize {
-        match self {
-            Opcode::Halt => 1,
-            Opcode::Set(_, _) => 3,
-            Opcode::Push(_) => 2,
-            Opcode::Pop(_) => 2,
-            Opcode::Eq(_, _, _) => 4,
-            Opcode::Gt(_, _, _) => 4,
-            Opcode::Jmp(_) => 2,
-            Opcode::Jt(_, _) => 3,
-            Opcode::Jf(_, _) => 3,
-            Opcode::Add(_, _, _) => 4,
-            Opcode::Mult(_, _, _) => 4,
-            Opcode::Mod(_, _, _) => 4,
-            Opcode::And(_, _, _) => 4,
-            Opcode::Or(_, _, _) => 4,
-            Opcode::Not(_, _) => 3,
-            Opcode::Rmem(_, _) => 3,
-            Opcode::Wmem(_, _) => 3,
-            Opcode::Call(_) => 2,
-            Opcode::Ret => 1,
-            Opcode::Out(_) => 2,
-            Opcode::In(_) => 2,
-            Opcode::Noop => 1,
+        1 + self.info().operands.len()
+    }
+
+    pub fn operands(&self) -> Vec<Val> {
+        match *self {
+            Opcode::Halt | Opcode::Ret | Opcode::Noop => vec![],
+            Opcode::Push(a)
+            | Opcode::Pop(a)
+            | Opcode::Jmp(a)
+            | Opcode::Call(a)
+            | Opcode::Out(a)
+            | Opcode::In(a) => vec![a],
+            Opcode::Set(a, b)
+            | Opcode::Jt(a, b)
+            | Opcode::Jf(a, b)
+            | Opcode::Not(a, b)
+            | Opcode::Rmem(a, b)
+            | Opcode::Wmem(a, b) => vec![a, b],
+            Opcode::Eq(a, b, c)
+            | Opcode::Gt(a, b, c)
+            | Opcode::Add(a, b, c)
+            | Opcode::Mult(a, b, c)
+            | Opcode::Mod(a, b, c)
+            | Opcode::And(a, b, c)
+            | Opcode::Or(a, b, c) => vec![a, b, c],
         }
     }
 
@@ -465,68 +512,767 @@ impl Opcode {
         }
     }
 
-    pub fn machine_code(&self) -> Vec<u16> {
+    pub fn machine_code(&self) -> Vec<u16> {
+        std::iter::once(self.number())
+            .chain(self.operands().iter().map(Val::as_binary))
+            .collect()
+    }
+
+    /// Decode the instruction at the start of `words`
+    ///
+    /// The only mapping of the opcode numbers to the variants, the rest comes from `OPCODES`.
+    pub fn decode(words: &[u16]) -> Result<Opcode, Box<dyn std::error::Error>> {
+        let instr_type = *words.first().ok_or("Nothing to decode")?;
+        let arg = |idx: usize| -> Result<Val, Box<dyn std::error::Error>> {
+            let word = words
+                .get(idx)
+                .ok_or_else(|| format!("Missing operand {} for opcode {}", idx, instr_type))?;
+            Ok(Val::new(*word))
+        };
+
+        let opcode = match instr_type {
+            0 => Opcode::Halt,
+            1 => Opcode::Set(arg(1)?, arg(2)?),
+            2 => Opcode::Push(arg(1)?),
+            3 => Opcode::Pop(arg(1)?),
+            4 => Opcode::Eq(arg(1)?, arg(2)?, arg(3)?),
+            5 => Opcode::Gt(arg(1)?, arg(2)?, arg(3)?),
+            6 => Opcode::Jmp(arg(1)?),
+            7 => Opcode::Jt(arg(1)?, arg(2)?),
+            8 => Opcode::Jf(arg(1)?, arg(2)?),
+            9 => Opcode::Add(arg(1)?, arg(2)?, arg(3)?),
+            10 => Opcode::Mult(arg(1)?, arg(2)?, arg(3)?),
+            11 => Opcode::Mod(arg(1)?, arg(2)?, arg(3)?),
+            12 => Opcode::And(arg(1)?, arg(2)?, arg(3)?),
+            13 => Opcode::Or(arg(1)?, arg(2)?, arg(3)?),
+            14 => Opcode::Not(arg(1)?, arg(2)?),
+            15 => Opcode::Rmem(arg(1)?, arg(2)?),
+            16 => Opcode::Wmem(arg(1)?, arg(2)?),
+            17 => Opcode::Call(arg(1)?),
+            18 => Opcode::Ret,
+            19 => Opcode::Out(arg(1)?),
+            20 => Opcode::In(arg(1)?),
+            21 => Opcode::Noop,
+            x => return Err(format!("Can't decode opcode {}", x).into()),
+        };
+
+        Ok(opcode)
+    }
+
+    /// Decode the instruction at `address`, rejecting operands that are neither a number nor a
+    /// register
+    pub fn decode_strict(
+        words: &[u16],
+        address: usize,
+    ) -> Result<Opcode, Box<dyn std::error::Error>> {
+        let opcode = Opcode::decode(words)?;
+        for (idx, &word) in words.iter().enumerate().take(opcode.size()).skip(1) {
+            if let Val::Invalid = Val::new(word) {
+                return Err(VmError::InvalidOperand {
+                    address: address + idx,
+                    word,
+                }
+                .into());
+            }
+        }
+
+        Ok(opcode)
+    }
+
+    /// Disassemble `count` instructions of `words`, starting at offset `start`
+    ///
+    /// With `InvalidPolicy::Skip`, undecodable words are considered as data and are not listed.
+    pub fn disassemble(
+        words: &[u16],
+        mut start: usize,
+        mut count: usize,
+        policy: InvalidPolicy,
+    ) -> Result<Vec<(usize, Opcode)>, Box<dyn std::error::Error>> {
+        let mut instructions = Vec::new();
+
+        while count > 0 {
+            let Some(slice) = words.get(start..).filter(|s| !s.is_empty()) else {
+                match policy {
+                    InvalidPolicy::Strict => {
+                        return Err(format!("Offset {} is out of bounds", start).into())
+                    }
+                    InvalidPolicy::Skip => break,
+                }
+            };
+
+            let instr = match (Opcode::decode_strict(slice, start), policy) {
+                (Ok(instr), _) => instr,
+                (Err(e), InvalidPolicy::Strict) => return Err(e),
+                (Err(_), InvalidPolicy::Skip) => {
+                    start += 1;
+                    continue;
+                }
+            };
+            let size = instr.size();
+            instructions.push((start, instr));
+
+            start += size;
+            count -= 1;
+        }
+
+        Ok(instructions)
+    }
+
+    pub fn vec_to_machine_code(v: &[Opcode]) -> Vec<u16> {
+        let mut machine_code = Vec::new();
+
+        for opcode in v {
+            machine_code.extend(&opcode.machine_code());
+        }
+
+        machine_code
+    }
+
+    /// Assemble a source with one instruction or directive per line, `#` starts a comment line:
+    /// - `Set(r0, 10)`: an instruction, see `Opcode::from_str`
+    /// - `.const NEWLINE 10`: a name usable as an operand or a word after its definition
+    /// - `.word 1, 0x20, NEWLINE`: raw words
+    /// - `.string "text\n"`: a word per character, as read by the game's print routines
+    /// - `.print "text\n"`: an `Out` per character
+    pub fn assemble(source: &str) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
+        let mut constants: HashMap<String, u16> = HashMap::new();
+        let mut words = Vec::new();
+
+        for (n, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            Self::assemble_line(line, &mut constants, &mut words)
+                .map_err(|e| format!("line {}: {}", n + 1, e))?;
+        }
+
+        Ok(words)
+    }
+
+    /// One line of `assemble`, `constants` being defined by the previous lines
+    pub fn assemble_line(
+        line: &str,
+        constants: &mut HashMap<String, u16>,
+        words: &mut Vec<u16>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (directive, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match directive {
+            ".const" => {
+                let (name, value) = args
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .ok_or("Expected .const NAME value")?;
+                let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if !is_name || name.parse::<Val>().is_ok() {
+                    return Err(format!("Invalid constant name {:?}", name).into());
+                }
+                let value = parse_word(value, constants)?;
+                constants.insert(name.to_string(), value);
+            }
+            ".word" => {
+                for arg in args.split(',') {
+                    words.push(parse_word(arg, constants)?);
+                }
+            }
+            ".string" => words.extend(parse_string(args)?.chars().map(|c| c as u16)),
+            ".print" => {
+                for c in parse_string(args)?.chars() {
+                    words.extend(Opcode::Out(Val::Num(c as u16)).machine_code());
+                }
+            }
+            _ if directive.starts_with('.') => {
+                return Err(format!("Unknown directive {}", directive).into())
+            }
+            _ => {
+                // the constants are replaced in the operands only
+                let line = match line.find('(') {
+                    Some(l_par) => {
+                        let inner = line[l_par + 1..]
+                            .strip_suffix(')')
+                            .ok_or("Missing right par")?;
+                        let operands = inner
+                            .split(',')
+                            .map(|operand| match constants.get(operand.trim()) {
+                                Some(value) => value.to_string(),
+                                None => operand.to_string(),
+                            })
+                            .collect::<Vec<_>>()
+                            .join(",");
+                        format!("{}({})", &line[..l_par], operands)
+                    }
+                    None => line.to_string(),
+                };
+                words.extend(line.parse::<Opcode>()?.machine_code());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A number in decimal or hex, or a constant
+fn parse_word(
+    s: &str,
+    constants: &HashMap<String, u16>,
+) -> Result<u16, Box<dyn std::error::Error>> {
+    let s = s.trim();
+    if let Some(&value) = constants.get(s) {
+        return Ok(value);
+    }
+    Ok(match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    })
+}
+
+/// `"text"` with the `\n`, `\"` and `\\` escapes
+fn parse_string(s: &str) -> Result<String, String> {
+    let inner = s
+        .trim()
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("Expected a quoted string, got {}", s.trim()))?;
+
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            if !c.is_ascii() {
+                return Err(format!("Not ASCII: {:?}", c));
+            }
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('"') => text.push('"'),
+            Some('\\') => text.push('\\'),
+            other => return Err(format!("Invalid escape \\{}", other.unwrap_or(' '))),
+        }
+    }
+
+    Ok(text)
+}
+
+/// Names of addresses: the labels given by the user, else `fn_<addr>`, and notes on them
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SymbolTable {
+    labels: BTreeMap<Addr, String>,
+    /// Comments of the user, shown after the instructions
+    #[serde(default)]
+    notes: BTreeMap<Addr, String>,
+}
+
+impl SymbolTable {
+    /// Label `addr`, a label names one address at most
+    pub fn set(&mut self, addr: Addr, name: &str) -> Result<(), String> {
+        let valid = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !valid || Self::auto_address(name).is_some() {
+            return Err(format!("Invalid label {:?}", name));
+        }
+
+        self.labels.retain(|_, label| label != name);
+        self.labels.insert(addr, name.to_string());
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, addr: Addr) -> Option<String> {
+        self.labels.remove(&addr)
+    }
+
+    /// Label of `addr`, none out of the address space
+    pub fn label(&self, addr: usize) -> Option<&str> {
+        let addr = Addr::try_from(addr).ok()?;
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    pub fn name(&self, addr: usize) -> String {
+        match self.label(addr) {
+            Some(label) => label.to_string(),
+            None => format!("fn_{}", addr),
+        }
+    }
+
+    /// Address of a label, or of `fn_<addr>`
+    pub fn resolve(&self, name: &str) -> Option<Addr> {
+        Self::auto_address(name).or_else(|| {
+            self.labels
+                .iter()
+                .find(|(_, label)| *label == name)
+                .map(|(&addr, _)| addr)
+        })
+    }
+
+    fn auto_address(name: &str) -> Option<Addr> {
+        name.strip_prefix("fn_")?.parse::<Addr>().ok()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Addr, &str)> {
+        self.labels
+            .iter()
+            .map(|(&addr, label)| (addr, label.as_str()))
+    }
+
+    /// Note on `addr`, replacing the previous one
+    pub fn set_note(&mut self, addr: Addr, text: &str) -> Result<(), String> {
+        let text = text.trim();
+        if text.is_empty() || text.contains('\n') {
+            return Err(format!("Invalid note {:?}", text));
+        }
+        self.notes.insert(addr, text.to_string());
+
+        Ok(())
+    }
+
+    pub fn remove_note(&mut self, addr: Addr) -> Option<String> {
+        self.notes.remove(&addr)
+    }
+
+    /// Note on `addr`, none out of the address space
+    pub fn note(&self, addr: usize) -> Option<&str> {
+        let addr = Addr::try_from(addr).ok()?;
+        self.notes.get(&addr).map(String::as_str)
+    }
+
+    pub fn notes(&self) -> impl Iterator<Item = (Addr, &str)> {
+        self.notes.iter().map(|(&addr, note)| (addr, note.as_str()))
+    }
+
+    /// Add the labels and notes of `other`, keeping the ones of `self` on conflicts
+    ///
+    /// Returns the labels of `other` that were skipped: invalid, or with an address or a name
+    /// labeled differently in `self`.
+    pub fn merge(&mut self, other: &SymbolTable) -> Vec<(Addr, String)> {
+        let mut skipped = Vec::new();
+        for (addr, label) in other.iter() {
+            let conflict = self.label(addr.into()).is_some_and(|l| l != label)
+                || self.resolve(label).is_some_and(|a| a != addr);
+            if conflict || self.set(addr, label).is_err() {
+                skipped.push((addr, label.to_string()));
+            }
+        }
+        for (addr, note) in other.notes() {
+            self.notes.entry(addr).or_insert_with(|| note.to_string());
+        }
+
+        skipped
+    }
+
+    /// `addr: instruction`, after a `label:` line if it's labeled, and with its `comment`
+    pub fn format_instruction(&self, addr: usize, opcode: &Opcode) -> String {
+        let mut line = String::new();
+        if let Some(label) = self.label(addr) {
+            line.push_str(&format!("{}:\n", label));
+        }
+        line.push_str(&format!("{}: {:?}", addr, opcode));
+        if let Some(comment) = self.comment(addr, opcode) {
+            line.push_str(&format!("  # {}", comment));
+        }
+
+        line
+    }
+
+    /// Labels of the targets of jumps and calls, then the note on `addr`
+    pub fn comment(&self, addr: usize, opcode: &Opcode) -> Option<String> {
+        let targets: Vec<&str> = opcode
+            .next_possible_ip()
+            .iter()
+            .filter_map(|target| self.label(target.addr()?.into()))
+            .collect();
+
+        match (targets.is_empty(), self.note(addr)) {
+            (true, None) => None,
+            (true, Some(note)) => Some(note.to_string()),
+            (false, None) => Some(targets.join(", ")),
+            (false, Some(note)) => Some(format!("{}; {}", targets.join(", "), note)),
+        }
+    }
+}
+
+/// Entry of the shadow call stack, maintained by `Call` and `Ret`
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Frame {
+    /// Address of the `Call` instruction
+    pub call_site: Addr,
+    /// Called function
+    pub target: Addr,
+    /// Length of the stack once the return address is pushed
+    pub depth: usize,
+}
+
+/// Executions counted between `Vm::start_profile` and `Vm::stop_profile`
+#[derive(Clone, Debug)]
+pub struct Profile {
+    /// Executions of each address
+    pub addresses: Vec<u64>,
+    /// Executions of each opcode, indexed by opcode number
+    pub opcodes: [u64; 22],
+}
+
+impl Profile {
+    pub fn total(&self) -> u64 {
+        self.opcodes.iter().sum()
+    }
+
+    /// The `n` most executed addresses, with their executions
+    pub fn hottest(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut hottest: Vec<(usize, u64)> = self
+            .addresses
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        hottest.sort_by_key(|&(addr, count)| (std::cmp::Reverse(count), addr));
+        hottest.truncate(n);
+
+        hottest
+    }
+}
+
+/// Instruction executed while its opcode was traced, see `Vm::set_traced_opcodes`
+#[derive(Clone, Debug)]
+pub struct TraceEntry {
+    pub pc: usize,
+    pub ip: Addr,
+    pub opcode: Opcode,
+    /// Registers before executing the instruction, to resolve its operands
+    pub registers: [u16; 8],
+}
+
+impl TraceEntry {
+    pub fn value(&self, value: &Val) -> Option<u16> {
+        match value {
+            Val::Num(x) => Some(*x),
+            Val::Reg(r) => Some(self.registers[*r]),
+            Val::Invalid => None,
+        }
+    }
+}
+
+/// Call of the string printing routine, recorded while its strings are traced, see
+/// `Vm::set_string_routine`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PrintCall {
+    /// Address of the `Call`
+    pub call_site: Addr,
+    pub target: usize,
+    /// Registers when called, `r0` is the address of the string
+    pub registers: [u16; 8],
+    /// Words of the length-prefixed string at `r0`, without the length
+    pub words: Vec<u16>,
+    /// Output of the call, callees included
+    pub text: String,
+}
+
+/// What a step changed, to undo it with `Vm::step_back`
+///
+/// A step pushes or pops at most one value and one frame, and writes at most one word.
+#[derive(Clone, Debug)]
+struct JournalEntry {
+    ip: Addr,
+    pc: usize,
+    cycles: u64,
+    state: VmState,
+    bank: usize,
+    registers: [u16; 8],
+    stack_len: usize,
+    stack_top: Option<u16>,
+    call_stack_len: usize,
+    last_frame: Option<Frame>,
+    /// Address and previous value of the word written by `Wmem`
+    memory: Option<(usize, u16)>,
+    output_len: usize,
+    messages_len: usize,
+    /// Length and first char of the input buffer, or of the raw input in raw mode
+    input_len: usize,
+    next_input: Option<char>,
+    mid_line: bool,
+}
+
+/// Bounded history of the last steps, see `Vm::set_journal`
+#[derive(Clone, Debug)]
+pub struct Journal {
+    entries: VecDeque<JournalEntry>,
+    capacity: usize,
+}
+
+impl Journal {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Snapshots of the VM taken during execution, to go back to any instruction count
+#[derive(Clone, Debug)]
+pub struct TimeTravel {
+    /// Instructions between snapshots
+    interval: usize,
+    /// Sorted by `pc`
+    snapshots: Vec<Vm>,
+    /// Lines fed to the VM, with the `pc` at that time
+    inputs: Vec<(usize, String)>,
+}
+
+/// Memory cells suspected to make runs vary
+///
+/// Reads of the watched cells by `Rmem` are logged, pinned cells are written before every step.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Entropy {
+    pub watched: BTreeSet<usize>,
+    /// Address -> (ip, value) of each read
+    #[serde(skip)]
+    pub reads: BTreeMap<usize, Vec<(usize, u16)>>,
+    pub pins: BTreeMap<usize, u16>,
+}
+
+/// Words per page, for the tracking of the writes
+pub const PAGE_SIZE: usize = 256;
+
+/// Writes per page since a baseline of the memory, see `Vm::clean_pages`
+///
+/// The pages without writes are the ones of the baseline, so the states sharing a baseline are
+/// compared and restored page by page.
+#[derive(Clone, Debug, Default)]
+struct PageTracker {
+    /// Shared by the clones and the checkpoints, `None` for a deserialized `Vm`
+    baseline: Option<Arc<Vec<u16>>>,
+    writes: Vec<u32>,
+}
+
+impl PageTracker {
+    fn new(memory: &[u16]) -> Self {
+        Self {
+            baseline: Some(Arc::new(memory.to_vec())),
+            writes: vec![0; memory.len().div_ceil(PAGE_SIZE)],
+        }
+    }
+
+    fn write(&mut self, addr: usize) {
+        if let Some(writes) = self.writes.get_mut(addr / PAGE_SIZE) {
+            *writes = writes.saturating_add(1);
+        }
+    }
+
+    fn dirty(&self) -> impl Iterator<Item = usize> + '_ {
+        self.writes
+            .iter()
+            .enumerate()
+            .filter(|(_, &writes)| writes > 0)
+            .map(|(page, _)| page)
+    }
+
+    /// Pages that can differ between the two memories, `None` without a common baseline
+    fn differing(&self, other: &PageTracker) -> Option<BTreeSet<usize>> {
+        match (&self.baseline, &other.baseline) {
+            (Some(a), Some(b)) if Arc::ptr_eq(a, b) => {
+                Some(self.dirty().chain(other.dirty()).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+fn page_range(page: usize, len: usize) -> std::ops::Range<usize> {
+    page * PAGE_SIZE..((page + 1) * PAGE_SIZE).min(len)
+}
+
+/// Memory of a checkpoint
+#[derive(Clone, Debug)]
+enum CheckpointMemory {
+    Full(Vec<u16>),
+    /// The dirty pages, the others are in the baseline
+    Pages(BTreeMap<usize, Vec<u16>>),
+}
+
+/// Execution state of a `Vm`, to branch from it without cloning everything
+///
+/// Only the messages count is kept: restore a checkpoint on the `Vm` it was taken from, or on a
+/// `Vm` with the same message history. Only the written pages of the memory are copied.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    memory: CheckpointMemory,
+    pages: PageTracker,
+    bank: usize,
+    registers: [u16; 8],
+    stack: Vec<u16>,
+    call_stack: Vec<Frame>,
+    ip: Addr,
+    pc: usize,
+    cycles: u64,
+    state: VmState,
+    output_buffer: Vec<char>,
+    input_buffer: VecDeque<char>,
+    mid_line: bool,
+    messages_len: usize,
+    /// The patch journal matches the memory
+    patches: Vec<AppliedPatch>,
+}
+
+/// Meaning of a value of the stack
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StackSlot {
+    /// Pushed by the `Call` of the frame
+    ReturnAddress(Frame),
+    /// Pushed by the prologue of the callee, and popped back before returning
+    SavedRegister(usize),
+    Local,
+}
+
+/// Calling convention of a function, from its instructions and the ones of its callees
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RegisterUsage {
+    /// Pushed by the prologue and popped back: preserved for the caller
+    pub saved: Vec<usize>,
+    /// Written by the function or its callees, and not saved
+    pub clobbered: BTreeSet<usize>,
+    /// `Push` that are not part of the prologue
+    pub temporaries: usize,
+    /// Calls to a register, their clobbered registers are unknown
+    pub indirect_calls: usize,
+}
+
+impl fmt::Display for RegisterUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let regs = |r: &mut dyn Iterator<Item = &usize>| {
+            let regs: Vec<String> = r.map(|r| format!("r{}", r)).collect();
+            match regs.is_empty() {
+                true => "none".to_string(),
+                false => regs.join(", "),
+            }
+        };
+        writeln!(f, "# preserves: {}", regs(&mut self.saved.iter()))?;
+        writeln!(f, "# clobbers: {}", regs(&mut self.clobbered.iter()))?;
+        write!(f, "# stack temporaries: {}", self.temporaries)?;
+        if self.indirect_calls > 0 {
+            write!(
+                f,
+                "\n# indirect calls: {}, may clobber more",
+                self.indirect_calls
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What to do with words that can't be decoded, e.g. operands in 32776..=65535
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InvalidPolicy {
+    /// Fail with the faulting address, this is how the VM runs
+    Strict,
+    /// Treat the word as data and continue with the next one, for bulk disassembly
+    Skip,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum VmError {
+    /// Operand that is neither a number nor a register
+    InvalidOperand { address: usize, word: u16 },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Opcode::Halt => vec![0],
-            Opcode::Set(a, b) => vec![1, a.as_binary(), b.as_binary()],
-            Opcode::Push(_) => todo!(),
-            Opcode::Pop(_) => todo!(),
-            Opcode::Eq(a, b, c) => vec![4, a.as_binary(), b.as_binary(), c.as_binary()],
-            Opcode::Gt(_, _, _) => todo!(),
-            Opcode::Jmp(a) => vec![6, a.as_binary()],
-            Opcode::Jt(a, b) => vec![7, a.as_binary(), b.as_binary()],
-            Opcode::Jf(a, b) => vec![8, a.as_binary(), b.as_binary()],
-            Opcode::Add(a, b, c) => vec![9, a.as_binary(), b.as_binary(), c.as_binary()],
-            Opcode::Mult(_, _, _) => todo!(),
-            Opcode::Mod(_, _, _) => todo!(),
-            Opcode::And(_, _, _) => todo!(),
-            Opcode::Or(_, _, _) => todo!(),
-            Opcode::Not(_, _) => todo!(),
-            Opcode::Rmem(_, _) => todo!(),
-            Opcode::Wmem(_, _) => todo!(),
-            Opcode::Call(a) => vec![17, a.as_binary()],
-            Opcode::Ret => vec![18],
-            Opcode::Out(_) => todo!(),
-            Opcode::In(_) => todo!(),
-            Opcode::Noop => vec![21],
+            VmError::InvalidOperand { address, word } => {
+                write!(f, "Invalid operand {} at {}", word, address)
+            }
         }
     }
+}
 
-    pub fn vec_to_machine_code(v: &[Opcode]) -> Vec<u16> {
-        let mut machine_code = Vec::new();
+impl std::error::Error for VmError {}
 
-        for opcode in v {
-            machine_code.extend(&opcode.machine_code());
-        }
+/// Size of the address space, and of each bank in extended mode
+pub const MEM_SIZE: usize = 32768;
+/// In extended mode, `wmem 32767 <n>` selects the data bank `n`, and `rmem <a> 32767` reads the
+/// selected bank
+pub const BANK_SELECT: u16 = 32767;
 
-        machine_code
-    }
+/// A patch applied with `Vm::patch` or `Vm::patch_words`, to undo it
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AppliedPatch {
+    pub offset: usize,
+    pub old: Vec<u16>,
+    pub new: Vec<u16>,
 }
 
-const MEM_SIZE: usize = 32768;
-
 #[serde_as]
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Vm {
     //#[serde_as(as = "[_; MEM_SIZE]")]
+    /// `MEM_SIZE` words per bank, code always runs from bank 0
     memory: Vec<u16>,
+    /// Data bank used by `Rmem` and `Wmem`, in extended mode
+    #[serde(default)]
+    bank: usize,
     registers: [u16; 8],
     stack: Vec<u16>,
     /// Instruction Pointer (next instruction)
-    ip: usize,
+    ip: Addr,
     /// Program Counter
     pc: usize,
+    /// Sum of the costs of the executed instructions, while a cost model is set
+    #[serde(default)]
+    cycles: u64,
+    /// Cost of each opcode, by number
+    #[serde(skip)]
+    costs: Option<[u64; 22]>,
 
     state: VmState,
 
     output_buffer: Vec<char>,
     input_buffer: VecDeque<char>,
+    /// The last `In` read a character of a line, not its newline
+    #[serde(default)]
+    mid_line: bool,
 
+    /// Message `n` is the output before the prompt `n`, the last one can be the output before
+    /// halting
     messages: Vec<String>,
 
     traced_opcodes: u32,
     #[serde(skip)]
-    trace_buffer: Vec<(usize, Opcode)>,
+    trace_buffer: Vec<TraceEntry>,
+    /// Printing routine whose calls are recorded
+    #[serde(skip)]
+    string_routine: Option<usize>,
+    #[serde(skip)]
+    print_calls: Vec<PrintCall>,
+    /// Stack length inside the recorded call being run, the nested calls are part of it
+    #[serde(skip)]
+    print_depth: Option<usize>,
+
+    /// Frames of the calls, innermost last
+    #[serde(default)]
+    call_stack: Vec<Frame>,
+
+    #[serde(skip)]
+    timetravel: Option<Box<TimeTravel>>,
+
+    #[serde(default)]
+    entropy: Entropy,
+
+    /// Execution count of each address, when enabled
+    #[serde(skip)]
+    coverage: Option<Vec<u32>>,
+    /// Counts of the last profile, updated while `profiling`
+    #[serde(skip)]
+    profile: Option<Profile>,
+    #[serde(skip)]
+    profiling: bool,
 
     #[serde(skip)]
     called_patched_fn: bool,
@@ -534,21 +1280,81 @@ pub struct Vm {
     fn_patching: bool,
 
     #[serde(skip)]
-    breakpoints: Vec<usize>,
+    breakpoints: Vec<Breakpoint>,
+    #[serde(skip)]
+    watches: Vec<Watch>,
+    /// Ranges `Wmem` must not write, see `protect`
+    #[serde(skip)]
+    protected: Vec<Range<usize>>,
+    /// Why the last step stopped, if it was a protected write
+    #[serde(skip)]
+    fault: Option<ProtectionFault>,
+    /// Execute the next instruction even if it has a breakpoint, see `resume`
+    #[serde(skip)]
+    skip_breakpoint: bool,
 
     #[serde(skip)]
     __6027_cache: HashMap<(u16, u16, u16), (u16, u16)>,
+    #[serde(skip)]
+    native_impl: NativeImpl,
+    #[serde(skip)]
+    hook_violations: Vec<HookViolation>,
+    /// Calls of patched code: index of the frame, patched function, stack before it runs
+    #[serde(skip)]
+    patched_frames: Vec<(usize, usize, Vec<u16>)>,
 
     #[serde(skip)]
     scanmem: Vec<Option<u16>>,
+
+    /// Byte I/O instead of the messages, when enabled
+    #[serde(skip)]
+    raw_io: Option<RawIo>,
+
+    /// Undo history of the last steps, when enabled
+    #[serde(skip)]
+    journal: Option<Journal>,
+
+    /// Labels of the addresses, kept in the snapshots
+    #[serde(default)]
+    symbols: SymbolTable,
+
+    /// Addresses of the analyzed functions, see `analysis update`
+    #[serde(skip)]
+    tracked_code: BTreeSet<usize>,
+    /// Tracked addresses modified since the last `take_modified_code`
+    #[serde(skip)]
+    modified_code: BTreeSet<usize>,
+
+    /// Pages written since the load, or since `clean_pages`
+    #[serde(skip)]
+    pages: PageTracker,
+
+    /// Patches applied since the load, kept in the snapshots
+    #[serde(default)]
+    patches: Vec<AppliedPatch>,
 }
 
 impl PartialEq for Vm {
     fn eq(&self, other: &Self) -> bool {
-        for (x, y) in self.memory.iter().zip(other.memory.iter()) {
-            if x != y {
-                return false;
-            }
+        if self.memory.len() != other.memory.len() || self.bank != other.bank {
+            return false;
+        }
+        if self.state != other.state {
+            return false;
+        }
+        let same_memory = match self.pages.differing(&other.pages) {
+            Some(pages) => pages.into_iter().all(|page| {
+                let range = page_range(page, self.memory.len());
+                self.memory[range.clone()] == other.memory[range]
+            }),
+            None => self
+                .memory
+                .iter()
+                .zip(other.memory.iter())
+                .all(|(x, y)| x == y),
+        };
+        if !same_memory {
+            return false;
         }
         if self.registers != other.registers {
             return false;
@@ -562,6 +1368,9 @@ impl PartialEq for Vm {
         if self.input_buffer != other.input_buffer {
             return false;
         }
+        if self.stack != other.stack || self.call_stack != other.call_stack {
+            return false;
+        }
 
         true
     }
@@ -581,6 +1390,146 @@ impl fmt::Debug for Vm {
     }
 }
 
+/// Extra reasons for `Vm::run_until` to stop, like a breakpoint
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StopCondition {
+    /// The depth of the call stack compares to `n` like this
+    StopWhenStackDepth(Ordering, usize),
+    /// The next instruction calls this address
+    StopOnCallTo(Addr),
+    /// At least this many cycles were spent, see `Vm::set_costs`
+    StopAtCycles(u64),
+}
+
+/// `3` for a depth of exactly 3, `<3` or `>3`
+impl std::str::FromStr for StopCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (cmp, n) = match s.as_bytes().first() {
+            Some(b'<') => (Ordering::Less, &s[1..]),
+            Some(b'>') => (Ordering::Greater, &s[1..]),
+            _ => (Ordering::Equal, s),
+        };
+        let n = n.parse().map_err(|_| format!("Invalid depth {:?}", s))?;
+
+        Ok(StopCondition::StopWhenStackDepth(cmp, n))
+    }
+}
+
+impl fmt::Display for StopCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopCondition::StopWhenStackDepth(cmp, n) => {
+                let cmp = match cmp {
+                    Ordering::Less => "<",
+                    Ordering::Equal => "=",
+                    Ordering::Greater => ">",
+                };
+                write!(f, "call stack depth {} {}", cmp, n)
+            }
+            StopCondition::StopOnCallTo(addr) => write!(f, "call to {}", addr),
+            StopCondition::StopAtCycles(n) => write!(f, "{} cycles", n),
+        }
+    }
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// `Wmem` into a protected range, stopped before writing
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ProtectionFault {
+    /// Address of the writing instruction
+    pub ip: Addr,
+    pub instruction: Opcode,
+    pub addr: usize,
+    pub value: u16,
+}
+
+/// Stop before executing `address`, saved by `bp export`
+#[serde_as]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Breakpoint {
+    pub address: Addr,
+    /// Only stop when it's true
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub condition: Option<Expr>,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl Breakpoint {
+    pub fn new(address: Addr) -> Self {
+        Breakpoint {
+            address,
+            condition: None,
+            enabled: true,
+            description: String::new(),
+        }
+    }
+
+    fn is_hit(&self, vm: &Vm) -> bool {
+        self.enabled
+            && self.address == vm.ip
+            && self.condition.as_ref().is_none_or(|c| c.is_true(vm))
+    }
+}
+
+/// Byte I/O without line buffering, for the programs that aren't text adventures
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RawIo {
+    pub input: VecDeque<u8>,
+    /// No more input will come: `In` halts instead of waiting
+    pub eof: bool,
+    pub output: Vec<u8>,
+}
+
+/// A native hook or a patched function left the stack different from its `Call`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HookViolation {
+    /// Address of the hooked or patched function
+    pub hook: usize,
+    pub pc: usize,
+    /// Stack with the return address pushed, before the hook ran
+    pub before: Vec<u16>,
+    pub after: Vec<u16>,
+}
+
+impl fmt::Display for HookViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let common = self
+            .before
+            .iter()
+            .zip(&self.after)
+            .take_while(|(a, b)| a == b)
+            .count();
+        write!(
+            f,
+            "hook {} at pc {} changed the stack from index {}: {} -> {} values",
+            self.hook,
+            self.pc,
+            common,
+            self.before.len(),
+            self.after.len()
+        )
+    }
+}
+
+/// How the patched function 6027 is computed
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum NativeImpl {
+    /// The recursion of the game, with a cache
+    #[default]
+    Memoized,
+    /// Rows of the function computed iteratively, for all the `r1` at once
+    Table,
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum VmState {
     Running,
@@ -588,11 +1537,16 @@ pub enum VmState {
     WaitingForInput,
     HitBreakPoint,
 }
+/// Environment variable with the path of the program, see `Vm::find_binary`
+pub const BINARY_ENV: &str = "SYNACOR_BIN";
+
 impl Default for Vm {
+    /// The VM with the program found by `Vm::find_binary`, panics without one
     fn default() -> Self {
         let mut vm = Vm::new();
-        vm.load_program_from_file("challenge.bin")
-            .expect("Unable to load default 'challenge.bin'");
+        let path = Vm::find_binary(None, None).unwrap_or_else(|e| panic!("{}", e));
+        vm.load_program_from_file(&path)
+            .unwrap_or_else(|e| panic!("Unable to load {}: {}", path.display(), e));
 
         vm
     }
@@ -602,63 +1556,215 @@ impl Vm {
     pub fn new() -> Self {
         Vm {
             memory: vec![0u16; MEM_SIZE],
+            bank: 0,
             registers: [0u16; 8],
             stack: Vec::new(),
-            ip: 0,
+            ip: Addr::default(),
             pc: 0,
+            cycles: 0,
+            costs: None,
 
             state: VmState::Running,
 
             output_buffer: Vec::new(),
             input_buffer: VecDeque::new(),
+            mid_line: false,
 
             messages: Vec::new(),
 
             traced_opcodes: 0,
             trace_buffer: Vec::new(),
+            string_routine: None,
+            print_calls: Vec::new(),
+            print_depth: None,
+
+            call_stack: Vec::new(),
+
+            timetravel: None,
+
+            entropy: Entropy::default(),
+
+            coverage: None,
+            profile: None,
+            profiling: false,
 
             fn_patching: false,
             called_patched_fn: false,
 
             breakpoints: Vec::new(),
+            watches: Vec::new(),
+            protected: Vec::new(),
+            fault: None,
+            skip_breakpoint: false,
 
             __6027_cache: HashMap::new(),
+            native_impl: NativeImpl::default(),
+            hook_violations: Vec::new(),
+            patched_frames: Vec::new(),
 
             scanmem: vec![None; MEM_SIZE],
+
+            raw_io: None,
+
+            journal: None,
+
+            symbols: SymbolTable::default(),
+
+            tracked_code: BTreeSet::new(),
+            modified_code: BTreeSet::new(),
+
+            pages: PageTracker::new(&[0; MEM_SIZE]),
+            patches: Vec::new(),
         }
     }
 
+    /// Extended mode: `banks` banks of data memory, selected with `BANK_SELECT`
+    pub fn with_banks(banks: usize) -> Self {
+        let mut vm = Vm::new();
+        vm.set_banks(banks);
+        vm
+    }
+
+    pub fn banks(&self) -> usize {
+        self.memory.len() / MEM_SIZE
+    }
+
+    /// Resize the memory, keeping the content of the remaining banks
+    pub fn set_banks(&mut self, banks: usize) {
+        let banks = banks.max(1);
+        self.memory.resize(banks * MEM_SIZE, 0);
+        self.scanmem = vec![None; self.memory.len()];
+        self.bank = self.bank.min(banks - 1);
+        self.clean_pages();
+    }
+
+    pub fn get_bank(&self) -> usize {
+        self.bank
+    }
+
+    /// Address of `addr` in the selected data bank
+    fn data_address(&self, addr: u16) -> usize {
+        self.bank * MEM_SIZE + addr as usize
+    }
+
     pub fn load_program_from_file<P: AsRef<Path>>(
         &mut self,
         path: P,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        let data = Self::read_binary(path)?;
+
+        if data.len() > self.memory.len() {
+            return Err(format!(
+                "The program is too big: {} words, the memory holds {}",
+                data.len(),
+                self.memory.len()
+            )
+            .into());
+        }
+        self.memory[..data.len()].copy_from_slice(&data[..]);
+        self.patches.clear();
+        self.clean_pages();
+
+        Ok(())
+    }
+
+    /// Locate the program: `path` if given, else `configured` (the `binary` of config.json),
+    /// else `$SYNACOR_BIN`, else the first `challenge.bin` in the working directory, next to
+    /// the executable, or in `~/.local/share/synacor-challenge/`
+    ///
+    /// The error explains where to put it.
+    pub fn find_binary(path: Option<&Path>, configured: Option<&Path>) -> Result<PathBuf, String> {
+        let chosen = path
+            .map(Path::to_path_buf)
+            .or_else(|| configured.map(Path::to_path_buf))
+            .or_else(|| std::env::var_os(BINARY_ENV).map(PathBuf::from));
+        if let Some(chosen) = chosen {
+            return match chosen.is_file() {
+                true => Ok(chosen),
+                false => Err(format!("Program {} not found", chosen.display())),
+            };
+        }
+
+        let mut candidates = vec![PathBuf::from("challenge.bin")];
+        if let Some(dir) = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        {
+            candidates.push(dir.join("challenge.bin"));
+        }
+        if let Some(home) = std::env::var_os("HOME") {
+            candidates
+                .push(PathBuf::from(home).join(".local/share/synacor-challenge/challenge.bin"));
+        }
+        if let Some(found) = candidates.iter().find(|candidate| candidate.is_file()) {
+            return Ok(found.clone());
+        }
+
+        let tried: Vec<String> = candidates
+            .iter()
+            .map(|candidate| format!("  {}", candidate.display()))
+            .collect();
+        Err(format!(
+            "challenge.bin not found, tried:\n{}\n\
+            Get it with the challenge, then give its path with `--binary <path>`, \
+            `\"binary\"` in config.json or ${}, or `load <path>` in the REPL",
+            tried.join("\n"),
+            BINARY_ENV
+        ))
+    }
+
+    /// Read a little endian binary, without loading it
+    pub fn read_binary<P: AsRef<Path>>(path: P) -> Result<Vec<u16>, Box<dyn std::error::Error>> {
         let mut f = File::open(path)?;
         let mut buff = Vec::new();
-
         f.read_to_end(&mut buff)?;
 
-        let data: Vec<_> = buff.chunks(2).map(LittleEndian::read_u16).collect();
+        Ok(buff.chunks(2).map(LittleEndian::read_u16).collect())
+    }
 
-        if data.len() > MEM_SIZE {
-            panic!("File is too big");
-        }
-        self.memory[..data.len()].copy_from_slice(&data[..]);
+    /// Write the memory as a little endian binary, like `challenge.bin`
+    pub fn dump_binary<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let mut buff = vec![0u8; self.memory.len() * 2];
+        LittleEndian::write_u16_into(&self.memory, &mut buff);
+        std::fs::write(path, buff)?;
 
         Ok(())
     }
 
     pub fn load_program_from_mem(&mut self, program: &[u16]) {
         self.memory[..program.len()].copy_from_slice(program);
+        self.patches.clear();
+        self.clean_pages();
+    }
+
+    /// Move the memory out, leaving it empty until `set_memory`
+    pub fn take_memory(&mut self) -> Vec<u16> {
+        std::mem::take(&mut self.memory)
+    }
+
+    /// Replace the whole memory, banks included, keeping the patches
+    pub fn set_memory(&mut self, memory: Vec<u16>) {
+        self.memory = memory;
+        self.scanmem = vec![None; self.memory.len()];
+        self.clean_pages();
     }
 
     pub fn get_messages(&self) -> &[String] {
         &self.messages
     }
 
+    pub fn get_memory(&self) -> &[u16] {
+        &self.memory
+    }
+
     pub fn get_state(&self) -> VmState {
         self.state
     }
 
+    pub fn get_registers(&self) -> &[u16; 8] {
+        &self.registers
+    }
+
     pub fn set_register(&mut self, reg: usize, value: u16) {
         self.registers[reg] = value;
     }
@@ -667,30 +1773,303 @@ impl Vm {
         self.traced_opcodes = traced;
     }
 
-    pub fn get_trace_buffer(&self) -> &[(usize, Opcode)] {
+    pub fn get_trace_buffer(&self) -> &[TraceEntry] {
         &self.trace_buffer
     }
 
+    pub fn clear_trace_buffer(&mut self) {
+        self.trace_buffer.clear();
+        self.print_calls.clear();
+        self.print_depth = None;
+    }
+
+    /// Record the calls of the string printing `routine`, with the string at `r0` and the
+    /// output, see `report::find_print_routine`
+    pub fn set_string_routine(&mut self, routine: Option<usize>) {
+        self.string_routine = routine;
+        self.print_depth = None;
+    }
+
+    pub fn get_string_routine(&self) -> Option<usize> {
+        self.string_routine
+    }
+
+    pub fn get_print_calls(&self) -> &[PrintCall] {
+        &self.print_calls
+    }
+
     pub fn set_patching(&mut self, val: bool) {
         self.fn_patching = val;
     }
 
-    pub fn get_breakpoints(&self) -> &[usize] {
+    /// Hooks that didn't leave the stack as they found it
+    pub fn get_hook_violations(&self) -> &[HookViolation] {
+        &self.hook_violations
+    }
+
+    /// Record a violation if patched code returns with another stack than it was called with
+    fn check_patched_ret(&mut self) {
+        match self.patched_frames.last() {
+            // a call made by the patched function returns
+            Some((frame, _, _)) if frame + 1 < self.call_stack.len() => (),
+            Some(_) => {
+                let (_, hook, before) = self.patched_frames.pop().unwrap();
+                self.check_hook(hook, before);
+            }
+            None => (),
+        }
+    }
+
+    /// Record a violation if a hook changed the stack
+    fn check_hook(&mut self, hook: usize, before: Vec<u16>) {
+        if self.stack != before {
+            let violation = HookViolation {
+                hook,
+                pc: self.pc,
+                before,
+                after: self.stack.clone(),
+            };
+            self.hook_violations.push(violation);
+        }
+    }
+
+    /// `In` reads bytes fed by `feed_bytes`, and `Out` writes bytes for `take_raw_output`,
+    /// instead of the lines and messages of the game
+    pub fn set_raw_io(&mut self, enabled: bool) {
+        self.raw_io = enabled.then(RawIo::default);
+    }
+
+    /// Add raw input, `eof` when there's no more
+    pub fn feed_bytes(&mut self, bytes: &[u8], eof: bool) -> Result<(), String> {
+        let raw = self.raw_io.as_mut().ok_or("Raw I/O is disabled")?;
+        raw.input.extend(bytes);
+        raw.eof |= eof;
+        if self.state == VmState::WaitingForInput {
+            self.state = VmState::Running;
+        }
+        Ok(())
+    }
+
+    /// Characters fed to the game and not read yet
+    pub fn get_input_buffer(&self) -> &VecDeque<char> {
+        &self.input_buffer
+    }
+
+    /// Output since the last prompt
+    pub fn get_output_buffer(&self) -> &[char] {
+        &self.output_buffer
+    }
+
+    pub fn get_raw_io(&self) -> Option<&RawIo> {
+        self.raw_io.as_ref()
+    }
+
+    /// Inconsistencies of the input path, that leave a session stuck
+    pub fn io_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.state == VmState::WaitingForInput && !self.input_buffer.is_empty() {
+            problems.push(format!(
+                "waiting for input, but {} characters are buffered",
+                self.input_buffer.len()
+            ));
+        }
+        if self.input_buffer.back().is_some_and(|&c| c != '\n') {
+            problems.push(
+                "the buffered input doesn't end with a newline, the game will wait in the \
+                 middle of a line"
+                    .to_string(),
+            );
+        }
+        if self.mid_line && self.input_buffer.is_empty() && self.state != VmState::Halted {
+            problems.push(
+                "the input ran out in the middle of a line, the read loop will take the next \
+                 input as its end"
+                    .to_string(),
+            );
+        }
+
+        problems
+    }
+
+    pub fn take_raw_output(&mut self) -> Vec<u8> {
+        self.raw_io
+            .as_mut()
+            .map(|raw| std::mem::take(&mut raw.output))
+            .unwrap_or_default()
+    }
+
+    /// Record the writes to these addresses, by `Wmem`, `patch` and `mem_set`
+    pub fn set_tracked_code(&mut self, addresses: BTreeSet<usize>) {
+        self.modified_code.retain(|addr| addresses.contains(addr));
+        self.tracked_code = addresses;
+    }
+
+    pub fn get_tracked_code(&self) -> &BTreeSet<usize> {
+        &self.tracked_code
+    }
+
+    /// Tracked addresses modified since the last call
+    pub fn take_modified_code(&mut self) -> BTreeSet<usize> {
+        std::mem::take(&mut self.modified_code)
+    }
+
+    fn track_write(&mut self, addr: usize) {
+        self.pages.write(addr);
+        if self.tracked_code.contains(&addr) {
+            self.modified_code.insert(addr);
+        }
+    }
+
+    /// Count the writes from the current memory, like after a load
+    pub fn clean_pages(&mut self) {
+        self.pages = PageTracker::new(&self.memory);
+    }
+
+    /// Pages written since the load or `clean_pages`, with their writes
+    ///
+    /// `None` if unknown, for a deserialized `Vm` before `clean_pages`.
+    pub fn dirty_pages(&self) -> Option<Vec<(usize, u32)>> {
+        self.pages.baseline.as_ref()?;
+        Some(
+            self.pages
+                .dirty()
+                .map(|page| (page, self.pages.writes[page]))
+                .collect(),
+        )
+    }
+
+    /// Pages where the memories can differ, `None` if they're not from a common state
+    ///
+    /// The clones of a `Vm` share its baseline, until one of them is loaded or cleaned.
+    pub fn differing_pages(&self, other: &Vm) -> Option<BTreeSet<usize>> {
+        self.pages.differing(&other.pages)
+    }
+
+    pub fn set_native_impl(&mut self, native_impl: NativeImpl) {
+        self.native_impl = native_impl;
+    }
+
+    pub fn get_symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn get_symbols_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
+
+    pub fn get_breakpoints(&self) -> &[Breakpoint] {
         &self.breakpoints
     }
 
-    pub fn set_breakpoint(&mut self, offset: usize) {
-        if !self.breakpoints.contains(&offset) {
-            self.breakpoints.push(offset);
+    pub fn set_breakpoint(&mut self, offset: Addr) {
+        if !self.breakpoints.iter().any(|bp| bp.address == offset) {
+            self.breakpoints.push(Breakpoint::new(offset));
+        }
+    }
+
+    /// Add `breakpoint`, replacing the one at the same address
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        match self
+            .breakpoints
+            .iter_mut()
+            .find(|bp| bp.address == breakpoint.address)
+        {
+            Some(bp) => *bp = breakpoint,
+            None => self.breakpoints.push(breakpoint),
         }
     }
 
-    pub fn unset_breakpoint(&mut self, offset: usize) {
-        self.breakpoints.retain(|bp| *bp != offset);
+    pub fn unset_breakpoint(&mut self, offset: Addr) {
+        self.breakpoints.retain(|bp| bp.address != offset);
+    }
+
+    pub fn enable_breakpoint(&mut self, offset: Addr, enabled: bool) -> Result<(), String> {
+        let bp = self
+            .breakpoints
+            .iter_mut()
+            .find(|bp| bp.address == offset)
+            .ok_or_else(|| format!("No breakpoint at {}", offset))?;
+        bp.enabled = enabled;
+        Ok(())
+    }
+
+    pub fn get_watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    /// Stop when the value of `expr` changes
+    pub fn add_watch(&mut self, expr: Expr) {
+        let watch = Watch::new(expr, self);
+        self.watches.push(watch);
+    }
+
+    pub fn remove_watch(&mut self, idx: usize) -> Result<Watch, String> {
+        if idx >= self.watches.len() {
+            return Err(format!("No watch {}", idx));
+        }
+        Ok(self.watches.remove(idx))
+    }
+
+    pub fn get_protected(&self) -> &[Range<usize>] {
+        &self.protected
+    }
+
+    /// Stop before a `Wmem` into `range`, resuming lets the write happen
+    pub fn protect(&mut self, range: Range<usize>) {
+        self.protected.push(range);
+    }
+
+    pub fn unprotect(&mut self, idx: usize) -> Result<Range<usize>, String> {
+        if idx >= self.protected.len() {
+            return Err(format!("No protected range {}", idx));
+        }
+        Ok(self.protected.remove(idx))
+    }
+
+    /// The protected write that stopped the last step
+    pub fn get_fault(&self) -> Option<&ProtectionFault> {
+        self.fault.as_ref()
+    }
+
+    /// `instruction` at ip, if it writes into a protected range
+    fn protection_fault(&self, instruction: &Opcode) -> Option<ProtectionFault> {
+        let Opcode::Wmem(a, b) = instruction else {
+            return None;
+        };
+        let addr = self.get_value(a)?;
+        if self.banks() > 1 && addr == BANK_SELECT {
+            return None;
+        }
+        let addr = self.data_address(addr);
+        self.protected
+            .iter()
+            .any(|range| range.contains(&addr))
+            .then(|| ProtectionFault {
+                ip: self.ip,
+                instruction: *instruction,
+                addr,
+                value: self.get_value(b).unwrap_or_default(),
+            })
+    }
+
+    /// Evaluate the watches, true if a value changed
+    fn update_watches(&mut self) -> bool {
+        if self.watches.is_empty() {
+            return false;
+        }
+        let mut watches = std::mem::take(&mut self.watches);
+        let mut changed = false;
+        for watch in &mut watches {
+            // all of them, to keep their values current
+            changed |= watch.update(self);
+        }
+        self.watches = watches;
+
+        changed
     }
 
     pub fn scanmem_init(&mut self) {
-        self.scanmem = vec![None; MEM_SIZE];
+        self.scanmem = vec![None; self.memory.len()];
         for (a, b) in self.memory.iter().zip(self.scanmem.iter_mut()) {
             *b = Some(*a);
         }
@@ -698,24 +2077,26 @@ impl Vm {
 
     pub fn mem_set(&mut self, offset: usize, value: u16) {
         self.memory[offset] = value;
+        self.track_write(offset);
     }
 
-    pub fn mem_get(&mut self, offset: usize) {
-        println!("{}: {}", offset, self.memory[offset]);
+    pub fn mem_get(&self, offset: usize) -> u16 {
+        self.memory[offset]
     }
 
-    pub fn scanmem_list(&self) {
-        for (idx, (mem, scanmem)) in self.memory.iter().zip(self.scanmem.iter()).enumerate() {
-            if let Some(scanmem) = scanmem {
-                println!("{}: {} -> {}", idx, scanmem, mem);
-            }
-        }
-
-        let count = self.scanmem.iter().filter(|x| x.is_some()).count();
-        println!("Listed {} values", count);
+    /// Selected addresses, with their value at `scanmem_init` and now
+    pub fn scanmem_list(&self) -> Vec<(usize, u16, u16)> {
+        self.memory
+            .iter()
+            .zip(self.scanmem.iter())
+            .enumerate()
+            .filter_map(|(idx, (mem, scanmem))| scanmem.map(|scanmem| (idx, scanmem, *mem)))
+            .collect()
     }
 
-    pub fn scanmem_filter(&mut self, op: &str, val: Option<u16>) {
+    /// Keep the addresses where `op` is true between the old value and `val`, or the current
+    /// value. Returns the number of addresses left
+    pub fn scanmem_filter(&mut self, op: &str, val: Option<u16>) -> Result<usize, String> {
         match op {
             "=" => {
                 for (a, b) in self.memory.iter().zip(self.scanmem.iter_mut()) {
@@ -790,30 +2171,81 @@ impl Vm {
                 }
             }
 
-            x => println!("unknown op {:?}", x),
+            x => return Err(format!("unknown op {:?}", x)),
         }
 
-        let count = self.scanmem.iter().filter(|x| x.is_some()).count();
-        println!("Selected {} values", count);
+        Ok(self.scanmem.iter().filter(|x| x.is_some()).count())
     }
 
-    pub fn patch(&mut self, opcode: Opcode, offset: usize) {
+    /// Write `opcode` at `offset`, warns if the instruction it replaces has another size
+    pub fn patch(&mut self, opcode: Opcode, offset: Addr) {
         let bin = opcode.machine_code();
-        let size = bin.len();
 
         match self.disassemble(offset, 1) {
             Ok(x) => {
-                let old_size = x.len();
-                if old_size != size {
+                let old_size = x[0].1.size();
+                if old_size != bin.len() {
                     println!("WARNING: patched opcode of different size");
                 }
             }
             Err(e) => println!("Can't disassemble {:?}", e),
         }
 
-        self.memory[offset..(offset + size)].copy_from_slice(&bin);
+        self.patch_words(&bin, offset.into());
     }
 
+    /// Like `patch`, for any words, like the output of `Opcode::assemble`
+    pub fn patch_words(&mut self, words: &[u16], offset: usize) {
+        let range = offset..(offset + words.len());
+        self.patches.push(AppliedPatch {
+            offset,
+            old: self.memory[range.clone()].to_vec(),
+            new: words.to_vec(),
+        });
+        self.memory[range.clone()].copy_from_slice(words);
+        for addr in range {
+            self.track_write(addr);
+        }
+    }
+
+    /// Patches in the order they were applied
+    pub fn get_patches(&self) -> &[AppliedPatch] {
+        &self.patches
+    }
+
+    /// Write back the words replaced by patch `idx`. Fails if the memory changed since, or if a
+    /// later patch overlaps it and must be undone first.
+    pub fn undo_patch(&mut self, idx: usize) -> Result<AppliedPatch, String> {
+        let patch = self
+            .patches
+            .get(idx)
+            .ok_or_else(|| format!("No patch {}, {} applied", idx, self.patches.len()))?;
+        let range = patch.offset..(patch.offset + patch.new.len());
+        if let Some(later) = self.patches[idx + 1..].iter().position(|later| {
+            later.offset < range.end && range.start < later.offset + later.new.len()
+        }) {
+            return Err(format!(
+                "Patch {} overlaps it, undo it first",
+                idx + 1 + later
+            ));
+        }
+        if self.memory[range.clone()] != patch.new[..] {
+            return Err(format!(
+                "The memory at {} changed since the patch",
+                patch.offset
+            ));
+        }
+
+        let patch = self.patches.remove(idx);
+        self.memory[range.clone()].copy_from_slice(&patch.old);
+        for addr in range {
+            self.track_write(addr);
+        }
+
+        Ok(patch)
+    }
+
+    /// ```text
     /// >> dis fn 2125
     /// 2125: Push(Reg(1))
     /// 2127: Push(Reg(2))
@@ -824,7 +2256,7 @@ impl Vm {
     /// 2144: Pop(Reg(2))
     /// 2146: Pop(Reg(1))
     /// 2148: Ret
-    #[allow(dead_code)]
+    /// ```
     fn patched_2125(&mut self) {
         fn op(mut reg0: u16, reg1: u16) -> u16 {
             let mut reg2 = reg0 & reg1;
@@ -842,6 +2274,7 @@ impl Vm {
         self.pc += 9;
     }
 
+    /// ```text
     /// >> dis fn 6027
     /// 6027: Jt(Reg(0), 6035)
     /// 6030: Add(Reg(0), Reg(1), 1)
@@ -880,6 +2313,7 @@ impl Vm {
     /// 5507: Set(Reg(1), 1531)
     /// 5510: Add(Reg(2), 21718, 1807)
     /// 5514: Call(1458)
+    /// ```
     #[allow(unused_assignments)]
     fn patched_6027(&mut self, mut r0: u16, mut r1: u16, r7: u16) -> (u16, u16) {
         let init_r0 = r0;
@@ -915,446 +2349,1357 @@ impl Vm {
         }
     }
 
-    pub fn disassemble(
-        &self,
-        mut start: usize,
-        mut count: usize,
-    ) -> Result<Vec<(usize, Opcode)>, Box<dyn std::error::Error>> {
-        let mut instructions = Vec::new();
+    /// Same as `patched_6027`, one row per value of `r0`, 32768 `r1` per row
+    ///
+    /// The final call of the recursion is `6027(0, n)`, so `r1` ends up as `r0 - 1`.
+    fn table_6027(r0: u16, r1: u16, r7: u16) -> (u16, u16) {
+        let mut row: Vec<u16> = (0..32768).map(|n| (n + 1) % 32768).collect();
+        for _ in 0..r0 {
+            let mut next = vec![0; 32768];
+            next[0] = row[r7 as usize];
+            for n in 1..32768 {
+                next[n] = row[next[n - 1] as usize];
+            }
+            row = next;
+        }
+
+        let r0 = row[r1 as usize];
+        (r0, r0.wrapping_add(32767) % 32768)
+    }
+
+    pub fn disassemble(
+        &self,
+        start: Addr,
+        count: usize,
+    ) -> Result<Vec<(usize, Opcode)>, Box<dyn std::error::Error>> {
+        Opcode::disassemble(&self.memory, start.into(), count, InvalidPolicy::Strict)
+    }
+
+    /// Disassemble from starting `Call` of function to all `Ret`
+    /// we don't expecte self modifying code
+    pub fn disassemble_function(
+        &self,
+        starting_ip: usize,
+    ) -> Result<Vec<(usize, Opcode)>, Box<dyn std::error::Error>> {
+        let mut instructions = Vec::new();
+
+        let mut explored: Vec<usize> = Vec::new();
+        let mut queue = VecDeque::new();
+        let instr = self.fetch(starting_ip)?;
+        let size = instr.size();
+        queue.push_back((starting_ip, instr, size));
+
+        while let Some((ip, instr, size)) = queue.pop_front() {
+            if explored.contains(&ip) {
+                continue;
+            }
+
+            //let mut next = instr.next_possible_ip(); // possible branches
+            let next: Vec<Val> = match instr {
+                Opcode::Halt => vec![],
+                Opcode::Ret => vec![],
+                Opcode::Call(_) => vec![Val::Num(ip as u16 + size as u16)], // don't follow calls
+                _ => {
+                    let mut next = instr.next_possible_ip();
+                    next.push(Val::Num(ip as u16 + size as u16));
+                    next
+                }
+            };
+
+            for n in &next {
+                let ip = match n {
+                    Val::Invalid => continue,
+                    Val::Reg(_r) => continue,
+                    Val::Num(x) => *x as usize,
+                };
+
+                if explored.contains(&ip) {
+                    continue;
+                }
+
+                let opcode = self.fetch(ip)?;
+                let size = opcode.size();
+                queue.push_back((ip, opcode, size));
+            }
+
+            explored.push(ip);
+            instructions.push((ip, instr));
+        }
+
+        instructions.sort_by_key(|a| a.0);
+        Ok(instructions)
+    }
+
+    /// One instruction per line, with `[...]` for the gaps, see `SymbolTable::format_instruction`
+    pub fn pretty_dis(instructions: &[(usize, Opcode)], symbols: &SymbolTable) -> String {
+        let mut lines = Vec::new();
+        let mut last: Option<(usize, Opcode)> = None;
+        for &(offset, opcode) in instructions.iter() {
+            if let Some((previous_offset, previous_opcode)) = last {
+                if previous_opcode.size() + previous_offset < offset {
+                    lines.push("[...]".to_string());
+                }
+            }
+
+            lines.push(symbols.format_instruction(offset, &opcode));
+            last = Some((offset, opcode));
+        }
+
+        lines.join("\n")
+    }
+
+    pub fn run_until_ret(&mut self) -> Result<Vec<(usize, Opcode)>, Box<dyn std::error::Error>> {
+        let mut executed = Vec::new();
+
+        let mut counter = 0;
+        loop {
+            let opcode = if self.called_patched_fn {
+                self.called_patched_fn = false;
+                Opcode::Ret
+            } else {
+                self.fetch(self.ip.into())?
+            };
+            match opcode {
+                Opcode::Ret => {
+                    counter -= 1;
+                    if counter == 0 {
+                        break;
+                    }
+                }
+                Opcode::Call(_) => counter += 1,
+                _ => (),
+            }
+
+            let next_instruction_ptr = usize::from(self.ip) + opcode.size();
+            self.execute(&opcode, next_instruction_ptr)?;
+            executed.push((self.ip.into(), opcode));
+        }
+
+        // execute last Ret
+        let opcode = Opcode::Ret;
+        let next_instruction_ptr = usize::from(self.ip) + opcode.size();
+        self.execute(&opcode, next_instruction_ptr)?;
+
+        executed.push((self.ip.into(), opcode));
+
+        Ok(executed)
+    }
+
+    pub fn run(&mut self) {
+        self.run_until(&[]);
+    }
+
+    /// Continue after a breakpoint: execute its instruction once, then run
+    pub fn resume(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.release_breakpoint()?;
+        self.step()?;
+        if self.state == VmState::Running {
+            self.run();
+        }
+
+        Ok(())
+    }
+
+    /// Back to running, the next step executes the instruction of the breakpoint
+    pub fn release_breakpoint(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.state != VmState::HitBreakPoint {
+            return Err(format!("Not stopped on a breakpoint: {:?}", self.state).into());
+        }
+
+        self.state = VmState::Running;
+        self.skip_breakpoint = true;
+
+        Ok(())
+    }
+
+    /// Run, and also stop like on a breakpoint when a condition is met
+    ///
+    /// Conditions are checked after each step, so running again continues. Does nothing once
+    /// halted.
+    pub fn run_until(&mut self, conditions: &[StopCondition]) -> Option<StopCondition> {
+        self.run_until_with(conditions, |_| false)
+    }
+
+    /// Like `run_until`, also stopping like on a breakpoint after the steps where `stop` is true
+    pub fn run_until_with(
+        &mut self,
+        conditions: &[StopCondition],
+        mut stop: impl FnMut(&Vm) -> bool,
+    ) -> Option<StopCondition> {
+        if self.state == VmState::Halted {
+            // the instructions after a halt aren't part of the program
+            return None;
+        }
+        self.state = VmState::Running;
+
+        let mut stopped = None;
+        while self.state == VmState::Running {
+            self.step().unwrap();
+
+            if self.state == VmState::Running {
+                stopped = conditions.iter().copied().find(|c| self.is_met(c));
+                if stopped.is_some() || stop(self) {
+                    self.state = VmState::HitBreakPoint;
+                }
+            }
+        }
+
+        stopped
+    }
+
+    fn is_met(&self, condition: &StopCondition) -> bool {
+        match condition {
+            StopCondition::StopWhenStackDepth(cmp, n) => self.call_stack.len().cmp(n) == *cmp,
+            StopCondition::StopAtCycles(n) => self.cycles >= *n,
+            StopCondition::StopOnCallTo(addr) => match self.fetch(self.ip.into()) {
+                Ok(Opcode::Call(a)) => self.get_value(&a) == Some(u16::from(*addr)),
+                _ => false,
+            },
+        }
+    }
+
+    /// Move the output produced so far into a new message, even if empty
+    ///
+    /// Done on each prompt and on halt, so there is one message per answer of the program.
+    pub fn flush_output(&mut self) {
+        let message = self.output_buffer.drain(..).collect();
+        self.messages.push(message);
+    }
+
+    pub fn feed(&mut self, line: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.state != VmState::WaitingForInput {
+            return Err(format!("State is {:?}, can't feed", self.state).into());
+        }
+        if !self.input_buffer.is_empty() {
+            return Err("Trying to feed but buffer is not empty".into());
+        }
+
+        if let Some(tt) = &mut self.timetravel {
+            // a new input starts a new timeline
+            let pc = self.pc;
+            tt.inputs.retain(|(input_pc, _)| *input_pc < pc);
+            tt.inputs.push((pc, line.to_string()));
+        }
+
+        self.input_buffer = line.chars().collect();
+        self.input_buffer.push_back('\n');
+        self.state = VmState::Running;
+
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.state != VmState::Running {
+            return Err(format!("Vm is not running: {:?}", self.state).into());
+        }
+
+        let fault = self.fault.take();
+        let skip_breakpoint = std::mem::take(&mut self.skip_breakpoint);
+        // before the breakpoints, to keep the values of the watches current
+        let watched = self.update_watches();
+        if !skip_breakpoint && (watched || self.breakpoints.iter().any(|bp| bp.is_hit(self))) {
+            self.state = VmState::HitBreakPoint;
+            return Ok(());
+        }
+
+        for (&addr, &value) in &self.entropy.pins {
+            if self.memory[addr] != value {
+                self.memory[addr] = value;
+                self.pages.write(addr);
+            }
+        }
+
+        let instruction = self.fetch(self.ip.into())?;
+        // only the write that stopped the last step goes through
+        let resumed = skip_breakpoint
+            && fault.is_some_and(|fault| fault.ip == self.ip && fault.instruction == instruction);
+        if !resumed && !self.protected.is_empty() {
+            self.fault = self.protection_fault(&instruction);
+            if self.fault.is_some() {
+                self.state = VmState::HitBreakPoint;
+                return Ok(());
+            }
+        }
+        let size = instruction.size();
+        let entry = self
+            .journal
+            .is_some()
+            .then(|| self.journal_entry(&instruction));
+
+        if let Some(costs) = &self.costs {
+            self.cycles += costs[instruction.discriminant().trailing_zeros() as usize];
+        }
+
+        if let Some(coverage) = &mut self.coverage {
+            let ip = usize::from(self.ip);
+            coverage[ip] = coverage[ip].saturating_add(1);
+        }
+
+        match &mut self.profile {
+            Some(profile) if self.profiling => {
+                profile.addresses[usize::from(self.ip)] += 1;
+                profile.opcodes[instruction.discriminant().trailing_zeros() as usize] += 1;
+            }
+            _ => (),
+        }
+
+        self.trace(&instruction);
+
+        let ip = self.ip;
+        if let Err(e) = self.execute(&instruction, usize::from(ip) + size) {
+            // stay on the instruction, like for a breakpoint
+            self.ip = ip;
+            return Err(format!("{} at {}: {:?}", e, ip, instruction).into());
+        }
+        self.pc += 1;
+
+        if self.state == VmState::Halted && self.raw_io.is_none() {
+            // the last output doesn't end with a prompt
+            self.flush_output();
+        }
+
+        if matches!(&self.timetravel, Some(tt) if self.pc.is_multiple_of(tt.interval)) {
+            self.timetravel_snapshot();
+        }
+
+        if self.called_patched_fn {
+            // a patched function ran natively, execute its `Ret`
+            self.called_patched_fn = false;
+            self.trace(&Opcode::Ret);
+            let next_instruction_ptr = usize::from(self.ip) + Opcode::Ret.size();
+            self.execute(&Opcode::Ret, next_instruction_ptr)?;
+        }
+
+        if let (Some(journal), Some(entry)) = (&mut self.journal, entry) {
+            if journal.entries.len() == journal.capacity {
+                journal.entries.pop_front();
+            }
+            journal.entries.push_back(entry);
+        }
+
+        Ok(())
+    }
+
+    fn trace(&mut self, instruction: &Opcode) {
+        if (instruction.discriminant() & self.traced_opcodes) != 0 {
+            self.trace_buffer.push(TraceEntry {
+                pc: self.pc,
+                ip: self.ip,
+                opcode: *instruction,
+                registers: self.registers,
+            });
+        }
+
+        if let (Some(routine), Opcode::Call(a), None) =
+            (self.string_routine, instruction, self.print_depth)
+        {
+            if self.get_value(a) == Some(routine as u16) {
+                let addr = self.registers[0] as usize;
+                let len = self.memory.get(addr).copied().unwrap_or_default() as usize;
+                let words = self
+                    .memory
+                    .get(addr + 1..addr + 1 + len)
+                    .map(|words| words.to_vec())
+                    .unwrap_or_default();
+                self.print_calls.push(PrintCall {
+                    call_site: self.ip,
+                    target: routine,
+                    registers: self.registers,
+                    words,
+                    text: String::new(),
+                });
+                self.print_depth = Some(self.stack.len() + 1);
+            }
+        }
+    }
+
+    /// State that `instruction` may change, before executing it
+    fn journal_entry(&self, instruction: &Opcode) -> JournalEntry {
+        let memory = match instruction {
+            Opcode::Wmem(a, _) => match self.get_value(a) {
+                Some(addr) if !(self.banks() > 1 && addr == BANK_SELECT) => {
+                    let addr = self.data_address(addr);
+                    Some((addr, self.memory[addr]))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        let (input_len, next_input) = match &self.raw_io {
+            Some(raw) => (raw.input.len(), raw.input.front().map(|&b| b as char)),
+            None => (self.input_buffer.len(), self.input_buffer.front().copied()),
+        };
+
+        JournalEntry {
+            ip: self.ip,
+            pc: self.pc,
+            cycles: self.cycles,
+            state: self.state,
+            bank: self.bank,
+            registers: self.registers,
+            stack_len: self.stack.len(),
+            stack_top: self.stack.last().copied(),
+            call_stack_len: self.call_stack.len(),
+            last_frame: self.call_stack.last().cloned(),
+            memory,
+            output_len: match &self.raw_io {
+                Some(raw) => raw.output.len(),
+                None => self.output_buffer.len(),
+            },
+            messages_len: self.messages.len(),
+            input_len,
+            next_input,
+            mid_line: self.mid_line,
+        }
+    }
+
+    /// Record the last `capacity` steps to undo them with `step_back`, or stop recording
+    pub fn set_journal(&mut self, capacity: Option<usize>) {
+        self.journal = capacity.map(|capacity| Journal {
+            entries: VecDeque::new(),
+            capacity: capacity.max(1),
+        });
+    }
+
+    pub fn get_journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Undo the last `count` steps recorded by the journal, and return how many were undone
+    ///
+    /// Coverage, traces and time travel snapshots are not unwound.
+    pub fn step_back(&mut self, count: usize) -> Result<usize, Box<dyn std::error::Error>> {
+        let journal = self.journal.as_mut().ok_or("The journal is off")?;
+        if journal.entries.is_empty() {
+            return Err("No step recorded".into());
+        }
+
+        let mut undone = 0;
+        while undone < count {
+            let Some(entry) = self.journal.as_mut().and_then(|j| j.entries.pop_back()) else {
+                break;
+            };
+            self.undo(entry);
+            undone += 1;
+        }
+
+        Ok(undone)
+    }
+
+    fn undo(&mut self, entry: JournalEntry) {
+        self.ip = entry.ip;
+        self.pc = entry.pc;
+        self.cycles = entry.cycles;
+        self.state = entry.state;
+        self.bank = entry.bank;
+        self.registers = entry.registers;
+
+        if self.stack.len() > entry.stack_len {
+            self.stack.truncate(entry.stack_len);
+        } else if self.stack.len() < entry.stack_len {
+            self.stack.extend(entry.stack_top);
+        }
+        if self.call_stack.len() > entry.call_stack_len {
+            self.call_stack.truncate(entry.call_stack_len);
+        } else if self.call_stack.len() < entry.call_stack_len {
+            self.call_stack.extend(entry.last_frame);
+        }
+
+        if let Some((addr, old)) = entry.memory {
+            self.memory[addr] = old;
+            self.pages.write(addr);
+        }
+
+        match &mut self.raw_io {
+            Some(raw) => {
+                raw.output.truncate(entry.output_len);
+                if raw.input.len() < entry.input_len {
+                    if let Some(c) = entry.next_input {
+                        raw.input.push_front(c as u8);
+                    }
+                }
+            }
+            None => {
+                if self.messages.len() > entry.messages_len {
+                    // the step flushed the output: take it back
+                    let message = self.messages.pop().unwrap_or_default();
+                    self.output_buffer = message.chars().collect();
+                }
+                self.output_buffer.truncate(entry.output_len);
+                if self.input_buffer.len() < entry.input_len {
+                    if let Some(c) = entry.next_input {
+                        self.input_buffer.push_front(c);
+                    }
+                }
+                self.mid_line = entry.mid_line;
+            }
+        }
+        self.called_patched_fn = false;
+        self.skip_breakpoint = false;
+    }
+
+    /// Short hash of the fields compared by `==`: memory and bank, state, registers, ip, I/O
+    /// buffers and stacks
+    ///
+    /// Equal states have the same hash, whatever the path that led to them.
+    pub fn state_hash(&self) -> String {
+        let mut words = self.memory.clone();
+        words.push(self.bank as u16);
+        words.push(self.state as u16);
+        words.extend(self.registers);
+        words.push(self.ip.get());
+        words.push(self.output_buffer.len() as u16);
+        words.extend(self.output_buffer.iter().map(|&c| c as u16));
+        words.push(self.input_buffer.len() as u16);
+        words.extend(self.input_buffer.iter().map(|&c| c as u16));
+        words.push(self.stack.len() as u16);
+        words.extend(&self.stack);
+        for frame in &self.call_stack {
+            words.extend([
+                frame.call_site.get(),
+                frame.target.get(),
+                frame.depth as u16,
+            ]);
+        }
 
-        while count > 0 {
-            let instr = self.fetch(start)?;
-            let size = instr.size();
-            instructions.push((start, instr));
+        let mut bytes = vec![0; words.len() * 2];
+        LittleEndian::write_u16_into(&words, &mut bytes);
+        format!("{:x}", md5::compute(bytes))[..12].to_string()
+    }
 
-            start += size;
-            count -= 1;
+    pub fn checkpoint(&self) -> Checkpoint {
+        let memory = match self.pages.baseline {
+            Some(_) => CheckpointMemory::Pages(
+                self.pages
+                    .dirty()
+                    .map(|page| {
+                        (
+                            page,
+                            self.memory[page_range(page, self.memory.len())].to_vec(),
+                        )
+                    })
+                    .collect(),
+            ),
+            None => CheckpointMemory::Full(self.memory.clone()),
+        };
+        Checkpoint {
+            memory,
+            pages: self.pages.clone(),
+            bank: self.bank,
+            registers: self.registers,
+            stack: self.stack.clone(),
+            call_stack: self.call_stack.clone(),
+            ip: self.ip,
+            pc: self.pc,
+            cycles: self.cycles,
+            state: self.state,
+            output_buffer: self.output_buffer.clone(),
+            input_buffer: self.input_buffer.clone(),
+            mid_line: self.mid_line,
+            messages_len: self.messages.len(),
+            patches: self.patches.clone(),
         }
+    }
 
-        Ok(instructions)
+    /// Go back to `checkpoint`, keeping breakpoints, traces and caches
+    pub fn restore(&mut self, checkpoint: &Checkpoint) -> Result<(), Box<dyn std::error::Error>> {
+        match (&checkpoint.memory, &checkpoint.pages.baseline) {
+            (CheckpointMemory::Pages(pages), Some(baseline)) => {
+                let stale = match self.pages.differing(&checkpoint.pages) {
+                    Some(stale) => stale,
+                    None => {
+                        self.memory.clone_from(baseline);
+                        pages.keys().copied().collect()
+                    }
+                };
+                for page in stale {
+                    let range = page_range(page, self.memory.len());
+                    match pages.get(&page) {
+                        Some(words) => self.memory[range].copy_from_slice(words),
+                        None => self.memory[range.clone()].copy_from_slice(&baseline[range]),
+                    }
+                }
+            }
+            (CheckpointMemory::Full(memory), _) => self.memory.clone_from(memory),
+            (CheckpointMemory::Pages(_), None) => {
+                return Err("Checkpoint with pages but without a baseline".into())
+            }
+        }
+        self.pages = checkpoint.pages.clone();
+        self.bank = checkpoint.bank;
+        self.registers = checkpoint.registers;
+        self.stack.clone_from(&checkpoint.stack);
+        self.call_stack.clone_from(&checkpoint.call_stack);
+        self.ip = checkpoint.ip;
+        self.pc = checkpoint.pc;
+        self.cycles = checkpoint.cycles;
+        self.state = checkpoint.state;
+        self.output_buffer.clone_from(&checkpoint.output_buffer);
+        self.input_buffer.clone_from(&checkpoint.input_buffer);
+        self.mid_line = checkpoint.mid_line;
+        self.messages.truncate(checkpoint.messages_len);
+        self.patches.clone_from(&checkpoint.patches);
+        self.called_patched_fn = false;
+        self.patched_frames.clear();
+        if let Some(journal) = &mut self.journal {
+            // the recorded steps don't lead to the restored state
+            journal.entries.clear();
+        }
+        Ok(())
     }
 
-    /// Disassemble from starting `Call` of function to all `Ret`
-    /// we don't expecte self modifying code
-    pub fn disassemble_function(
-        &self,
-        starting_ip: usize,
-    ) -> Result<Vec<(usize, Opcode)>, Box<dyn std::error::Error>> {
-        let mut instructions = Vec::new();
+    /// Count cycles with these costs per opcode number, or stop with `None`
+    pub fn set_costs(&mut self, costs: Option<[u64; 22]>) {
+        self.costs = costs;
+    }
 
-        let mut explored: Vec<usize> = Vec::new();
-        let mut queue = VecDeque::new();
-        let instr = self.fetch(starting_ip)?;
-        let size = instr.size();
-        queue.push_back((starting_ip, instr, size));
+    pub fn get_costs(&self) -> Option<&[u64; 22]> {
+        self.costs.as_ref()
+    }
 
-        while let Some((ip, instr, size)) = queue.pop_front() {
-            if explored.contains(&ip) {
-                continue;
-            }
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
 
-            //let mut next = instr.next_possible_ip(); // possible branches
-            let next: Vec<Val> = match instr {
-                Opcode::Halt => vec![],
-                Opcode::Ret => vec![],
-                Opcode::Call(_) => vec![Val::Num(ip as u16 + size as u16)], // don't follow calls
-                _ => {
-                    let mut next = instr.next_possible_ip();
-                    next.push(Val::Num(ip as u16 + size as u16));
-                    next
-                }
-            };
+    /// Address of the next instruction
+    pub fn get_ip(&self) -> Addr {
+        self.ip
+    }
 
-            for n in &next {
-                let ip = match n {
-                    Val::Invalid => continue,
-                    Val::Reg(_r) => continue,
-                    Val::Num(x) => *x as usize,
-                };
+    pub fn get_pc(&self) -> usize {
+        self.pc
+    }
 
-                if explored.contains(&ip) {
-                    continue;
+    /// The next instruction in plain English, with its operands resolved
+    ///
+    /// ```text
+    /// jump to 5636 because r1 == 0
+    /// ```
+    pub fn explain(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let instruction = self.fetch(self.ip.into())?;
+        let value = |v: &Val| self.get_value(v).ok_or("Invalid operand");
+        // registers with their value, the reader doesn't have to look them up
+        let show = |v: &Val| match v {
+            Val::Reg(r) => format!("r{} ({})", r, self.registers[*r]),
+            _ => format!("{}", v),
+        };
+        let dest = |v: &Val| match v {
+            Val::Reg(r) => format!("r{}", r),
+            _ => format!("{} (not a register!)", v),
+        };
+        let next = usize::from(self.ip) + instruction.size();
+
+        let explanation = match &instruction {
+            Opcode::Halt => "stop the program".to_string(),
+            Opcode::Set(a, b) => format!("{} = {}", dest(a), show(b)),
+            Opcode::Push(a) => format!("push {} on the stack", show(a)),
+            Opcode::Pop(a) => match self.stack.last() {
+                Some(top) => format!("pop {} from the stack into {}", top, dest(a)),
+                None => "pop from an empty stack: error".to_string(),
+            },
+            Opcode::Eq(a, b, c) => {
+                let equal = value(b)? == value(c)?;
+                let op = if equal { "==" } else { "!=" };
+                format!(
+                    "{} = {} because {} {} {}",
+                    dest(a),
+                    equal as u16,
+                    show(b),
+                    op,
+                    show(c)
+                )
+            }
+            Opcode::Gt(a, b, c) => {
+                let greater = value(b)? > value(c)?;
+                let op = if greater { ">" } else { "<=" };
+                format!(
+                    "{} = {} because {} {} {}",
+                    dest(a),
+                    greater as u16,
+                    show(b),
+                    op,
+                    show(c)
+                )
+            }
+            Opcode::Jmp(a) => format!("jump to {}", show(a)),
+            Opcode::Jt(a, b) | Opcode::Jf(a, b) => {
+                let zero = value(a)? == 0;
+                let jump = zero == matches!(instruction, Opcode::Jf(_, _));
+                let cmp = if zero { "==" } else { "!=" };
+                if jump {
+                    format!("jump to {} because {} {} 0", show(b), show(a), cmp)
+                } else {
+                    format!("continue at {} because {} {} 0", next, show(a), cmp)
+                }
+            }
+            Opcode::Add(a, b, c) => format!(
+                "{} = {} + {} = {}",
+                dest(a),
+                show(b),
+                show(c),
+                (value(b)? + value(c)?) % 32768
+            ),
+            Opcode::Mult(a, b, c) => format!(
+                "{} = {} * {} = {}",
+                dest(a),
+                show(b),
+                show(c),
+                value(b)?.wrapping_mul(value(c)?) % 32768
+            ),
+            Opcode::Mod(a, b, c) => match value(c)? {
+                0 => format!("{} = {} % 0: division by zero", dest(a), show(b)),
+                c_val => format!(
+                    "{} = {} % {} = {}",
+                    dest(a),
+                    show(b),
+                    show(c),
+                    value(b)? % c_val
+                ),
+            },
+            Opcode::And(a, b, c) => format!(
+                "{} = {} & {} = {}",
+                dest(a),
+                show(b),
+                show(c),
+                value(b)? & value(c)?
+            ),
+            Opcode::Or(a, b, c) => format!(
+                "{} = {} | {} = {}",
+                dest(a),
+                show(b),
+                show(c),
+                value(b)? | value(c)?
+            ),
+            Opcode::Not(a, b) => format!(
+                "{} = ~{} = {} (15 bits)",
+                dest(a),
+                show(b),
+                !value(b)? % 32768
+            ),
+            Opcode::Rmem(a, b) => {
+                let addr = value(b)?;
+                let val = self.memory.get(self.data_address(addr)).copied();
+                match val {
+                    Some(val) => format!("{} = memory[{}] = {}", dest(a), show(b), val),
+                    None => format!("{} = memory[{}]: out of memory", dest(a), show(b)),
                 }
-
-                let opcode = self.fetch(ip)?;
-                let size = opcode.size();
-                queue.push_back((ip, opcode, size));
             }
+            Opcode::Wmem(a, b) => format!("memory[{}] = {}", show(a), show(b)),
+            Opcode::Call(a) => format!("call {}, it will return to {}", show(a), next),
+            Opcode::Ret => match self.stack.last() {
+                Some(addr) => format!("return to {}", addr),
+                None => "return with an empty stack: halt".to_string(),
+            },
+            Opcode::Out(a) => format!("print {:?}", value(a)? as u8 as char),
+            Opcode::In(a) => format!("read a character of input into {}", dest(a)),
+            Opcode::Noop => "do nothing".to_string(),
+        };
 
-            explored.push(ip);
-            instructions.push((ip, instr));
-        }
+        Ok(explanation)
+    }
 
-        instructions.sort_by_key(|a| a.0);
-        Ok(instructions)
+    pub fn get_entropy(&self) -> &Entropy {
+        &self.entropy
     }
 
-    pub fn pretty_print_dis(instructions: &[(usize, Opcode)]) {
-        let mut last: Option<(usize, Opcode)> = None;
-        for &(offset, opcode) in instructions.iter() {
-            if let Some((previous_offset, previous_opcode)) = last {
-                if previous_opcode.size() + previous_offset < offset {
-                    println!("[...]");
-                }
-            }
+    /// Count the executions of each address, or stop and forget them
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.coverage = match enabled {
+            true => Some(self.coverage.take().unwrap_or_else(|| vec![0; MEM_SIZE])),
+            false => None,
+        };
+    }
 
-            println!("{}: {:?}", offset, opcode);
-            last = Some((offset, opcode));
+    /// Forget the last profile, and count the executions of each address and opcode
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile {
+            addresses: vec![0; self.memory.len()],
+            opcodes: [0; 22],
+        });
+        self.profiling = true;
+    }
+
+    /// Stop counting, keeping the profile for `get_profile`
+    pub fn stop_profile(&mut self) {
+        self.profiling = false;
+    }
+
+    pub fn get_profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn get_coverage(&self) -> Option<&[u32]> {
+        self.coverage.as_deref()
+    }
+
+    /// Log the reads of `addr`
+    pub fn set_watch(&mut self, addr: usize) {
+        self.entropy.watched.insert(addr);
+    }
+
+    pub fn unset_watch(&mut self, addr: usize) {
+        self.entropy.watched.remove(&addr);
+        self.entropy.reads.remove(&addr);
+    }
+
+    /// Force `addr` to `value` before every step
+    pub fn set_pin(&mut self, addr: usize, value: u16) {
+        self.entropy.pins.insert(addr, value);
+    }
+
+    pub fn unset_pin(&mut self, addr: usize) {
+        self.entropy.pins.remove(&addr);
+    }
+
+    /// Snapshot every `interval` instructions, or stop with `None`
+    pub fn set_timetravel(&mut self, interval: Option<usize>) {
+        self.timetravel = None;
+        if let Some(interval) = interval {
+            self.timetravel = Some(Box::new(TimeTravel {
+                interval: interval.max(1),
+                snapshots: vec![self.clone()],
+                inputs: Vec::new(),
+            }));
         }
     }
 
-    pub fn run_until_ret(&mut self) -> Result<Vec<(usize, Opcode)>, Box<dyn std::error::Error>> {
-        let mut executed = Vec::new();
+    /// Instruction counts of the snapshots
+    pub fn timetravel_snapshots(&self) -> Vec<usize> {
+        match &self.timetravel {
+            Some(tt) => tt.snapshots.iter().map(|vm| vm.pc).collect(),
+            None => Vec::new(),
+        }
+    }
 
-        let mut counter = 0;
-        loop {
-            let opcode = if self.called_patched_fn {
-                self.called_patched_fn = false;
-                Opcode::Ret
-            } else {
-                self.fetch(self.ip)?
-            };
-            match opcode {
-                Opcode::Ret => {
-                    counter -= 1;
-                    if counter == 0 {
+    fn timetravel_snapshot(&mut self) {
+        if let Some(mut tt) = self.timetravel.take() {
+            // the journal isn't needed to replay
+            let journal = self.journal.take();
+            tt.snapshots.retain(|snapshot| snapshot.pc < self.pc);
+            tt.snapshots.push(self.clone());
+            self.timetravel = Some(tt);
+            self.journal = journal;
+        }
+    }
+
+    /// Restore the nearest snapshot before `target`, then execute forward up to `target`
+    /// instructions, replaying the recorded inputs
+    ///
+    /// Snapshots after `target` are dropped, inputs are dropped when a new one is fed.
+    pub fn goto_pc(&mut self, target: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let mut tt = self.timetravel.take().ok_or("Timetravel is not enabled")?;
+        let mut vm = match tt.snapshots.iter().rev().find(|vm| vm.pc <= target) {
+            Some(vm) => vm.clone(),
+            None => {
+                self.timetravel = Some(tt);
+                return Err(format!("No snapshot before {}", target).into());
+            }
+        };
+
+        let breakpoints = std::mem::take(&mut vm.breakpoints);
+        let mut watches = std::mem::take(&mut vm.watches);
+        let start = vm.pc;
+        let mut inputs = tt.inputs.iter().filter(|(pc, _)| *pc >= start).peekable();
+
+        let mut result = Ok(());
+        while vm.pc < target {
+            match vm.state {
+                VmState::Running | VmState::HitBreakPoint => {
+                    vm.state = VmState::Running;
+                    if let Err(e) = vm.step() {
+                        result = Err(e);
                         break;
                     }
                 }
-                Opcode::Call(_) => counter += 1,
-                _ => (),
+                VmState::WaitingForInput => match inputs.next_if(|(pc, _)| *pc == vm.pc).cloned() {
+                    Some((_, line)) => {
+                        vm.input_buffer = line.chars().collect();
+                        vm.input_buffer.push_back('\n');
+                        vm.state = VmState::Running;
+                    }
+                    None => {
+                        result = Err(format!("No recorded input at {}", vm.pc).into());
+                        break;
+                    }
+                },
+                VmState::Halted => {
+                    result = Err(format!("Halted at {}", vm.pc).into());
+                    break;
+                }
             }
+        }
+        if vm.state == VmState::Running {
+            vm.state = VmState::HitBreakPoint;
+        }
+        vm.breakpoints = breakpoints;
+        for watch in &mut watches {
+            // the jump isn't a change
+            watch.value = watch.expr.eval(&vm).ok();
+        }
+        vm.watches = watches;
+
+        let pc = vm.pc;
+        tt.snapshots.retain(|snapshot| snapshot.pc <= pc);
+        vm.journal = self.journal.take().map(|mut journal| {
+            journal.entries.clear();
+            journal
+        });
+        *self = vm;
+        self.timetravel = Some(tt);
+
+        result
+    }
 
-            let next_instruction_ptr = self.ip + opcode.size();
-            self.execute(&opcode, next_instruction_ptr);
-            executed.push((self.ip, opcode));
+    /// Drop the frames whose return address is no longer on the stack
+    fn unwind_call_stack(&mut self) {
+        while matches!(self.call_stack.last(), Some(frame) if frame.depth > self.stack.len()) {
+            self.call_stack.pop();
         }
+    }
 
-        // execute last Ret
-        let opcode = Opcode::Ret;
-        let next_instruction_ptr = self.ip + opcode.size();
-        self.execute(&opcode, next_instruction_ptr);
+    pub fn get_call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
 
-        executed.push((self.ip, opcode));
+    /// Innermost first: the address executed in each frame, and the function of the frame
+    ///
+    /// The outermost frame is the code running outside of the known calls.
+    pub fn backtrace(&self) -> Vec<(Addr, Option<Addr>)> {
+        let mut frames = vec![(self.ip, self.call_stack.last().map(|f| f.target))];
+        for (n, frame) in self.call_stack.iter().enumerate().rev() {
+            let caller = n.checked_sub(1).map(|n| self.call_stack[n].target);
+            frames.push((frame.call_site, caller));
+        }
 
-        Ok(executed)
+        frames
     }
 
-    pub fn run(&mut self) {
-        self.state = VmState::Running;
+    /// Registers pushed at the start of `function` and popped somewhere in it, in push order
+    fn saved_registers(&self, function: usize) -> Vec<usize> {
+        let pops: Vec<Opcode> = match self.disassemble_function(function) {
+            Ok(instructions) => instructions.into_iter().map(|(_, instr)| instr).collect(),
+            Err(_) => return Vec::new(),
+        };
 
-        while self.state == VmState::Running {
-            self.step().unwrap();
+        let mut saved = Vec::new();
+        let mut ip = function;
+        while let Ok(Opcode::Push(Val::Reg(r))) = self.fetch(ip) {
+            if !pops.contains(&Opcode::Pop(Val::Reg(r))) {
+                break;
+            }
+            saved.push(r);
+            ip += Opcode::Push(Val::Reg(r)).size();
         }
 
-        if self.state == VmState::Halted {
-            let message = self.output_buffer.iter().collect::<String>();
-            self.messages.push(message.clone());
-            println!("\n\nHalted");
-        }
+        saved
+    }
 
-        if self.state == VmState::HitBreakPoint {
-            println!("Hit breakpoint at {}", self.ip);
-        }
+    /// Registers preserved and clobbered by `function`, following the calls
+    pub fn register_usage(&self, function: usize) -> RegisterUsage {
+        self.register_usage_rec(function, &mut BTreeSet::new())
     }
 
-    pub fn feed(&mut self, line: &str) -> Result<(), Box<dyn std::error::Error>> {
-        if self.state != VmState::WaitingForInput {
-            return Err(format!("State is {:?}, can't feed", self.state).into());
-        }
-        if !self.input_buffer.is_empty() {
-            return Err("Trying to feed but buffer is not empty".into());
+    fn register_usage_rec(&self, function: usize, visited: &mut BTreeSet<usize>) -> RegisterUsage {
+        let mut usage = RegisterUsage::default();
+        if !visited.insert(function) {
+            // recursion, the registers are counted by the first call
+            return usage;
         }
+        let Ok(instructions) = self.disassemble_function(function) else {
+            return usage;
+        };
 
-        self.input_buffer = line.chars().collect();
-        self.input_buffer.push_back('\n');
-        self.state = VmState::Running;
+        usage.saved = self.saved_registers(function);
+        let prologue_end = function + usage.saved.len() * Opcode::Push(Val::Num(0)).size();
+        for (ip, instruction) in &instructions {
+            let written = match instruction {
+                Opcode::Set(a, _)
+                | Opcode::Eq(a, _, _)
+                | Opcode::Gt(a, _, _)
+                | Opcode::Add(a, _, _)
+                | Opcode::Mult(a, _, _)
+                | Opcode::Mod(a, _, _)
+                | Opcode::And(a, _, _)
+                | Opcode::Or(a, _, _)
+                | Opcode::Not(a, _)
+                | Opcode::Rmem(a, _)
+                | Opcode::In(a)
+                | Opcode::Pop(a) => Some(a),
+                Opcode::Push(_) if *ip >= prologue_end => {
+                    usage.temporaries += 1;
+                    None
+                }
+                Opcode::Call(Val::Num(target)) => {
+                    let callee = self.register_usage_rec(*target as usize, visited);
+                    usage.clobbered.extend(callee.clobbered);
+                    usage.indirect_calls += callee.indirect_calls;
+                    None
+                }
+                Opcode::Call(_) => {
+                    usage.indirect_calls += 1;
+                    None
+                }
+                _ => None,
+            };
+            if let Some(Val::Reg(r)) = written {
+                usage.clobbered.insert(*r);
+            }
+        }
+        for r in &usage.saved {
+            usage.clobbered.remove(r);
+        }
 
-        Ok(())
+        usage
     }
 
-    pub fn step(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if self.state != VmState::Running {
-            return Err(format!("Vm is not running: {:?}", self.state).into());
-        }
+    /// Annotate each value of the stack, bottom first
+    pub fn stack_slots(&self) -> Vec<StackSlot> {
+        let mut slots = vec![StackSlot::Local; self.stack.len()];
 
-        if self.breakpoints.contains(&self.ip) {
-            self.state = VmState::HitBreakPoint;
-            return Ok(());
+        for frame in &self.call_stack {
+            let Some(idx) = frame.depth.checked_sub(1) else {
+                continue;
+            };
+            if idx >= slots.len() {
+                continue;
+            }
+            slots[idx] = StackSlot::ReturnAddress(frame.clone());
+
+            for (offset, reg) in self
+                .saved_registers(frame.target.into())
+                .into_iter()
+                .enumerate()
+            {
+                match slots.get_mut(frame.depth + offset) {
+                    Some(slot @ StackSlot::Local) => *slot = StackSlot::SavedRegister(reg),
+                    _ => break,
+                }
+            }
         }
 
-        let instruction = self.fetch(self.ip)?;
-        let size = instruction.size();
+        slots
+    }
 
-        if (instruction.discriminant() & self.traced_opcodes) != 0 {
-            self.trace_buffer.push((self.ip, instruction));
+    /// The stack grouped in frames, innermost first
+    pub fn format_stack(&self) -> String {
+        let slots = self.stack_slots();
+        let mut lines = Vec::new();
+        let mut top = self.stack.len();
+
+        for (n, frame) in self.call_stack.iter().enumerate().rev() {
+            let bottom = frame.depth.saturating_sub(1).min(top);
+            lines.push(format!(
+                "#{} fn {} called from {}",
+                n, frame.target, frame.call_site
+            ));
+            self.format_stack_range(&slots, bottom, top, &mut lines);
+            top = bottom;
         }
 
-        let next_instruction_ptr = self.ip + size;
-        self.execute(&instruction, next_instruction_ptr);
-        self.pc += 1;
+        if top > 0 {
+            lines.push("# outside of known calls".to_string());
+            self.format_stack_range(&slots, 0, top, &mut lines);
+        }
 
-        Ok(())
+        lines.join("\n")
+    }
+
+    fn format_stack_range(
+        &self,
+        slots: &[StackSlot],
+        bottom: usize,
+        top: usize,
+        lines: &mut Vec<String>,
+    ) {
+        for idx in (bottom..top).rev() {
+            let value = self.stack[idx];
+            lines.push(match &slots[idx] {
+                StackSlot::ReturnAddress(_) => {
+                    format!("{:>5}: {:>5}   return address", idx, value)
+                }
+                StackSlot::SavedRegister(reg) => {
+                    format!("{:>5}: {:>5}   saved Reg({})", idx, value, reg)
+                }
+                StackSlot::Local => format!("{:>5}: {:>5}", idx, value),
+            });
+        }
     }
 
     /// Return `Opcode)` decoded at `ip`
+    /// The code runs from bank 0 only
     fn fetch(&self, ip: usize) -> Result<Opcode, Box<dyn std::error::Error>> {
-        let instr_type = self.memory[ip];
-
-        let opcode = match instr_type {
-            0 => Opcode::Halt,
-            1 => Opcode::Set(Val::new(self.memory[ip + 1]), Val::new(self.memory[ip + 2])),
-            2 => Opcode::Push(Val::new(self.memory[ip + 1])),
-            3 => Opcode::Pop(Val::new(self.memory[ip + 1])),
-            4 => Opcode::Eq(
-                Val::new(self.memory[ip + 1]),
-                Val::new(self.memory[ip + 2]),
-                Val::new(self.memory[ip + 3]),
-            ),
-            5 => Opcode::Gt(
-                Val::new(self.memory[ip + 1]),
-                Val::new(self.memory[ip + 2]),
-                Val::new(self.memory[ip + 3]),
-            ),
-            6 => Opcode::Jmp(Val::new(self.memory[ip + 1])),
-            7 => Opcode::Jt(Val::new(self.memory[ip + 1]), Val::new(self.memory[ip + 2])),
-            8 => Opcode::Jf(Val::new(self.memory[ip + 1]), Val::new(self.memory[ip + 2])),
-            9 => Opcode::Add(
-                Val::new(self.memory[ip + 1]),
-                Val::new(self.memory[ip + 2]),
-                Val::new(self.memory[ip + 3]),
-            ),
-            10 => Opcode::Mult(
-                Val::new(self.memory[ip + 1]),
-                Val::new(self.memory[ip + 2]),
-                Val::new(self.memory[ip + 3]),
-            ),
-            11 => Opcode::Mod(
-                Val::new(self.memory[ip + 1]),
-                Val::new(self.memory[ip + 2]),
-                Val::new(self.memory[ip + 3]),
-            ),
-            12 => Opcode::And(
-                Val::new(self.memory[ip + 1]),
-                Val::new(self.memory[ip + 2]),
-                Val::new(self.memory[ip + 3]),
-            ),
-            13 => Opcode::Or(
-                Val::new(self.memory[ip + 1]),
-                Val::new(self.memory[ip + 2]),
-                Val::new(self.memory[ip + 3]),
-            ),
-            14 => Opcode::Not(Val::new(self.memory[ip + 1]), Val::new(self.memory[ip + 2])),
-            15 => Opcode::Rmem(Val::new(self.memory[ip + 1]), Val::new(self.memory[ip + 2])),
-            16 => Opcode::Wmem(Val::new(self.memory[ip + 1]), Val::new(self.memory[ip + 2])),
-            17 => Opcode::Call(Val::new(self.memory[ip + 1])),
-            18 => Opcode::Ret,
-            19 => Opcode::Out(Val::new(self.memory[ip + 1])),
-            20 => Opcode::In(Val::new(self.memory[ip + 1])),
-            21 => Opcode::Noop,
-            x => return Err(format!("Can't decode opcode {}", x).into()),
-        };
+        let ip = usize::from(Addr::try_from(ip)?);
 
-        Ok(opcode)
+        Opcode::decode_strict(&self.memory[ip..MEM_SIZE], ip)
     }
 
-    fn execute(&mut self, instruction: &Opcode, next_instruction_ptr: usize) {
-        //println!("{:?}", instruction);
-
-        self.ip = next_instruction_ptr;
+    /// Fails on operands of the wrong kind, pops of an empty stack, divisions by zero and
+    /// addresses out of the memory. `ip` may have moved.
+    fn execute(
+        &mut self,
+        instruction: &Opcode,
+        next_instruction_ptr: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.ip = Addr::try_from(next_instruction_ptr)?;
 
         match instruction {
             Opcode::Halt => self.state = VmState::Halted,
             Opcode::Set(a, b) => {
-                let val = self.get_value(b).expect("Invalid number");
-                let reg = self.get_register(a).expect("Not a register");
+                let val = self.get_value(b).ok_or("Invalid number")?;
+                let reg = self.get_register(a).ok_or("Not a register")?;
 
                 self.registers[reg] = val;
             }
             Opcode::Push(a) => {
-                let val = self.get_value(a).expect("Invalid number");
+                let val = self.get_value(a).ok_or("Invalid number")?;
 
                 self.stack.push(val);
             }
             Opcode::Pop(a) => {
-                let val = self.stack.pop().expect("Pop: empty stack");
-                let reg = self.get_register(a).expect("Not a register");
+                let reg = self.get_register(a).ok_or("Not a register")?;
+                let val = self.stack.pop().ok_or("Pop: empty stack")?;
+                self.unwind_call_stack();
 
                 self.registers[reg] = val;
             }
             Opcode::Eq(a, b, c) => {
-                let val_b = self.get_value(b).expect("Invalid number");
-                let val_c = self.get_value(c).expect("Invalid number");
+                let val_b = self.get_value(b).ok_or("Invalid number")?;
+                let val_c = self.get_value(c).ok_or("Invalid number")?;
 
                 let val_a = if val_b == val_c { 1 } else { 0 };
 
-                let reg = self.get_register(a).expect("Not a register");
+                let reg = self.get_register(a).ok_or("Not a register")?;
                 self.registers[reg] = val_a;
             }
             Opcode::Gt(a, b, c) => {
-                let val_b = self.get_value(b).expect("Invalid number");
-                let val_c = self.get_value(c).expect("Invalid number");
+                let val_b = self.get_value(b).ok_or("Invalid number")?;
+                let val_c = self.get_value(c).ok_or("Invalid number")?;
 
                 let val_a = if val_b > val_c { 1 } else { 0 };
 
-                let reg = self.get_register(a).expect("Not a register");
+                let reg = self.get_register(a).ok_or("Not a register")?;
                 self.registers[reg] = val_a;
             }
             Opcode::Jmp(a) => {
-                self.ip = self.get_value(a).expect("Invalid number") as usize;
+                self.ip = Addr::try_from(self.get_value(a).ok_or("Invalid number")?)?;
             }
             Opcode::Jt(a, b) => {
-                let must_jump = self.get_value(a).expect("Invalid number") != 0;
+                let must_jump = self.get_value(a).ok_or("Invalid number")? != 0;
 
                 if must_jump {
-                    self.ip = self.get_value(b).expect("Invalid number") as usize;
+                    self.ip = Addr::try_from(self.get_value(b).ok_or("Invalid number")?)?;
                 }
             }
             Opcode::Jf(a, b) => {
-                let must_jump = self.get_value(a).expect("Invalid number") == 0;
+                let must_jump = self.get_value(a).ok_or("Invalid number")? == 0;
 
                 if must_jump {
-                    self.ip = self.get_value(b).expect("Invalid number") as usize;
+                    self.ip = Addr::try_from(self.get_value(b).ok_or("Invalid number")?)?;
                 }
             }
             Opcode::Add(a, b, c) => {
-                let val_b = self.get_value(b).expect("Invalid number");
-                let val_c = self.get_value(c).expect("Invalid number");
-                let reg = self.get_register(a).expect("Not a register");
+                let val_b = self.get_value(b).ok_or("Invalid number")?;
+                let val_c = self.get_value(c).ok_or("Invalid number")?;
+                let reg = self.get_register(a).ok_or("Not a register")?;
 
-                self.registers[reg] = (val_b + val_c) % 32768; //TODO wrapping_add?
+                self.registers[reg] = val_b.wrapping_add(val_c) % 32768;
             }
             Opcode::Mult(a, b, c) => {
-                let val_b = self.get_value(b).expect("Invalid number");
-                let val_c = self.get_value(c).expect("Invalid number");
-                let reg = self.get_register(a).expect("Not a register");
+                let val_b = self.get_value(b).ok_or("Invalid number")?;
+                let val_c = self.get_value(c).ok_or("Invalid number")?;
+                let reg = self.get_register(a).ok_or("Not a register")?;
 
                 self.registers[reg] = val_b.wrapping_mul(val_c) % 32768;
             }
             Opcode::Mod(a, b, c) => {
-                let val_b = self.get_value(b).expect("Invalid number");
-                let val_c = self.get_value(c).expect("Invalid number");
-                let reg = self.get_register(a).expect("Not a register");
+                let val_b = self.get_value(b).ok_or("Invalid number")?;
+                let val_c = self.get_value(c).ok_or("Invalid number")?;
+                let reg = self.get_register(a).ok_or("Not a register")?;
+                if val_c == 0 {
+                    return Err("Mod: division by zero".into());
+                }
 
                 self.registers[reg] = val_b % val_c;
             }
             Opcode::And(a, b, c) => {
-                let val_b = self.get_value(b).expect("Invalid number");
-                let val_c = self.get_value(c).expect("Invalid number");
-                let reg = self.get_register(a).expect("Not a register");
+                let val_b = self.get_value(b).ok_or("Invalid number")?;
+                let val_c = self.get_value(c).ok_or("Invalid number")?;
+                let reg = self.get_register(a).ok_or("Not a register")?;
 
                 self.registers[reg] = (val_b & val_c) % 32768;
             }
             Opcode::Or(a, b, c) => {
-                let val_b = self.get_value(b).expect("Invalid number");
-                let val_c = self.get_value(c).expect("Invalid number");
-                let reg = self.get_register(a).expect("Not a register");
+                let val_b = self.get_value(b).ok_or("Invalid number")?;
+                let val_c = self.get_value(c).ok_or("Invalid number")?;
+                let reg = self.get_register(a).ok_or("Not a register")?;
 
                 self.registers[reg] = (val_b | val_c) % 32768;
             }
             Opcode::Not(a, b) => {
-                let val_b = self.get_value(b).expect("Invalid number");
-                let reg = self.get_register(a).expect("Not a register");
+                let val_b = self.get_value(b).ok_or("Invalid number")?;
+                let reg = self.get_register(a).ok_or("Not a register")?;
 
                 self.registers[reg] = (!val_b) % 32768;
             }
             Opcode::Rmem(a, b) => {
-                let addr = self.get_value(b).expect("Invalid number");
-                let reg = self.get_register(a).expect("Not a register");
+                let addr = self.get_value(b).ok_or("Invalid number")?;
+                let reg = self.get_register(a).ok_or("Not a register")?;
+                if addr as usize >= MEM_SIZE {
+                    return Err(format!("Rmem: invalid address {}", addr).into());
+                }
 
-                let val = self.memory[addr as usize];
+                let val = if self.banks() > 1 && addr == BANK_SELECT {
+                    self.bank as u16
+                } else {
+                    self.memory[self.data_address(addr)]
+                };
+
+                let addr = self.data_address(addr);
+                if self.entropy.watched.contains(&addr) {
+                    self.entropy
+                        .reads
+                        .entry(addr)
+                        .or_default()
+                        .push((usize::from(self.ip) - instruction.size(), val));
+                }
 
                 self.registers[reg] = val;
             }
             Opcode::Wmem(a, b) => {
-                let val = self.get_value(b).expect("Invalid number");
-                let addr = self.get_value(a).expect("Not a register");
+                let val = self.get_value(b).ok_or("Invalid number")?;
+                let addr = self.get_value(a).ok_or("Not a register")?;
+                if addr as usize >= MEM_SIZE {
+                    return Err(format!("Wmem: invalid address {}", addr).into());
+                }
 
-                self.memory[addr as usize] = val;
+                if self.banks() > 1 && addr == BANK_SELECT {
+                    self.bank = val as usize % self.banks();
+                } else {
+                    let addr = self.data_address(addr);
+                    self.memory[addr] = val;
+                    self.track_write(addr);
+                }
             }
             Opcode::Call(a) => {
-                let addr = self.get_value(a).expect("Invalid number");
+                let addr = self.get_value(a).ok_or("Invalid number")?;
 
                 //dbg!(addr);
-                if self.fn_patching {
+                if self.fn_patching && matches!(addr, 3 | 2125 | 6027) {
+                    self.stack.push(self.ip.get());
+                    // the hook must leave the return address for its `Ret`
+                    let before = self.stack.clone();
                     match addr {
                         3 => {
-                            self.stack.push(self.ip as u16);
-                            {
-                                // function code
-                                self.registers[0] = 20;
-                            }
-                            self.called_patched_fn = true;
-                            return;
-                        }
-                        2125 => {
-                            //let mut test = self.clone();
-                            //test.run_until_ret();
-
-                            //self.stack.push(self.ip as u16);
-                            //self.patched_2125();
-                            //self.called_patched_fn = true;
-
-                            //assert_eq!(&test, self);
-                            //return;
+                            // function code
+                            self.registers[0] = 20;
                         }
-                        6027 => {
-                            self.stack.push(self.ip as u16);
-                            let (r0, r1) = self.patched_6027(
-                                self.registers[0],
-                                self.registers[1],
-                                self.registers[7],
-                            );
+                        2125 => self.patched_2125(),
+                        _ => {
+                            let (r0, r1, r7) =
+                                (self.registers[0], self.registers[1], self.registers[7]);
+                            let (r0, r1) = match self.native_impl {
+                                NativeImpl::Memoized => self.patched_6027(r0, r1, r7),
+                                NativeImpl::Table => Self::table_6027(r0, r1, r7),
+                            };
                             self.registers[0] = r0;
                             self.registers[1] = r1;
-                            self.called_patched_fn = true;
-                            return;
                         }
-                        _ => (),
                     }
+                    self.check_hook(addr as usize, before);
+                    self.called_patched_fn = true;
+                    return Ok(());
                 }
 
-                self.stack.push(self.ip as u16);
-                self.ip = addr as usize;
+                let target = Addr::try_from(addr)?;
+                self.stack.push(self.ip.get());
+                self.call_stack.push(Frame {
+                    call_site: self.ip.wrapping_sub(instruction.size() as u16),
+                    target,
+                    depth: self.stack.len(),
+                });
+                let patched = self.patches.iter().any(|patch| {
+                    (patch.offset..patch.offset + patch.new.len()).contains(&(addr as usize))
+                });
+                if patched {
+                    self.patched_frames.push((
+                        self.call_stack.len() - 1,
+                        addr as usize,
+                        self.stack.clone(),
+                    ));
+                }
+                self.ip = target;
             }
-            Opcode::Ret => match self.stack.pop() {
-                Some(addr) => {
-                    self.ip = addr as usize;
+            Opcode::Ret => {
+                if !self.patched_frames.is_empty() {
+                    self.check_patched_ret();
                 }
-                None => {
-                    self.state = {
-                        println!("poped empty stack!");
-                        VmState::Halted
+                match self.stack.pop() {
+                    Some(addr) => {
+                        self.unwind_call_stack();
+                        self.ip = Addr::try_from(addr)?;
                     }
+                    // returning from the top level ends the program
+                    None => self.state = VmState::Halted,
                 }
-            },
+                if self
+                    .print_depth
+                    .is_some_and(|depth| self.stack.len() < depth)
+                {
+                    self.print_depth = None;
+                }
+            }
             Opcode::Out(a) => {
-                let c = self.get_value(a).expect("Invalid number");
+                let c = self.get_value(a).ok_or("Invalid number")?;
+                if self.print_depth.is_some() {
+                    if let Some(call) = self.print_calls.last_mut() {
+                        call.text.push(c as u8 as char);
+                    }
+                }
 
-                self.output_buffer.push(c as u8 as char);
+                match &mut self.raw_io {
+                    Some(raw) => raw.output.push(c as u8),
+                    None => self.output_buffer.push(c as u8 as char),
+                }
             }
             Opcode::In(a) => {
-                let reg = self.get_register(a).expect("In: not a register");
+                let reg = self.get_register(a).ok_or("In: not a register")?;
+
+                if let Some(raw) = &mut self.raw_io {
+                    match raw.input.pop_front() {
+                        Some(byte) => self.registers[reg] = byte as u16,
+                        None => {
+                            // stay on the `In`, to read when there's more input
+                            self.ip = self.ip.wrapping_sub(2);
+                            self.state = if raw.eof {
+                                VmState::Halted
+                            } else {
+                                VmState::WaitingForInput
+                            };
+                        }
+                    }
+                    return Ok(());
+                }
 
                 match self.input_buffer.pop_front() {
                     Some(c) => {
                         // just feed the current input
                         self.registers[reg] = c as u16;
+                        self.mid_line = c != '\n';
                     }
                     None => {
                         // asking for new input
                         // first, flush current output
-                        let out = self.output_buffer.iter().collect::<String>(); //TODO: separate function
-                        self.messages.push(out.clone());
-                        self.output_buffer = Vec::new();
+                        self.flush_output();
 
                         self.state = VmState::WaitingForInput;
-                        self.ip -= 2; // size of `In` instruction
+                        self.ip = self.ip.wrapping_sub(2); // size of `In` instruction
                     }
                 }
             }
             Opcode::Noop => (),
         }
+
+        Ok(())
     }
 
     fn get_value(&self, value: &Val) -> Option<u16> {
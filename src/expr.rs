@@ -0,0 +1,310 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::emulator::Vm;
+
+/// Binary operators by precedence, loosest first
+const LEVELS: [&[(&str, BinOp)]; 5] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[
+        ("==", BinOp::Eq),
+        ("!=", BinOp::Ne),
+        ("<=", BinOp::Le),
+        (">=", BinOp::Ge),
+        ("<", BinOp::Lt),
+        (">", BinOp::Gt),
+    ],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+];
+
+/// Symbols of the tokenizer, the longest first
+const SYMBOLS: [&str; 18] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")", "[", "]",
+];
+
+/// Expression over the state of the VM, like `mem[r1 + 2] == 5489 && ip > 6000`
+///
+/// Variables are the registers `r0` to `r7`, `ip` and `pc`, the instruction count. `mem[addr]`
+/// reads the memory. Values are `i64`, comparisons and `&&`, `||`, `!` give 1 or 0 like in C.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Expr {
+    /// Source of the expression, for `Display`
+    text: String,
+    root: Node,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Node {
+    Num(i64),
+    Reg(usize),
+    Ip,
+    Pc,
+    Mem(Box<Node>),
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Sym(&'static str),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let num = match word.strip_prefix("0x") {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => word.parse(),
+            };
+            tokens.push(Token::Num(
+                num.map_err(|_| format!("invalid number {:?}", word))?,
+            ));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_string()));
+            len
+        } else {
+            let sym = SYMBOLS
+                .iter()
+                .find(|sym| rest.starts_with(*sym))
+                .ok_or_else(|| format!("unexpected {:?}", c))?;
+            tokens.push(Token::Sym(sym));
+            sym.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, sym: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Sym(s)) if *s == sym);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, sym: &str) -> Result<(), String> {
+        if self.eat(sym) {
+            Ok(())
+        } else {
+            Err(format!("expected `{}`", sym))
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary();
+        };
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(&(_, op)) = ops.iter().find(|(sym, _)| self.eat(sym)) {
+            let right = self.binary(level + 1)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.eat("-") {
+            return Ok(Node::Neg(Box::new(self.unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Node::Num(n)),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "ip" => Ok(Node::Ip),
+                "pc" => Ok(Node::Pc),
+                "mem" => {
+                    self.expect("[")?;
+                    let addr = self.binary(0)?;
+                    self.expect("]")?;
+                    Ok(Node::Mem(Box::new(addr)))
+                }
+                _ => name
+                    .strip_prefix('r')
+                    .and_then(|r| r.parse().ok())
+                    .filter(|&r: &usize| r < 8)
+                    .map(Node::Reg)
+                    .ok_or_else(|| format!("unknown variable `{}`", name)),
+            },
+            Some(Token::Sym("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(Token::Sym(sym)) => Err(format!("unexpected `{}`", sym)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+}
+
+impl Node {
+    fn eval(&self, vm: &Vm) -> Result<i64, String> {
+        let value = match self {
+            Node::Num(n) => *n,
+            Node::Reg(r) => vm.get_registers()[*r] as i64,
+            Node::Ip => vm.get_ip().get() as i64,
+            Node::Pc => vm.get_pc() as i64,
+            Node::Mem(addr) => {
+                let addr = addr.eval(vm)?;
+                let word = usize::try_from(addr)
+                    .ok()
+                    .and_then(|addr| vm.get_memory().get(addr))
+                    .ok_or_else(|| format!("mem[{}] is out of memory", addr))?;
+                *word as i64
+            }
+            Node::Neg(node) => node.eval(vm)?.wrapping_neg(),
+            Node::Not(node) => (node.eval(vm)? == 0) as i64,
+            Node::Binary(BinOp::And, a, b) => (a.eval(vm)? != 0 && b.eval(vm)? != 0) as i64,
+            Node::Binary(BinOp::Or, a, b) => (a.eval(vm)? != 0 || b.eval(vm)? != 0) as i64,
+            Node::Binary(op, a, b) => {
+                let (a, b) = (a.eval(vm)?, b.eval(vm)?);
+                match op {
+                    BinOp::Eq => (a == b) as i64,
+                    BinOp::Ne => (a != b) as i64,
+                    BinOp::Lt => (a < b) as i64,
+                    BinOp::Le => (a <= b) as i64,
+                    BinOp::Gt => (a > b) as i64,
+                    BinOp::Ge => (a >= b) as i64,
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Mul => a.wrapping_mul(b),
+                    BinOp::Div | BinOp::Mod if b == 0 => return Err("division by zero".into()),
+                    BinOp::Div => a.wrapping_div(b),
+                    BinOp::Mod => a.wrapping_rem(b),
+                    BinOp::And | BinOp::Or => return Err("unreachable?".into()),
+                }
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, vm: &Vm) -> Result<i64, String> {
+        self.root.eval(vm)
+    }
+
+    /// Non zero, an expression that can't be evaluated is false
+    pub fn is_true(&self, vm: &Vm) -> bool {
+        self.eval(vm).is_ok_and(|value| value != 0)
+    }
+}
+
+impl std::str::FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |e: String| format!("Invalid expression {:?}: {}", s, e);
+        let mut parser = Parser {
+            tokens: tokenize(s).map_err(invalid)?,
+            pos: 0,
+        };
+        let root = parser.binary(0).map_err(invalid)?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(format!("unexpected {:?}", token)));
+        }
+
+        Ok(Expr {
+            text: s.trim().to_string(),
+            root,
+        })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Expression stopping the VM when its value changes, added with `watch add`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Watch {
+    pub expr: Expr,
+    /// `None` while it can't be evaluated
+    pub value: Option<i64>,
+    /// Value before the last change
+    pub previous: Option<i64>,
+    /// Instruction count of the last change
+    pub changed_at: Option<usize>,
+}
+
+impl Watch {
+    pub fn new(expr: Expr, vm: &Vm) -> Self {
+        Watch {
+            value: expr.eval(vm).ok(),
+            expr,
+            previous: None,
+            changed_at: None,
+        }
+    }
+
+    /// Evaluate again, true if the value changed
+    pub fn update(&mut self, vm: &Vm) -> bool {
+        let value = self.expr.eval(vm).ok();
+        if value == self.value {
+            return false;
+        }
+        self.previous = std::mem::replace(&mut self.value, value);
+        self.changed_at = Some(vm.get_pc());
+        true
+    }
+}
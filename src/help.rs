@@ -0,0 +1,839 @@
+use clap::Command;
+
+/// Curated documentation of a REPL command
+pub struct CommandHelp {
+    pub name: &'static str,
+    pub summary: &'static str,
+    /// (command line, explanation)
+    pub examples: &'static [(&'static str, &'static str)],
+}
+
+/// One entry per top level command of the REPL
+pub const COMMANDS: [CommandHelp; 55] = [
+    CommandHelp {
+        name: "help",
+        summary: "list the commands, or show the examples of one",
+        examples: &[("help bp", "breakpoint workflow")],
+    },
+    CommandHelp {
+        name: "tutorial",
+        summary: "first steps with the emulator",
+        examples: &[("tutorial", "")],
+    },
+    CommandHelp {
+        name: "artifacts",
+        summary: "generated files, in a folder per session like artifacts/2026-10-15_14-03-22/",
+        examples: &[
+            ("artifacts list", "files written by this session"),
+            ("artifacts list --all", "folders of all the sessions"),
+            ("artifacts open graphviz.dot", "with xdg-open"),
+            ("artifacts open", "the folder of this session"),
+        ],
+    },
+    CommandHelp {
+        name: "load",
+        summary: "start over with a program, when the emulator started without one",
+        examples: &[
+            ("load", "find challenge.bin again, like at startup"),
+            ("load ../challenge.bin", ""),
+        ],
+    },
+    CommandHelp {
+        name: "quit",
+        summary: "stop the emulator, `exit` works too",
+        examples: &[
+            ("quit", ""),
+            (
+                "quit --save autosave.json",
+                "dump the state to snaps/ first",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "run",
+        summary: "run until the game waits for input, halts, or hits a breakpoint",
+        examples: &[
+            ("run", "boot the game"),
+            (
+                "take tablet",
+                "anything that isn't a command is fed to the game",
+            ),
+            (
+                ">help",
+                "force a line to the game, here the game's own help",
+            ),
+            ("run --until-call 1518", "stop before the next call to 1518"),
+            (
+                "run --until-depth <2",
+                "until the call stack is shallower than 2",
+            ),
+            ("run --max-cycles 1000000", "after `vm costs on`"),
+        ],
+    },
+    CommandHelp {
+        name: "bench",
+        summary: "compare the patched functions to the original code",
+        examples: &[
+            ("snap load teleporter.json", "in front of the teleporter"),
+            ("vm register set 7 25734", ""),
+            (
+                "bench patch 6027 --max-steps 10000000",
+                "unpatched, memoized hook, and table",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "runfile",
+        summary: "run another program in a fresh VM, with limits",
+        examples: &[
+            (
+                "runfile hello.bin --max-steps 1000 --input answers.txt",
+                "each line of answers.txt answers a prompt",
+            ),
+            (
+                "runfile cat.bin --raw --input data.bin",
+                "bytes in and out, halts at the end of the input",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "continue",
+        summary: "run past the breakpoint the VM stopped on",
+        examples: &[("continue", ""), ("c", "short alias")],
+    },
+    CommandHelp {
+        name: "bg",
+        summary: "run on a background thread, and keep the prompt for long runs",
+        examples: &[
+            (
+                "bg run",
+                "start running, or continue after a breakpoint or a pause",
+            ),
+            ("bg run \"use teleporter\"", "answer the prompt"),
+            ("bg status", "the output and events so far"),
+            ("bg break 6027", "set a breakpoint while running"),
+            ("bg snap before_6027", "copy the running VM to a snapshot"),
+            ("bg fg", "pause, and get the VM back for the other commands"),
+        ],
+    },
+    CommandHelp {
+        name: "input",
+        summary: "feed a line to the game without running",
+        examples: &[("input look", "")],
+    },
+    CommandHelp {
+        name: "undo",
+        summary:
+            "rewind the VM and its patches before the last game inputs, not the codes and stats",
+        examples: &[
+            ("undo", "before the last input"),
+            ("undo 3", "before the third last input"),
+        ],
+    },
+    CommandHelp {
+        name: "source",
+        summary: "run a file of commands, stop at the first failing one",
+        examples: &[("source solve.txt", "with `assert` lines, a regression test")],
+    },
+    CommandHelp {
+        name: "tee",
+        summary: "append the inputs and the answers of the game to a transcript file",
+        examples: &[
+            (
+                "tee playthrough.txt",
+                "from the current prompt, `> ` lines for the inputs",
+            ),
+            ("tee", "the file being written"),
+            ("tee --stop", ""),
+        ],
+    },
+    CommandHelp {
+        name: "verify",
+        summary: "check that the game still answers like a recorded transcript",
+        examples: &[
+            (
+                "verify transcript golden.txt",
+                "replay the `> ` lines on a copy of the VM, report the first different line",
+            ),
+            (
+                "verify transcript golden.txt --update",
+                "record the outputs, e.g. in a file of `> ` inputs",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "assert",
+        summary: "fail the script if the VM isn't in this state",
+        examples: &[
+            ("assert reg r0 == 6", "==, !=, <, >, <= or >="),
+            ("assert mem[3952] == 22", ""),
+            (
+                "assert output contains \"self-test complete\"",
+                "the last message",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "on",
+        summary: "run a command when a condition becomes true",
+        examples: &[
+            (
+                "on mem 3952 == 30 do \"snap take vault\"",
+                "checked after each step of the runs, and after each command",
+            ),
+            (
+                "on output contains \"grue\" do \"snap take grue\"",
+                "conditions like `assert`",
+            ),
+            ("on list", ""),
+            ("on remove 0", ""),
+        ],
+    },
+    CommandHelp {
+        name: "play",
+        summary: "feed a file of game inputs, one per line",
+        examples: &[(
+            "play walkthrough.txt",
+            "`#` comments and blank lines are skipped",
+        )],
+    },
+    CommandHelp {
+        name: "game",
+        summary: "type game inputs without `>`, Tab completes verbs, things and exits",
+        examples: &[
+            (
+                "game",
+                "then `take tab<Tab>`, the inputs have their own history",
+            ),
+            (":bp list; look", "a debugger command, then a game input"),
+            (":game off", "back to the debugger commands"),
+        ],
+    },
+    CommandHelp {
+        name: "step",
+        summary: "execute instructions one by one",
+        examples: &[
+            ("step", "one instruction"),
+            ("step 10", "ten instructions"),
+            ("set explain on", "describe them in plain English"),
+            (
+                "step back 5",
+                "undo five instructions, after `vm journal on`",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "print",
+        summary: "evaluate an expression over the registers, ip, pc and the memory",
+        examples: &[
+            ("print r0 + 1", ""),
+            (
+                "print mem[0x1571]",
+                "a memory cell, addresses in decimal or hex",
+            ),
+            (
+                "print mem[r1 + 2] == 5489 && ip > 6000",
+                "comparisons and logical operators give 1 or 0",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "watch",
+        summary: "stop running when the value of an expression changes",
+        examples: &[
+            (
+                "watch add mem[3952]",
+                "stop after an instruction changes the cell",
+            ),
+            ("watch add r7 != 0", ""),
+            ("watch list", "the watched expressions and their values"),
+            ("watch remove 0", ""),
+        ],
+    },
+    CommandHelp {
+        name: "bp",
+        summary: "breakpoints on instruction addresses",
+        examples: &[
+            ("bp set 6027", "stop before executing 6027"),
+            ("run", "run until the breakpoint"),
+            ("continue", "execute 6027 and run until the next hit"),
+            ("stack", "see who called it"),
+            ("step 5", "continue slowly"),
+            ("bp list", ""),
+            ("bp unset 6027", ""),
+            (
+                "bp set 6027 --if \"r7 != 0\" --desc 'teleporter check'",
+                "only stop when r7 is set",
+            ),
+            ("bp set 1800 --cond \"r4 == 10\"", "--cond is --if"),
+            (
+                "bp set 1531 --if \"mem[r1] > 3 && r0 != 0\"",
+                "conditions are `print` expressions",
+            ),
+            ("bp disable 6027", "keep it for later"),
+            ("bp set @fn_start+12", "addresses take hex, @names and sums"),
+            (
+                "bp export teleporter.json",
+                "address, condition, enabled, description",
+            ),
+            ("bp import teleporter.json", "add them back, in a script"),
+        ],
+    },
+    CommandHelp {
+        name: "report",
+        summary: "export the disassembly and analysis",
+        examples: &[
+            ("vm coverage on", "color the executed code"),
+            ("run", ""),
+            (
+                "report html",
+                "in report/ of the artifacts, `artifacts open report/index.html`",
+            ),
+            (
+                "report html report/ --palette viridis --scaling linear",
+                "colorblind-safe heatmap",
+            ),
+            (
+                "report r2 synacor.r2",
+                "then r2 -i synacor.r2 challenge.bin",
+            ),
+            (
+                "report ghidra synacor.py",
+                "run it from Ghidra's Script Manager",
+            ),
+            (
+                "report callgraph calls.dot",
+                "every function, the calls to registers resolved by `trace start`",
+            ),
+            ("report callgraph calls.json --json", ""),
+        ],
+    },
+    CommandHelp {
+        name: "analysis",
+        summary: "functions analyzed once and saved, per binary",
+        examples: &[
+            ("vm coverage on", "find the executed functions"),
+            (
+                "analysis update",
+                "only the modified functions are analyzed again",
+            ),
+            ("analysis list", ""),
+            (
+                "analysis show 6027",
+                "args, saved registers, purity, blocks",
+            ),
+            ("analysis update --db patched.json", ""),
+            (
+                "dis fn 2125",
+                "warns if the function was modified since its analysis",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "bookmark",
+        summary: "labels saved with the analysis, used as @name in the commands",
+        examples: &[
+            ("bookmark add ackermann 6027", "saved with the analysis"),
+            ("dis at @ackermann 5", ""),
+            ("bp set @ackermann", ""),
+            ("bookmark list", "with the function and instruction"),
+            ("bookmark remove ackermann", ""),
+        ],
+    },
+    CommandHelp {
+        name: "label",
+        summary: "name addresses, the names are shown by `dis`, `bt` and the call graphs",
+        examples: &[
+            ("label set 6027 ackermann", "then `bp set @ackermann`"),
+            (
+                "patch Call(@ackermann) 5489 --dry-run",
+                "names work in operands too",
+            ),
+            ("label remove @ackermann", ""),
+            ("label list", "they are saved with `snap dump`"),
+        ],
+    },
+    CommandHelp {
+        name: "note",
+        summary: "comment addresses, the notes are shown by `dis`",
+        examples: &[
+            ("note add 5489 \"teleporter check call\"", "quote the text"),
+            ("note add @ackermann \"r0 = A(r0, r1)\"", ""),
+            ("note remove 5489", ""),
+            (
+                "note list",
+                "they are saved with `snap dump`, like the labels",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "alert",
+        summary: "get notified when the game prints something",
+        examples: &[
+            ("alert add \"Chiseled on the wall\"", ""),
+            (
+                "alert sink 'notify-send synacor \"$ALERT_MATCH\"'",
+                "desktop notification",
+            ),
+            (
+                "alert sink 'curl -s -d \"$ALERT_MATCH\" https://ntfy.sh/mytopic'",
+                "webhook",
+            ),
+            ("alert sink off", "only print the matches"),
+            ("alert list", ""),
+        ],
+    },
+    CommandHelp {
+        name: "var",
+        summary: "find and pin the memory cells that could make runs vary",
+        examples: &[
+            ("var watch 0 32767", "log the reads of every cell"),
+            ("look", "play a bit"),
+            ("var reads", "cells read with different values come first"),
+            (
+                "var pin 3952 0",
+                "keep a cell constant for deterministic runs",
+            ),
+            ("var list", ""),
+        ],
+    },
+    CommandHelp {
+        name: "dis",
+        summary: "disassemble the memory, a function, or a binary file",
+        examples: &[
+            ("dis at 0 20", "20 instructions from 0"),
+            (
+                "dis fn 6027",
+                "a whole function, with the registers it preserves",
+            ),
+            (
+                "dis graph",
+                "basic blocks of the current function, `>` marks ip",
+            ),
+            (
+                "dis graph -i",
+                "one block at a time: n/p, 1-9 follow an edge, s steps and follows ip, q quits",
+            ),
+            (
+                "dis callgraph --min-calls 100 --merge --scale",
+                "call graph of the trace, in callgraph.dot of the artifacts",
+            ),
+            (
+                "dis callgraph --color --palette heat",
+                "hot functions in red",
+            ),
+            ("dis file challenge.bin --offset 2125 --count 10", ""),
+        ],
+    },
+    CommandHelp {
+        name: "x",
+        summary: "examine the memory like gdb: words, instructions or strings",
+        examples: &[
+            ("x/16w 3952", "16 words in hex, with their characters"),
+            ("x/8i 6027", "8 instructions"),
+            (
+                "x/3s 27000",
+                "3 length prefixed strings, decoded once traced",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "asm",
+        summary: "encode, decode and document instructions, assemble files",
+        examples: &[
+            ("asm encode Set(Reg(1), 1531)", ""),
+            ("asm decode 9 32768 32769 4", ""),
+            ("asm help jt", "semantics of an opcode"),
+            (
+                "asm file hello.asm hello.bin",
+                "lines like `Out(NEWLINE)`, `.const NEWLINE 10`, `.word 1, 2`, `.string \"hi\"`",
+            ),
+            (
+                "runfile hello.bin",
+                "`.print \"hi\\n\"` assembles to an `Out` per character",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "patch",
+        summary: "overwrite an instruction",
+        examples: &[
+            ("patch Noop 5489 --dry-run", "show what would be replaced"),
+            (
+                "patch Noop 5489",
+                "asks before changing the instruction size",
+            ),
+            ("patch list", "the patches applied since the load"),
+            ("patch undo 0", "write back what patch 0 replaced"),
+            (
+                "patch apply teleporter.txt --dry-run",
+                "lines like `5489: Noop`, `@name+2: .word 1, 2` or `.const R8 25734`",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "mem",
+        summary: "read and write the memory, and search values like scanmem",
+        examples: &[
+            ("mem init", "remember every value of the memory"),
+            ("take tablet", "change something in the game"),
+            ("mem filter !=", "keep the addresses that changed"),
+            ("mem list", "show the remaining candidates"),
+            ("mem set 3952 22", "write a value"),
+            (
+                "mem get 0xf70+1",
+                "offsets and values also take hex and sums",
+            ),
+            (
+                "mem edit 3952",
+                "step through the words, Enter skips, a value or 'c' writes",
+            ),
+            (
+                "mem dump 25974 64",
+                "rows of 8 words in hex, with the characters they print",
+            ),
+            ("mem dump 25974 64 --width 16", ""),
+            (
+                "mem protect 2125 2148",
+                "stop before a Wmem into 2125..2148, `continue` writes",
+            ),
+            ("mem protect", "list the protected ranges"),
+            ("mem unprotect 0", ""),
+            ("mem fill 10 20 0 --dry-run", ""),
+            (
+                "mem dirty --clean",
+                "pages written since the load, then reset the counts",
+            ),
+            (
+                "mem dumpbin patched.bin",
+                "write the memory like challenge.bin",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "vm",
+        summary: "settings of the VM",
+        examples: &[
+            ("vm patch true", "run the slow functions natively"),
+            ("vm register set 7 25734", "set a register"),
+            ("vm banks 4", "extended memory, selected with wmem 32767"),
+            ("vm coverage on", "count executions, for `report html`"),
+            (
+                "vm journal on --size 1000",
+                "record the last steps for `step back`",
+            ),
+            (
+                "vm io",
+                "input and output buffers, when the game seems stuck",
+            ),
+            (
+                "vm costs on",
+                "count cycles, with the opcode_costs of config.json",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "bruteforce",
+        summary: "try each value of a register or memory cell, keep the matching outputs",
+        examples: &[
+            (
+                "bruteforce --var r7 --range 1..32768 --input \"use teleporter\" --success beach",
+                "after `vm patch true`, to skip the slow check",
+            ),
+            (
+                "bruteforce --var 3952 --range 0..=100 --input north --success \"vault door\"",
+                "an orb weight",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "profile",
+        summary: "find where the time goes: executions of each address and opcode",
+        examples: &[
+            ("profile start", "forget the last profile, and count"),
+            ("profile stop", ""),
+            (
+                "profile report --top 10",
+                "hottest addresses with their instruction",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "trace",
+        summary: "record the calls and returns, and print the dynamic call tree",
+        examples: &[
+            ("trace start", "forget the last trace, and record"),
+            (
+                "trace tree",
+                "calls with their instruction counts, repeats as ×N",
+            ),
+            ("trace tree --depth 3", "only the outer calls"),
+            (
+                "trace profile --top 10",
+                "inclusive and exclusive instructions per function",
+            ),
+            ("dis callgraph", "the same, as an annotated call graph"),
+            (
+                "trace start --strings",
+                "also record the strings of the printing routine found in the code",
+            ),
+            ("trace start --strings --routine 1518", "of another routine"),
+            (
+                "trace strings",
+                "calls of the printing routine, with their text",
+            ),
+            (
+                "trace decode",
+                "decode the strings of every call, printed or not",
+            ),
+            ("trace stop", ""),
+        ],
+    },
+    CommandHelp {
+        name: "state",
+        summary: "check whether two paths led to the same state",
+        examples: &[
+            ("state hash", "short hash of the live state"),
+            (
+                "state hash before-vault",
+                "of a snapshot, or of a file of snaps/",
+            ),
+            (
+                "state same live before-vault",
+                "quick verdict, before a full diff",
+            ),
+            (
+                "state list --sort name",
+                "snapshots in memory and files of snaps/",
+            ),
+            ("state find ruins", "by name or room"),
+            ("state gc", "size of the snapshots and of snaps/"),
+            (
+                "state gc --older-than 2d --duplicates --dry-run",
+                "what would be dropped",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "strings",
+        summary: "search the text hidden in the memory",
+        examples: &[
+            ("strings", "printable runs and `Out` of literal characters"),
+            ("strings --min 10 code", "long strings matching a regex"),
+            (
+                "trace start --strings",
+                "then play: the strings of the printing routine get decoded too",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "compare-input",
+        summary: "feed two inputs to clones of the VM, and diff the results",
+        examples: &[(
+            "compare-input \"use can\" \"use lantern\"",
+            "what does each item do",
+        )],
+    },
+    CommandHelp {
+        name: "session",
+        summary: "overview of the solving session",
+        examples: &[("session stats", "instructions, rooms, items, codes...")],
+    },
+    CommandHelp {
+        name: "messages",
+        summary: "outputs of the game, numbered by prompt",
+        examples: &[
+            ("messages", "first line of the last 10 messages"),
+            ("messages 0", "the boot output in full"),
+            ("messages --last 50", ""),
+        ],
+    },
+    CommandHelp {
+        name: "stack",
+        summary: "show the stack grouped in call frames",
+        examples: &[("stack", "")],
+    },
+    CommandHelp {
+        name: "bt",
+        summary: "backtrace: the calls leading to ip, named with the labels",
+        examples: &[("bt", ""), ("bookmark add decode 2125", "shown as decode")],
+    },
+    CommandHelp {
+        name: "snap",
+        summary: "save and restore the state of the game",
+        examples: &[
+            ("snap take before-vault", "keep the current state in memory"),
+            (
+                "snap take --tag coins",
+                "named after the room and the instruction count, like ruins-coins-01_2.3Minstr",
+            ),
+            (
+                "snap dump before-vault vault.json",
+                "write it in snaps/, as the words that differ from the program",
+            ),
+            ("snap dump before-vault vault.zst --compress", "kilobytes"),
+            ("snap load vault.json", "read it back, compressed or not"),
+            ("snap restore before-vault", ""),
+            ("snap list", ""),
+        ],
+    },
+    CommandHelp {
+        name: "timetravel",
+        summary: "snapshot the VM periodically, to jump back with goto-pc",
+        examples: &[
+            ("timetravel enable 1", "every million instructions"),
+            ("timetravel list", ""),
+        ],
+    },
+    CommandHelp {
+        name: "goto-pc",
+        summary: "go to an instruction count, using the timetravel snapshots",
+        examples: &[("goto-pc 500000", "")],
+    },
+    CommandHelp {
+        name: "solver",
+        summary: "automated exploration and puzzles",
+        examples: &[
+            (
+                "solver explore --max-rooms 100 --resume maze.json",
+                "map the rooms",
+            ),
+            ("solver graphviz maze.json --cluster area", "render the map"),
+            (
+                "solver walkthrough ^Ruins$ --maze maze.json",
+                "shortest inputs to a room",
+            ),
+            ("solver tablet", "the code of the tablet"),
+            ("solver fuzz-verbs", "find hidden commands"),
+            ("solver vault north east", "try every orb weight"),
+        ],
+    },
+    CommandHelp {
+        name: "glossary",
+        summary: "items seen while exploring, where and what they are",
+        examples: &[
+            (
+                "solver explore --resume maze.json",
+                "looks at every item it sees",
+            ),
+            ("glossary", "every item, with its rooms"),
+            (
+                "glossary book",
+                "rooms and description of the matching items, with their texts",
+            ),
+            ("glossary --maze maze.json", "from a saved maze database"),
+            (
+                "glossary --texts",
+                "the items with something written, like the tablet and the journal",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "hint",
+        summary: "suggest the next objective when stuck",
+        examples: &[
+            (
+                "hint",
+                "from the items held, the rooms visited and the codes",
+            ),
+            ("hint --full", "with the answers"),
+        ],
+    },
+    CommandHelp {
+        name: "codes",
+        summary: "codes captured so far",
+        examples: &[("codes", "")],
+    },
+    CommandHelp {
+        name: "copy",
+        summary: "copy an artifact to the clipboard, `! command` runs a shell command",
+        examples: &[
+            ("copy codes", "one `slot code` line per code found"),
+            ("copy last-output", "the last answer of the game"),
+            (
+                "copy graphviz.dot",
+                "the content of a file, in the artifacts first",
+            ),
+            (
+                "! dot -Tsvg maze.dot > map.svg; xdg-open map.svg",
+                "the rest of the line is run by `sh -c`",
+            ),
+        ],
+    },
+    CommandHelp {
+        name: "set",
+        summary: "settings of the REPL",
+        examples: &[
+            ("set confirm off", "don't ask before destructive commands"),
+            ("set explain on", "describe each stepped instruction"),
+            (
+                "set prompt \"{state} {room} {pc} >> \"",
+                "like `input Foothills 1.2M >> `, or `prompt` in config.json",
+            ),
+            (
+                "set watchdog off",
+                "on when stdin is piped: scripts, `source`, `play` and `runfile --input` can end \
+                 while the game waits for input",
+            ),
+        ],
+    },
+];
+
+/// Steps of the `tutorial` command: (command line, explanation)
+pub const TUTORIAL: [(&str, &str); 8] = [
+    (
+        "run",
+        "boot the VM: it runs the self-test, then waits in the foothills",
+    ),
+    ("take tablet", "lines that are not commands are game inputs"),
+    ("snap take start", "keep this state, to come back later"),
+    (
+        "snap dump start start.json",
+        "and save it to snaps/start.json",
+    ),
+    ("dis at 0 10", "look at the code"),
+    ("bp set 1531", "stop the next time this address is reached"),
+    ("run", "then `stack` and `step` to see what happens"),
+    (
+        "snap restore start",
+        "go back, `help` lists everything else",
+    ),
+];
+
+pub fn find(name: &str) -> Option<&'static CommandHelp> {
+    COMMANDS.iter().find(|help| help.name == name)
+}
+
+/// Summaries of all the commands
+pub fn print_commands() {
+    for help in &COMMANDS {
+        println!("{:>12}  {}", help.name, help.summary);
+    }
+    println!(
+        "\n`help <command>` for examples, `>line` to send a line to the game, \
+         `! command` to run a shell command"
+    );
+}
+
+/// Usage and examples of a command
+pub fn print_command(cli: &Command, help: &CommandHelp) {
+    println!("{}: {}\n", help.name, help.summary);
+    if let Some(mut command) = cli.find_subcommand(help.name).cloned() {
+        println!("{}\n", command.render_usage());
+    }
+
+    println!("Examples:");
+    for (line, explanation) in help.examples {
+        if explanation.is_empty() {
+            println!("    >> {}", line);
+        } else {
+            println!("    >> {:<40} {}", line, explanation);
+        }
+    }
+}
+
+pub fn print_tutorial() {
+    for (idx, (line, explanation)) in TUTORIAL.iter().enumerate() {
+        println!("{}. {}\n    >> {}", idx + 1, explanation, line);
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeSet;
+
+use crate::codes::{CodeRegistry, CodeSlot};
+
+/// What the `hint` rules know about the game
+pub struct HintContext<'a> {
+    /// Items held, see `planner::inventory`
+    pub inventory: Vec<String>,
+    /// Names of the rooms visited during the session
+    pub rooms: BTreeSet<String>,
+    pub codes: &'a CodeRegistry,
+    /// Eighth register, set for the second destination of the teleporter
+    pub r7: u16,
+}
+
+impl HintContext<'_> {
+    fn holds(&self, item: &str) -> bool {
+        self.inventory.iter().any(|i| i == item)
+    }
+
+    fn holds_coins(&self) -> bool {
+        self.inventory.iter().any(|i| i.ends_with(" coin"))
+    }
+
+    fn visited(&self, room: &str) -> bool {
+        self.rooms.iter().any(|r| r.contains(room))
+    }
+
+    fn has_code(&self, slot: CodeSlot) -> bool {
+        self.codes.get(slot).is_some()
+    }
+}
+
+/// A rule of the `hint` table
+pub struct Hint {
+    pub name: &'static str,
+    applies: fn(&HintContext) -> bool,
+    /// The next objective, without the answer
+    pub vague: &'static str,
+    /// The answer, for `hint --full`
+    pub full: &'static str,
+}
+
+/// The rules, in the order of the game: the first applying one is the next objective
+pub const HINTS: [Hint; 11] = [
+    Hint {
+        name: "take tablet",
+        applies: |ctx| !ctx.has_code(CodeSlot::Tablet) && !ctx.holds("tablet"),
+        vague: "something lies on the ground of the first room",
+        full: "`take tablet` in the Foothills, then `use tablet`",
+    },
+    Hint {
+        name: "use tablet",
+        applies: |ctx| !ctx.has_code(CodeSlot::Tablet) && ctx.holds("tablet"),
+        vague: "you hold a writing surface, but haven't written anything",
+        full: "`use tablet` writes the code",
+    },
+    Hint {
+        name: "fill lantern",
+        applies: |ctx| ctx.holds("empty lantern") && ctx.holds("can"),
+        vague: "your lantern is empty, and you hold a can",
+        full: "`use can` fills the lantern, then `use lantern` lights it",
+    },
+    Hint {
+        name: "light lantern",
+        applies: |ctx| ctx.holds("lantern"),
+        vague: "your lantern could be lit",
+        full: "`use lantern`, the grues stay away from a lit lantern",
+    },
+    Hint {
+        name: "twisty passages",
+        applies: |ctx| !ctx.has_code(CodeSlot::TwistyPassages),
+        vague: "a code is hidden in the dark passages below the foothills",
+        full: "down the ladder of the dark passage, the code is chiseled on a wall of the \
+               twisty passages, `solver walkthrough` finds the way",
+    },
+    Hint {
+        name: "find the monument",
+        applies: |ctx| ctx.holds_coins() && !ctx.visited("Ruins"),
+        vague: "you have coins but haven't visited the monument",
+        full: "the monument is in the Ruins, `solver walkthrough Ruins --maze maze.json`",
+    },
+    Hint {
+        name: "solve the monument",
+        applies: |ctx| ctx.holds_coins() && ctx.visited("Ruins"),
+        vague: "the equation of the monument has a slot per coin, `look` at the coins",
+        full: "_ + _ * _^2 + _^3 - _ = 399: use the blue, red, shiny, concave then corroded coin",
+    },
+    Hint {
+        name: "use teleporter",
+        applies: |ctx| ctx.holds("teleporter") && !ctx.has_code(CodeSlot::Teleporter),
+        vague: "you hold a device you haven't tried",
+        full: "`use teleporter` takes you to Synacor Headquarters",
+    },
+    Hint {
+        name: "set r7",
+        applies: |ctx| {
+            ctx.has_code(CodeSlot::Teleporter) && !ctx.has_code(CodeSlot::Beach) && ctx.r7 == 0
+        },
+        vague: "teleporter present but r7 is 0, read the strange book",
+        full: "`vm register set 7 25734` and `vm patch true` to skip the check, then \
+               `use teleporter`, see `solver teleporter`",
+    },
+    Hint {
+        name: "vault",
+        applies: |ctx| ctx.has_code(CodeSlot::Beach) && !ctx.holds("mirror"),
+        vague: "the weight of the orb must match the number on the vault door",
+        full: "`solver vault` finds the shortest path in the grid of rooms, from the orb",
+    },
+    Hint {
+        name: "use mirror",
+        applies: |ctx| ctx.holds("mirror") && !ctx.has_code(CodeSlot::Mirror),
+        vague: "you can see yourself in what you hold",
+        full: "`use mirror`, the code is reversed and `codes` shows it read back",
+    },
+];
+
+/// The hints applying to `ctx`, the next objective first
+pub fn hints(ctx: &HintContext) -> Vec<&'static Hint> {
+    HINTS.iter().filter(|hint| (hint.applies)(ctx)).collect()
+}
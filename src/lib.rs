@@ -1,6 +1,20 @@
+pub mod analysis;
+pub mod artifacts;
 pub mod cli;
+pub mod codes;
+pub mod config;
+pub mod diff;
+pub mod driver;
 pub mod emulator;
+pub mod expr;
+pub mod help;
+pub mod hints;
+pub mod planner;
+pub mod report;
 pub mod solver;
+pub mod viz;
+
+pub use solver::{Game, GameResponse, Level, SavedGame};
 
 #[cfg(test)]
 mod tests;
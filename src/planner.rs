@@ -0,0 +1,309 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+use regex::Regex;
+
+use crate::emulator::{Checkpoint, Vm, VmState};
+use crate::solver::{GameResponse, Level, Maze, CURRENT_ROOM};
+
+/// What matters to the planner: where we are, and what we carry
+#[derive(Clone, Debug)]
+pub struct GameState {
+    /// Identifier of the room in the game memory, some rooms have identical texts
+    pub room_id: u16,
+    pub room: Level,
+    /// Sorted
+    pub inventory: Vec<String>,
+    /// Items that left the inventory, in order of first removal (e.g. coins in the monument)
+    pub consumed: Vec<String>,
+}
+
+impl GameState {
+    /// Read the state of a VM waiting for input, using `look` and `inv` on clones
+    pub fn from_vm(vm: &Vm) -> Result<Self, Box<dyn std::error::Error>> {
+        let room = match act(vm, "look")?.1 {
+            GameResponse::Room(room) => room,
+            x => return Err(format!("Can't find current room: {:?}", x).into()),
+        };
+        let inventory = inventory(vm)?;
+
+        Ok(GameState {
+            room_id: vm.get_memory()[CURRENT_ROOM],
+            room,
+            inventory,
+            consumed: Vec::new(),
+        })
+    }
+
+    fn key(&self) -> (u16, Vec<String>, Vec<String>) {
+        (self.room_id, self.inventory.clone(), self.consumed.clone())
+    }
+}
+
+/// Feed `input` to a clone of `vm`
+fn act(vm: &Vm, input: &str) -> Result<(Vm, GameResponse), Box<dyn std::error::Error>> {
+    let mut vm = vm.clone();
+    let response = act_mut(&mut vm, input)?;
+
+    Ok((vm, response))
+}
+
+fn act_mut(vm: &mut Vm, input: &str) -> Result<GameResponse, Box<dyn std::error::Error>> {
+    vm.feed(input)?;
+    vm.run();
+
+    Ok(GameResponse::from_vm(vm))
+}
+
+/// Feed `input` to `vm`, then go back to where it was
+fn probe(vm: &mut Vm, input: &str) -> Result<(GameResponse, String), Box<dyn std::error::Error>> {
+    let checkpoint = vm.checkpoint();
+    let response = act_mut(vm, input)?;
+    let message = vm.get_messages().last().cloned().unwrap_or_default();
+    vm.restore(&checkpoint)?;
+
+    Ok((response, message))
+}
+
+/// Items held, asked to a clone of the VM
+pub fn inventory(vm: &Vm) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    inventory_mut(&mut vm.clone())
+}
+
+fn inventory_mut(vm: &mut Vm) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let (_, message) = probe(vm, "inv")?;
+
+    let mut inventory: Vec<String> = message
+        .lines()
+        .skip_while(|line| !line.starts_with("Your inventory:"))
+        .skip(1)
+        .take_while(|line| line.starts_with("- "))
+        .map(|line| line[2..].to_string())
+        .collect();
+    inventory.sort();
+
+    Ok(inventory)
+}
+
+/// Estimation of the number of inputs left to reach the goal, used to order the search
+///
+/// Never overestimate, or the walkthrough found may not be the shortest. The states are
+/// closed when expanded, so the estimate must also not drop by more than 1 per input.
+pub trait Heuristic {
+    fn estimate(&self, state: &GameState) -> usize;
+}
+
+/// Blind search, equivalent to a breadth first search
+pub struct NoHeuristic;
+
+impl Heuristic for NoHeuristic {
+    fn estimate(&self, _state: &GameState) -> usize {
+        0
+    }
+}
+
+/// One `take` per wanted item not yet in the inventory
+pub struct MissingItems {
+    pub items: Vec<Regex>,
+}
+
+impl Heuristic for MissingItems {
+    fn estimate(&self, state: &GameState) -> usize {
+        self.items
+            .iter()
+            .filter(|item| !state.inventory.iter().any(|i| item.is_match(i)))
+            .count()
+    }
+}
+
+/// Distance to the goal rooms in the graph of an explored maze
+pub struct RoomDistance {
+    /// By `Level::stable_hash`
+    distances: HashMap<u64, usize>,
+}
+
+impl RoomDistance {
+    /// Backward breadth first search from the rooms matching `room`
+    pub fn new(maze: &Maze, room: &Regex) -> Self {
+        let mut distances = HashMap::new();
+        let mut queue = VecDeque::new();
+        for level in maze
+            .explored
+            .iter()
+            .chain(maze.edges.iter().map(|(_, _, to)| to))
+        {
+            if room.is_match(&level.name) && distances.insert(level.stable_hash(), 0).is_none() {
+                queue.push_back(level.stable_hash());
+            }
+        }
+
+        while let Some(hash) = queue.pop_front() {
+            let distance = distances[&hash];
+            for (from, _, to) in &maze.edges {
+                if to.stable_hash() == hash && !distances.contains_key(&from.stable_hash()) {
+                    distances.insert(from.stable_hash(), distance + 1);
+                    queue.push_back(from.stable_hash());
+                }
+            }
+        }
+
+        RoomDistance { distances }
+    }
+}
+
+impl Heuristic for RoomDistance {
+    /// Unknown rooms are estimated at 0
+    fn estimate(&self, state: &GameState) -> usize {
+        self.distances
+            .get(&state.room.stable_hash())
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// The largest of the estimations
+impl Heuristic for Vec<Box<dyn Heuristic>> {
+    fn estimate(&self, state: &GameState) -> usize {
+        self.iter().map(|h| h.estimate(state)).max().unwrap_or(0)
+    }
+}
+
+/// Counters of a search
+#[derive(Debug, Default)]
+pub struct SearchStats {
+    /// States taken out of the queue
+    pub expanded: usize,
+    /// New states found
+    pub generated: usize,
+}
+
+/// Inputs reaching the goal, if found
+pub type SearchResult = (Option<Vec<String>>, SearchStats);
+
+/// A* search over (room, inventory) states
+///
+/// Actions are the exits of the room, `take` of the things in the room, and `use` of the
+/// items in the inventory. Deaths are pruned. Without heuristic, this is a breadth first search.
+pub struct Planner {
+    pub max_states: usize,
+}
+
+impl Default for Planner {
+    fn default() -> Self {
+        Planner { max_states: 20000 }
+    }
+}
+
+impl Planner {
+    /// Shortest list of inputs reaching a state matching `goal`
+    pub fn search<F>(
+        &self,
+        vm: &Vm,
+        goal: F,
+    ) -> Result<Option<Vec<String>>, Box<dyn std::error::Error>>
+    where
+        F: Fn(&GameState) -> bool,
+    {
+        Ok(self.search_with(vm, goal, &NoHeuristic)?.0)
+    }
+
+    /// Shortest list of inputs reaching a state matching `goal`, guided by `heuristic`
+    pub fn search_with<F>(
+        &self,
+        vm: &Vm,
+        goal: F,
+        heuristic: &dyn Heuristic,
+    ) -> Result<SearchResult, Box<dyn std::error::Error>>
+    where
+        F: Fn(&GameState) -> bool,
+    {
+        let mut stats = SearchStats::default();
+        let start = GameState::from_vm(vm)?;
+
+        // a single VM, branched with checkpoints
+        let mut work = vm.clone();
+        // shortest known path length of each state, and the states expanded with it
+        let mut best = HashMap::new();
+        best.insert(start.key(), 0);
+        let mut closed = HashSet::new();
+        // the queue points to the nodes by index, the oldest first on ties
+        let mut nodes: Vec<Option<(GameState, Checkpoint, Vec<String>)>> = Vec::new();
+        // (estimated total, node index), smallest first
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse((heuristic.estimate(&start), 0)));
+        nodes.push(Some((start, work.checkpoint(), Vec::new())));
+
+        while let Some(Reverse((_, idx))) = queue.pop() {
+            let (state, checkpoint, path) = nodes[idx].take().unwrap();
+            if !closed.insert(state.key()) {
+                // reached again by a shorter path, already expanded
+                continue;
+            }
+            stats.expanded += 1;
+            if goal(&state) {
+                return Ok((Some(path), stats));
+            }
+            if best.len() >= self.max_states {
+                println!("Giving up after {} states", best.len());
+                break;
+            }
+
+            let actions = state
+                .room
+                .exits
+                .iter()
+                .cloned()
+                .chain(state.room.things.iter().map(|t| format!("take {}", t)))
+                .chain(state.inventory.iter().map(|i| format!("use {}", i)));
+
+            for action in actions {
+                work.restore(&checkpoint)?;
+                let response = act_mut(&mut work, &action)?;
+                if work.get_state() != VmState::WaitingForInput {
+                    continue;
+                }
+
+                let mut new_state = state.clone();
+                new_state.room_id = work.get_memory()[CURRENT_ROOM];
+                match response {
+                    GameResponse::Terminal(_) => continue,
+                    GameResponse::Room(room) => new_state.room = room,
+                    GameResponse::Message(_) => (),
+                }
+                if action.starts_with("take ") || action.starts_with("use ") {
+                    new_state.inventory = inventory_mut(&mut work)?;
+                    for item in &state.inventory {
+                        // only once, the lantern can be lit and unlit forever
+                        if !new_state.inventory.contains(item) && !new_state.consumed.contains(item)
+                        {
+                            new_state.consumed.push(item.clone());
+                        }
+                    }
+                    if action.starts_with("take ") {
+                        // refresh the things of the room
+                        if let GameResponse::Room(room) = probe(&mut work, "look")?.0 {
+                            new_state.room = room;
+                        }
+                    }
+                }
+
+                let key = new_state.key();
+                let cost = path.len() + 1;
+                if closed.contains(&key) || best.get(&key).is_some_and(|&known| known <= cost) {
+                    continue;
+                }
+                best.insert(key, cost);
+
+                let mut new_path = path.clone();
+                new_path.push(action);
+
+                stats.generated += 1;
+                let estimate = new_path.len() + heuristic.estimate(&new_state);
+                queue.push(Reverse((estimate, nodes.len())));
+                nodes.push(Some((new_state, work.checkpoint(), new_path)));
+            }
+        }
+
+        Ok((None, stats))
+    }
+}
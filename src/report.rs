@@ -0,0 +1,1386 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::{self, Write as _};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::{Opcode, PrintCall, SymbolTable, TraceEntry, Val, Vm};
+use crate::viz::Gradient;
+
+/// Shortest run of printable words reported as a string
+const MIN_STRING_LEN: usize = 4;
+
+/// Pixels of a line of the CFG boxes
+const LINE_HEIGHT: usize = 14;
+const BLOCK_WIDTH: usize = 340;
+
+/// Instructions between two branchings, and where they continue
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub instructions: Vec<(usize, Opcode)>,
+    pub successors: Vec<usize>,
+}
+
+/// Static analysis of the memory of a VM, rendered by `report html`
+pub struct Analysis {
+    /// Start -> instructions
+    pub functions: BTreeMap<usize, Vec<(usize, Opcode)>>,
+    /// Length-prefixed strings of the memory: (address of the length, text)
+    ///
+    /// With the recorded calls of the printing routine, see `StringDecoder`, the strings it
+    /// prints are decoded.
+    pub strings: Vec<(usize, String)>,
+    /// Address -> instructions with this address as a literal operand
+    pub xrefs: BTreeMap<usize, BTreeSet<usize>>,
+    /// Execution counts, if coverage was enabled
+    pub coverage: Option<Vec<u32>>,
+    /// Colors of the executed instructions, by execution count
+    pub gradient: Gradient,
+}
+
+/// Addresses the instruction can continue to, inside its function
+fn successors(ip: usize, opcode: &Opcode) -> Vec<usize> {
+    let next = ip + opcode.size();
+    let target = |val: &Val| val.addr().map(usize::from);
+
+    match opcode {
+        Opcode::Halt | Opcode::Ret => vec![],
+        Opcode::Jmp(a) => target(a).into_iter().collect(),
+        Opcode::Jt(_, b) | Opcode::Jf(_, b) => {
+            let mut successors = vec![next];
+            successors.extend(target(b));
+            successors
+        }
+        _ => vec![next],
+    }
+}
+
+/// Literal operands of an instruction
+fn literals(opcode: &Opcode) -> Vec<usize> {
+    opcode.machine_code()[1..]
+        .iter()
+        .filter(|&&word| word < 32768)
+        .map(|&word| word as usize)
+        .collect()
+}
+
+/// Instructions reachable from `start` without following calls
+///
+/// Unlike `Vm::disassemble_function`, nothing falls through a `Jmp`, and paths running into
+/// undecodable words stop there.
+pub fn walk_function(memory: &[u16], start: usize) -> Vec<(usize, Opcode)> {
+    let mut instructions = BTreeMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(ip) = queue.pop_front() {
+        if instructions.contains_key(&ip) {
+            continue;
+        }
+        let Ok(opcode) = Opcode::decode_strict(memory.get(ip..).unwrap_or_default(), ip) else {
+            continue;
+        };
+
+        queue.extend(successors(ip, &opcode));
+        instructions.insert(ip, opcode);
+    }
+
+    instructions.into_iter().collect()
+}
+
+/// Functions found from 0, the current call stack, the executed calls if coverage is enabled,
+/// and the traced calls, then by following the calls to literal addresses
+pub fn discover_functions(vm: &Vm) -> BTreeMap<usize, Vec<(usize, Opcode)>> {
+    let memory = vm.get_memory();
+    let mut queue = VecDeque::from([0]);
+    queue.extend(
+        vm.get_call_stack()
+            .iter()
+            .map(|frame| usize::from(frame.target)),
+    );
+    queue.extend(
+        indirect_calls(vm.get_trace_buffer())
+            .into_values()
+            .flatten(),
+    );
+    if let Some(coverage) = vm.get_coverage() {
+        for (ip, _) in coverage.iter().enumerate().filter(|(_, &hits)| hits > 0) {
+            if let Ok(Opcode::Call(Val::Num(target))) = Opcode::decode(&memory[ip..]) {
+                queue.push_back(target as usize);
+            }
+        }
+    }
+
+    let mut functions = BTreeMap::new();
+    while let Some(start) = queue.pop_front() {
+        if functions.contains_key(&start) {
+            continue;
+        }
+
+        let instructions = walk_function(memory, start);
+        for (_, opcode) in &instructions {
+            if let Opcode::Call(Val::Num(target)) = opcode {
+                queue.push_back(*target as usize);
+            }
+        }
+        functions.insert(start, instructions);
+    }
+
+    functions
+}
+
+/// Split a function in basic blocks, in address order
+pub fn basic_blocks(instructions: &[(usize, Opcode)]) -> Vec<BasicBlock> {
+    let addresses: BTreeSet<usize> = instructions.iter().map(|(ip, _)| *ip).collect();
+
+    let mut leaders = BTreeSet::new();
+    if let Some((first, _)) = instructions.first() {
+        leaders.insert(*first);
+    }
+    for (ip, opcode) in instructions {
+        let successors = successors(*ip, opcode);
+        if successors != [ip + opcode.size()] {
+            leaders.extend(successors.into_iter().filter(|s| addresses.contains(s)));
+            leaders.insert(ip + opcode.size());
+        }
+    }
+
+    let mut blocks: Vec<BasicBlock> = Vec::new();
+    for &(ip, opcode) in instructions {
+        let contiguous = blocks
+            .last()
+            .and_then(|block| block.instructions.last())
+            .is_some_and(|(last, op)| last + op.size() == ip);
+        if leaders.contains(&ip) || !contiguous {
+            blocks.push(BasicBlock {
+                start: ip,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+            });
+        }
+        let block = blocks.last_mut().unwrap();
+        block.instructions.push((ip, opcode));
+        block.successors = successors(ip, &opcode)
+            .into_iter()
+            .filter(|s| addresses.contains(s))
+            .collect();
+    }
+
+    blocks
+}
+
+/// Basic blocks drawn as boxes in address order, `>` marks `ip`
+///
+/// The instructions end with the comments of `symbols`.
+pub fn text_graph(
+    instructions: &[(usize, Opcode)],
+    ip: Option<usize>,
+    symbols: &SymbolTable,
+) -> String {
+    let blocks = basic_blocks(instructions);
+    let lines: Vec<Vec<String>> = blocks
+        .iter()
+        .map(|block| block_lines(block, ip, symbols))
+        .collect();
+    let width = lines.iter().flatten().map(|l| l.len()).max().unwrap_or(0) + 2;
+
+    let mut graph = String::new();
+    for (block, lines) in blocks.iter().zip(lines) {
+        draw_block(&mut graph, block.start, &lines, width);
+        let successors: Vec<String> = block.successors.iter().map(|s| s.to_string()).collect();
+        match successors.is_empty() {
+            true => graph.push_str("  (end)\n"),
+            false => {
+                let _ = writeln!(graph, "  -> {}", successors.join(", "));
+            }
+        }
+    }
+
+    graph
+}
+
+/// The block of `blocks` starting at `start`, between the blocks jumping to it and its
+/// numbered successors, for `dis graph -i`
+pub fn text_block(
+    blocks: &[BasicBlock],
+    start: usize,
+    ip: Option<usize>,
+    symbols: &SymbolTable,
+) -> String {
+    let Some(block) = blocks.iter().find(|b| b.start == start) else {
+        return format!("No block at {}\n", start);
+    };
+    let predecessors: Vec<String> = blocks
+        .iter()
+        .filter(|b| b.successors.contains(&start))
+        .map(|b| b.start.to_string())
+        .collect();
+    let lines = block_lines(block, ip, symbols);
+    let width = lines.iter().map(|l| l.len()).max().unwrap_or(0) + 2;
+
+    let mut view = String::new();
+    match predecessors.is_empty() {
+        true => view.push_str("  (entry)\n"),
+        false => {
+            let _ = writeln!(view, "  <- {}", predecessors.join(", "));
+        }
+    }
+    draw_block(&mut view, block.start, &lines, width);
+    if block.successors.is_empty() {
+        view.push_str("  (end)\n");
+    }
+    for (n, successor) in block.successors.iter().enumerate() {
+        let _ = writeln!(view, "  {}) -> {}", n + 1, successor);
+    }
+
+    view
+}
+
+/// The instructions of `block`, `>` marks `ip`
+fn block_lines(block: &BasicBlock, ip: Option<usize>, symbols: &SymbolTable) -> Vec<String> {
+    block
+        .instructions
+        .iter()
+        .map(|(addr, opcode)| {
+            let marker = if Some(*addr) == ip { ">" } else { " " };
+            match symbols.comment(*addr, opcode) {
+                Some(comment) => format!("{} {}: {:?}  # {}", marker, addr, opcode, comment),
+                None => format!("{} {}: {:?}", marker, addr, opcode),
+            }
+        })
+        .collect()
+}
+
+fn draw_block(out: &mut String, start: usize, lines: &[String], width: usize) {
+    let title = format!("+-- {} ", start);
+    let _ = writeln!(
+        out,
+        "{}{}+",
+        title,
+        "-".repeat(width.saturating_sub(title.len() - 1))
+    );
+    for line in lines {
+        let _ = writeln!(out, "|{:<w$}|", line, w = width);
+    }
+    let _ = writeln!(out, "+{}+", "-".repeat(width));
+}
+
+/// Call of the dynamic call tree, nodes are in an arena to handle deep recursions
+struct CallNode {
+    /// `None` for the root, and for a call made before the trace started
+    target: Option<usize>,
+    /// Address of the `Call`
+    call_site: Option<usize>,
+    /// `pc` of the `Call`, and after the `Ret`
+    start: usize,
+    end: usize,
+    children: Vec<usize>,
+    /// Hash of the targets of the subtree, equal for repeated calls
+    shape: u64,
+}
+
+impl CallNode {
+    fn label(&self, symbols: &SymbolTable) -> String {
+        self.target.map_or("?".to_string(), |t| symbols.name(t))
+    }
+
+    fn instructions(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// Rebuild the calls of a trace of `Call` and `Ret`, the root is the first node
+fn build_call_tree(trace: &[TraceEntry], end_pc: usize) -> Vec<CallNode> {
+    fn close(nodes: &mut [CallNode], id: usize) {
+        let mut hasher = DefaultHasher::new();
+        nodes[id].target.hash(&mut hasher);
+        for &child in &nodes[id].children {
+            nodes[child].shape.hash(&mut hasher);
+        }
+        nodes[id].shape = hasher.finish();
+    }
+
+    let start = trace.first().map_or(end_pc, |entry| entry.pc);
+    let node = |target, call_site, start| CallNode {
+        target,
+        call_site,
+        start,
+        end: end_pc,
+        children: Vec::new(),
+        shape: 0,
+    };
+    let mut nodes = vec![node(None, None, start)];
+    let mut open = vec![0];
+    for entry in trace {
+        match entry.opcode {
+            Opcode::Call(a) => {
+                let id = nodes.len();
+                let target = entry.value(&a).map(usize::from);
+                nodes.push(node(target, Some(entry.ip.into()), entry.pc));
+                nodes[*open.last().unwrap()].children.push(id);
+                open.push(id);
+            }
+            Opcode::Ret if open.len() > 1 => {
+                let id = open.pop().unwrap();
+                nodes[id].end = entry.pc + 1;
+                close(&mut nodes, id);
+            }
+            Opcode::Ret => {
+                // the trace so far ran inside a call made before it started
+                let id = nodes.len();
+                let mut caller = node(None, None, start);
+                caller.end = entry.pc + 1;
+                caller.children = std::mem::take(&mut nodes[0].children);
+                nodes.push(caller);
+                close(&mut nodes, id);
+                nodes[0].children.push(id);
+            }
+            _ => (),
+        }
+    }
+    for &id in open.iter().rev() {
+        close(&mut nodes, id);
+    }
+
+    nodes
+}
+
+/// Dynamic call tree of a trace of `Call` and `Ret`, as indented text
+///
+/// Each call shows the instructions it executed, callees included. Consecutive calls with the
+/// same subtree are collapsed as `×N`. Calls still running end at `end_pc`.
+pub fn call_tree(
+    trace: &[TraceEntry],
+    end_pc: usize,
+    symbols: &SymbolTable,
+    max_depth: usize,
+) -> String {
+    fn render(
+        nodes: &[CallNode],
+        symbols: &SymbolTable,
+        id: usize,
+        depth: usize,
+        max_depth: usize,
+        tree: &mut String,
+    ) {
+        let children = &nodes[id].children;
+        if depth == max_depth {
+            if !children.is_empty() {
+                let _ = writeln!(tree, "{}...", "  ".repeat(depth + 1));
+            }
+            return;
+        }
+        for group in children.chunk_by(|&a, &b| nodes[a].shape == nodes[b].shape) {
+            let count: usize = group.iter().map(|&c| nodes[c].instructions()).sum();
+            let repeat = match group.len() {
+                1 => String::new(),
+                n => format!(" ×{}", n),
+            };
+            let _ = writeln!(
+                tree,
+                "{}{}{}: {} instructions",
+                "  ".repeat(depth + 1),
+                nodes[group[0]].label(symbols),
+                repeat,
+                count
+            );
+            render(nodes, symbols, group[0], depth + 1, max_depth, tree);
+        }
+    }
+
+    let nodes = build_call_tree(trace, end_pc);
+    let mut tree = format!("trace: {} instructions\n", nodes[0].instructions());
+    render(&nodes, symbols, 0, 0, max_depth, &mut tree);
+
+    tree
+}
+
+/// The routine printing the length-prefixed strings, found statically
+///
+/// Its call sites set `r0` to the constant address of a string in the straight code before them,
+/// the string may still be encrypted. The routine called the most this way wins, the lowest
+/// address on a tie.
+pub fn find_print_routine(memory: &[u16]) -> Option<usize> {
+    let mut calls: BTreeMap<usize, usize> = BTreeMap::new();
+    for ip in 0..memory.len() {
+        if let Ok(Opcode::Call(Val::Num(target))) = Opcode::decode_strict(&memory[ip..], ip) {
+            let string =
+                registers_before(memory, ip)[0].is_some_and(|addr| (addr as usize) < memory.len());
+            if string && (target as usize) < memory.len() {
+                *calls.entry(target as usize).or_default() += 1;
+            }
+        }
+    }
+
+    calls
+        .into_iter()
+        .max_by_key(|&(target, count)| (count, std::cmp::Reverse(target)))
+        .map(|(target, _)| target)
+}
+
+/// How a callback of the printing routine turns the words of a string into characters
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Decoder {
+    Plain,
+    /// Xor with a register of the call
+    Xor(usize),
+}
+
+impl Decoder {
+    /// Decoders of the call printing `text`, from the string at `r0`
+    fn candidates(call: &PrintCall) -> Vec<Decoder> {
+        let chars: Vec<u16> = call.text.chars().map(|c| c as u16).collect();
+        if call.words.len() != chars.len() {
+            return Vec::new();
+        }
+        std::iter::once(Decoder::Plain)
+            .chain((2..8).map(Decoder::Xor))
+            .filter(|decoder| {
+                call.words
+                    .iter()
+                    .zip(&chars)
+                    .all(|(&word, &c)| decoder.decode(word, &call.registers.map(Some)) == Some(c))
+            })
+            .collect()
+    }
+
+    fn decode(&self, word: u16, registers: &[Option<u16>; 8]) -> Option<u16> {
+        match self {
+            Decoder::Plain => Some(word),
+            Decoder::Xor(r) => registers[*r].map(|key| word ^ key),
+        }
+    }
+}
+
+/// The printing routine and the decoders of its callbacks, learned from its recorded calls, to
+/// decode the strings printed by every call site, even the ones not run yet
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StringDecoder {
+    pub routine: usize,
+    /// Callback in `r1` -> decoder
+    pub decoders: BTreeMap<usize, Decoder>,
+}
+
+impl StringDecoder {
+    /// From the calls recorded by `Vm::set_string_routine`
+    ///
+    /// A callback gets the first decoder matching all its recorded calls.
+    pub fn from_calls(calls: &[PrintCall]) -> Option<Self> {
+        let routine = calls.first()?.target;
+
+        let mut candidates: BTreeMap<usize, Vec<Decoder>> = BTreeMap::new();
+        for call in calls {
+            let matching = Decoder::candidates(call);
+            if matching.is_empty() {
+                continue;
+            }
+            candidates
+                .entry(call.registers[1] as usize)
+                .and_modify(|decoders| decoders.retain(|d| matching.contains(d)))
+                .or_insert(matching);
+        }
+        let decoders = candidates
+            .into_iter()
+            .filter_map(|(callback, decoders)| Some((callback, *decoders.first()?)))
+            .collect();
+
+        Some(Self { routine, decoders })
+    }
+
+    /// Strings printed by the calls to the routine in `memory`: address -> text
+    ///
+    /// The registers of a call are the constants set by the straight code before it, in the
+    /// `CALL_CONTEXT` words before.
+    pub fn decode(&self, memory: &[u16]) -> BTreeMap<usize, String> {
+        let call = Opcode::Call(Val::Num(self.routine as u16)).machine_code();
+        memory
+            .windows(call.len())
+            .enumerate()
+            .filter(|(_, words)| *words == &call[..])
+            .filter_map(|(ip, _)| self.decode_call(memory, &registers_before(memory, ip)))
+            .collect()
+    }
+
+    fn decode_call(&self, memory: &[u16], registers: &[Option<u16>; 8]) -> Option<(usize, String)> {
+        let addr = registers[0]? as usize;
+        let decoder = self.decoders.get(&(registers[1]? as usize))?;
+        let len = *memory.get(addr)? as usize;
+        let text = memory
+            .get(addr + 1..addr + 1 + len)?
+            .iter()
+            .map(|&word| decoder.decode(word, registers).map(|c| c as u8 as char))
+            .collect::<Option<String>>()?;
+
+        Some((addr, text))
+    }
+}
+
+/// Words decoded before a call, to find the constant arguments
+const CALL_CONTEXT: usize = 16;
+
+/// Constant registers before `ip`, from the longest straight code ending there
+fn registers_before(memory: &[u16], ip: usize) -> [Option<u16>; 8] {
+    let straight = |start: usize| {
+        let mut instructions = Vec::new();
+        let mut addr = start;
+        while addr < ip {
+            let opcode = Opcode::decode_strict(&memory[addr..], addr).ok()?;
+            let jumps = matches!(
+                opcode,
+                Opcode::Halt
+                    | Opcode::Jmp(_)
+                    | Opcode::Jt(_, _)
+                    | Opcode::Jf(_, _)
+                    | Opcode::Call(_)
+                    | Opcode::Ret
+            );
+            if jumps {
+                return None;
+            }
+            addr += opcode.size();
+            instructions.push(opcode);
+        }
+        (addr == ip).then_some(instructions)
+    };
+
+    let mut registers = [None; 8];
+    if let Some(instructions) = (ip.saturating_sub(CALL_CONTEXT)..ip).find_map(straight) {
+        for opcode in &instructions {
+            propagate(opcode, &mut registers);
+        }
+    }
+
+    registers
+}
+
+fn is_printable(word: u16) -> bool {
+    (32..127).contains(&word) || word == b'\n' as u16
+}
+
+/// Where `find_text` found a string
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum TextSource {
+    /// Run of printable words
+    Data,
+    /// Consecutive `Out` of literal characters
+    Out,
+    /// Printed by the printing routine, see `StringDecoder`
+    Decoded,
+}
+
+impl fmt::Display for TextSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TextSource::Data => "data",
+            TextSource::Out => "out",
+            TextSource::Decoded => "decoded",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Strings of the memory with at least `min_len` characters, by address
+///
+/// The decoded strings need a `decoder`, learned from the recorded calls of the printing routine.
+pub fn find_text(
+    memory: &[u16],
+    min_len: usize,
+    decoder: Option<&StringDecoder>,
+) -> Vec<(usize, TextSource, String)> {
+    let mut texts = Vec::new();
+
+    let mut run: Option<(usize, String)> = None;
+    for (addr, &word) in memory.iter().enumerate() {
+        if is_printable(word) {
+            run.get_or_insert_with(|| (addr, String::new()))
+                .1
+                .push(word as u8 as char);
+        } else if let Some((start, text)) = run.take() {
+            texts.push((start, TextSource::Data, text));
+        }
+    }
+    texts.extend(run.map(|(start, text)| (start, TextSource::Data, text)));
+
+    let mut addr = 0;
+    while addr < memory.len() {
+        let mut text = String::new();
+        let mut next = addr;
+        while let Ok(Opcode::Out(Val::Num(c))) = Opcode::decode_strict(&memory[next..], next) {
+            if !is_printable(c) {
+                break;
+            }
+            text.push(c as u8 as char);
+            next += 2;
+        }
+        if text.is_empty() {
+            addr += 1;
+        } else {
+            texts.push((addr, TextSource::Out, text));
+            addr = next;
+        }
+    }
+
+    if let Some(decoder) = decoder {
+        let decoded = decoder.decode(memory);
+        texts.extend(
+            decoded
+                .into_iter()
+                .map(|(addr, text)| (addr, TextSource::Decoded, text)),
+        );
+    }
+
+    texts.retain(|(_, _, text)| text.chars().count() >= min_len);
+    texts.sort();
+
+    texts
+}
+
+/// Update the constant registers after `opcode`, `None` for the unknown values
+fn propagate(opcode: &Opcode, registers: &mut [Option<u16>; 8]) {
+    let value = |val: &Val, registers: &[Option<u16>; 8]| match val {
+        Val::Num(x) => Some(*x),
+        Val::Reg(r) => registers[*r],
+        Val::Invalid => None,
+    };
+    let binary = |b: &Val, c: &Val, registers: &[Option<u16>; 8], f: fn(u32, u32) -> u32| {
+        Some((f(value(b, registers)? as u32, value(c, registers)? as u32) % 32768) as u16)
+    };
+
+    let (dest, result) = match opcode {
+        Opcode::Set(a, b) => (a, value(b, registers)),
+        Opcode::Add(a, b, c) => (a, binary(b, c, registers, |x, y| x + y)),
+        Opcode::Mult(a, b, c) => (a, binary(b, c, registers, |x, y| x * y)),
+        Opcode::Mod(a, b, c) => (
+            a,
+            binary(b, c, registers, |x, y| x.checked_rem(y).unwrap_or(0)),
+        ),
+        Opcode::And(a, b, c) => (a, binary(b, c, registers, |x, y| x & y)),
+        Opcode::Or(a, b, c) => (a, binary(b, c, registers, |x, y| x | y)),
+        Opcode::Not(a, b) => (a, value(b, registers).map(|x| !x & 0x7fff)),
+        Opcode::Eq(a, b, c) => (a, binary(b, c, registers, |x, y| (x == y) as u32)),
+        Opcode::Gt(a, b, c) => (a, binary(b, c, registers, |x, y| (x > y) as u32)),
+        Opcode::Pop(a) | Opcode::Rmem(a, _) | Opcode::In(a) => (a, None),
+        _ => return,
+    };
+    if let Val::Reg(r) = dest {
+        registers[*r] = result;
+    }
+}
+
+/// Options of `call_graph`
+#[derive(Debug, Default)]
+pub struct CallGraphOptions {
+    /// Edges with fewer calls are dropped
+    pub min_calls: usize,
+    /// One edge per caller and callee, instead of one per call site
+    pub merge: bool,
+    /// Scale the nodes with the instructions of the function, callees included
+    pub scale: bool,
+    /// Fill the nodes with the color of their instructions
+    pub color: bool,
+    pub gradient: Gradient,
+}
+
+/// Instructions executed by a function, see `function_profile`
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FunctionProfile {
+    pub calls: usize,
+    /// Instructions of its calls, callees included, recursive calls counted once
+    pub inclusive: usize,
+    /// Instructions of the function itself
+    pub exclusive: usize,
+}
+
+/// Walk the call tree in order, `visit` is called on the start and the end of each call
+fn walk_call_tree(nodes: &[CallNode], mut visit: impl FnMut(usize, bool)) {
+    let mut stack = vec![(0, true)];
+    while let Some((id, enter)) = stack.pop() {
+        visit(id, enter);
+        if enter {
+            stack.push((id, false));
+            stack.extend(nodes[id].children.iter().rev().map(|&child| (child, true)));
+        }
+    }
+}
+
+fn profile_nodes(nodes: &[CallNode], symbols: &SymbolTable) -> BTreeMap<String, FunctionProfile> {
+    let mut profiles: BTreeMap<String, FunctionProfile> = BTreeMap::new();
+    let mut active: BTreeMap<String, usize> = BTreeMap::new();
+    walk_call_tree(nodes, |id, enter| {
+        let node = &nodes[id];
+        let depth = active.entry(node.label(symbols)).or_default();
+        if !enter {
+            *depth -= 1;
+            return;
+        }
+        let profile = profiles.entry(node.label(symbols)).or_default();
+        if *depth == 0 {
+            profile.inclusive += node.instructions();
+        }
+        *depth += 1;
+        let callees: usize = node.children.iter().map(|&c| nodes[c].instructions()).sum();
+        profile.exclusive += node.instructions().saturating_sub(callees);
+        if id != 0 {
+            profile.calls += 1;
+        }
+    });
+
+    profiles
+}
+
+/// Instructions executed by each function of a trace of `Call` and `Ret`, the most expensive
+/// first
+///
+/// `?` is the code outside of the traced calls.
+pub fn function_profile(
+    trace: &[TraceEntry],
+    end_pc: usize,
+    symbols: &SymbolTable,
+) -> Vec<(String, FunctionProfile)> {
+    let mut profiles: Vec<_> = profile_nodes(&build_call_tree(trace, end_pc), symbols)
+        .into_iter()
+        .collect();
+    profiles.sort_by_key(|(label, profile)| (std::cmp::Reverse(profile.exclusive), label.clone()));
+
+    profiles
+}
+
+/// Graphviz call graph of a trace of `Call` and `Ret`, edges are labeled with their calls
+///
+/// Nodes show the calls and the instructions of their function, see `function_profile`. `?` is
+/// the code outside of the traced calls.
+pub fn call_graph(
+    trace: &[TraceEntry],
+    end_pc: usize,
+    symbols: &SymbolTable,
+    options: &CallGraphOptions,
+) -> String {
+    let nodes = build_call_tree(trace, end_pc);
+    let profiles = profile_nodes(&nodes, symbols);
+
+    // (caller, callee, call site) -> calls
+    let mut edges: BTreeMap<(String, String, Option<usize>), usize> = BTreeMap::new();
+    walk_call_tree(&nodes, |id, enter| {
+        if !enter {
+            return;
+        }
+        for &child in &nodes[id].children {
+            let call_site = if options.merge {
+                None
+            } else {
+                nodes[child].call_site
+            };
+            *edges
+                .entry((
+                    nodes[id].label(symbols),
+                    nodes[child].label(symbols),
+                    call_site,
+                ))
+                .or_default() += 1;
+        }
+    });
+    edges.retain(|_, calls| *calls >= options.min_calls);
+
+    let shown: BTreeSet<&String> = edges.keys().flat_map(|(a, b, _)| [a, b]).collect();
+    let max_cost = shown
+        .iter()
+        .map(|label| profiles[*label].inclusive)
+        .max()
+        .unwrap_or(0);
+
+    let mut graph = String::from("digraph calls {\nnode [shape = box];\n");
+    for label in shown {
+        let profile = &profiles[label];
+        let cost = profile.inclusive;
+        let ratio = options.gradient.scale.map(cost as u64, max_cost as u64);
+        let mut style = String::new();
+        if options.scale {
+            let _ = write!(
+                style,
+                ", width = {:.2}, height = {:.2}",
+                0.75 + 3.0 * ratio,
+                0.5 + ratio
+            );
+        }
+        if options.color {
+            let palette = options.gradient.palette;
+            let _ = write!(
+                style,
+                ", style = filled, fillcolor = \"{}\", fontcolor = {}",
+                palette.color(ratio),
+                palette.text_color(ratio)
+            );
+        }
+        let _ = writeln!(
+            graph,
+            "\"{}\" [label = \"{}\\n{} calls\\n{} instructions, {} self\"{}];",
+            label, label, profile.calls, cost, profile.exclusive, style
+        );
+    }
+    for ((caller, callee, call_site), calls) in &edges {
+        let label = match call_site {
+            Some(call_site) => format!("{}: {}", call_site, calls),
+            None => calls.to_string(),
+        };
+        let _ = writeln!(
+            graph,
+            "\"{}\" -> \"{}\" [label = \"{}\"];",
+            caller, callee, label
+        );
+    }
+    graph.push_str("}\n");
+
+    graph
+}
+
+/// Targets of the traced `Call` of a register: call site -> targets
+fn indirect_calls(trace: &[TraceEntry]) -> BTreeMap<usize, BTreeSet<usize>> {
+    let mut calls: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+    for entry in trace {
+        if let Opcode::Call(a @ Val::Reg(_)) = entry.opcode {
+            if let Some(target) = entry.value(&a) {
+                calls
+                    .entry(usize::from(entry.ip))
+                    .or_default()
+                    .insert(target as usize);
+            }
+        }
+    }
+
+    calls
+}
+
+/// Function of a `StaticCallGraph`
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct StaticFunction {
+    pub addr: usize,
+    pub name: String,
+    pub instructions: usize,
+}
+
+/// Calls from a function to another
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct StaticCall {
+    pub caller: usize,
+    pub callee: usize,
+    pub call_sites: BTreeSet<usize>,
+    /// Through a register, the target was seen in the trace
+    pub indirect: bool,
+}
+
+/// Call graph of the whole program, from the code instead of the executed calls
+///
+/// The functions are the ones of `discover_functions`. The calls to registers are resolved
+/// with their targets in the trace, if any.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct StaticCallGraph {
+    pub functions: Vec<StaticFunction>,
+    pub calls: Vec<StaticCall>,
+}
+
+impl StaticCallGraph {
+    pub fn new(vm: &Vm) -> Self {
+        let functions = discover_functions(vm);
+        let indirect = indirect_calls(vm.get_trace_buffer());
+
+        let mut calls: BTreeMap<(usize, usize), StaticCall> = BTreeMap::new();
+        for (&caller, instructions) in &functions {
+            for (ip, opcode) in instructions {
+                let Opcode::Call(a) = opcode else {
+                    continue;
+                };
+                let targets: Vec<usize> = match a.addr() {
+                    Some(target) => vec![target.into()],
+                    None => indirect.get(ip).into_iter().flatten().copied().collect(),
+                };
+                for callee in targets {
+                    calls
+                        .entry((caller, callee))
+                        .or_insert_with(|| StaticCall {
+                            caller,
+                            callee,
+                            call_sites: BTreeSet::new(),
+                            indirect: a.addr().is_none(),
+                        })
+                        .call_sites
+                        .insert(*ip);
+                }
+            }
+        }
+
+        let symbols = vm.get_symbols();
+        Self {
+            functions: functions
+                .iter()
+                .map(|(&addr, instructions)| StaticFunction {
+                    addr,
+                    name: symbols.name(addr),
+                    instructions: instructions.len(),
+                })
+                .collect(),
+            calls: calls.into_values().collect(),
+        }
+    }
+
+    /// Graphviz graph, the indirect calls are dashed
+    pub fn to_dot(&self) -> String {
+        let mut graph = String::from("digraph calls {\nnode [shape = box];\n");
+        for function in &self.functions {
+            let _ = writeln!(
+                graph,
+                "{} [label = \"{}\\n{} instructions\"];",
+                function.addr, function.name, function.instructions
+            );
+        }
+        for call in &self.calls {
+            let sites = call
+                .call_sites
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<_>>();
+            let style = if call.indirect {
+                ", style = dashed"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                graph,
+                "{} -> {} [label = \"{}\"{}];",
+                call.caller,
+                call.callee,
+                sites.join(", "),
+                style
+            );
+        }
+        graph.push_str("}\n");
+
+        graph
+    }
+}
+
+/// Standard base64 with padding, for the r2 comments
+fn base64(s: &str) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in s.as_bytes().chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (idx, &b)| n | (b as u32) << (16 - 8 * idx));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * idx) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Analysis {
+    /// Functions, strings, and cross-references
+    pub fn new(vm: &Vm) -> Self {
+        let functions = discover_functions(vm);
+
+        let mut xrefs: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+        for (ip, opcode) in functions.values().flatten() {
+            for literal in literals(opcode) {
+                xrefs.entry(literal).or_default().insert(*ip);
+            }
+        }
+
+        let mut strings = Self::find_strings(vm.get_memory());
+        if let Some(decoder) = StringDecoder::from_calls(vm.get_print_calls()) {
+            let decoded = decoder.decode(vm.get_memory());
+            strings.retain(|(addr, _)| !decoded.contains_key(addr));
+            strings.extend(decoded);
+            strings.sort();
+        }
+
+        Analysis {
+            functions,
+            strings,
+            xrefs,
+            coverage: vm.get_coverage().map(|c| c.to_vec()),
+            gradient: Gradient::default(),
+        }
+    }
+
+    /// Runs of printable words, preceded by their length
+    fn find_strings(memory: &[u16]) -> Vec<(usize, String)> {
+        let mut strings = Vec::new();
+        let mut addr = 0;
+        while addr < memory.len() {
+            let len = memory[addr] as usize;
+            let text = memory.get(addr + 1..addr + 1 + len);
+            match text {
+                Some(text) if len >= MIN_STRING_LEN && text.iter().all(|&w| is_printable(w)) => {
+                    let text = text.iter().map(|&w| w as u8 as char).collect();
+                    strings.push((addr, text));
+                    addr += len + 1;
+                }
+                _ => addr += 1,
+            }
+        }
+
+        strings
+    }
+
+    /// Function containing `ip`
+    fn owner(&self, ip: usize) -> Option<usize> {
+        self.functions
+            .iter()
+            .find(|(_, instructions)| instructions.iter().any(|(i, _)| *i == ip))
+            .map(|(start, _)| *start)
+    }
+
+    fn link(&self, ip: usize) -> String {
+        match self.owner(ip) {
+            Some(function) => format!("<a href=\"fn_{}.html#{}\">{}</a>", function, ip, ip),
+            None => ip.to_string(),
+        }
+    }
+
+    fn hits(&self, ip: usize) -> Option<u32> {
+        self.coverage.as_ref().map(|c| c[ip])
+    }
+
+    fn max_hits(&self) -> u32 {
+        self.coverage.iter().flatten().max().copied().unwrap_or(0)
+    }
+
+    /// Percentage of the instructions executed at least once
+    fn covered(&self, instructions: &[(usize, Opcode)]) -> Option<usize> {
+        let coverage = self.coverage.as_ref()?;
+        let hit = instructions
+            .iter()
+            .filter(|(ip, _)| coverage[*ip] > 0)
+            .count();
+        Some(hit * 100 / instructions.len().max(1))
+    }
+
+    /// CFG of a function, blocks stacked in address order, jumps drawn on the right
+    pub fn cfg_svg(&self, instructions: &[(usize, Opcode)]) -> String {
+        let blocks = basic_blocks(instructions);
+        let max_hits = self.max_hits();
+
+        let mut top = BTreeMap::new();
+        let mut y = 10;
+        for block in &blocks {
+            top.insert(block.start, y);
+            y += (block.instructions.len() + 1) * LINE_HEIGHT + 20;
+        }
+        let height = y;
+        let width = BLOCK_WIDTH + 40 + 12 * blocks.len();
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"monospace\" font-size=\"11\">",
+            width, height
+        );
+        let _ = writeln!(
+            svg,
+            "<defs><marker id=\"arrow\" markerWidth=\"8\" markerHeight=\"8\" refX=\"8\" refY=\"4\" orient=\"auto\"><path d=\"M0,0 L8,4 L0,8 z\"/></marker></defs>"
+        );
+
+        for (idx, block) in blocks.iter().enumerate() {
+            let y = top[&block.start];
+            let h = block.instructions.len() * LINE_HEIGHT + 6;
+            let hits = block
+                .instructions
+                .iter()
+                .filter_map(|(ip, _)| self.hits(*ip))
+                .max()
+                .unwrap_or(0);
+            let (fill, text_color) = match hits {
+                0 => ("#f4f4f4".to_string(), "black"),
+                hits => (
+                    self.gradient.color(hits as u64, max_hits as u64),
+                    self.gradient.text_color(hits as u64, max_hits as u64),
+                ),
+            };
+            let _ = writeln!(
+                svg,
+                "<a href=\"#{}\"><rect x=\"10\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"black\"/></a>",
+                block.start,
+                y,
+                BLOCK_WIDTH,
+                h,
+                fill
+            );
+            for (line, (ip, opcode)) in block.instructions.iter().enumerate() {
+                let text = format!("{}: {:?}", ip, opcode);
+                let _ = writeln!(
+                    svg,
+                    "<text x=\"14\" y=\"{}\" fill=\"{}\">{}</text>",
+                    y + (line + 1) * LINE_HEIGHT,
+                    text_color,
+                    escape(&text.chars().take(50).collect::<String>())
+                );
+            }
+
+            let bottom = y + h;
+            let next_start = blocks.get(idx + 1).map(|b| b.start);
+            for &successor in &block.successors {
+                let target = top[&successor];
+                if Some(successor) == next_start {
+                    let _ = writeln!(
+                        svg,
+                        "<line x1=\"{x}\" y1=\"{}\" x2=\"{x}\" y2=\"{}\" stroke=\"black\" marker-end=\"url(#arrow)\"/>",
+                        bottom,
+                        target,
+                        x = 10 + BLOCK_WIDTH / 2
+                    );
+                } else {
+                    let x = 10 + BLOCK_WIDTH + 20 + 12 * idx;
+                    let _ = writeln!(
+                        svg,
+                        "<polyline points=\"{},{} {},{} {},{} {},{}\" fill=\"none\" stroke=\"{}\" marker-end=\"url(#arrow)\"/>",
+                        10 + BLOCK_WIDTH,
+                        bottom - 3,
+                        x,
+                        bottom - 3,
+                        x,
+                        target + 3,
+                        10 + BLOCK_WIDTH,
+                        target + 3,
+                        if target < y { "blue" } else { "darkred" }
+                    );
+                }
+            }
+        }
+        svg.push_str("</svg>\n");
+
+        svg
+    }
+
+    fn page(title: &str, body: &str) -> String {
+        format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title><style>\
+             body {{ font-family: monospace; }} \
+             td {{ padding: 0 8px; }}</style></head>\n<body>\n<a href=\"index.html\">index</a> \
+             <a href=\"strings.html\">strings</a>\n<h1>{}</h1>\n{}</body></html>\n",
+            escape(title),
+            escape(title),
+            body
+        )
+    }
+
+    fn function_page(&self, start: usize, instructions: &[(usize, Opcode)]) -> String {
+        let mut body = String::new();
+        let max_hits = self.max_hits();
+
+        body.push_str("<h2>Called from</h2>\n<ul>\n");
+        for caller in self.callers(start) {
+            let _ = writeln!(body, "<li>{}</li>", self.link(caller));
+        }
+        body.push_str("</ul>\n<h2>Graph</h2>\n");
+        body.push_str(&self.cfg_svg(instructions));
+
+        body.push_str("<h2>Listing</h2>\n<table>\n");
+        for (ip, opcode) in instructions {
+            let mut text = escape(&format!("{:?}", opcode));
+            if let Opcode::Call(Val::Num(target)) = opcode {
+                text = format!("<a href=\"fn_{}.html\">{}</a>", target, text);
+            }
+            let xrefs = self
+                .xrefs
+                .get(ip)
+                .map(|refs| {
+                    refs.iter()
+                        .map(|r| self.link(*r))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .unwrap_or_default();
+            let hits = self.hits(*ip);
+            let _ = writeln!(
+                body,
+                "<tr id=\"{ip}\"{}><td>{ip}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                match hits {
+                    Some(hits) if hits > 0 => format!(
+                        " style=\"background: {}; color: {}\"",
+                        self.gradient.color(hits as u64, max_hits as u64),
+                        self.gradient.text_color(hits as u64, max_hits as u64)
+                    ),
+                    _ => String::new(),
+                },
+                text,
+                hits.map(|h| h.to_string()).unwrap_or_default(),
+                xrefs,
+            );
+        }
+        body.push_str("</table>\n");
+
+        Self::page(&format!("Function {}", start), &body)
+    }
+
+    /// Call sites of a function
+    fn callers(&self, start: usize) -> Vec<usize> {
+        self.functions
+            .values()
+            .flatten()
+            .filter(
+                |(_, opcode)| matches!(opcode, Opcode::Call(Val::Num(t)) if *t as usize == start),
+            )
+            .map(|(ip, _)| *ip)
+            .collect()
+    }
+
+    fn index_page(&self) -> String {
+        let mut body = String::from(
+            "<table>\n<tr><th>function</th><th>instructions</th><th>callers</th><th>covered</th></tr>\n",
+        );
+        for (start, instructions) in &self.functions {
+            let _ = writeln!(
+                body,
+                "<tr><td><a href=\"fn_{s}.html\">{s}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                instructions.len(),
+                self.callers(*start).len(),
+                self.covered(instructions)
+                    .map(|c| format!("{}%", c))
+                    .unwrap_or_default(),
+                s = start,
+            );
+        }
+        body.push_str("</table>\n");
+
+        Self::page("Functions", &body)
+    }
+
+    fn strings_page(&self) -> String {
+        let mut body =
+            String::from("<table>\n<tr><th>address</th><th>text</th><th>xrefs</th></tr>\n");
+        for (addr, text) in &self.strings {
+            let xrefs = self
+                .xrefs
+                .get(addr)
+                .map(|refs| {
+                    refs.iter()
+                        .map(|r| self.link(*r))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .unwrap_or_default();
+            let _ = writeln!(
+                body,
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                addr,
+                escape(text),
+                xrefs
+            );
+        }
+        body.push_str("</table>\n");
+
+        Self::page("Strings", &body)
+    }
+
+    /// Comments of the instructions using a string as operand
+    pub fn comments(&self) -> BTreeMap<usize, String> {
+        let mut comments = BTreeMap::new();
+        for (addr, text) in &self.strings {
+            let text: String = text.chars().take(40).collect();
+            for ip in self.xrefs.get(addr).into_iter().flatten() {
+                comments.insert(*ip, format!("str_{}: {:?}", addr, text));
+            }
+        }
+
+        comments
+    }
+
+    /// radare2 script of the flags, functions and comments
+    ///
+    /// r2 addresses bytes, so the offsets are twice the word addresses.
+    pub fn to_radare2(&self) -> String {
+        let mut script = String::from("# r2 -i script.r2 challenge.bin\n");
+        for (start, instructions) in &self.functions {
+            let _ = writeln!(script, "af+ {:#x} fn_{}", start * 2, start);
+            for block in basic_blocks(instructions) {
+                let (last, opcode) = block.instructions.last().unwrap();
+                let size = (last + opcode.size() - block.start) * 2;
+                let _ = write!(
+                    script,
+                    "afb+ {:#x} {:#x} {}",
+                    start * 2,
+                    block.start * 2,
+                    size
+                );
+                for successor in &block.successors {
+                    let _ = write!(script, " {:#x}", successor * 2);
+                }
+                script.push('\n');
+            }
+        }
+        for (addr, text) in &self.strings {
+            let _ = writeln!(
+                script,
+                "f str_{} {} {:#x}",
+                addr,
+                (text.len() + 1) * 2,
+                addr * 2
+            );
+        }
+        for (ip, comment) in self.comments() {
+            // encoded, r2 would parse the quotes and `;`
+            let _ = writeln!(script, "CC base64:{} @ {:#x}", base64(&comment), ip * 2);
+        }
+
+        script
+    }
+
+    /// Ghidra script creating the functions, labels and comments, at word addresses
+    pub fn to_ghidra(&self) -> String {
+        let mut script = String::from(
+            "# Run from the Script Manager, on challenge.bin loaded with one word per address\n\
+             # @category Synacor\n",
+        );
+        for start in self.functions.keys() {
+            let _ = writeln!(
+                script,
+                "createFunction(toAddr({:#x}), \"fn_{}\")",
+                start, start
+            );
+        }
+        for (addr, _) in &self.strings {
+            let _ = writeln!(
+                script,
+                "createLabel(toAddr({:#x}), \"str_{}\", True)",
+                addr, addr
+            );
+        }
+        for (ip, comment) in self.comments() {
+            let _ = writeln!(script, "setEOLComment(toAddr({:#x}), {:?})", ip, comment);
+        }
+
+        script
+    }
+
+    /// Write `index.html`, `strings.html`, and a `fn_<addr>.html` per function in `dir`
+    pub fn write_html<P: AsRef<Path>>(&self, dir: P) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        std::fs::write(dir.join("index.html"), self.index_page())?;
+        std::fs::write(dir.join("strings.html"), self.strings_page())?;
+        for (start, instructions) in &self.functions {
+            std::fs::write(
+                dir.join(format!("fn_{}.html", start)),
+                self.function_page(*start, instructions),
+            )?;
+        }
+
+        Ok(())
+    }
+}
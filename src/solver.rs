@@ -1,113 +1,1055 @@
 use regex::Regex;
 
-use crate::emulator::{Vm, VmState};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::emulator::{Vm, VmState, MEM_SIZE};
+use crate::planner::{GameState, Heuristic, MissingItems, Planner, RoomDistance};
 use std::{
-    collections::{hash_map::DefaultHasher, BTreeMap, HashSet},
-    hash::{Hash, Hasher},
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
 };
 
-pub struct GameSolver {}
+/// Memory cell holding the identifier of the current room
+pub const CURRENT_ROOM: usize = 2732;
 
-impl GameSolver {
-    pub fn explore_maze(vm: &Vm) {
-        let message = vm.get_messages().last().unwrap();
-        let level = Level::from(message).unwrap();
-        let first_level = level.clone();
+/// Memory cell holding the current weight of the orb
+pub const VAULT_ORB_WEIGHT: usize = 3952;
+
+/// The orb weight is a 15 bits number
+const VAULT_ORB_WEIGHTS: std::ops::Range<u16> = 0..32768;
+
+/// Messages meaning the orb is lost and the vault can't be opened, the game goes on
+const VAULT_LOSING_MESSAGES: [&str; 3] = [
+    "flashes black",
+    "evaporates out of your hands",
+    "orb shatters",
+];
+
+/// Candidates of `GameSolver::fuzz_verbs` when there is no word list
+const FUZZ_WORDS: [&str; 40] = [
+    "go", "look", "take", "drop", "use", "inv", "help", "north", "south", "east", "west", "up",
+    "down", "in", "out", "climb", "read", "open", "close", "push", "pull", "jump", "swim", "dig",
+    "light", "eat", "drink", "talk", "examine", "give", "throw", "wait", "sleep", "save", "load",
+    "quit", "score", "xyzzy", "plugh", "hello",
+];
+
+/// A word the game can't know, to get its boilerplate answers
+const FUZZ_GARBAGE: &str = "qzxqzxq";
+
+/// Messages ending the game: (message, kind)
+const TERMINAL_MESSAGES: [(&str, TerminalKind); 3] = [
+    ("You have been eaten by a grue", TerminalKind::Grue),
+    ("you have been eaten", TerminalKind::Death),
+    (
+        "Congratulations; you have reached the end of the challenge!",
+        TerminalKind::Victory,
+    ),
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TerminalKind {
+    Grue,
+    /// Any other way to halt the game
+    Death,
+    Victory,
+}
+
+impl std::fmt::Display for TerminalKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TerminalKind::Grue => write!(f, "EATEN BY A GRUE"),
+            TerminalKind::Death => write!(f, "GAME OVER"),
+            TerminalKind::Victory => write!(f, "VICTORY"),
+        }
+    }
+}
+
+/// What the game answered to the last input
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GameResponse {
+    Room(Level),
+    Terminal(TerminalKind),
+    /// Anything else, like "Taken."
+    Message(String),
+}
+
+impl GameResponse {
+    /// Classify the last message of `vm`
+    pub fn from_vm(vm: &Vm) -> Self {
+        let message = vm.get_messages().last().map(|m| m.as_str()).unwrap_or("");
+        Self::classify(message, vm.get_state() == VmState::Halted)
+    }
+
+    pub fn classify(message: &str, halted: bool) -> Self {
+        let lowercase = message.to_lowercase();
+        for (pattern, kind) in TERMINAL_MESSAGES {
+            if lowercase.contains(&pattern.to_lowercase()) {
+                return GameResponse::Terminal(kind);
+            }
+        }
+        if halted {
+            return GameResponse::Terminal(TerminalKind::Death);
+        }
+
+        match Level::from(message) {
+            Ok(level) => GameResponse::Room(level),
+            Err(_) => GameResponse::Message(message.to_string()),
+        }
+    }
+}
+
+/// A playthrough of the text adventure, without the VM details
+///
+/// The debugger is for reverse engineering, this is for playing the game from code:
+///
+/// ```no_run
+/// use synacor_challenge::{Game, GameResponse};
+///
+/// let mut game = Game::new()?;
+/// game.act("take tablet")?;
+/// let before = game.save();
+/// if let GameResponse::Room(room) = game.act("doorway")? {
+///     println!("{}: {:?}", room.name, room.exits);
+/// }
+/// game.restore(&before);
+/// assert_eq!(game.inventory()?, vec!["tablet"]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone)]
+pub struct Game {
+    vm: Vm,
+}
+
+/// A state of a `Game` to go back to, from `Game::save`
+#[derive(Clone)]
+pub struct SavedGame {
+    vm: Vm,
+}
+
+impl Game {
+    /// Boot the program found by `Vm::find_binary` until the first prompt
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_file(Vm::find_binary(None, None)?)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut vm = Vm::new();
+        vm.load_program_from_file(path)?;
+        Self::from_vm(vm)
+    }
+
+    /// Play from `vm`, like a snapshot of the debugger, after running it to the next prompt
+    pub fn from_vm(vm: Vm) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut game = Game { vm };
+        if game.vm.get_state() != VmState::WaitingForInput {
+            game.run()?;
+        }
+
+        Ok(game)
+    }
+
+    fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.vm.run();
+        // the breakpoints of the VM are not part of the game
+        while self.vm.get_state() == VmState::HitBreakPoint {
+            self.vm.resume()?;
+        }
+
+        Ok(())
+    }
+
+    /// Feed `input`, and classify the answer
+    pub fn act(&mut self, input: &str) -> Result<GameResponse, Box<dyn std::error::Error>> {
+        self.vm.feed(input)?;
+        self.run()?;
+
+        Ok(GameResponse::from_vm(&self.vm))
+    }
+
+    /// The last answer of the game, with its prompt
+    pub fn output(&self) -> &str {
+        self.vm.get_messages().last().map_or("", |m| m.as_str())
+    }
+
+    /// The current room, asked with `look` without changing the game
+    pub fn room(&self) -> Result<Level, Box<dyn std::error::Error>> {
+        match self.clone().act("look")? {
+            GameResponse::Room(room) => Ok(room),
+            x => Err(format!("Can't find current room: {:?}", x).into()),
+        }
+    }
+
+    /// Items held, sorted, asked with `inv` without changing the game
+    pub fn inventory(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        crate::planner::inventory(&self.vm)
+    }
+
+    /// Won or lost, no more input is accepted
+    pub fn is_over(&self) -> bool {
+        self.vm.get_state() == VmState::Halted
+    }
+
+    pub fn save(&self) -> SavedGame {
+        SavedGame {
+            vm: self.vm.clone(),
+        }
+    }
+
+    /// Go back to `saved`, which can come from another `Game`
+    pub fn restore(&mut self, saved: &SavedGame) {
+        self.vm = saved.vm.clone();
+    }
+
+    /// The VM, for the debugger
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    pub fn into_vm(self) -> Vm {
+        self.vm
+    }
+}
+
+/// Limits for `GameSolver::explore_maze`
+#[derive(Debug, Default)]
+pub struct ExploreOptions {
+    /// Stop after exploring this many rooms
+    pub max_rooms: Option<usize>,
+    /// Stop after this much time
+    pub max_time: Option<Duration>,
+    /// Maze database to resume from, and to save to when stopping
+    pub resume: Option<PathBuf>,
+    /// Graphviz file of the maze, written when stopping
+    pub graphviz: Option<PathBuf>,
+}
+
+/// What `GameSolver::bruteforce` sets to each candidate value
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Variable {
+    Register(usize),
+    Memory(usize),
+}
+
+/// `r0` to `r7`, or a memory address
+impl std::str::FromStr for Variable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(reg) = s.strip_prefix('r') {
+            return match reg.parse() {
+                Ok(reg) if reg < 8 => Ok(Variable::Register(reg)),
+                _ => Err(format!("Invalid register {:?}, expected r0 to r7", s)),
+            };
+        }
+        match s.parse() {
+            Ok(addr) if addr < MEM_SIZE => Ok(Variable::Memory(addr)),
+            _ => Err(format!(
+                "Invalid variable {:?}, expected r0 to r7 or an address",
+                s
+            )),
+        }
+    }
+}
+
+/// Candidates and objective of `GameSolver::bruteforce`
+#[derive(Debug)]
+pub struct BruteforceOptions {
+    pub variable: Variable,
+    pub values: RangeInclusive<u16>,
+    /// Game inputs fed after setting the variable
+    pub inputs: Vec<String>,
+    /// Matches the output of the successful values
+    pub success: Regex,
+    /// Steps per candidate, the ones running longer are failures
+    pub max_steps: usize,
+}
+
+/// Call `f` on each value in parallel, printing the progress, and return the results sorted by
+/// value
+fn sweep<T: Send>(values: &[u16], f: impl Fn(u16) -> Option<T> + Sync) -> Vec<(u16, T)> {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let chunk_size = values.len().div_ceil(threads).max(1);
+    let done = AtomicUsize::new(0);
+    let report_every = (values.len() / 10).max(1);
+
+    let mut results: Vec<(u16, T)> = std::thread::scope(|s| {
+        let handles: Vec<_> = values
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let (f, done) = (&f, &done);
+                // like the main thread, for the recursion of the native 6027
+                let builder = std::thread::Builder::new().stack_size(8 * 1024 * 1024);
+                builder.spawn_scoped(s, move || {
+                    let mut results = Vec::new();
+                    for &val in chunk {
+                        if let Some(result) = f(val) {
+                            results.push((val, result));
+                        }
+                        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                        if done % report_every == 0 {
+                            println!("{}/{} values tried", done, values.len());
+                        }
+                    }
+                    results
+                })
+            })
+            .collect::<Result<_, _>>()
+            .expect("Can't spawn the threads");
+
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|(val, _)| *val);
+
+    results
+}
+
+/// How rooms are grouped in graphviz clusters
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ClusterBy {
+    /// Same room name, merges distinct areas with identical names
+    #[default]
+    Name,
+    /// Same room name, and connected to each other
+    Area,
+    /// Same first exits on the shortest path from the start
+    PathPrefix(usize),
+}
+
+impl std::str::FromStr for ClusterBy {
+    type Err = Box<dyn std::error::Error>;
+
+    /// `name`, `area`, or `prefix:<depth>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "name" => Ok(ClusterBy::Name),
+            None if s == "area" => Ok(ClusterBy::Area),
+            None if s == "prefix" => Ok(ClusterBy::PathPrefix(2)),
+            Some(("prefix", depth)) => Ok(ClusterBy::PathPrefix(depth.parse()?)),
+            _ => Err(format!("Unknown clustering {:?}", s).into()),
+        }
+    }
+}
 
-        let mut explored: HashSet<Level> = Default::default();
-        let mut queue: BTreeMap<Level, Vm> = Default::default();
-        queue.insert(level, vm.clone());
+#[derive(Debug, Default)]
+pub struct GraphvizOptions {
+    pub cluster_by: ClusterBy,
+    /// Clusters with a matching label are drawn as a single node
+    pub collapse: Option<Regex>,
+}
+
+/// Maze database, with the queue of the rooms still to explore
+#[serde_as]
+#[derive(Default, Serialize, Deserialize)]
+pub struct Maze {
+    pub start: Option<Level>,
+    pub explored: BTreeSet<Level>,
+    /// (from, exit, to)
+    pub edges: Vec<(Level, String, Level)>,
+    #[serde_as(as = "Vec<(_, _)>")]
+    pub queue: BTreeMap<Level, Vm>,
+    /// The items seen while exploring, by name
+    #[serde(default)]
+    pub glossary: BTreeMap<String, GlossaryEntry>,
+}
+
+/// An item of the game, see `Maze::glossary`
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GlossaryEntry {
+    /// Names of the rooms where it was seen
+    pub rooms: BTreeSet<String>,
+    /// Answer to `look <item>`, in the first room where it was seen
+    pub look: Option<String>,
+    /// What is written on the item, if `look` reads a text
+    #[serde(default)]
+    pub text: Option<TextEntry>,
+}
+
+/// Words of the `look` answers of the items with something written on them
+const TEXT_MARKERS: [&str; 5] = ["writ", "reads", "titled", "notes", "journal"];
+
+/// Phrases of the texts describing the check of the teleporter, like the strange book
+const ALGORITHM_MARKERS: [&str; 3] = ["eighth register", "confirmation mechanism", "teleportation"];
+
+/// A text read with `look <item>`: the tablet, the strange book, the journal...
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TextEntry {
+    /// The first paragraph, describing the item itself
+    pub description: String,
+    /// The next paragraphs, what is written
+    pub paragraphs: Vec<String>,
+    /// Codes in the text, see `GameSolver::find_codes`
+    pub codes: Vec<String>,
+    /// The text describes the algorithm checked by the teleporter
+    pub algorithm: bool,
+}
+
+impl TextEntry {
+    /// Parse the answer to `look <item>`, `None` if nothing is written on the item
+    pub fn parse(look: &str) -> Option<Self> {
+        let mut paragraphs = look
+            .split("\n\n")
+            .map(|p| p.trim_end().trim_start_matches('\n').to_string())
+            .filter(|p| !p.trim().is_empty());
+        let description = paragraphs.next()?.trim().to_string();
+        let paragraphs: Vec<String> = paragraphs.collect();
+        let codes = GameSolver::find_codes(look);
 
+        let lowercase = look.to_lowercase();
+        let is_text = TEXT_MARKERS.iter().any(|m| lowercase.contains(m));
+        if paragraphs.is_empty() && codes.is_empty() && !is_text {
+            return None;
+        }
+        let algorithm = ALGORITHM_MARKERS
+            .iter()
+            .filter(|m| lowercase.contains(*m))
+            .count()
+            >= 2;
+
+        Some(TextEntry {
+            description,
+            paragraphs,
+            codes,
+            algorithm,
+        })
+    }
+
+    /// The paragraphs about the check of the teleporter
+    pub fn algorithm_paragraphs(&self) -> Vec<&str> {
+        self.paragraphs
+            .iter()
+            .filter(|p| {
+                let p = p.to_lowercase();
+                ALGORITHM_MARKERS.iter().any(|m| p.contains(m))
+            })
+            .map(|p| p.as_str())
+            .collect()
+    }
+}
+
+impl Maze {
+    /// Add the things of `level` to the glossary, looking at the new ones on a clone of `vm`
+    pub fn record_things(&mut self, level: &Level, vm: &Vm) {
+        for thing in &level.things {
+            let entry = self.glossary.entry(thing.clone()).or_default();
+            entry.rooms.insert(level.name.clone());
+            if entry.look.is_none() {
+                entry.look = GameSolver::answer(vm, &format!("look {}", thing)).ok();
+                entry.text = entry.look.as_deref().and_then(TextEntry::parse);
+            }
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let f = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(f))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let f = std::fs::File::create(path)?;
+        serde_json::to_writer(std::io::BufWriter::new(f), self)?;
+        Ok(())
+    }
+
+    pub fn to_graphviz(&self) -> String {
+        self.to_graphviz_with(&GraphvizOptions::default())
+    }
+
+    /// Cluster (id, label) of each explored room, by `stable_hash`
+    fn clusters(&self, cluster_by: ClusterBy) -> BTreeMap<u64, (String, String)> {
+        let sanitize = |name: &str| -> String {
+            name.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect()
+        };
+
+        match cluster_by {
+            ClusterBy::Name => self
+                .explored
+                .iter()
+                .map(|level| {
+                    (
+                        level.stable_hash(),
+                        (sanitize(&level.name), level.name.clone()),
+                    )
+                })
+                .collect(),
+            ClusterBy::Area => {
+                // connected components of the rooms with the same name
+                let names: BTreeMap<u64, &str> = self
+                    .explored
+                    .iter()
+                    .map(|level| (level.stable_hash(), level.name.as_str()))
+                    .collect();
+                let mut area: BTreeMap<u64, u64> = names.keys().map(|h| (*h, *h)).collect();
+                let find = |area: &BTreeMap<u64, u64>, mut h: u64| {
+                    while area[&h] != h {
+                        h = area[&h];
+                    }
+                    h
+                };
+                for (from, _, to) in &self.edges {
+                    let (from, to) = (from.stable_hash(), to.stable_hash());
+                    if from != to && names.contains_key(&to) && names.get(&from) == names.get(&to) {
+                        let (a, b) = (find(&area, from), find(&area, to));
+                        area.insert(a.max(b), a.min(b));
+                    }
+                }
+
+                // number the areas of a name by their smallest room hash
+                let mut numbers: BTreeMap<&str, BTreeSet<u64>> = BTreeMap::new();
+                for (hash, name) in &names {
+                    numbers.entry(name).or_default().insert(find(&area, *hash));
+                }
+                names
+                    .iter()
+                    .map(|(hash, name)| {
+                        let root = find(&area, *hash);
+                        if numbers[name].len() == 1 {
+                            return (*hash, (sanitize(name), name.to_string()));
+                        }
+                        let n = numbers[name].iter().position(|r| *r == root).unwrap();
+                        let id = format!("{}_{}", sanitize(name), n);
+                        (*hash, (id, format!("{} #{}", name, n)))
+                    })
+                    .collect()
+            }
+            ClusterBy::PathPrefix(depth) => {
+                let mut edges: Vec<_> = self
+                    .edges
+                    .iter()
+                    .map(|(from, exit, to)| (from.stable_hash(), exit.as_str(), to.stable_hash()))
+                    .collect();
+                edges.sort();
+
+                // shortest paths from the start
+                let mut prefixes: BTreeMap<u64, Vec<&str>> = BTreeMap::new();
+                let mut queue = VecDeque::new();
+                if let Some(start) = &self.start {
+                    prefixes.insert(start.stable_hash(), Vec::new());
+                    queue.push_back(start.stable_hash());
+                }
+                while let Some(hash) = queue.pop_front() {
+                    let path = prefixes[&hash].clone();
+                    for (_, exit, to) in edges.iter().filter(|(from, _, _)| *from == hash) {
+                        if !prefixes.contains_key(to) {
+                            let mut path = path.clone();
+                            path.push(exit);
+                            prefixes.insert(*to, path);
+                            queue.push_back(*to);
+                        }
+                    }
+                }
+
+                self.explored
+                    .iter()
+                    .map(|level| {
+                        let label = match prefixes.get(&level.stable_hash()) {
+                            Some(path) if path.is_empty() => "start".to_string(),
+                            Some(path) => path[..path.len().min(depth)].join(" "),
+                            None => "unreachable".to_string(),
+                        };
+                        (level.stable_hash(), (sanitize(&label), label))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    pub fn to_graphviz_with(&self, options: &GraphvizOptions) -> String {
         let mut graphviz = String::from("digraph G {\n");
 
-        while let Some((current_level, current_vm)) = queue.pop_first() {
-            if explored.contains(&current_level) {
-                continue;
+        let clusters = self.clusters(options.cluster_by);
+        let collapsed = |label: &str| {
+            options
+                .collapse
+                .as_ref()
+                .is_some_and(|re| re.is_match(label))
+        };
+        // rooms of collapsed clusters are replaced by the cluster node
+        let node = |hash: u64| -> String {
+            match clusters.get(&hash) {
+                Some((id, label)) if collapsed(label) => format!("collapsed_{}", id),
+                _ => hash.to_string(),
             }
+        };
 
-            //dbg!(explored.len(), queue.len());
-            //println!("Exploring {}", current_level.name);
+        let mut edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|(from, exit, to)| (node(from.stable_hash()), exit, node(to.stable_hash())))
+            .filter(|(from, _, to)| !(from == to && from.starts_with("collapsed_")))
+            .collect();
+        edges.sort();
+        edges.dedup();
 
-            for exit in &current_level.exits {
-                let mut vm = current_vm.clone();
-                vm.feed(exit).unwrap();
-                vm.run();
+        let explored: BTreeSet<String> = self
+            .explored
+            .iter()
+            .map(|level| node(level.stable_hash()))
+            .collect();
+        for (from, exit, to) in &edges {
+            let reverse = opposite_exit(exit).and_then(|opposite| {
+                edges
+                    .iter()
+                    .find(|(f, e, t)| f == to && e.as_str() == opposite && t == from)
+            });
 
-                if vm.get_state() == VmState::Halted {
-                    continue;
+            match reverse {
+                // drawn once, from the smallest end
+                Some((_, opposite, _)) if (from, exit) < (to, opposite) => {
+                    graphviz.push_str(&format!(
+                        "{} -> {} [label =\"{} / {}\", dir = both];\n",
+                        from, to, exit, opposite
+                    ));
                 }
-                let message = vm.get_messages().last().unwrap();
-                let new_level = match Level::from(message) {
-                    Ok(l) => l,
-                    Err(_) => Level {
-                        name: "custom level".into(),
-                        description: message.to_string(),
-                        exits: Vec::new(),
-                        things: Vec::new(),
-                    },
-                };
+                Some(_) => (),
+                // no way back, once the other room is explored
+                None if explored.contains(to)
+                    && !edges.iter().any(|(f, _, t)| f == to && t == from) =>
+                {
+                    graphviz.push_str(&format!(
+                        "{} -> {} [label =\"{} (one-way)\", style = dashed];\n",
+                        from, to, exit
+                    ));
+                }
+                None => {
+                    graphviz.push_str(&format!("{} -> {} [label =\"{}\"];\n", from, to, exit));
+                }
+            }
+        }
 
-                //println!("exit {} => {}", exit, new_level.name);
-                fn hash_string(input: &str) -> u64 {
-                    let mut hasher = DefaultHasher::new();
-                    input.hash(&mut hasher);
-                    hasher.finish()
-                }
-                let from = hash_string(&format!(
-                    "{}{}",
-                    current_level.name, current_level.description
+        let mut groups: BTreeMap<&(String, String), Vec<&Level>> = BTreeMap::new();
+        for level in &self.explored {
+            groups
+                .entry(&clusters[&level.stable_hash()])
+                .or_default()
+                .push(level);
+        }
+
+        for ((cluster_id, label), levels) in groups {
+            if collapsed(label) {
+                graphviz.push_str(&format!(
+                    "collapsed_{} [label=\"{} ({} rooms)\", shape = box3d];\n",
+                    cluster_id,
+                    label.replace('\"', ""),
+                    levels.len()
                 ));
-                let to = hash_string(&format!("{}{}", new_level.name, new_level.description));
-                let things = current_level.things.join(" ");
-                let color = if current_level.things.is_empty() {
+                continue;
+            }
+
+            graphviz.push_str(&format!("subgraph cluster_{} {{\n", cluster_id));
+            graphviz.push_str(&format!("label = \"{}\";\n", label.replace('\"', "")));
+
+            for level in levels {
+                let things = level.things.join(" ");
+                let color = if level.things.is_empty() {
                     "black"
                 } else {
                     "red"
                 };
-
-                let shape = if current_level == first_level {
+                let shape = if Some(level) == self.start.as_ref() {
                     "Mdiamond"
                 } else {
                     "ellipse"
                 };
-                graphviz.push_str(&format!("{} -> {} [label =\"{}\"];\n", from, to, exit));
 
-                #[allow(clippy::format_in_format_args)]
                 graphviz.push_str(&format!(
                     "{} [label=\"{} - {}: {}\", color = {}, shape = {}];\n",
-                    from,
-                    current_level.name,
-                    current_level.description.replace('\"', ""),
+                    level.stable_hash(),
+                    level.name,
+                    level.description.replace('\"', ""),
                     things,
                     color,
                     shape
                 ));
+            }
+            graphviz.push_str("}\n");
+        }
+
+        graphviz.push_str("}\n\n");
+        graphviz
+    }
+}
+
+/// Exit leading back, for the exits that have one
+pub fn opposite_exit(exit: &str) -> Option<&'static str> {
+    match exit {
+        "north" => Some("south"),
+        "south" => Some("north"),
+        "east" => Some("west"),
+        "west" => Some("east"),
+        "up" => Some("down"),
+        "down" => Some("up"),
+        _ => None,
+    }
+}
 
-                if explored.contains(&new_level) {
+pub struct GameSolver {}
+
+impl GameSolver {
+    /// Answer of the game to `input` on a clone of `vm`, without the prompt
+    pub fn answer(vm: &Vm, input: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let mut vm = vm.clone();
+        vm.feed(input)?;
+        vm.run();
+        let message = vm.get_messages().last().cloned().unwrap_or_default();
+        Ok(message
+            .trim_end()
+            .trim_end_matches("What do you do?")
+            .trim()
+            .to_string())
+    }
+
+    /// Explore from the current room, returns the maze unless `options.resume` can't be loaded
+    pub fn explore_maze(vm: &Vm, options: &ExploreOptions) -> Option<Maze> {
+        let mut maze = match &options.resume {
+            Some(path) if path.exists() => match Maze::load(path) {
+                Ok(maze) => {
+                    println!(
+                        "Resuming from {:?}: {} explored, {} queued",
+                        path,
+                        maze.explored.len(),
+                        maze.queue.len()
+                    );
+                    maze
+                }
+                Err(e) => {
+                    println!("Can't load {:?}: {}", path, e);
+                    return None;
+                }
+            },
+            _ => {
+                let message = vm.get_messages().last().unwrap();
+                let level = Level::from(message).unwrap();
+                let mut maze = Maze {
+                    start: Some(level.clone()),
+                    ..Default::default()
+                };
+                maze.queue.insert(level, vm.clone());
+                maze
+            }
+        };
+
+        let started = Instant::now();
+        let mut explored_rooms = 0;
+
+        loop {
+            if options.max_rooms.is_some_and(|max| explored_rooms >= max) {
+                println!("Stopping: explored {} rooms", explored_rooms);
+                break;
+            }
+            if options.max_time.is_some_and(|max| started.elapsed() >= max) {
+                println!("Stopping: time limit reached");
+                break;
+            }
+            let Some((current_level, current_vm)) = maze.queue.pop_first() else {
+                println!("Finished exploring");
+                break;
+            };
+
+            if maze.explored.contains(&current_level) {
+                continue;
+            }
+
+            //dbg!(explored.len(), queue.len());
+            //println!("Exploring {}", current_level.name);
+            maze.record_things(&current_level, &current_vm);
+
+            for exit in &current_level.exits {
+                let mut vm = current_vm.clone();
+                vm.feed(exit).unwrap();
+                vm.run();
+
+                let new_level = match GameResponse::from_vm(&vm) {
+                    GameResponse::Room(l) => l,
+                    GameResponse::Terminal(_) => continue,
+                    GameResponse::Message(message) => Level {
+                        name: "custom level".into(),
+                        description: message,
+                        exits: Vec::new(),
+                        things: Vec::new(),
+                        normalized: None,
+                    },
+                };
+
+                //println!("exit {} => {}", exit, new_level.name);
+                maze.edges
+                    .push((current_level.clone(), exit.clone(), new_level.clone()));
+
+                if maze.explored.contains(&new_level) {
                     continue;
                 }
 
-                queue.insert(new_level, vm.clone());
+                maze.queue.insert(new_level, vm.clone());
             }
 
-            explored.insert(current_level);
+            maze.explored.insert(current_level);
+            explored_rooms += 1;
         }
 
-        println!("Finished exploring");
-        for level in &explored {
+        for level in &maze.explored {
             println!("{}", level.name);
             for thing in &level.things {
                 println!("- {}", thing);
             }
         }
+        let normalized: Vec<_> = maze
+            .explored
+            .iter()
+            .filter_map(|level| Some((level, level.normalized.as_ref()?)))
+            .collect();
+        if !normalized.is_empty() {
+            println!("{} rooms needed normalization:", normalized.len());
+            for (level, rule) in normalized {
+                println!("- {} ({}): {}", level.name, rule, level.description);
+            }
+        }
 
-        graphviz.push_str("}\n\n");
+        println!(
+            "{} explored, {} still queued, {} items in the glossary",
+            maze.explored.len(),
+            maze.queue.len(),
+            maze.glossary.len()
+        );
 
-        match std::fs::write("graphviz.dot", graphviz) {
-            Ok(_) => (),
-            Err(x) => println!("{:?}", x),
+        if let Some(path) = &options.resume {
+            match maze.save(path) {
+                Ok(_) => println!("Saved maze to {:?}", path),
+                Err(x) => println!("{:?}", x),
+            }
+        }
+
+        if let Some(path) = &options.graphviz {
+            match std::fs::write(path, maze.to_graphviz()) {
+                Ok(_) => println!("{:?}", path),
+                Err(x) => println!("{:?}", x),
+            }
+        }
+
+        Some(maze)
+    }
+
+    /// Codes are 12 alphanumeric chars, quoted, after a colon, or alone on an indented line
+    pub fn find_codes(message: &str) -> Vec<String> {
+        let re = Regex::new(r#"(?m)(?:^    |"|: )([A-Za-z0-9]{12})(?:"|\s|$)"#).unwrap();
+
+        re.captures_iter(message)
+            .map(|caps| caps.get(1).unwrap().as_str().to_string())
+            .collect()
+    }
+
+    pub fn default_fuzz_words() -> Vec<String> {
+        FUZZ_WORDS.iter().map(|w| w.to_string()).collect()
+    }
+
+    /// Try `words` as verbs, then the verbs found with the things around and the inventory,
+    /// and return the inputs with an unusual answer: (input, answer)
+    pub fn fuzz_verbs(
+        vm: &Vm,
+        words: &[String],
+    ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+        let mut work = vm.clone();
+        let checkpoint = work.checkpoint();
+        let mut answer = |input: &str| -> Result<String, Box<dyn std::error::Error>> {
+            work.restore(&checkpoint)?;
+            work.feed(input)?;
+            work.run();
+            let message = work.get_messages().last().cloned().unwrap_or_default();
+            Ok(message
+                .trim_end()
+                .trim_end_matches("What do you do?")
+                .trim()
+                .to_string())
+        };
+
+        let unknown = answer(FUZZ_GARBAGE)?;
+        let nouns: Vec<String> = match GameResponse::from_vm(vm) {
+            GameResponse::Room(room) => room.things,
+            _ => Vec::new(),
+        }
+        .into_iter()
+        .chain(crate::planner::GameState::from_vm(vm)?.inventory)
+        .collect();
+
+        let mut interesting = Vec::new();
+        for word in words {
+            let alone = answer(word)?;
+            if alone != unknown {
+                interesting.push((word.clone(), alone.clone()));
+            }
+
+            // a verb expecting an object answers something specific to garbage objects
+            let with_garbage = answer(&format!("{} {}", word, FUZZ_GARBAGE))?;
+            if with_garbage == unknown {
+                continue;
+            }
+            if with_garbage != alone {
+                interesting.push((format!("{} {}", word, FUZZ_GARBAGE), with_garbage.clone()));
+            }
+            for noun in &nouns {
+                let input = format!("{} {}", word, noun);
+                let response = answer(&input)?;
+                if response != with_garbage && response != unknown {
+                    interesting.push((input, response));
+                }
+            }
+        }
+
+        Ok(interesting)
+    }
+
+    /// From a fresh boot, take and use the tablet, and return the code written on it
+    pub fn tablet() -> Result<String, Box<dyn std::error::Error>> {
+        let mut game = Game::new()?;
+        for input in ["take tablet", "use tablet"] {
+            game.act(input)?;
+        }
+
+        let message = game.output();
+        Self::find_codes(message)
+            .pop()
+            .ok_or_else(|| format!("No code found in {:?}", message).into())
+    }
+
+    /// Find the shortest list of inputs, including `take` and `use`, to reach `room`
+    /// while holding `items`
+    pub fn walkthrough(
+        vm: &Vm,
+        planner: &Planner,
+        room: &Regex,
+        items: &[Regex],
+        maze: Option<&Maze>,
+        out: Option<&PathBuf>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let goal = |state: &GameState| {
+            room.is_match(&state.room.name)
+                && items
+                    .iter()
+                    .all(|item| state.inventory.iter().any(|i| item.is_match(i)))
+        };
+
+        let mut heuristics: Vec<Box<dyn Heuristic>> = vec![Box::new(MissingItems {
+            items: items.to_vec(),
+        })];
+        if let Some(maze) = maze {
+            heuristics.push(Box::new(RoomDistance::new(maze, room)));
+        }
+
+        let (inputs, stats) = planner.search_with(vm, goal, &heuristics)?;
+        println!(
+            "Expanded {} states, generated {}",
+            stats.expanded, stats.generated
+        );
+        let inputs = match inputs {
+            Some(inputs) => inputs,
+            None => {
+                println!("No walkthrough found");
+                return Ok(());
+            }
+        };
+
+        println!("Walkthrough in {} inputs:", inputs.len());
+        for input in &inputs {
+            println!("{}", input);
+        }
+
+        if let Some(path) = out {
+            let mut transcript = format!("# walkthrough to {}\n", room);
+            for input in &inputs {
+                transcript.push_str(input);
+                transcript.push('\n');
+            }
+            std::fs::write(path, transcript)?;
+            println!("{:?}", path);
+        }
+
+        Ok(())
+    }
+
+    /// Try every orb weight, feed `inputs` and report the values that don't lose the orb
+    ///
+    /// The VM must be in the vault area, holding the orb
+    pub fn solve_vault(vm: &Vm, inputs: &[String]) {
+        let values: Vec<u16> = VAULT_ORB_WEIGHTS.collect();
+        let results = sweep(&values, |val| {
+            let mut vm = vm.clone();
+            vm.mem_set(VAULT_ORB_WEIGHT, val);
+            for input in inputs {
+                vm.feed(input).ok()?;
+                vm.run();
+                if vm.get_state() != VmState::WaitingForInput {
+                    return None;
+                }
+                if let GameResponse::Terminal(_) = GameResponse::from_vm(&vm) {
+                    return None;
+                }
+                // not terminal, the rooms of the vault are still alive
+                let message = vm.get_messages().last()?;
+                if VAULT_LOSING_MESSAGES.iter().any(|m| message.contains(m)) {
+                    return None;
+                }
+            }
+
+            Some(vm.get_messages().last()?.clone())
+        });
+
+        println!("{} non losing values:", results.len());
+        println!("{:>6} | message", "weight");
+        for (val, message) in &results {
+            // skip the room name
+            let first_line = message
+                .lines()
+                .find(|line| !line.trim().is_empty() && !line.starts_with("=="))
+                .unwrap_or("");
+            println!("{:>6} | {}", val, first_line);
         }
-        println!("./graphviz.dot");
+    }
+
+    /// Set the variable to each value, feed the inputs, and return the values whose output
+    /// matches, with the first matching line
+    pub fn bruteforce(vm: &Vm, options: &BruteforceOptions) -> Vec<(u16, String)> {
+        let values: Vec<u16> = options.values.clone().collect();
+        sweep(&values, |val| {
+            let mut vm = vm.clone();
+            match options.variable {
+                Variable::Register(reg) => vm.set_register(reg, val),
+                Variable::Memory(addr) => vm.mem_set(addr, val),
+            }
+            let first_message = vm.get_messages().len();
+
+            let mut steps = 0;
+            let mut inputs = options.inputs.iter();
+            loop {
+                match vm.get_state() {
+                    VmState::Running if steps < options.max_steps => {
+                        vm.step().ok()?;
+                        steps += 1;
+                    }
+                    VmState::WaitingForInput => match inputs.next() {
+                        Some(input) => vm.feed(input).ok()?,
+                        None => break,
+                    },
+                    VmState::Halted => break,
+                    // out of steps, or on a breakpoint
+                    _ => return None,
+                }
+            }
+
+            let output = vm.get_messages()[first_message..].concat();
+            let found = options.success.find(&output)?;
+            let line = output[found.start()..].lines().next().unwrap_or("");
+            let line_start = output[..found.start()].rfind('\n').map_or(0, |idx| idx + 1);
+            Some(format!("{}{}", &output[line_start..found.start()], line))
+        })
     }
 
     pub fn trace_teleporter(vm: &Vm) {
@@ -133,7 +1075,7 @@ impl GameSolver {
                     break;
                 }
             }
-            //Vm::pretty_print_dis(&vm.get_trace_buffer());
+            //Vm::pretty_dis(&vm.get_trace_buffer());
             if vm.get_state() == VmState::WaitingForInput {
                 dbg!(&vm.get_messages().last());
                 panic!("{}", val);
@@ -142,15 +1084,43 @@ impl GameSolver {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Level {
     pub name: String,
     pub description: String,
     pub things: Vec<String>,
     pub exits: Vec<String>,
+    /// Name of the normalization rule applied to the description, if any
+    #[serde(default)]
+    pub normalized: Option<String>,
 }
 
+/// Description fragments that change with the game state: (rule name, regex, replacement)
+const NORMALIZATION_RULES: [(&str, &str, &str); 1] = [(
+    "lantern",
+    r"It is pitch black\.  You are likely to be eaten by a grue\.|You feel that your light source is more than sufficient to keep grues away\.",
+    "It is dark here.",
+)];
+
 impl Level {
+    /// Hash of the name and description, stable across runs and Rust versions (FNV-1a)
+    pub fn stable_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+
+        let mut hash = FNV_OFFSET;
+        for byte in self
+            .name
+            .bytes()
+            .chain(std::iter::once(b'\n'))
+            .chain(self.description.bytes())
+        {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+        hash
+    }
+
     pub fn from(raw: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let re_name = Regex::new(r"== (.+?) ==\n(.+?)\n").unwrap();
         let (name, mut description) = {
@@ -163,8 +1133,22 @@ impl Level {
         };
 
         if description.contains("You are in a grid of rooms that control the door to the vault.") {
-            description.push_str(raw.lines().nth(5).unwrap());
-            description = description.replace('\n', " ");
+            // the orb may print a message before the room, search the mosaic line
+            let re_mosaic =
+                Regex::new(r"The floor of this room is a large mosaic depicting [^\n]+").unwrap();
+            if let Some(mosaic) = re_mosaic.find(raw) {
+                description.push(' ');
+                description.push_str(mosaic.as_str());
+            }
+        }
+
+        let mut normalized = None;
+        for (rule, pattern, replacement) in NORMALIZATION_RULES {
+            let re = Regex::new(pattern).unwrap();
+            if re.is_match(&description) {
+                description = re.replace_all(&description, replacement).to_string();
+                normalized = Some(rule.to_string());
+            }
         }
 
         fn get_things(raw: &str) -> Vec<String> {
@@ -204,6 +1188,7 @@ impl Level {
             name,
             things,
             exits,
+            normalized,
         };
 
         Ok(level)
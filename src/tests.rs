@@ -1,6 +1,6 @@
 use itertools::iproduct;
 
-use crate::emulator::{Opcode, Val, Vm};
+use crate::emulator::{Addr, InvalidPolicy, Opcode, StopCondition, Val, Vm, VmError, VmState};
 
 #[test]
 fn load_program_from_file() -> Result<(), Box<dyn std::error::Error>> {
@@ -53,7 +53,7 @@ fn disassemble_function() -> Result<(), Box<dyn std::error::Error>> {
 
     let starting_ip = 0;
     let instructions = vm.disassemble_function(starting_ip)?;
-    Vm::pretty_print_dis(&instructions);
+    println!("{}", Vm::pretty_dis(&instructions, vm.get_symbols()));
 
     Ok(())
 }
@@ -97,11 +97,11 @@ fn run_until_ret_2125() -> Result<(), Box<dyn std::error::Error>> {
         let mut vm2 = vm.clone();
 
         println!("vm1");
-        let instr = vm1.run_until_ret()?;
+        vm1.run_until_ret()?;
 
         println!("vm2");
         vm2.set_patching(true);
-        let instr = vm2.run_until_ret()?;
+        vm2.run_until_ret()?;
 
         assert_eq!(vm1, vm2);
     }
@@ -122,8 +122,8 @@ fn patching_3() -> Result<(), Box<dyn std::error::Error>> {
     let mut vm = Vm::new();
     vm.load_program_from_mem(&prog);
 
-    let x = vm.disassemble(0, 5)?;
-    Vm::pretty_print_dis(&x);
+    let x = vm.disassemble(Addr::default(), 5)?;
+    println!("{}", Vm::pretty_dis(&x, vm.get_symbols()));
 
     let mut vm1 = vm.clone();
     let mut vm2 = vm.clone();
@@ -155,22 +155,22 @@ fn run_until_ret_3() -> Result<(), Box<dyn std::error::Error>> {
     let mut vm = Vm::new();
     vm.load_program_from_mem(&prog);
 
-    let x = vm.disassemble(0, 5)?;
-    Vm::pretty_print_dis(&x);
+    let x = vm.disassemble(Addr::default(), 5)?;
+    println!("{}", Vm::pretty_dis(&x, vm.get_symbols()));
     println!();
 
     let mut vm1 = vm.clone();
     let mut vm2 = vm.clone();
 
     println!("vm1");
-    vm1.run_until_ret();
+    vm1.run_until_ret()?;
     //vm1.step().unwrap();
     //vm1.step().unwrap();
     //vm1.step().unwrap();
 
     println!("vm2");
     vm2.set_patching(true);
-    vm2.run_until_ret();
+    vm2.run_until_ret()?;
     //vm2.step().unwrap();
 
     assert_eq!(vm1, vm2);
@@ -190,3 +190,3078 @@ Add(Reg(2), 10666, 956)";
         let _opcode: Opcode = line.parse().unwrap();
     }
 }
+
+#[test]
+fn level_stable_hash() {
+    use crate::solver::Level;
+
+    let level = Level {
+        name: "Foothills".into(),
+        description: "You find yourself standing at the base of an enormous mountain.".into(),
+        things: vec!["tablet".into()],
+        exits: vec!["doorway".into(), "south".into()],
+        normalized: None,
+    };
+
+    // must never change, exported graphs use it as the node id
+    assert_eq!(level.stable_hash(), 6713243521693990194);
+}
+
+#[test]
+fn level_normalization() {
+    use crate::solver::Level;
+
+    let dark = "\n\n== Passage ==\nIt is pitch black.  You are likely to be eaten by a grue.\n\nThere are 2 exits:\n- continue\n- back\n\nWhat do you do?\n";
+    let lit = "\n\n== Passage ==\nYou feel that your light source is more than sufficient to keep grues away.\n\nThere are 2 exits:\n- continue\n- back\n\nWhat do you do?\n";
+
+    let dark = Level::from(dark).unwrap();
+    let lit = Level::from(lit).unwrap();
+    assert_eq!(dark, lit);
+    assert_eq!(dark.normalized.as_deref(), Some("lantern"));
+
+    let lock = "\n\nAs you enter the room, the orb briefly flashes red.\n\n== Vault Lock ==\nYou are in a grid of rooms that control the door to the vault.\n\nThe floor of this room is a large mosaic depicting the number '4'.\n\nThere are 2 exits:\n- north\n- east\n\nWhat do you do?\n";
+    let lock = Level::from(lock).unwrap();
+    assert!(lock.description.ends_with("depicting the number '4'."));
+    assert_eq!(lock.normalized, None);
+}
+
+#[test]
+fn game_response_classify() {
+    use crate::solver::{GameResponse, TerminalKind};
+
+    let grue = "\n\nYou have been eaten by a grue.\n";
+    assert_eq!(
+        GameResponse::classify(grue, true),
+        GameResponse::Terminal(TerminalKind::Grue)
+    );
+    let victory =
+        "\n\nCongratulations; you have reached the end of the challenge!\n\n\nWhat do you do?\n";
+    assert_eq!(
+        GameResponse::classify(victory, false),
+        GameResponse::Terminal(TerminalKind::Victory)
+    );
+    assert_eq!(
+        GameResponse::classify("\n\nTaken.\n\nWhat do you do?\n", false),
+        GameResponse::Message("\n\nTaken.\n\nWhat do you do?\n".into())
+    );
+    assert_eq!(
+        GameResponse::classify("", true),
+        GameResponse::Terminal(TerminalKind::Death)
+    );
+    let vault = "\n\nAs you enter the room, the orb briefly flashes black.\n\nWhat do you do?\n";
+    // losing the orb doesn't end the game
+    assert!(!matches!(
+        GameResponse::classify(vault, false),
+        GameResponse::Terminal(_)
+    ));
+}
+
+#[test]
+fn find_codes() {
+    use crate::solver::GameSolver;
+
+    let welcome = "Please record your progress by putting codes like\nthis one into the challenge website: hKRuXKPwTwlo\n";
+    assert_eq!(GameSolver::find_codes(welcome), vec!["hKRuXKPwTwlo"]);
+
+    let stars =
+        "you think you see a pattern in the stars...\n\n    yjdhyEgpXPgW\n\nAfter a few moments";
+    assert_eq!(GameSolver::find_codes(stars), vec!["yjdhyEgpXPgW"]);
+
+    assert!(GameSolver::find_codes("\n\nTaken.\n\nWhat do you do?\n").is_empty());
+}
+
+#[test]
+fn glossary() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+    use crate::solver::{GameResponse, Level, Maze};
+
+    let mut cli = Cli::new(Vm::default());
+    assert!(cli.parse_command("glossary").is_err());
+
+    cli.parse_command("run")?;
+    let level = Level::from(cli.vm.get_messages().last().unwrap())?;
+    let mut maze = Maze::default();
+    maze.record_things(&level, &cli.vm);
+    maze.record_things(&level, &cli.vm);
+    let tablet = &maze.glossary["tablet"];
+    assert_eq!(tablet.rooms.iter().collect::<Vec<_>>(), ["Foothills"]);
+    assert!(tablet
+        .look
+        .as_ref()
+        .unwrap()
+        .starts_with("The tablet seems appropriate"));
+    assert!(tablet.text.is_some());
+    // looked at on a clone
+    assert_eq!(
+        GameResponse::from_vm(&cli.vm),
+        GameResponse::Room(level.clone())
+    );
+
+    cli.glossary = maze.glossary;
+    cli.parse_command("glossary TAB")?;
+    cli.parse_command("glossary --texts")?;
+    assert!(cli.parse_command("glossary (").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn look_texts() {
+    use crate::solver::TextEntry;
+
+    assert_eq!(TextEntry::parse("It is a lantern."), None);
+
+    let tablet = TextEntry::parse(
+        "The tablet seems appropriate for use as a writing surface but is unfortunately blank.",
+    )
+    .unwrap();
+    assert!(tablet.paragraphs.is_empty() && !tablet.algorithm);
+
+    let book = "The cover of this book subtly swirls with colors.  It is titled \"A Brief \
+                Introduction to Interdimensional Physics\".  It reads:\n\n\
+                The second destination requires a specific energy level in the eighth register.\n\n\
+                This required precision implies that the confirmation mechanism is expensive.\n\n\
+                Of course, since teleportation is impossible, this is all totally ridiculous.";
+    let book = TextEntry::parse(book).unwrap();
+    assert!(book.algorithm);
+    assert_eq!(book.paragraphs.len(), 3);
+    assert_eq!(book.algorithm_paragraphs().len(), 3);
+    assert!(book.description.starts_with("The cover"));
+
+    let note = TextEntry::parse("A note.\n\n    abcdEFGH1234\n").unwrap();
+    assert_eq!(note.codes, ["abcdEFGH1234"]);
+    assert!(!note.algorithm);
+}
+
+#[test]
+fn tablet_code() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::solver::GameSolver;
+
+    let code = GameSolver::tablet()?;
+    assert_eq!(code.len(), 12);
+
+    Ok(())
+}
+
+#[test]
+fn code_registry() {
+    use crate::codes::{CodeRegistry, CodeSlot};
+
+    let mut codes = CodeRegistry::default();
+    let boot = "this one into the challenge website: hKRuXKPwTwlo\n\nExecuting self-test...\n\nself-test complete, all tests pass\nThe self-test completion code is: NhMSeBfjuEsD\n";
+    assert_eq!(codes.capture(boot).len(), 2);
+    assert_eq!(codes.capture(boot).len(), 0);
+    assert_eq!(codes.get(CodeSlot::SelfTest), Some("NhMSeBfjuEsD"));
+
+    let mirror =
+        "Through the mirror, you see \"IpWWlixwbpXH\" scrawled in charcoal on your forehead.";
+    codes.capture(mirror);
+    assert_eq!(codes.get(CodeSlot::Mirror), Some("HXqdwxilWWqI"));
+}
+
+#[test]
+fn code_hash_verification() {
+    use std::collections::BTreeMap;
+
+    use crate::codes::{CodeRegistry, CodeSlot};
+
+    let mut hashes = BTreeMap::new();
+    hashes.insert(
+        CodeSlot::Tablet,
+        format!("{:x}", md5::compute("jtQUShZPqyoL")).to_uppercase(),
+    );
+    hashes.insert(
+        CodeSlot::SelfTest,
+        "00000000000000000000000000000000".into(),
+    );
+
+    let mut codes = CodeRegistry::with_hashes(&hashes);
+    assert_eq!(codes.verify(CodeSlot::Tablet), None);
+
+    codes.insert(CodeSlot::Tablet, "jtQUShZPqyoL");
+    codes.insert(CodeSlot::SelfTest, "NhMSeBfjuEsD");
+    codes.insert(CodeSlot::Boot, "hKRuXKPwTwlo");
+    assert_eq!(codes.verify(CodeSlot::Tablet), Some(true));
+    assert_eq!(codes.verify(CodeSlot::SelfTest), Some(false));
+    assert_eq!(codes.verify(CodeSlot::Boot), None);
+}
+
+#[test]
+fn encode_decode_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+    let prog = vec![
+        Opcode::Push(Val::Reg(1)),
+        Opcode::Pop(Val::Reg(2)),
+        Opcode::Gt(Val::Reg(1), Val::Reg(2), Val::Num(3)),
+        Opcode::Mult(Val::Reg(0), Val::Num(2), Val::Num(3)),
+        Opcode::Mod(Val::Reg(0), Val::Num(2), Val::Num(3)),
+        Opcode::And(Val::Reg(0), Val::Num(2), Val::Num(3)),
+        Opcode::Or(Val::Reg(0), Val::Num(2), Val::Num(3)),
+        Opcode::Not(Val::Reg(0), Val::Num(2)),
+        Opcode::Rmem(Val::Reg(0), Val::Num(2)),
+        Opcode::Wmem(Val::Num(2), Val::Reg(0)),
+        Opcode::Out(Val::Num(65)),
+        Opcode::In(Val::Reg(7)),
+    ];
+
+    for opcode in prog {
+        let words = opcode.machine_code();
+        assert_eq!(words.len(), opcode.size());
+        assert_eq!(Opcode::decode(&words)?, opcode);
+    }
+
+    assert!(Opcode::decode(&[1, 32768]).is_err());
+    assert!(Opcode::decode(&[22]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn disassemble_binary_file() -> Result<(), Box<dyn std::error::Error>> {
+    let mut vm = Vm::default();
+    vm.load_program_from_file("challenge.bin")?;
+
+    let words = Vm::read_binary("challenge.bin")?;
+    assert_eq!(
+        Opcode::disassemble(&words, 0, 20, InvalidPolicy::Strict)?,
+        vm.disassemble(Addr::default(), 20)?
+    );
+    assert!(Opcode::disassemble(&words, words.len() + 1, 1, InvalidPolicy::Strict).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn opcode_table() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::emulator::{OpcodeInfo, OPCODES};
+
+    for (idx, info) in OPCODES.iter().enumerate() {
+        assert_eq!(info.number as usize, idx);
+
+        let opcode = Opcode::decode(&[info.number, 1, 2, 3])?;
+        assert_eq!(opcode.size(), 1 + info.operands.len());
+        assert_eq!(opcode.machine_code()[0], info.number);
+        assert!(format!("{:?}", opcode).starts_with(info.mnemonic));
+        // the encoding is derived from the table and the decoding
+        assert_eq!(opcode.number(), info.number);
+        assert_eq!(opcode.discriminant(), 1 << idx);
+        assert_eq!(
+            opcode.machine_code(),
+            [info.number, 1, 2, 3][..opcode.size()]
+        );
+        assert_eq!(opcode.operands().len(), info.operands.len());
+        assert_eq!(format!("{:?}", opcode).parse::<Opcode>()?, opcode);
+
+        let asm = match info.operands.len() {
+            0 => info.mnemonic.to_string(),
+            n => format!("{}({})", info.mnemonic, vec!["1"; n].join(", ")),
+        };
+        assert_eq!(asm.parse::<Opcode>()?.machine_code()[0], info.number);
+    }
+
+    assert_eq!(OpcodeInfo::find("jmp").unwrap().number, 6);
+    assert_eq!(OpcodeInfo::find("19").unwrap().mnemonic, "Out");
+    assert!(OpcodeInfo::find("foo").is_none());
+
+    assert!("Jmp(1, 2)".parse::<Opcode>().is_err());
+    assert!("Add(1, 2)".parse::<Opcode>().is_err());
+    assert!("Call(1".parse::<Opcode>().is_err());
+    assert_eq!("halt".parse::<Opcode>()?, Opcode::Halt);
+
+    Ok(())
+}
+
+#[test]
+fn assemble() -> Result<(), Box<dyn std::error::Error>> {
+    let source = r#"
+# greets, then halts
+.const NEWLINE 10
+.const DATA 0x10
+Set(r0, DATA)
+.print "hi"
+Out(NEWLINE)
+Halt
+.word 1, 0xffff, NEWLINE
+.string "a\"b\n"
+"#;
+    let words = Opcode::assemble(source)?;
+    assert_eq!(
+        words,
+        vec![1, 32768, 16, 19, 104, 19, 105, 19, 10, 0, 1, 65535, 10, 97, 34, 98, 10]
+    );
+
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&words);
+    vm.run();
+    assert_eq!(vm.get_messages().concat(), "hi\n");
+
+    let err = Opcode::assemble("Halt\n.word 70000").unwrap_err();
+    assert!(err.to_string().starts_with("line 2:"), "{}", err);
+    assert!(Opcode::assemble(".const r1 3").is_err());
+    assert!(Opcode::assemble(".print \"é\"").is_err());
+    assert!(Opcode::assemble(".org 10").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn val_parse_display() -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!("1531".parse::<Val>()?, Val::Num(1531));
+    assert_eq!("0x7fff".parse::<Val>()?, Val::Num(32767));
+    assert_eq!("Reg(3)".parse::<Val>()?, Val::Reg(3));
+    assert_eq!("r7".parse::<Val>()?, Val::Reg(7));
+    assert_eq!("32771".parse::<Val>()?, Val::Reg(3));
+    assert_eq!("0x8007".parse::<Val>()?, Val::Reg(7));
+
+    assert!("Reg(8)".parse::<Val>().is_err());
+    assert!("32776".parse::<Val>().is_err());
+    assert!("65536".parse::<Val>().is_err());
+    assert!("Reg(3".parse::<Val>().is_err());
+
+    assert_eq!(format!("{}", Val::Reg(3)), "Reg(3)");
+    assert_eq!(format!("{:#}", Val::Reg(3)), "32771");
+    assert_eq!(format!("{:#}", Val::Num(12)), "12");
+
+    Ok(())
+}
+
+#[test]
+fn invalid_operand_policy() -> Result<(), Box<dyn std::error::Error>> {
+    // out(40000), noop, out(65)
+    let words = [19, 40000, 21, 19, 65];
+
+    let e = Opcode::decode_strict(&words, 100).unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<VmError>(),
+        Some(&VmError::InvalidOperand {
+            address: 101,
+            word: 40000
+        })
+    );
+    assert!(Opcode::disassemble(&words, 0, 2, InvalidPolicy::Strict).is_err());
+    assert_eq!(
+        Opcode::disassemble(&words, 0, 5, InvalidPolicy::Skip)?,
+        vec![(2, Opcode::Noop), (3, Opcode::Out(Val::Num(65)))]
+    );
+
+    let mut vm = Vm::default();
+    vm.load_program_from_mem(&words);
+    assert!(vm.step().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn extended_memory_banks() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::emulator::{BANK_SELECT, MEM_SIZE};
+
+    let prog = [
+        Opcode::Wmem(Val::Num(BANK_SELECT), Val::Num(2)),
+        Opcode::Wmem(Val::Num(100), Val::Num(42)),
+        Opcode::Rmem(Val::Reg(0), Val::Num(BANK_SELECT)),
+        Opcode::Wmem(Val::Num(BANK_SELECT), Val::Num(0)),
+        Opcode::Rmem(Val::Reg(1), Val::Num(100)),
+        Opcode::Halt,
+    ];
+
+    let mut vm = Vm::with_banks(3);
+    assert_eq!(vm.banks(), 3);
+    vm.load_program_from_mem(&Opcode::vec_to_machine_code(&prog));
+    vm.run();
+
+    let memory = vm.get_memory();
+    assert_eq!(memory.len(), 3 * MEM_SIZE);
+    assert_eq!(memory[2 * MEM_SIZE + 100], 42);
+    assert_eq!(memory[100], 0);
+    assert_eq!(vm.get_bank(), 0);
+
+    // without banks, 32767 is a plain address
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&Opcode::vec_to_machine_code(&prog[..2]));
+    vm.run();
+    assert_eq!(vm.get_memory()[BANK_SELECT as usize], 2);
+
+    Ok(())
+}
+
+#[test]
+fn stack_frames() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::emulator::StackSlot;
+
+    let prog = [
+        Opcode::Push(Val::Num(7)),
+        Opcode::Call(Val::Num(100)),
+        Opcode::Halt,
+    ];
+    // fn 100: saves r0 and r1, then jumps to a halt at 200 before returning
+    let func = [
+        Opcode::Push(Val::Reg(0)),
+        Opcode::Push(Val::Reg(1)),
+        Opcode::Push(Val::Num(3)),
+        Opcode::Jf(Val::Num(0), Val::Num(200)),
+        Opcode::Pop(Val::Reg(1)),
+        Opcode::Pop(Val::Reg(0)),
+        Opcode::Ret,
+    ];
+
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&Opcode::vec_to_machine_code(&prog));
+    for (idx, word) in Opcode::vec_to_machine_code(&func).into_iter().enumerate() {
+        vm.mem_set(100 + idx, word);
+    }
+    vm.run();
+
+    let frames = vm.get_call_stack();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].call_site, Addr::new(2).unwrap());
+    assert_eq!(frames[0].target, Addr::new(100).unwrap());
+    assert_eq!(
+        vm.stack_slots(),
+        vec![
+            StackSlot::Local,
+            StackSlot::ReturnAddress(frames[0].clone()),
+            StackSlot::SavedRegister(0),
+            StackSlot::SavedRegister(1),
+            StackSlot::Local,
+        ]
+    );
+    // ip is past the halt
+    assert_eq!(
+        vm.backtrace(),
+        vec![
+            (Addr::new(201).unwrap(), Addr::new(100)),
+            (Addr::new(2).unwrap(), None)
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn timetravel_goto_pc() -> Result<(), Box<dyn std::error::Error>> {
+    let mut vm = Vm::default();
+    vm.set_timetravel(Some(100_000));
+    vm.run();
+    vm.feed("take tablet")?;
+    vm.run();
+    vm.feed("look")?;
+    vm.run();
+    let end = vm.clone();
+    let end_pc = vm.get_pc();
+    assert!(vm.timetravel_snapshots().len() > 2);
+
+    // back in the middle of the boot, then forward again with the recorded inputs
+    vm.goto_pc(123_456)?;
+    assert_eq!(vm.get_pc(), 123_456);
+    vm.goto_pc(end_pc)?;
+    assert_eq!(vm.get_pc(), end_pc);
+    assert_eq!(vm.get_messages(), end.get_messages());
+    assert_eq!(vm.get_memory(), end.get_memory());
+
+    Ok(())
+}
+
+#[test]
+fn checkpoint_restore() -> Result<(), Box<dyn std::error::Error>> {
+    let mut vm = Vm::default();
+    vm.run();
+    let before = vm.clone();
+    let checkpoint = vm.checkpoint();
+
+    vm.feed("take tablet")?;
+    vm.run();
+    assert_ne!(vm, before);
+
+    vm.restore(&checkpoint)?;
+    assert_eq!(vm, before);
+    assert_eq!(vm.get_messages(), before.get_messages());
+
+    // branch again from the same checkpoint
+    vm.feed("look")?;
+    vm.run();
+    assert!(vm
+        .get_messages()
+        .last()
+        .unwrap()
+        .contains("== Foothills =="));
+
+    Ok(())
+}
+
+/// A room without exits nor things, for the maze tests
+fn level(name: &str, description: &str) -> crate::solver::Level {
+    crate::solver::Level {
+        name: name.to_string(),
+        description: description.to_string(),
+        things: Vec::new(),
+        exits: Vec::new(),
+        normalized: None,
+    }
+}
+
+#[test]
+fn planner_heuristics() {
+    use crate::planner::{GameState, Heuristic, MissingItems, RoomDistance};
+    use crate::solver::Maze;
+    use regex::Regex;
+
+    let state = |room: &str, inventory: &[&str]| GameState {
+        room_id: 0,
+        room: level(room, ""),
+        inventory: inventory.iter().map(|i| i.to_string()).collect(),
+        consumed: Vec::new(),
+    };
+
+    // a -> b -> c, and a shortcut c -> a
+    let maze = Maze {
+        edges: vec![
+            (level("a", ""), "north".to_string(), level("b", "")),
+            (level("b", ""), "north".to_string(), level("c", "")),
+            (level("c", ""), "south".to_string(), level("a", "")),
+        ],
+        ..Default::default()
+    };
+    let distance = RoomDistance::new(&maze, &Regex::new("^c$").unwrap());
+    assert_eq!(distance.estimate(&state("a", &[])), 2);
+    assert_eq!(distance.estimate(&state("b", &[])), 1);
+    assert_eq!(distance.estimate(&state("c", &[])), 0);
+    assert_eq!(distance.estimate(&state("unknown", &[])), 0);
+
+    let items = MissingItems {
+        items: vec![Regex::new("lantern").unwrap(), Regex::new("^can$").unwrap()],
+    };
+    assert_eq!(items.estimate(&state("a", &[])), 2);
+    assert_eq!(items.estimate(&state("a", &["lit lantern"])), 1);
+
+    let combined: Vec<Box<dyn Heuristic>> = vec![Box::new(distance), Box::new(items)];
+    assert_eq!(combined.estimate(&state("b", &["can", "lantern"])), 1);
+    assert_eq!(combined.estimate(&state("b", &[])), 2);
+}
+
+#[test]
+fn planner_walkthrough() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+    use crate::planner::{GameState, MissingItems, Planner};
+    use regex::Regex;
+
+    let game = crate::Game::new()?;
+    let heuristic = MissingItems {
+        items: vec![Regex::new("^lit lantern$")?],
+    };
+    let path = Planner::default()
+        .search_with(game.vm(), |s| s.room.name == "Ruins", &heuristic)?
+        .0
+        .expect("no path to the ruins");
+
+    // as short as the breadth first search
+    let shortest = Planner::default().search(game.vm(), |s| s.room.name == "Ruins")?;
+    assert_eq!(shortest.map(|p| p.len()), Some(path.len()));
+
+    // the lantern is filled and lit before the dark passage
+    let position = |action: &str| path.iter().position(|a| a == action);
+    let fill = position("use can").expect("can not used");
+    let light = position("use lantern").expect("lantern not lit");
+    assert!(position("take empty lantern").unwrap() < fill);
+    assert!(position("take can").unwrap() < fill);
+    assert!(fill < light);
+
+    // and it plays back from the start
+    let file = std::env::temp_dir().join("synacor_planner_walkthrough.txt");
+    std::fs::write(&file, path.join("\n"))?;
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command(&format!("run; play {}", file.display()))?;
+    std::fs::remove_file(&file)?;
+    let state = GameState::from_vm(&cli.vm)?;
+    assert_eq!(state.room.name, "Ruins");
+    assert!(state.inventory.contains(&"lit lantern".to_string()));
+
+    Ok(())
+}
+
+#[test]
+fn graphviz_clusters() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::solver::{ClusterBy, GraphvizOptions, Level, Maze};
+    use regex::Regex;
+
+    let start = level("Start", "");
+    let (a1, a2) = (level("Twisty", "1"), level("Twisty", "2"));
+    let b1 = level("Twisty", "3");
+    let edge = |from: &Level, exit: &str, to: &Level| (from.clone(), exit.to_string(), to.clone());
+
+    // two separate groups of twisty passages
+    let maze = Maze {
+        start: Some(start.clone()),
+        explored: vec![&start, &a1, &a2, &b1].into_iter().cloned().collect(),
+        edges: vec![
+            edge(&start, "west", &a1),
+            edge(&a1, "north", &a2),
+            edge(&start, "east", &b1),
+        ],
+        ..Default::default()
+    };
+
+    let by_name = maze.to_graphviz();
+    assert_eq!(by_name.matches("subgraph cluster_Twisty ").count(), 1);
+
+    let by_area = maze.to_graphviz_with(&GraphvizOptions {
+        cluster_by: "area".parse()?,
+        collapse: None,
+    });
+    assert!(by_area.contains("subgraph cluster_Twisty_0 "));
+    assert!(by_area.contains("subgraph cluster_Twisty_1 "));
+    assert!(by_area.contains("subgraph cluster_Start "));
+
+    let by_prefix = maze.to_graphviz_with(&GraphvizOptions {
+        cluster_by: ClusterBy::PathPrefix(1),
+        collapse: Some(Regex::new("^west$")?),
+    });
+    assert!(by_prefix.contains("collapsed_west [label=\"west (2 rooms)\", shape = box3d];"));
+    assert!(by_prefix.contains(&format!(
+        "{} -> collapsed_west [label =\"west (one-way)\", style = dashed];",
+        start.stable_hash()
+    )));
+    assert!(!by_prefix.contains("collapsed_west -> collapsed_west"));
+    assert!(by_prefix.contains("subgraph cluster_east "));
+
+    assert!("prefix:x".parse::<ClusterBy>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn graphviz_edge_directions() {
+    use crate::solver::{Level, Maze};
+
+    let (a, b, c) = (level("a", ""), level("b", ""), level("c", ""));
+    let edge = |from: &Level, exit: &str, to: &Level| (from.clone(), exit.to_string(), to.clone());
+
+    let maze = Maze {
+        explored: vec![&a, &b, &c].into_iter().cloned().collect(),
+        edges: vec![
+            edge(&a, "north", &b),
+            edge(&b, "south", &a),
+            edge(&b, "down", &c),
+        ],
+        ..Default::default()
+    };
+    let graphviz = maze.to_graphviz();
+
+    let (ha, hb, hc) = (a.stable_hash(), b.stable_hash(), c.stable_hash());
+    let bidirectional = if (ha.to_string(), "north") < (hb.to_string(), "south") {
+        format!("{} -> {} [label =\"north / south\", dir = both];", ha, hb)
+    } else {
+        format!("{} -> {} [label =\"south / north\", dir = both];", hb, ha)
+    };
+    assert!(graphviz.contains(&bidirectional));
+    assert_eq!(graphviz.matches(" -> ").count(), 2);
+    assert!(graphviz.contains(&format!(
+        "{} -> {} [label =\"down (one-way)\", style = dashed];",
+        hb, hc
+    )));
+}
+
+#[test]
+fn explore_limits() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::parse_duration;
+    use crate::solver::{ExploreOptions, GameSolver};
+    use std::time::Duration;
+
+    assert_eq!(parse_duration("30s")?, Duration::from_secs(30));
+    assert_eq!(parse_duration("45")?, Duration::from_secs(45));
+    assert_eq!(parse_duration("5m")?, Duration::from_secs(300));
+    assert_eq!(parse_duration("2h")?, Duration::from_secs(7200));
+    assert_eq!(parse_duration("1d")?, Duration::from_secs(86400));
+    assert!(parse_duration("1w").is_err());
+    assert!(parse_duration("m").is_err());
+
+    let mut vm = Vm::default();
+    vm.run();
+    let path = std::env::temp_dir().join("synacor_explore_resume.json");
+    let _ = std::fs::remove_file(&path);
+    let mut options = ExploreOptions {
+        max_rooms: Some(3),
+        resume: Some(path.clone()),
+        ..Default::default()
+    };
+    let maze = GameSolver::explore_maze(&vm, &options).unwrap();
+    assert_eq!(maze.explored.len(), 3);
+    assert!(!maze.queue.is_empty());
+
+    // from the saved maze, whatever the VM
+    options.max_rooms = Some(2);
+    let maze = GameSolver::explore_maze(&Vm::new(), &options).unwrap();
+    assert_eq!(maze.explored.len(), 5);
+
+    // no time left, the maze is saved as is
+    options.max_rooms = None;
+    options.max_time = Some(Duration::ZERO);
+    let maze = GameSolver::explore_maze(&Vm::new(), &options).unwrap();
+    assert_eq!(maze.explored.len(), 5);
+    std::fs::remove_file(&path)?;
+
+    Ok(())
+}
+
+#[test]
+fn addresses() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+    use crate::emulator::Addr;
+    use std::convert::TryFrom;
+
+    assert_eq!("6027".parse::<Addr>()?, Addr::try_from(6027usize)?);
+    assert_eq!(usize::from("0x7fff".parse::<Addr>()?), 32767);
+    assert!("32768".parse::<Addr>().is_err());
+    assert!(Addr::try_from(70000usize).is_err());
+    // checked when loaded too
+    assert_eq!(
+        serde_json::from_str::<Addr>("6027")?,
+        Addr::new(6027).unwrap()
+    );
+    assert!(serde_json::from_str::<Addr>("40000").is_err());
+    assert_eq!(Addr::MAX.wrapping_add(2), Addr::try_from(1u16)?);
+    assert_eq!(Addr::default().wrapping_sub(1), Addr::MAX);
+    assert_eq!(Addr::MAX.checked_add(1), None);
+    assert_eq!(Val::Num(5).addr(), Addr::new(5));
+    assert_eq!(Val::Reg(0).addr(), None);
+
+    // rejected when parsing the commands
+    let mut cli = Cli::new(Vm::new());
+    assert!(cli.parse_command("bp set 32768").is_err());
+    assert!(cli.parse_command("dis at 40000 1").is_err());
+    cli.parse_command("bp set 0x10")?;
+    assert_eq!(cli.vm.get_breakpoints()[0].address, Addr::new(16).unwrap());
+
+    // hex, names and sums
+    cli.parse_command("label set 6027 fn_start")?;
+    cli.parse_command("bp set @fn_start+12")?;
+    assert_eq!(
+        cli.vm.get_breakpoints()[1].address,
+        Addr::new(6039).unwrap()
+    );
+    cli.parse_command("mem set 0x100-1 0x20+1")?;
+    assert_eq!(cli.vm.mem_get(255), 33);
+    assert!(cli.parse_command("bp set @unknown+1").is_err());
+    assert!(cli.parse_command("mem get 1-2").is_err());
+    assert!(cli.parse_command("mem get 32768").is_err());
+    assert!(cli.parse_command("mem set 0 65536").is_err());
+    cli.parse_command("mem fill 32760 32768 0 --dry-run")?;
+
+    // the quoted text is left alone
+    cli.parse_command("note add 0 \"calls @fn_start and fn_12\"")?;
+    assert_eq!(
+        cli.vm.get_symbols().note(0),
+        Some("calls @fn_start and fn_12")
+    );
+
+    // the bookmarks are labels saved with the analysis
+    let path = std::env::temp_dir().join("synacor_bookmarks.json");
+    let mut db = crate::analysis::AnalysisDb::new(&[0]);
+    db.bookmarks.insert("decode".to_string(), 2125);
+    db.save(&path)?;
+    cli.parse_command(&format!("analysis list --db {}", path.display()))?;
+    assert_eq!(cli.vm.get_symbols().resolve("decode"), Addr::new(2125));
+    cli.parse_command("bookmark add check 0x20; bp set @check")?;
+    assert_eq!(cli.vm.get_breakpoints()[2].address, Addr::new(32).unwrap());
+    cli.parse_command("bookmark remove check")?;
+    assert_eq!(cli.vm.get_symbols().label(32), None);
+    let saved = crate::analysis::AnalysisDb::load(&path)?;
+    assert_eq!(saved.bookmarks.len(), 1);
+    std::fs::remove_file(&path)?;
+
+    // the extended banks are in reach
+    cli.vm.set_banks(2);
+    cli.parse_command("set confirm off")?;
+    cli.parse_command("mem set 40000 7; mem fill 65530 65536 1; mem dump 65530 6")?;
+    assert_eq!(cli.vm.mem_get(40000), 7);
+    assert_eq!(cli.vm.mem_get(65535), 1);
+    assert!(cli.parse_command("mem get 65536").is_err());
+    assert!(cli.parse_command("mem protect 65535 65537").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn patch_journal() -> Result<(), Box<dyn std::error::Error>> {
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[17, 6027, 21, 0]);
+    vm.patch(Opcode::Noop, Addr::default());
+    vm.patch(Opcode::Set(Val::Reg(7), Val::Num(1)), Addr::default());
+    vm.patch(Opcode::Out(Val::Num(65)), Addr::new(10).unwrap());
+    assert_eq!(vm.get_patches().len(), 3);
+    assert_eq!(vm.get_patches()[1].old, [21, 6027, 21]);
+
+    // overlapping patches are undone from the last one
+    assert!(vm.undo_patch(0).unwrap_err().contains("overlaps"));
+    vm.undo_patch(1)?;
+    vm.undo_patch(0)?;
+    assert_eq!(vm.get_memory()[0..4], [17, 6027, 21, 0]);
+    assert!(vm.undo_patch(1).is_err());
+
+    vm.mem_set(11, 66);
+    assert!(vm.undo_patch(0).unwrap_err().contains("changed"));
+    vm.load_program_from_mem(&[0]);
+    assert!(vm.get_patches().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn patch_apply() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let path = std::env::temp_dir().join("synacor_patches.txt");
+    let path_arg = path.to_str().unwrap();
+    let mut cli = Cli::new(Vm::new());
+    cli.parse_command("label set 100 table")?;
+    cli.parse_command("set confirm off")?;
+
+    std::fs::write(
+        &path,
+        "# comment\n.const R 25734\n0: Set(r7, R)\n@table+1: .word 1, 2\n4: Noop\n",
+    )?;
+    cli.parse_command(&format!("patch apply {} --dry-run", path_arg))?;
+    assert_eq!(cli.vm.get_memory()[0], 0);
+    cli.parse_command(&format!("patch apply {}", path_arg))?;
+    assert_eq!(cli.vm.get_memory()[0..5], [1, 32775, 25734, 0, 21]);
+    assert_eq!(cli.vm.get_memory()[101..103], [1, 2]);
+
+    // nothing is applied when a line is invalid
+    std::fs::write(&path, "10: Noop\n11 Noop\n")?;
+    let err = cli
+        .parse_command(&format!("patch apply {}", path_arg))
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .ends_with(":2: Expected `<offset>: <opcode>`"));
+    assert_eq!(cli.vm.get_memory()[10], 0);
+    std::fs::remove_file(&path)?;
+
+    Ok(())
+}
+
+#[test]
+fn mem_edit() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::new());
+    cli.parse_command("label set 6027 fn_start")?;
+    // the lines of the REPL go to the editor until `q`
+    cli.parse_command("mem edit 100")?;
+    for line in ["", "5", "'A'", "-", "bad", "0x10+@fn_start"] {
+        cli.parse_command(line)?;
+    }
+    assert_eq!(cli.prompt(), "mem 103 > ");
+    cli.parse_command("q")?;
+    assert!(!cli.prompt().starts_with("mem"));
+    assert_eq!(cli.vm.mem_get(101), 5);
+    assert_eq!(cli.vm.mem_get(102), 6043);
+    assert_eq!(cli.vm.mem_get(103), 0);
+
+    // stops at the end of the memory
+    cli.parse_command("mem edit 32767")?;
+    cli.parse_command("1")?;
+    assert_eq!(cli.vm.mem_get(32767), 1);
+    assert!(!cli.prompt().starts_with("mem"));
+
+    Ok(())
+}
+
+#[test]
+fn cli_multi_command_lines() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command("  run ;  take   tablet  # grab it")?;
+    cli.parse_command("# only a comment")?;
+    cli.parse_command("inv;")?;
+
+    let messages = cli.vm.get_messages();
+    assert!(messages[messages.len() - 2].contains("Taken."));
+    assert!(messages.last().unwrap().contains("- tablet"));
+
+    // stops at the first error
+    assert!(cli.parse_command("input a; input b; look").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn cli_command_or_game_input() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command("run")?;
+    let count = cli.vm.get_messages().len();
+
+    // typo of a command, bad arguments of a command, forced command: not fed
+    assert!(cli.parse_command("stpe 5").is_err());
+    assert!(cli.parse_command("step abc").is_err());
+    assert!(cli.parse_command(":look").is_err());
+    assert_eq!(cli.vm.get_messages().len(), count);
+
+    // game inputs, and forced game input
+    cli.parse_command("take tablet")?;
+    cli.parse_command(">stpe")?;
+    let messages = cli.vm.get_messages();
+    assert_eq!(messages.len(), count + 2);
+    assert!(messages.last().unwrap().contains("I don't understand"));
+
+    Ok(())
+}
+
+#[test]
+fn snapshot_names() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::default());
+    assert_eq!(cli.suggest_snapshot_name(None), "start-01_0instr");
+    cli.parse_command("run")?;
+    cli.parse_command("snap take --tag Tablet!")?;
+    cli.parse_command("snap take --tag tablet")?;
+    let name = cli.suggest_snapshot_name(Some("tablet"));
+    assert!(
+        name.starts_with("foothills-tablet-03_") && name.ends_with("kinstr"),
+        "{}",
+        name
+    );
+    assert!(cli.parse_command("snap take mine --tag tablet").is_err());
+
+    cli.parse_command("state list --sort name")?;
+    cli.parse_command("state find foothills")?;
+    assert!(cli.parse_command("state find (").is_err());
+
+    // the first two snapshots are identical, snaps/ is only listed
+    cli.parse_command("set confirm off")?;
+    cli.parse_command("state gc --older-than 1d --duplicates --dry-run")?;
+    assert_eq!(cli.snapshots.len(), 2);
+    cli.parse_command("state gc --duplicates")?;
+    assert_eq!(cli.snapshots.len(), 1);
+    assert!(cli.parse_command("state gc --older-than 1w").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn cli_dry_run_and_confirm() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command("snap take a")?;
+    let memory = cli.vm.get_memory().to_vec();
+
+    cli.parse_command("mem fill 10 12 21 --dry-run")?;
+    cli.parse_command("patch Out(65) 0 --dry-run")?;
+    cli.parse_command("snap remove a --dry-run")?;
+    assert_eq!(cli.vm.get_memory(), &memory[..]);
+    assert_eq!(cli.snapshots.len(), 1);
+
+    cli.parse_command("set confirm off")?;
+    cli.parse_command("mem fill 10 12 21")?;
+    cli.parse_command("patch Out(65) 0")?;
+    cli.parse_command("snap remove a")?;
+    assert_eq!(cli.vm.get_memory()[10..13], [21, 21, memory[12]]);
+    assert_eq!(cli.vm.get_memory()[0..2], [19, 65]);
+    assert!(cli.snapshots.is_empty());
+
+    assert!(cli.parse_command("mem fill 12 10 0").is_err());
+    assert!(cli.parse_command("snap remove a").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn help_registry() {
+    use crate::cli::Cli;
+    use crate::help::{COMMANDS, TUTORIAL};
+
+    let cli = Cli::new(Vm::new());
+    let names: Vec<&str> = cli.cli.get_subcommands().map(|c| c.get_name()).collect();
+
+    for name in &names {
+        assert!(crate::help::find(name).is_some(), "no help for {}", name);
+    }
+
+    let examples = COMMANDS
+        .iter()
+        .flat_map(|help| help.examples.iter())
+        .chain(TUTORIAL.iter());
+    for (line, _) in examples {
+        let word = line.split_whitespace().next().unwrap();
+        if names.contains(&word) {
+            // bookmarks and labels are replaced by their address before parsing
+            let argv = crate::cli::split_args(line).into_iter().map(|arg| {
+                if arg.starts_with('@') {
+                    "0".to_string()
+                } else {
+                    arg
+                }
+            });
+            let parsed = cli.cli.clone().try_get_matches_from(argv);
+            assert!(parsed.is_ok(), "invalid example {:?}", line);
+        }
+    }
+    for help in &COMMANDS {
+        assert!(names.contains(&help.name), "help for unknown {}", help.name);
+    }
+}
+
+#[test]
+fn hints() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+    use crate::codes::{CodeRegistry, CodeSlot};
+    use crate::hints::{hints, HintContext};
+    use std::collections::BTreeSet;
+
+    let mut codes = CodeRegistry::default();
+    let names = |ctx: &HintContext| -> Vec<&str> { hints(ctx).iter().map(|h| h.name).collect() };
+    let mut context = HintContext {
+        inventory: vec!["red coin".to_string(), "tablet".to_string()],
+        rooms: BTreeSet::new(),
+        codes: &codes,
+        r7: 0,
+    };
+    assert_eq!(
+        names(&context),
+        ["use tablet", "twisty passages", "find the monument"]
+    );
+    context.rooms.insert("Ruins".to_string());
+    assert_eq!(names(&context)[2], "solve the monument");
+
+    codes.insert(CodeSlot::Tablet, "a");
+    codes.insert(CodeSlot::TwistyPassages, "b");
+    codes.insert(CodeSlot::Teleporter, "c");
+    let mut context = HintContext {
+        inventory: vec!["teleporter".to_string()],
+        rooms: BTreeSet::new(),
+        codes: &codes,
+        r7: 0,
+    };
+    assert_eq!(names(&context), ["set r7"]);
+    context.r7 = 25734;
+    assert!(names(&context).is_empty());
+
+    let mut cli = Cli::new(Vm::default());
+    assert!(cli.parse_command("hint").is_err());
+    cli.parse_command("run; take tablet; hint; hint --full")?;
+
+    Ok(())
+}
+
+#[test]
+fn undo_inputs() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::default());
+    assert!(cli.parse_command("undo").is_err());
+    cli.parse_command("run")?;
+    let start = cli.vm.clone();
+    cli.parse_command("take tablet")?;
+    let taken = cli.vm.clone();
+    cli.parse_command("input doorway; run; look")?;
+    cli.vm.patch(Opcode::Noop, Addr::new(10).unwrap());
+    assert!(cli.parse_command("undo 4").is_err());
+
+    // the patches are rewound with the memory
+    cli.parse_command("undo 2")?;
+    assert_eq!(cli.vm, taken);
+    assert!(cli.vm.get_patches().is_empty());
+    assert_eq!(cli.vm.get_messages(), taken.get_messages());
+    cli.parse_command("undo")?;
+    assert_eq!(cli.vm, start);
+    assert!(cli.parse_command("undo").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn cli_prompt() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command("set prompt \"{state} {room} {ip} >> \"")?;
+    assert_eq!(cli.prompt(), "running - 0 >> ");
+    cli.parse_command("run")?;
+    assert!(cli.prompt().starts_with("input Foothills "));
+
+    cli.parse_command("set prompt \"{pc} >> \"")?;
+    assert!(cli.prompt().ends_with("k >> "));
+
+    Ok(())
+}
+
+#[test]
+fn shell_and_copy() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let path = std::env::temp_dir().join("synacor-shell.txt");
+    let path = path.to_str().unwrap();
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command(&format!("! echo one > {0}; echo 'two # kept' >> {0}", path))?;
+    assert!(cli.parse_command("! exit 3").is_err());
+
+    assert_eq!(cli.artifact(path)?, "one\ntwo # kept\n");
+    std::fs::remove_file(path)?;
+    assert!(cli.artifact(path).is_err());
+
+    cli.parse_command("run")?;
+    // `>word` is fed to the game, with or without a space
+    cli.parse_command(">look")?;
+    assert!(cli.artifact("last-output")?.contains("== Foothills =="));
+    cli.parse_command("> take tablet")?;
+    assert!(cli.artifact("last-output")?.contains("Taken."));
+    assert!(cli.artifact("codes")?.contains("\nBoot "));
+
+    Ok(())
+}
+
+#[test]
+fn tee_transcript() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let path = std::env::temp_dir().join("synacor-tee.txt");
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command("set confirm off; run; snap take start")?;
+    cli.parse_command(&format!("tee {}", path))?;
+    cli.parse_command("take tablet; input \"use tablet\"; run")?;
+    cli.parse_command("tee --stop")?;
+    cli.parse_command("look")?;
+    assert!(cli.parse_command("tee --stop").is_err());
+
+    let text = std::fs::read_to_string(path)?;
+    assert!(text.starts_with("> take tablet\n\n\nTaken.\n"));
+    assert!(text.contains("> use tablet\n"));
+    assert!(!text.contains("> look"));
+
+    // the transcript replays from the state where it started
+    cli.parse_command("snap restore start")?;
+    cli.parse_command(&format!("verify transcript {}", path))?;
+    std::fs::remove_file(path)?;
+
+    Ok(())
+}
+
+#[test]
+fn input_starvation() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command("set watchdog on")?;
+    assert!(cli.starvation().is_ok());
+    cli.parse_command("run; take tablet")?;
+    let e = cli.starvation().unwrap_err();
+    assert!(e.contains("Taken.\n\nWhat do you do?"));
+
+    cli.parse_command("set watchdog off")?;
+    assert!(cli.starvation().is_ok());
+
+    // the sourced scripts and played inputs are checked too
+    let path = std::env::temp_dir().join("synacor_starvation.txt");
+    std::fs::write(&path, "take tablet\n")?;
+    cli.parse_command(&format!("play {}", path.display()))?;
+    cli.parse_command("set watchdog on")?;
+    let e = cli
+        .parse_command(&format!("play {}", path.display()))
+        .unwrap_err();
+    assert!(e.to_string().contains("waits for input"));
+    std::fs::write(&path, "look\n")?;
+    let e = cli
+        .parse_command(&format!("source {}", path.display()))
+        .unwrap_err();
+    assert!(e.to_string().contains("waits for input"));
+    std::fs::write(&path, "look\nquit\n")?;
+    cli.parse_command(&format!("source {}", path.display()))?;
+    std::fs::remove_file(&path)?;
+    assert!(cli.starvation().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn game_api() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::{Game, GameResponse};
+
+    let mut game = Game::new()?;
+    assert_eq!(game.room()?.name, "Foothills");
+    assert_eq!(
+        game.act("take tablet")?,
+        GameResponse::Message(game.output().into())
+    );
+    let saved = game.save();
+    assert!(matches!(game.act("doorway")?, GameResponse::Room(room) if room.name == "Dark cave"));
+    assert_eq!(game.inventory()?, vec!["tablet"]);
+
+    // from another game, like a snapshot
+    let mut other = Game::from_vm(game.vm().clone())?;
+    other.restore(&saved);
+    assert_eq!(other.room()?.name, "Foothills");
+    assert_eq!(game.room()?.name, "Dark cave");
+    assert!(!other.is_over());
+
+    Ok(())
+}
+
+#[test]
+fn mem_dump() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::{dump_words, Cli};
+
+    let words = [72, 105, 32, 0x7fff, 10, 33, 0x7e, 0x7f, 65];
+    assert_eq!(
+        dump_words(&words, 100, 4),
+        vec![
+            "  100: 0048 0069 0020 7fff  |Hi .|",
+            "  104: 000a 0021 007e 007f  |.!~.|",
+            "  108: 0041                 |A|",
+        ]
+    );
+
+    let mut cli = Cli::new(Vm::new());
+    cli.parse_command("mem dump 0 16; mem dump 32760 100 --width 3")?;
+    assert!(cli.parse_command("mem dump 0 0").is_err());
+    assert!(cli.parse_command("mem dump 32768 1").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn examine() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::new());
+    cli.parse_command("mem set 100 2; mem set 101 111; mem set 102 107")?;
+    cli.parse_command("x/16w 0; x/4i 0; x 100; x/s 100; x/2s 100")?;
+    cli.parse_command("x/1w 32767; mem set 32767 5")?;
+    for invalid in ["x/0w 0", "x/4q 0", "x/w", "x/s 32767", "x 32768"] {
+        assert!(cli.parse_command(invalid).is_err(), "{}", invalid);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn find_binary() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+    use std::path::{Path, PathBuf};
+
+    let found = Vm::find_binary(Some(Path::new("challenge.bin")), None)?;
+    assert_eq!(found, PathBuf::from("challenge.bin"));
+    let found = Vm::find_binary(None, Some(Path::new("challenge.bin")))?;
+    assert_eq!(found, PathBuf::from("challenge.bin"));
+    let e = Vm::find_binary(
+        Some(Path::new("missing.bin")),
+        Some(Path::new("challenge.bin")),
+    );
+    assert_eq!(e, Err("Program missing.bin not found".to_string()));
+
+    let mut cli = Cli::new(Vm::new());
+    assert!(cli.parse_command("load missing.bin").is_err());
+    assert_eq!(cli.binary, None);
+    cli.parse_command("set confirm off; load challenge.bin; run")?;
+    assert_eq!(cli.binary, Some(PathBuf::from("challenge.bin")));
+    assert_eq!(cli.vm.get_state(), VmState::WaitingForInput);
+
+    // one word more than the memory
+    let big = std::env::temp_dir().join("synacor_too_big.bin");
+    std::fs::write(&big, vec![0u8; 2 * 32769])?;
+    let e = cli.parse_command(&format!("load {}", big.display()));
+    std::fs::remove_file(&big)?;
+    assert!(e.unwrap_err().to_string().contains("too big"));
+    assert_eq!(cli.binary, Some(PathBuf::from("challenge.bin")));
+    assert_eq!(cli.vm.get_state(), VmState::WaitingForInput);
+
+    Ok(())
+}
+
+#[test]
+fn write_protection() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    // 0: add r0 r0 1; wmem 100 r0; wmem 200 r0; jmp 0
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[9, 32768, 32768, 1, 16, 100, 32768, 16, 200, 32768, 6, 0]);
+    let mut cli = Cli::new(vm);
+    cli.parse_command("mem protect 150 250; mem protect 300")?;
+    assert_eq!(cli.vm.get_protected(), [150..250, 300..301]);
+    cli.parse_command("run")?;
+    assert_eq!(cli.vm.get_state(), VmState::HitBreakPoint);
+    assert_eq!(usize::from(cli.vm.get_ip()), 7);
+    let fault = *cli.vm.get_fault().unwrap();
+    assert_eq!(
+        (usize::from(fault.ip), fault.addr, fault.value),
+        (7, 200, 1)
+    );
+    assert_eq!(cli.vm.get_memory()[200], 0);
+
+    // resuming writes, until the next loop
+    cli.parse_command("continue")?;
+    assert_eq!(cli.vm.get_memory()[200], 1);
+    assert_eq!(cli.vm.get_fault().map(|fault| fault.value), Some(2));
+
+    // resuming from a breakpoint on the write still faults
+    cli.parse_command("bp set 7; continue")?;
+    assert_eq!(usize::from(cli.vm.get_ip()), 7);
+    assert!(cli.vm.get_fault().is_none());
+    cli.parse_command("continue")?;
+    assert_eq!(cli.vm.get_fault().map(|fault| fault.value), Some(3));
+    assert_eq!(cli.vm.get_memory()[200], 2);
+
+    cli.parse_command("mem unprotect 0; mem protect")?;
+    assert!(cli.parse_command("mem unprotect 1").is_err());
+    assert!(cli.parse_command("mem protect 10 10").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn session_artifacts() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::artifacts::{utc_timestamp, Artifacts};
+    use crate::cli::Cli;
+    use std::time::{Duration, SystemTime};
+
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_792_073_002);
+    assert_eq!(utc_timestamp(time), "2026-10-15_14-03-22");
+    assert_eq!(utc_timestamp(SystemTime::UNIX_EPOCH), "1970-01-01_00-00-00");
+
+    let root = std::env::temp_dir().join("synacor_artifacts");
+    let _ = std::fs::remove_dir_all(&root);
+    let mut cli = Cli::new(Vm::new());
+    cli.artifacts = Artifacts::new(&root);
+    cli.parse_command("artifacts list; artifacts list --all")?;
+    assert!(cli.parse_command("artifacts open").is_err());
+    assert!(!root.exists());
+
+    // 0: len 4 "Test"
+    cli.vm.load_program_from_mem(&[4, 84, 101, 115, 116]);
+    cli.parse_command("report html; artifacts list")?;
+    let session = cli.artifacts.session().unwrap().to_path_buf();
+    assert!(session.join("report/index.html").exists());
+    assert!(cli.artifact("report/strings.html")?.contains("Test"));
+
+    // a second session in the same second gets its own folder
+    let mut other = Artifacts::new(&root);
+    let path = other.path("graphviz.dot")?;
+    assert_ne!(path.parent(), Some(session.as_path()));
+    std::fs::write(&path, "digraph {}")?;
+    let sessions = other.sessions()?;
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[1].1, vec![path]);
+    std::fs::remove_dir_all(&root)?;
+
+    Ok(())
+}
+
+#[test]
+fn game_mode() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::{Cli, GameWords};
+
+    let words = GameWords {
+        verbs: vec!["take".into(), "use".into(), "go".into()],
+        things: vec!["tablet".into(), "empty lantern".into()],
+        inventory: vec!["can".into()],
+        exits: vec!["doorway".into(), "south".into()],
+    };
+    assert_eq!(words.complete("t"), (0, vec!["take".into()]));
+    assert_eq!(words.complete("  do"), (2, vec!["doorway".into()]));
+    assert_eq!(words.complete("take em"), (5, vec!["empty lantern".into()]));
+    assert_eq!(
+        words.complete("take empty l"),
+        (5, vec!["empty lantern".into()])
+    );
+    assert_eq!(words.complete("use "), (4, vec!["can".into()]));
+    assert_eq!(words.complete("go s"), (3, vec!["south".into()]));
+    assert_eq!(
+        words.complete("look "),
+        (
+            5,
+            vec!["can", "empty lantern", "tablet"]
+                .into_iter()
+                .map(String::from)
+                .collect()
+        )
+    );
+
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command("run; game")?;
+    assert!(cli.prompt().starts_with("game input Foothills"));
+    let words = cli.game_words();
+    assert_eq!(words.things, vec!["tablet"]);
+    assert!(words.exits.contains(&"doorway".to_string()));
+    assert!(words.inventory.is_empty());
+    let pc = cli.vm.get_pc();
+    assert_eq!(cli.game_words(), words);
+    assert_eq!(cli.vm.get_pc(), pc);
+
+    // `run` and `bp` are game inputs now
+    cli.parse_command("take tablet")?;
+    cli.parse_command("run")?;
+    assert!(cli
+        .vm
+        .get_messages()
+        .last()
+        .unwrap()
+        .contains("I don't understand"));
+    cli.parse_command(":bp list; inv")?;
+    assert!(cli
+        .vm
+        .get_messages()
+        .last()
+        .unwrap()
+        .contains("Your inventory"));
+    cli.parse_command(":bp list; run")?;
+    assert!(cli
+        .vm
+        .get_messages()
+        .last()
+        .unwrap()
+        .contains("I don't understand"));
+    cli.parse_command(":bp list; :game off")?;
+    assert!(!cli.game_mode);
+    assert_eq!(cli.game_words().inventory, vec!["tablet"]);
+
+    Ok(())
+}
+
+#[test]
+fn cli_quit() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::new());
+    assert!(!cli.quit);
+    cli.parse_command("exit")?;
+    assert!(cli.quit);
+
+    Ok(())
+}
+
+#[test]
+fn fuzz_verbs() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::solver::GameSolver;
+
+    let mut vm = Vm::default();
+    vm.run();
+    let words: Vec<String> = ["foo", "take", "inv"]
+        .iter()
+        .map(|w| w.to_string())
+        .collect();
+    let interesting = GameSolver::fuzz_verbs(&vm, &words)?;
+    let inputs: Vec<&str> = interesting.iter().map(|(i, _)| i.as_str()).collect();
+
+    assert!(inputs.contains(&"take"));
+    assert!(inputs.contains(&"take tablet"));
+    assert!(inputs.contains(&"inv"));
+    assert!(!inputs.iter().any(|i| i.starts_with("foo")));
+
+    Ok(())
+}
+
+#[test]
+fn compare_input_diff() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+    use crate::diff::{line_diff, memory_diff};
+
+    assert_eq!(
+        line_diff("a\nb\nc", "a\nx\nc\nd"),
+        vec![" a", "-b", "+x", " c", "+d"]
+    );
+    assert_eq!(memory_diff(&[1, 2, 3], &[1, 5, 3]), vec![(1, 2, 5)]);
+
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command("run")?;
+    let before = cli.vm.get_memory().to_vec();
+    cli.parse_command("compare-input \"take tablet\" \"look\"")?;
+    // the VM itself is left untouched
+    assert_eq!(cli.vm.get_memory(), &before[..]);
+
+    Ok(())
+}
+
+#[test]
+fn session_stats() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command("run")?;
+    cli.parse_command("take tablet")?;
+    cli.parse_command("doorway")?;
+    cli.parse_command("session stats")?;
+
+    assert_eq!(cli.session.game_commands, 2);
+    assert_eq!(cli.session.rooms.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn entropy_watch_and_pin() {
+    // wmem 100 7; rmem r0 100; rmem r1 101; wmem 200 r1; halt
+    let prog = vec![
+        16, 100, 7, 15, 32768, 100, 15, 32769, 101, 16, 200, 32769, 0,
+    ];
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+    vm.set_watch(100);
+    vm.set_pin(101, 42);
+    vm.run();
+
+    assert_eq!(vm.get_entropy().reads[&100], vec![(3, 7)]);
+    assert!(!vm.get_entropy().reads.contains_key(&101));
+    assert_eq!(vm.get_memory()[200], 42);
+}
+
+#[test]
+fn alert_sink() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let out = std::env::temp_dir().join("synacor_alert_sink.txt");
+    let _ = std::fs::remove_file(&out);
+
+    let mut cli = Cli::new(Vm::default());
+    cli.parse_command("alert add 'Things of interest'")?;
+    cli.parse_command(&format!(
+        "alert sink 'echo \"$ALERT_MATCH\" >> {}'",
+        out.display()
+    ))?;
+    cli.parse_command("run")?;
+    cli.parse_command("take tablet")?;
+
+    // only the room description matched, taking the tablet didn't
+    let alerts = std::fs::read_to_string(&out)?;
+    assert_eq!(alerts, "Things of interest here:\n");
+    std::fs::remove_file(&out)?;
+
+    Ok(())
+}
+
+#[test]
+fn html_report() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::report::{basic_blocks, walk_function, Analysis};
+
+    // 0: call 10; 2: halt; 3: len 4 "Test"
+    // 10: jf r0 16; 13: out 'a'; 15: noop; 16: ret
+    let mut prog = vec![17, 10, 0, 4, 84, 101, 115, 116, 0, 0];
+    prog.extend([8, 32768, 16, 19, 97, 21, 18]);
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+
+    let function = walk_function(vm.get_memory(), 10);
+    let blocks = basic_blocks(&function);
+    let starts: Vec<usize> = blocks.iter().map(|b| b.start).collect();
+    assert_eq!(starts, vec![10, 13, 16]);
+    assert_eq!(blocks[0].successors, vec![13, 16]);
+
+    let analysis = Analysis::new(&vm);
+    assert_eq!(
+        analysis.functions.keys().copied().collect::<Vec<_>>(),
+        vec![0, 10]
+    );
+    assert_eq!(analysis.strings, vec![(3, "Test".to_string())]);
+
+    let dir = std::env::temp_dir().join("synacor_html_report");
+    analysis.write_html(&dir)?;
+    let page = std::fs::read_to_string(dir.join("fn_10.html"))?;
+    assert!(page.contains("<svg"));
+    assert!(page.contains("<a href=\"fn_0.html#0\">0</a>"));
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn re_tool_exports() {
+    use crate::report::Analysis;
+
+    // 0: call 10; 2: halt; 3: len 4 "Test"
+    // 10: set r0 3; 13: ret
+    let prog = vec![17, 10, 0, 4, 84, 101, 115, 116, 0, 0, 1, 32768, 3, 18];
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+
+    let analysis = Analysis::new(&vm);
+    let r2 = analysis.to_radare2();
+    assert!(r2.contains("af+ 0x14 fn_10\n"));
+    assert!(r2.contains("f str_3 10 0x6\n"));
+    // base64 of `str_3: "Test"`
+    assert!(r2.contains("CC base64:c3RyXzM6ICJUZXN0Ig== @ 0x14\n"));
+
+    let ghidra = analysis.to_ghidra();
+    assert!(ghidra.contains("createFunction(toAddr(0xa), \"fn_10\")\n"));
+    assert!(ghidra.contains("setEOLComment(toAddr(0xa), \"str_3: \\\"Test\\\"\")\n"));
+}
+
+#[test]
+fn text_graph() {
+    use crate::report::{text_graph, walk_function};
+
+    // 0: jf r0 5; 3: out 'a'; 5: ret
+    let prog = vec![8, 32768, 5, 19, 97, 18];
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+
+    let graph = text_graph(
+        &walk_function(vm.get_memory(), 0),
+        Some(3),
+        vm.get_symbols(),
+    );
+    let expected = "\
++-- 0 ---------------+
+|  0: Jf(Reg(0), 5)  |
++--------------------+
+  -> 3, 5
++-- 3 ---------------+
+|> 3: Out(97)        |
++--------------------+
+  -> 5
++-- 5 ---------------+
+|  5: Ret            |
++--------------------+
+  (end)
+";
+    assert_eq!(graph, expected);
+}
+
+#[test]
+fn graph_view() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+    use crate::report::{basic_blocks, text_block, walk_function};
+
+    // 0: jf r0 5; 3: out 'a'; 5: ret
+    let prog = vec![8, 32768, 5, 19, 97, 18];
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+
+    let blocks = basic_blocks(&walk_function(vm.get_memory(), 0));
+    let expected = "  <- 0
++-- 3 ---------+
+|> 3: Out(97)  |
++--------------+
+  1) -> 5
+";
+    assert_eq!(text_block(&blocks, 3, Some(3), vm.get_symbols()), expected);
+
+    let mut cli = Cli::new(vm);
+    cli.parse_command("dis graph -i")?;
+    assert_eq!(cli.prompt(), "graph 0 > ");
+    // the lines are keys: the second edge, then the previous block
+    cli.parse_command("2")?;
+    assert_eq!(cli.prompt(), "graph 5 > ");
+    cli.parse_command("p")?;
+    assert_eq!(cli.prompt(), "graph 3 > ");
+    cli.parse_command("9")?;
+    assert_eq!(cli.prompt(), "graph 3 > ");
+    // stepping selects the block of ip, r0 is 0 so `jf` jumps
+    cli.parse_command("s")?;
+    assert_eq!(usize::from(cli.vm.get_ip()), 5);
+    assert_eq!(cli.prompt(), "graph 5 > ");
+    // `:command` runs a command, the view stays open
+    cli.parse_command(":dis at 0 1")?;
+    assert_eq!(cli.prompt(), "graph 5 > ");
+    assert!(cli.parse_command(":stpe").is_err());
+    assert_eq!(cli.prompt(), "graph 5 > ");
+    cli.parse_command("q")?;
+    assert!(!cli.prompt().starts_with("graph"));
+
+    Ok(())
+}
+
+#[test]
+fn stop_conditions() -> Result<(), Box<dyn std::error::Error>> {
+    use std::cmp::Ordering;
+
+    // 0: call 6; 2: call 6; 4: halt; 6: call 9; 8: ret; 9: ret
+    let prog = vec![17, 6, 17, 6, 0, 21, 17, 9, 18, 18];
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+
+    let call = StopCondition::StopOnCallTo(Addr::new(9).unwrap());
+    assert_eq!(vm.clone().run_until(&[call]), Some(call));
+    // running again continues to the second call
+    let mut vm1 = vm.clone();
+    vm1.run_until(&[call]);
+    assert_eq!(usize::from(vm1.get_ip()), 6);
+    assert_eq!(vm1.get_state(), VmState::HitBreakPoint);
+    vm1.run_until(&[call]);
+    assert_eq!(vm1.get_pc(), 5);
+    vm1.run_until(&[call]);
+    assert_eq!(vm1.get_state(), VmState::Halted);
+
+    let deep: StopCondition = ">1".parse()?;
+    assert_eq!(
+        deep,
+        StopCondition::StopWhenStackDepth(Ordering::Greater, 1)
+    );
+    vm.run_until(&[deep]);
+    assert_eq!(usize::from(vm.get_ip()), 9);
+    assert_eq!(vm.get_call_stack().len(), 2);
+
+    assert!("<x".parse::<StopCondition>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn output_flushed_on_halt() -> Result<(), Box<dyn std::error::Error>> {
+    // out 'h'; out 'i'; halt
+    let prog = vec![19, 104, 19, 105, 0];
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+    for _ in 0..3 {
+        vm.step()?;
+    }
+    assert_eq!(vm.get_state(), VmState::Halted);
+    assert_eq!(vm.get_messages(), &["hi".to_string()]);
+
+    // out 'a'; in r0; out 'b'; halt
+    let prog = vec![19, 97, 20, 32768, 19, 98, 0];
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+    vm.run();
+    vm.feed("")?;
+    vm.run();
+    assert_eq!(vm.get_messages(), &["a".to_string(), "b".to_string()]);
+
+    Ok(())
+}
+
+#[test]
+fn halted_messages_once() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    // out 'h'; halt; out 'x'; halt
+    let prog = vec![19, 104, 0, 19, 120, 0];
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+    vm.run();
+    vm.run();
+    assert_eq!(vm.get_messages(), &["h".to_string()]);
+    assert_eq!(vm.get_pc(), 2);
+
+    let mut cli = Cli::new(vm);
+    cli.parse_command("messages")?;
+    cli.parse_command("messages 0")?;
+    assert!(cli.parse_command("messages 1").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn runfile_limits() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::run_file;
+
+    let path = std::env::temp_dir().join("synacor_runfile.bin");
+    let write = |program: &[u16]| -> Result<(), Box<dyn std::error::Error>> {
+        let mut vm = Vm::new();
+        vm.load_program_from_mem(program);
+        vm.dump_binary(&path)
+    };
+
+    // out 'a'; in r0; out r0; halt
+    write(&[19, 97, 20, 32768, 19, 32768, 0])?;
+    let (messages, reason) = run_file(&path, 100, &["z".to_string()])?;
+    assert_eq!(messages, vec!["a".to_string(), "z".to_string()]);
+    assert_eq!(reason, "halted after 5 steps");
+    let (_, reason) = run_file(&path, 100, &[])?;
+    assert_eq!(reason, "waiting for input");
+
+    // the watchdog fails the inputs ending at a prompt
+    let inputs = std::env::temp_dir().join("synacor_runfile_inputs.txt");
+    std::fs::write(&inputs, "")?;
+    let mut cli = crate::cli::Cli::new(Vm::default());
+    let line = format!("runfile {} --input {}", path.display(), inputs.display());
+    cli.parse_command(&line)?;
+    cli.parse_command("set watchdog on")?;
+    assert!(cli.parse_command(&line).is_err());
+    std::fs::remove_file(&inputs)?;
+
+    // jmp 0
+    write(&[6, 0])?;
+    let (_, reason) = run_file(&path, 100, &[])?;
+    assert_eq!(reason, "step limit of 100 reached");
+
+    // set 5 5: not a register
+    write(&[1, 5, 5])?;
+    let (_, reason) = run_file(&path, 100, &[])?;
+    assert_eq!(
+        reason,
+        "crashed after 0 steps: Not a register at 0: Set(5, 5)"
+    );
+
+    // out 'a'; pop r0: empty stack
+    write(&[19, 97, 3, 32768])?;
+    let (messages, reason) = run_file(&path, 100, &[])?;
+    assert_eq!(messages, vec!["a".to_string()]);
+    assert_eq!(
+        reason,
+        "crashed after 1 steps: Pop: empty stack at 2: Pop(Reg(0))"
+    );
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn cycle_costs() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::emulator::OpcodeInfo;
+    use std::collections::BTreeMap;
+
+    let weights = BTreeMap::from([("mult".to_string(), 4), ("21".to_string(), 0)]);
+    let costs = OpcodeInfo::cost_table(&weights)?;
+    assert_eq!((costs[10], costs[21], costs[9]), (4, 0, 1));
+    assert!(OpcodeInfo::cost_table(&BTreeMap::from([("nop".to_string(), 1)])).is_err());
+
+    // 0: mult r0 r0 r0; 4: noop; 5: jmp 0
+    let prog = vec![10, 32768, 32768, 32768, 21, 6, 0];
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+    vm.set_costs(Some(costs));
+
+    let stop = StopCondition::StopAtCycles(10);
+    assert_eq!(vm.run_until(&[stop]), Some(stop));
+    // mult, noop, jmp, mult, noop, jmp: 4 + 0 + 1 + 4 + 0 + 1
+    assert_eq!(vm.get_cycles(), 10);
+    assert_eq!(vm.get_pc(), 6);
+
+    Ok(())
+}
+
+#[test]
+fn native_6027_variants() {
+    use crate::emulator::NativeImpl;
+
+    // set r0 a; set r1 b; set r7 c; call 6027; wmem 100 r0; wmem 101 r1; halt
+    let run = |(a, b, c): (u16, u16, u16), patching: bool, native_impl: NativeImpl| {
+        let prog = vec![
+            1, 32768, a, 1, 32769, b, 1, 32775, c, 17, 6027, 16, 100, 32768, 16, 101, 32769, 0,
+        ];
+        let mut vm = Vm::default();
+        vm.load_program_from_mem(&prog);
+        vm.set_patching(patching);
+        vm.set_native_impl(native_impl);
+        vm.run();
+        (vm.get_memory()[100], vm.get_memory()[101])
+    };
+
+    for args in [(0, 5, 3), (1, 0, 7), (2, 3, 5), (3, 2, 1)] {
+        let original = run(args, false, NativeImpl::Memoized);
+        assert_eq!(
+            run(args, true, NativeImpl::Memoized),
+            original,
+            "{:?}",
+            args
+        );
+        assert_eq!(run(args, true, NativeImpl::Table), original, "{:?}", args);
+    }
+    // too slow for the original code
+    for args in [(3, 5, 2), (3, 5, 3), (2, 50, 7)] {
+        assert_eq!(
+            run(args, true, NativeImpl::Memoized),
+            run(args, true, NativeImpl::Table),
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn register_usage() {
+    // 0: push r1; push r2; set r1 1; push r3; pop r3; call 20; pop r2; pop r1; ret
+    // 20: set r4 0; call r5; ret
+    let mut prog = vec![2, 32769, 2, 32770, 1, 32769, 1, 2, 32771, 3, 32771, 17, 20];
+    prog.extend([3, 32770, 3, 32769, 18, 0, 0]);
+    prog.extend([1, 32772, 0, 17, 32773, 18]);
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+
+    let usage = vm.register_usage(0);
+    assert_eq!(usage.saved, vec![1, 2]);
+    assert_eq!(usage.clobbered.into_iter().collect::<Vec<_>>(), vec![3, 4]);
+    assert_eq!(usage.temporaries, 1);
+    assert_eq!(usage.indirect_calls, 1);
+}
+
+#[test]
+fn hook_stack_check() {
+    use crate::emulator::HookViolation;
+
+    let mut vm = Vm::default();
+    vm.set_patching(true);
+    vm.run();
+    assert!(vm.get_hook_violations().is_empty());
+
+    // 0: call 10; halt; 10: ret, patched to push 5; ret
+    let mut prog = vec![17, 10, 0, 0, 0, 0, 0, 0, 0, 0, 18];
+    prog.resize(20, 0);
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+    let mut balanced = vm.clone();
+    // push 5; pop r0; ret
+    balanced.patch_words(&[2, 5, 3, 32768, 18], 10);
+    balanced.run();
+    assert_eq!(usize::from(balanced.get_ip()), 3);
+    assert!(balanced.get_hook_violations().is_empty());
+
+    vm.patch_words(&[2, 5, 18], 10);
+    vm.run();
+    // the `Ret` pops 5 instead of the return address 2
+    assert_eq!(usize::from(vm.get_ip()), 6);
+    let violations = vm.get_hook_violations();
+    assert_eq!(violations.len(), 1);
+    assert_eq!(
+        violations[0].to_string(),
+        "hook 10 at pc 2 changed the stack from index 1: 1 -> 2 values"
+    );
+
+    let violation = HookViolation {
+        hook: 2125,
+        pc: 10,
+        before: vec![1, 2, 3],
+        after: vec![1, 2, 5, 3],
+    };
+    assert_eq!(
+        violation.to_string(),
+        "hook 2125 at pc 10 changed the stack from index 2: 3 -> 4 values"
+    );
+}
+
+#[test]
+fn analysis_db_update() {
+    use crate::analysis::AnalysisDb;
+
+    // 0: call 10; call 20; halt
+    // 10: add r0 r0 r1; ret
+    // 20: out r0; ret
+    let mut prog = vec![17, 10, 17, 20, 0, 0, 0, 0, 0, 0];
+    prog.extend([9, 32768, 32768, 32769, 18, 0, 0, 0, 0, 0]);
+    prog.extend([19, 32768, 18]);
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+
+    let mut db = AnalysisDb::new(&prog);
+    assert_eq!(db.update(&vm), 3);
+    assert_eq!(db.update(&vm), 0);
+    let add = &db.functions[&10];
+    assert_eq!(add.args.iter().copied().collect::<Vec<_>>(), vec![0, 1]);
+    assert!(add.pure);
+    assert!(!db.functions[&20].pure);
+    assert!(!db.functions[&0].pure);
+
+    let labels = db
+        .functions_at(12)
+        .map(|f| f.label.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(labels, vec!["fn_10"]);
+
+    db.bookmarks.insert("add".to_string(), 10);
+    let json = serde_json::to_string(&db).unwrap();
+    let loaded: AnalysisDb = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.functions, db.functions);
+    assert_eq!(loaded.bookmarks, db.bookmarks);
+    // databases saved before the bookmarks
+    let old: AnalysisDb = serde_json::from_str(r#"{"binary": "", "functions": {}}"#).unwrap();
+    assert!(old.bookmarks.is_empty());
+
+    // add r0 r0 r2: the function and its caller are analyzed again
+    vm.mem_set(13, 32770);
+    assert_eq!(db.update(&vm), 2);
+    let add = &db.functions[&10];
+    assert_eq!(add.args.iter().copied().collect::<Vec<_>>(), vec![0, 2]);
+}
+
+#[test]
+fn analysis_db_stale() {
+    use crate::analysis::AnalysisDb;
+
+    // 0: call 10; halt
+    // 10: wmem 11 2; ret
+    let mut prog = vec![17, 10, 0, 0, 0, 0, 0, 0, 0, 0];
+    prog.extend([16, 11, 2, 18]);
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&prog);
+
+    let mut db = AnalysisDb::new(&prog);
+    db.update(&vm);
+    vm.set_tracked_code(db.code_addresses());
+    assert!(vm.get_tracked_code().contains(&13));
+
+    // the function rewrites its own operand
+    vm.run();
+    let modified = vm.take_modified_code();
+    assert_eq!(modified.iter().copied().collect::<Vec<_>>(), vec![11]);
+    assert!(vm.take_modified_code().is_empty());
+    assert_eq!(db.mark_modified(&modified), vec![10]);
+    assert_eq!(db.stale_at(11).count(), 1);
+    assert_eq!(db.stale_at(0).count(), 0);
+
+    // the function and its caller
+    assert_eq!(db.update(&vm), 2);
+    assert!(db.functions.values().all(|f| !f.stale));
+}
+
+#[test]
+fn breakpoint_export() {
+    use crate::emulator::Breakpoint;
+
+    // 0: add r0 r0 1; jmp 0
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[9, 32768, 32768, 1, 6, 0]);
+    vm.set_breakpoint(Addr::default());
+    vm.enable_breakpoint(Addr::default(), false).unwrap();
+    vm.add_breakpoint(Breakpoint {
+        description: "loop".to_string(),
+        ..Breakpoint::new(Addr::new(4).unwrap())
+    });
+    vm.run();
+    assert_eq!(vm.get_state(), VmState::HitBreakPoint);
+    assert_eq!((usize::from(vm.get_ip()), vm.get_pc()), (4, 1));
+
+    // exported like `bp export`, only the address is needed to import
+    let json = serde_json::to_string(vm.get_breakpoints()).unwrap();
+    let imported: Vec<Breakpoint> = serde_json::from_str(&json).unwrap();
+    assert_eq!(imported, vm.get_breakpoints());
+    let imported: Vec<Breakpoint> = serde_json::from_str(r#"[{"address": 6027}]"#).unwrap();
+    assert_eq!(imported, vec![Breakpoint::new(Addr::new(6027).unwrap())]);
+}
+
+#[test]
+fn conditional_breakpoints() {
+    use crate::emulator::Breakpoint;
+    use crate::expr::Expr;
+
+    let condition: Expr = "r0 == 3".parse().unwrap();
+    assert_eq!(condition.to_string(), "r0 == 3");
+    assert_eq!("r7 != 0".parse::<Expr>().unwrap().to_string(), "r7 != 0");
+    for invalid in ["r8 == 1", "r0 = 1", "r0 == x", "r0 =="] {
+        assert!(invalid.parse::<Expr>().is_err(), "{}", invalid);
+    }
+
+    // 0: add r0 r0 1; jmp 0
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[9, 32768, 32768, 1, 6, 0]);
+    vm.add_breakpoint(Breakpoint {
+        condition: Some(condition.clone()),
+        ..Breakpoint::new(Addr::new(4).unwrap())
+    });
+    // the `jmp` is reached with r0 at 1 and 2 first
+    vm.run();
+    assert_eq!(vm.get_state(), VmState::HitBreakPoint);
+    assert_eq!((usize::from(vm.get_ip()), vm.get_pc()), (4, 5));
+    assert_eq!(vm.get_registers()[0], 3);
+
+    // the condition is exported with the breakpoint
+    let json = serde_json::to_string(vm.get_breakpoints()).unwrap();
+    let imported: Vec<Breakpoint> = serde_json::from_str(&json).unwrap();
+    assert_eq!(imported[0].condition, Some(condition));
+}
+
+#[test]
+fn expressions() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+    use crate::expr::Expr;
+
+    // 0: add r0 r0 1; wmem 100 r0; jmp 0
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[9, 32768, 32768, 1, 16, 100, 32768, 6, 0]);
+    for _ in 0..5 {
+        vm.step()?;
+    }
+    let eval = |s: &str| s.parse::<Expr>().unwrap().eval(&vm);
+    assert_eq!(eval("r0"), Ok(2));
+    assert_eq!(eval("ip + pc * 2"), Ok(7 + 5 * 2));
+    assert_eq!(eval("(ip + pc) * 2"), Ok(24));
+    assert_eq!(eval("mem[0x64] == r0 && !(r1 > 0)"), Ok(1));
+    assert_eq!(eval("mem[r0 * 50] - 10 % 4 >= 0 || 1 / 0"), Ok(1));
+    assert_eq!(eval("-r0 < 0"), Ok(1));
+    assert!(eval("1 / r1").is_err());
+    assert!(eval("mem[-1]").is_err());
+    for invalid in ["", "mem[1", "(r0", "r0 r1", "1 +", "0xfg", "r0 ; r1"] {
+        assert!(invalid.parse::<Expr>().is_err(), "{:?}", invalid);
+    }
+
+    // the watch stops after each write, not on the other instructions
+    let mut cli = Cli::new(vm);
+    cli.parse_command("print mem[100] + 1")?;
+    cli.parse_command("watch add mem[100]")?;
+    cli.parse_command("run")?;
+    assert_eq!(cli.vm.get_state(), VmState::HitBreakPoint);
+    assert_eq!((usize::from(cli.vm.get_ip()), cli.vm.get_pc()), (7, 8));
+    assert_eq!(cli.vm.get_watches()[0].value, Some(3));
+    assert_eq!(cli.vm.get_watches()[0].previous, Some(2));
+    cli.parse_command("continue")?;
+    assert_eq!(cli.vm.get_pc(), 11);
+
+    // a breakpoint stopping with the watch keeps its value current
+    cli.parse_command("bp set 7; continue")?;
+    assert_eq!((usize::from(cli.vm.get_ip()), cli.vm.get_pc()), (7, 14));
+    assert_eq!(cli.vm.get_watches()[0].value, Some(5));
+    cli.parse_command("continue")?;
+    assert_eq!((usize::from(cli.vm.get_ip()), cli.vm.get_pc()), (7, 17));
+    cli.parse_command("watch remove 0")?;
+    assert!(cli.parse_command("watch remove 0").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn explain_instructions() {
+    // 0: set r1 32767; add r0 r1 2; jf r0 20; out 65
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[1, 32769, 32767, 9, 32768, 32769, 2, 8, 32768, 20, 19, 65]);
+
+    let mut explanations = Vec::new();
+    for _ in 0..4 {
+        explanations.push(vm.explain().unwrap());
+        vm.step().unwrap();
+    }
+    assert_eq!(
+        explanations,
+        vec![
+            "r1 = 32767",
+            "r0 = r1 (32767) + 2 = 1",
+            "continue at 10 because r0 (1) != 0",
+            "print 'A'",
+        ]
+    );
+}
+
+/// Every arithmetic and logic opcode against the arch-spec, on the boundary values, with the
+/// operands as literals and as registers
+#[test]
+fn arithmetic_matrix() {
+    const BOUNDARIES: [u16; 8] = [0, 1, 2, 3, 16383, 16384, 32766, 32767];
+    // None when the operation is undefined
+    type Reference = fn(u32, u32) -> Option<u32>;
+    // (opcode number, reference), everything is modulo 32768
+    let reference: [(u16, Reference); 8] = [
+        (4, |b, c| Some((b == c) as u32)),
+        (5, |b, c| Some((b > c) as u32)),
+        (9, |b, c| Some((b + c) % 32768)),
+        (10, |b, c| Some((b * c) % 32768)),
+        (11, |b, c| if c == 0 { None } else { Some(b % c) }),
+        (12, |b, c| Some(b & c)),
+        (13, |b, c| Some(b | c)),
+        (14, |b, _| Some(!b & 0x7fff)),
+    ];
+
+    for ((number, expected), b, c, as_registers) in
+        iproduct!(reference, BOUNDARIES, BOUNDARIES, [false, true])
+    {
+        let Some(expected) = expected(b as u32, c as u32) else {
+            continue;
+        };
+        let (b_arg, c_arg) = if as_registers { (32769, 32770) } else { (b, c) };
+        let operation = if number == 14 {
+            vec![number, 32768, b_arg]
+        } else {
+            vec![number, 32768, b_arg, c_arg]
+        };
+
+        // set r1 b; set r2 c; <operation> into r0; wmem 100 r0
+        let mut prog = vec![1, 32769, b, 1, 32770, c];
+        prog.extend(operation);
+        prog.extend([16, 100, 32768, 0]);
+        let mut vm = Vm::new();
+        vm.load_program_from_mem(&prog);
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(
+            vm.get_memory()[100] as u32,
+            expected,
+            "opcode {} with {} and {}, registers: {}",
+            number,
+            b,
+            c,
+            as_registers
+        );
+    }
+}
+
+#[test]
+fn raw_io() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::run_file_raw;
+
+    // 0: in r0; out r0; jmp 0
+    let cat = [20, 32768, 19, 32768, 6, 0];
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&cat);
+    vm.set_raw_io(true);
+    vm.feed_bytes(&[0, 200], false)?;
+    vm.run();
+    assert_eq!(vm.get_state(), VmState::WaitingForInput);
+    assert_eq!(vm.take_raw_output(), vec![0, 200]);
+    vm.feed_bytes(b"\n", true)?;
+    vm.run();
+    assert_eq!(vm.get_state(), VmState::Halted);
+    assert_eq!(vm.take_raw_output(), b"\n");
+    assert!(vm.get_messages().is_empty());
+
+    let path = std::env::temp_dir().join("synacor_raw_io.bin");
+    vm.dump_binary(&path)?;
+    let (output, reason) = run_file_raw(&path, 100, &[1, 2, 255])?;
+    assert_eq!(output, vec![1, 2, 255]);
+    assert_eq!(reason, "halted after 10 steps");
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn io_problems() -> Result<(), Box<dyn std::error::Error>> {
+    // in r0
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[20, 32768]);
+    vm.run();
+    assert_eq!(vm.get_state(), VmState::WaitingForInput);
+    assert!(vm.io_problems().is_empty());
+    vm.feed("ab")?;
+    assert_eq!(vm.get_input_buffer().len(), 3);
+    assert!(vm.io_problems().is_empty());
+
+    // like an old snapshot, stuck with a half line
+    let mut json = serde_json::to_value(&vm)?;
+    json["state"] = serde_json::json!("WaitingForInput");
+    json["input_buffer"] = serde_json::json!(["b"]);
+    let stuck: Vm = serde_json::from_value(json)?;
+    assert_eq!(
+        stuck.io_problems(),
+        vec![
+            "waiting for input, but 1 characters are buffered".to_string(),
+            "the buffered input doesn't end with a newline, the game will wait in the middle of \
+             a line"
+                .to_string()
+        ]
+    );
+
+    // 0: in r0; jmp 0, a read loop that empties a half line
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[20, 32768, 6, 0]);
+    vm.run();
+    vm.feed("ab")?;
+    vm.run();
+    assert_eq!(vm.get_state(), VmState::WaitingForInput);
+    assert!(vm.io_problems().is_empty());
+    let mut json = serde_json::to_value(&vm)?;
+    json["state"] = serde_json::json!("Running");
+    json["input_buffer"] = serde_json::json!(["c"]);
+    let mut stuck: Vm = serde_json::from_value(json)?;
+    stuck.step()?;
+    assert_eq!(stuck.get_registers()[0], 'c' as u16);
+    assert_eq!(
+        stuck.io_problems(),
+        vec![
+            "the input ran out in the middle of a line, the read loop will take the next input \
+             as its end"
+                .to_string()
+        ]
+    );
+    stuck.run();
+    assert_eq!(stuck.io_problems().len(), 1);
+    stuck.feed("d")?;
+    stuck.run();
+    assert!(stuck.io_problems().is_empty());
+    Ok(())
+}
+
+#[test]
+fn script_assertions() {
+    use crate::cli::Assertion;
+
+    let parse = |line: &str| Assertion::parse(&crate::cli::split_args(line));
+    assert_eq!(
+        parse("reg r0 == 6"),
+        Ok(Assertion::Register(0, "==".to_string(), 6))
+    );
+    assert_eq!(
+        parse("mem[3952] >= 22"),
+        Ok(Assertion::Memory(3952, ">=".to_string(), 22))
+    );
+    assert_eq!(parse("mem 3952 >= 22"), parse("mem[3952] >= 22"));
+    assert_eq!(
+        parse("output contains \"self-test complete\""),
+        Ok(Assertion::OutputContains("self-test complete".to_string()))
+    );
+    for invalid in [
+        "reg r8 == 1",
+        "mem[40000] == 1",
+        "mem x == 1",
+        "reg r0 = 1",
+        "output",
+    ] {
+        assert!(parse(invalid).is_err(), "{}", invalid);
+    }
+
+    // set r0 6; wmem 100 22; out 'A'; halt
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[1, 32768, 6, 16, 100, 22, 19, 65, 0]);
+    vm.run();
+    for ok in [
+        "reg r0 == 6",
+        "reg r1 < 1",
+        "mem[100] == 22",
+        "output contains A",
+    ] {
+        assert_eq!(parse(ok).unwrap().check(&vm), Ok(()), "{}", ok);
+    }
+    assert_eq!(
+        parse("reg r0 != 6").unwrap().check(&vm),
+        Err("Assertion failed: r0 != 6, r0 is 6".to_string())
+    );
+    assert!(parse("output contains B").unwrap().check(&vm).is_err());
+}
+
+#[test]
+fn resume_from_breakpoint() {
+    // 0: add r0 r0 1; jmp 0
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[9, 32768, 32768, 1, 6, 0]);
+    assert!(vm.resume().is_err());
+
+    vm.set_breakpoint(Addr::default());
+    vm.run();
+    assert_eq!(
+        (vm.get_state(), usize::from(vm.get_ip()), vm.get_pc()),
+        (VmState::HitBreakPoint, 0, 0)
+    );
+    // running again stays on the breakpoint
+    vm.run();
+    assert_eq!(vm.get_pc(), 0);
+
+    for loops in 1..=3 {
+        vm.resume().unwrap();
+        assert_eq!(
+            (vm.get_state(), usize::from(vm.get_ip())),
+            (VmState::HitBreakPoint, 0)
+        );
+        assert_eq!(vm.get_registers()[0], loops);
+    }
+}
+
+#[test]
+fn trigger_rules() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+
+    let mut cli = Cli::new(Vm::new());
+    cli.parse_command("on reg r7 == 5 do \"vm register set 0 1\"")?;
+    cli.parse_command("vm register set 7 5")?;
+    assert_eq!(cli.vm.get_registers()[0], 1);
+
+    // only when the condition becomes true
+    cli.parse_command("vm register set 0 0; vm register set 7 5")?;
+    assert_eq!(cli.vm.get_registers()[0], 0);
+    cli.parse_command("vm register set 7 4; vm register set 7 5")?;
+    assert_eq!(cli.vm.get_registers()[0], 1);
+
+    cli.parse_command("on remove 0")?;
+    assert!(cli.rules.is_empty());
+    assert!(cli.parse_command("on reg r7 == 5").is_err());
+
+    // checked during the runs: 0: wmem 100 1; wmem 100 2; halt
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[16, 100, 1, 16, 100, 2, 0]);
+    let mut cli = Cli::new(vm);
+    cli.parse_command("on mem[100] == 1 do \"vm register set 0 7\"")?;
+    cli.parse_command("run")?;
+    assert_eq!(cli.vm.get_registers()[0], 7);
+    assert_eq!(cli.vm.get_memory()[100], 2);
+    assert_eq!(cli.vm.get_state(), VmState::Halted);
+    Ok(())
+}
+
+#[test]
+fn step_back() -> Result<(), Box<dyn std::error::Error>> {
+    // 0: in r0; wmem 100 r0; call 15; eq r1 r0 '\n'; jf r1 0; halt
+    // 15: push r0; out r0; pop r2; ret
+    let program = [
+        20, 32768, 16, 100, 32768, 17, 15, 4, 32769, 32768, 10, 8, 32769, 0, 0, 2, 32768, 19,
+        32768, 3, 32770, 18,
+    ];
+    let state = |vm: &Vm| {
+        (
+            (
+                vm.get_ip(),
+                vm.get_pc(),
+                vm.get_state(),
+                *vm.get_registers(),
+            ),
+            vm.get_call_stack().to_vec(),
+            vm.get_memory()[100],
+            vm.get_messages().to_vec(),
+            vm.get_output_buffer().to_vec(),
+            vm.get_input_buffer().clone(),
+        )
+    };
+
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&program);
+    assert!(vm.step_back(1).is_err());
+    vm.set_journal(Some(1000));
+    let start = state(&vm);
+
+    vm.run();
+    vm.feed("ab")?;
+    let fed = state(&vm);
+    let recorded = vm.get_journal().unwrap().len();
+    vm.run();
+    assert_eq!(vm.get_state(), VmState::Halted);
+    assert_eq!(vm.get_messages(), ["", "ab\n"]);
+
+    let steps = vm.get_journal().unwrap().len() - recorded;
+    assert_eq!(vm.step_back(steps)?, steps);
+    assert_eq!(state(&vm), fed);
+    // stepping forward again gives the same result
+    vm.run();
+    assert_eq!(vm.get_messages(), ["", "ab\n"]);
+
+    // the fed line stays in the input buffer, to be read again
+    assert_eq!(vm.step_back(usize::MAX)?, recorded + steps);
+    let mut expected = start;
+    expected.5 = "ab\n".chars().collect();
+    assert_eq!(state(&vm), expected);
+
+    // only the last steps are kept
+    vm.set_journal(Some(3));
+    vm.run();
+    let pc = vm.get_pc();
+    assert_eq!(vm.step_back(10)?, 3);
+    assert_eq!(vm.get_pc(), pc - 3);
+
+    Ok(())
+}
+
+#[test]
+fn state_hash() -> Result<(), Box<dyn std::error::Error>> {
+    let mut a = Vm::new();
+    a.load_program_from_mem(&[9, 32768, 32768, 1, 6, 0]);
+    let hash = a.state_hash();
+    assert_eq!(hash.len(), 12);
+
+    // the instruction count isn't part of the state: two loops come back to the same one
+    let mut b = a.clone();
+    for _ in 0..4 {
+        b.step()?;
+    }
+    b.set_register(0, 0);
+    assert_eq!(b.get_pc(), 4);
+    assert!(a == b);
+    assert_eq!(b.state_hash(), hash);
+
+    b.set_register(0, 1);
+    assert_ne!(b.state_hash(), hash);
+    b.set_register(0, 0);
+    b.mem_set(100, 1);
+    assert_ne!(b.state_hash(), hash);
+
+    // the stack is part of the state: 0: push 0; jmp 0
+    let mut a = Vm::new();
+    a.load_program_from_mem(&[2, 0, 6, 0]);
+    let mut b = a.clone();
+    b.step()?;
+    b.step()?;
+    assert_eq!(
+        (b.get_ip(), b.get_registers()),
+        (a.get_ip(), a.get_registers())
+    );
+    assert!(a != b);
+    assert_ne!(b.state_hash(), a.state_hash());
+
+    // and so are the extended banks
+    let mut b = a.clone();
+    b.set_banks(2);
+    assert!(a != b);
+    assert_ne!(b.state_hash(), a.state_hash());
+
+    Ok(())
+}
+
+#[test]
+fn trace_call_tree() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::report::call_tree;
+
+    // 0: call 5; call 5; halt
+    // 5: call 8; ret
+    // 8: ret
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[17, 5, 17, 5, 0, 17, 8, 18, 18]);
+    vm.set_traced_opcodes(Opcode::Call(Val::Invalid).discriminant() | Opcode::Ret.discriminant());
+    vm.run();
+    assert_eq!(
+        call_tree(vm.get_trace_buffer(), vm.get_pc(), vm.get_symbols(), 10),
+        "trace: 9 instructions\n  fn_5 ×2: 8 instructions\n    fn_8: 2 instructions\n"
+    );
+    assert_eq!(
+        call_tree(vm.get_trace_buffer(), vm.get_pc(), vm.get_symbols(), 1),
+        "trace: 9 instructions\n  fn_5 ×2: 8 instructions\n    ...\n"
+    );
+
+    // the first call is made before tracing
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[17, 5, 17, 5, 0, 17, 8, 18, 18]);
+    vm.step()?;
+    vm.set_traced_opcodes(Opcode::Call(Val::Invalid).discriminant() | Opcode::Ret.discriminant());
+    vm.run();
+    assert_eq!(
+        call_tree(vm.get_trace_buffer(), vm.get_pc(), vm.get_symbols(), 10),
+        "trace: 8 instructions\n  ?: 3 instructions\n    fn_8: 2 instructions\n  \
+         fn_5: 4 instructions\n    fn_8: 2 instructions\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn function_profile() {
+    use crate::report::{function_profile, FunctionProfile};
+
+    // 0: call 5; call 5; halt
+    // 5: call 8; ret
+    // 8: ret
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[17, 5, 17, 5, 0, 17, 8, 18, 18]);
+    vm.set_traced_opcodes(Opcode::Call(Val::Invalid).discriminant() | Opcode::Ret.discriminant());
+    vm.run();
+
+    let profile = |calls, inclusive, exclusive| FunctionProfile {
+        calls,
+        inclusive,
+        exclusive,
+    };
+    assert_eq!(
+        function_profile(vm.get_trace_buffer(), vm.get_pc(), vm.get_symbols()),
+        vec![
+            ("fn_5".to_string(), profile(2, 8, 4)),
+            ("fn_8".to_string(), profile(2, 4, 4)),
+            ("?".to_string(), profile(0, 9, 1)),
+        ]
+    );
+
+    // recursive calls are counted once in the inclusive instructions
+    // 0: call 3; halt
+    // 3: jf r0 9; add r0 r0 32767; call 3; ret
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[
+        17, 3, 0, 8, 32768, 13, 9, 32768, 32768, 32767, 17, 3, 18, 18,
+    ]);
+    vm.set_register(0, 2);
+    vm.set_traced_opcodes(Opcode::Call(Val::Invalid).discriminant() | Opcode::Ret.discriminant());
+    vm.run();
+    let profiles = function_profile(vm.get_trace_buffer(), vm.get_pc(), vm.get_symbols());
+    assert_eq!(profiles[0], ("fn_3".to_string(), profile(3, 11, 11)));
+}
+
+#[test]
+fn call_graph_options() {
+    use crate::report::{call_graph, CallGraphOptions};
+
+    // 0: call 5; call 5; halt
+    // 5: call 8; ret
+    // 8: ret
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[17, 5, 17, 5, 0, 17, 8, 18, 18]);
+    vm.set_traced_opcodes(Opcode::Call(Val::Invalid).discriminant() | Opcode::Ret.discriminant());
+    vm.run();
+    let graph = |options| {
+        call_graph(
+            vm.get_trace_buffer(),
+            vm.get_pc(),
+            vm.get_symbols(),
+            &options,
+        )
+    };
+
+    // one edge per call site
+    let dot = graph(CallGraphOptions::default());
+    assert!(dot.contains("\"?\" -> \"fn_5\" [label = \"0: 1\"];"));
+    assert!(dot.contains("\"?\" -> \"fn_5\" [label = \"2: 1\"];"));
+    assert!(dot.contains("\"fn_5\" -> \"fn_8\" [label = \"5: 2\"];"));
+    assert!(dot.contains("\"fn_5\" [label = \"fn_5\\n2 calls\\n8 instructions, 4 self\"];"));
+
+    let dot = graph(CallGraphOptions {
+        min_calls: 2,
+        ..Default::default()
+    });
+    assert!(!dot.contains("\"?\""));
+    assert!(dot.contains("\"fn_5\" -> \"fn_8\" [label = \"5: 2\"];"));
+
+    // the calls of both sites add up
+    let dot = graph(CallGraphOptions {
+        min_calls: 2,
+        merge: true,
+        scale: true,
+        ..Default::default()
+    });
+    assert!(dot.contains("\"?\" -> \"fn_5\" [label = \"2\"];"));
+    assert!(dot.contains("\"fn_8\" [label = \"fn_8\\n2 calls\\n4 instructions, 4 self\", width = "));
+}
+
+#[test]
+fn profile_counts() -> Result<(), Box<dyn std::error::Error>> {
+    // 0: add r0 r0 1; eq r1 r0 3; jf r1 0; halt
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[9, 32768, 32768, 1, 4, 32769, 32768, 3, 8, 32769, 0, 0]);
+    assert!(vm.get_profile().is_none());
+
+    vm.step()?;
+    vm.start_profile();
+    vm.run();
+    let profile = vm.get_profile().unwrap();
+    assert_eq!(profile.total(), 9);
+    assert_eq!(profile.opcodes[9], 2);
+    assert_eq!(profile.opcodes[4], 3);
+    assert_eq!(profile.hottest(2), vec![(4, 3), (8, 3)]);
+
+    // stopped profiles are kept, and don't count anymore
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[9, 32768, 32768, 1, 4, 32769, 32768, 3, 8, 32769, 0, 0]);
+    vm.start_profile();
+    for _ in 0..3 {
+        vm.step()?;
+    }
+    vm.stop_profile();
+    vm.run();
+    assert_eq!(vm.get_profile().unwrap().total(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn viz_gradients() {
+    use crate::viz::{Gradient, Palette, Scale};
+
+    assert_eq!(Scale::Linear.map(5, 10), 0.5);
+    assert_eq!(Scale::Log.map(0, 1000), 0.0);
+    assert_eq!(Scale::Log.map(1000, 1000), 1.0);
+    assert!(Scale::Log.map(10, 1000) > Scale::Linear.map(10, 1000));
+    assert_eq!(Scale::Log.map(3, 0), 0.0);
+    assert!("cubic".parse::<Scale>().is_err());
+
+    assert_eq!(Palette::Viridis.color(0.0), "#fde725");
+    assert_eq!(Palette::Viridis.color(1.0), "#440154");
+    assert_eq!(Palette::Heat.color(0.5), "#fd8d3c");
+    assert_eq!(Palette::Viridis.text_color(1.0), "white");
+    assert_eq!(Palette::Greens.text_color(0.0), "black");
+
+    let gradient = Gradient {
+        scale: Scale::Linear,
+        palette: "greens".parse().unwrap(),
+    };
+    assert_eq!(gradient.color(10, 10), "#006d2c");
+}
+
+#[test]
+fn bruteforce() {
+    use crate::solver::{BruteforceOptions, GameSolver, Variable};
+
+    // 0: eq r1 r0 5; jf r1 11; out 'Y'; out '\n'; halt
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[4, 32769, 32768, 5, 8, 32769, 11, 19, 89, 19, 10, 0]);
+
+    assert_eq!("r0".parse(), Ok(Variable::Register(0)));
+    assert_eq!("3952".parse(), Ok(Variable::Memory(3952)));
+    assert!("r8".parse::<Variable>().is_err());
+
+    let mut options = BruteforceOptions {
+        variable: Variable::Register(0),
+        values: 0..=20,
+        inputs: vec![],
+        success: regex::Regex::new("Y").unwrap(),
+        max_steps: 100,
+    };
+    assert_eq!(
+        GameSolver::bruteforce(&vm, &options),
+        vec![(5, "Y".to_string())]
+    );
+
+    // patches the constant of the comparison
+    options.variable = Variable::Memory(3);
+    vm.set_register(0, 7);
+    assert_eq!(
+        GameSolver::bruteforce(&vm, &options),
+        vec![(7, "Y".to_string())]
+    );
+
+    options.max_steps = 2;
+    assert!(GameSolver::bruteforce(&vm, &options).is_empty());
+}
+
+#[test]
+fn vm_driver() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::driver::VmDriver;
+
+    // 0: in r0; eq r1 r0 'q'; jt r1 13; out r0; jmp 0; halt
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[
+        20, 32768, 4, 32769, 32768, 113, 7, 32769, 13, 19, 32768, 6, 0, 0,
+    ]);
+
+    let mut output = Vec::new();
+    let mut halted = false;
+    let mut inputs = vec!["ab"].into_iter();
+    let state = VmDriver::new(&mut vm)
+        .on_output(|text| output.push(text.to_string()))
+        .on_input_request(|| inputs.next().map(String::from))
+        .on_halt(|| halted = true)
+        .run()?;
+    assert_eq!(state, VmState::WaitingForInput);
+    assert_eq!(output, vec!["ab\n"]);
+    assert!(!halted);
+
+    vm.set_breakpoint(Addr::new(9).unwrap());
+    let mut inputs = vec!["xq"].into_iter();
+    let mut driver = VmDriver::new(&mut vm)
+        .on_output(|text| output.push(text.to_string()))
+        .on_input_request(|| inputs.next().map(String::from))
+        .on_halt(|| halted = true);
+    assert_eq!(driver.run()?, VmState::HitBreakPoint);
+    assert_eq!(usize::from(driver.vm().get_ip()), 9);
+    assert_eq!(driver.run()?, VmState::Halted);
+    drop(driver);
+    assert_eq!(output, vec!["ab\n", "x"]);
+    assert!(halted);
+
+    Ok(())
+}
+
+#[test]
+fn vm_handle() {
+    use crate::driver::{Command, Event, VmHandle};
+
+    // 0: in r0; eq r1 r0 'q'; jt r1 13; out r0; jmp 0; halt
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[
+        20, 32768, 4, 32769, 32768, 113, 7, 32769, 13, 19, 32768, 6, 0, 0,
+    ]);
+
+    let handle = VmHandle::spawn(vm);
+    let next = || format!("{:?}", handle.recv().unwrap());
+    handle.send(Command::Run).unwrap();
+    assert_eq!(next(), "Stopped(WaitingForInput)");
+    handle.send(Command::Input("ab".to_string())).unwrap();
+    assert_eq!(next(), "Output(\"ab\\n\")");
+    assert_eq!(next(), "Stopped(WaitingForInput)");
+
+    handle.send(Command::Snapshot).unwrap();
+    match handle.recv() {
+        Some(Event::Snapshot(vm)) => assert_eq!(vm.get_messages().concat(), "ab\n"),
+        event => panic!("Not a snapshot: {:?}", event),
+    }
+
+    handle
+        .send(Command::SetBreakpoint(Addr::new(9).unwrap()))
+        .unwrap();
+    handle.send(Command::Input("xq".to_string())).unwrap();
+    assert_eq!(next(), "Stopped(HitBreakPoint)");
+    handle.send(Command::Run).unwrap();
+    assert_eq!(next(), "Output(\"x\")");
+    assert_eq!(next(), "Stopped(Halted)");
+
+    let (vm, events) = handle.join().unwrap();
+    assert_eq!(vm.get_state(), VmState::Halted);
+    assert!(events.is_empty());
+
+    // 0: jmp 0
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[6, 0]);
+    let handle = VmHandle::spawn(vm);
+    handle.send(Command::Run).unwrap();
+    handle.send(Command::Pause).unwrap();
+    assert!(matches!(handle.recv(), Some(Event::Paused { ip, .. }) if ip == Addr::default()));
+    let (vm, _) = handle.join().unwrap();
+    assert_eq!(vm.get_state(), VmState::Running);
+}
+
+#[test]
+fn transcript() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::diff::{Divergence, Transcript};
+
+    // 0: in r0; eq r1 r0 'q'; jt r1 13; out r0; jmp 0; halt
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[
+        20, 32768, 4, 32769, 32768, 113, 7, 32769, 13, 19, 32768, 6, 0, 0,
+    ]);
+
+    let recorded = Transcript::parse("> ab\n> cd\n").replay(&vm)?;
+    assert_eq!(recorded.to_string(), "> ab\nab\n> cd\ncd\n");
+    let golden = Transcript::parse(&recorded.to_string());
+    assert_eq!(golden.first_divergence(&golden.replay(&vm)?), None);
+
+    let expected = Transcript::parse("> ab\nab\n> cd\nce\n");
+    assert_eq!(
+        expected.first_divergence(&expected.replay(&vm)?),
+        Some(Divergence {
+            line: 4,
+            after: Some("cd".to_string()),
+            expected: Some("ce".to_string()),
+            actual: Some("cd".to_string()),
+        })
+    );
+
+    // halted before the last input
+    let expected = Transcript::parse("> aq\na\n> b\nb\n");
+    let divergence = expected.first_divergence(&expected.replay(&vm)?).unwrap();
+    assert_eq!((divergence.line, divergence.actual), (3, None));
+
+    Ok(())
+}
+
+#[test]
+fn symbol_table() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::emulator::SymbolTable;
+    use crate::report::call_tree;
+
+    let addr = |n: u16| Addr::new(n).unwrap();
+    let mut symbols = SymbolTable::default();
+    symbols.set(addr(6027), "ackermann")?;
+    assert!(symbols.set(addr(1), "fn_2").is_err());
+    assert!(symbols.set(addr(1), "2fast").is_err());
+    assert_eq!(symbols.resolve("ackermann"), Some(addr(6027)));
+    assert_eq!(symbols.resolve("fn_5489"), Some(addr(5489)));
+    assert_eq!(symbols.name(5489), "fn_5489");
+
+    // a label names one address
+    symbols.set(addr(6028), "ackermann")?;
+    assert_eq!(
+        symbols.iter().collect::<Vec<_>>(),
+        vec![(addr(6028), "ackermann")]
+    );
+
+    // the conflicts of a merge are skipped, the current labels and notes win
+    let mut other = SymbolTable::default();
+    other.set(addr(6028), "ack")?;
+    other.set(addr(7000), "ackermann")?;
+    other.set(addr(7001), "confirm")?;
+    let skipped = symbols.merge(&other);
+    assert_eq!(
+        skipped,
+        vec![
+            (addr(6028), "ack".to_string()),
+            (addr(7000), "ackermann".to_string())
+        ]
+    );
+    assert_eq!(symbols.resolve("confirm"), Some(addr(7001)));
+    assert_eq!(symbols.label(6028), Some("ackermann"));
+
+    // 0: call 4; halt; 3: noop; 4: ret
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[17, 4, 0, 21, 18]);
+    vm.get_symbols_mut().set(addr(4), "leaf")?;
+    assert_eq!(
+        Vm::pretty_dis(&vm.disassemble(Addr::default(), 2)?, vm.get_symbols()),
+        "0: Call(4)  # leaf\n2: Halt"
+    );
+    assert_eq!(
+        vm.get_symbols().format_instruction(4, &Opcode::Ret),
+        "leaf:\n4: Ret"
+    );
+
+    // notes follow the labels of the targets
+    assert!(vm.get_symbols_mut().set_note(addr(0), " \n").is_err());
+    vm.get_symbols_mut().set_note(addr(0), "enter the leaf")?;
+    vm.get_symbols_mut().set_note(addr(2), "done")?;
+    assert_eq!(
+        Vm::pretty_dis(&vm.disassemble(Addr::default(), 2)?, vm.get_symbols()),
+        "0: Call(4)  # leaf; enter the leaf\n2: Halt  # done"
+    );
+    assert_eq!(
+        vm.get_symbols_mut().remove_note(addr(2)).as_deref(),
+        Some("done")
+    );
+
+    vm.set_traced_opcodes(Opcode::Call(Val::Invalid).discriminant() | Opcode::Ret.discriminant());
+    vm.run();
+    let tree = call_tree(vm.get_trace_buffer(), vm.get_pc(), vm.get_symbols(), 10);
+    assert!(tree.contains("leaf: 2 instructions"), "{}", tree);
+
+    // saved with the VM
+    let saved: Vm = serde_json::from_str(&serde_json::to_string(&vm)?)?;
+    assert_eq!(saved.get_symbols().resolve("leaf"), Some(addr(4)));
+    assert_eq!(saved.get_symbols().note(0), Some("enter the leaf"));
+    // saved before the notes
+    let old: SymbolTable = serde_json::from_str(r#"{"labels":{"4":"leaf"}}"#)?;
+    assert_eq!(old.note(4), None);
+
+    Ok(())
+}
+
+#[test]
+fn dirty_pages() {
+    use crate::diff::vm_memory_diff;
+
+    // wmem 600 7; wmem 601 8; noop; wmem 2000 1; halt
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[16, 600, 7, 16, 601, 8, 21, 16, 2000, 1, 0]);
+    vm.set_breakpoint(Addr::new(6).unwrap());
+    assert_eq!(vm.dirty_pages(), Some(vec![]));
+    let start = vm.checkpoint();
+
+    vm.run();
+    assert_eq!(vm.dirty_pages(), Some(vec![(2, 2)]));
+    let middle = vm.clone();
+    let checkpoint = vm.checkpoint();
+
+    vm.mem_set(100, 21);
+    vm.resume().unwrap();
+    assert_eq!(vm.dirty_pages(), Some(vec![(0, 1), (2, 2), (7, 1)]));
+    assert_ne!(vm, middle);
+    assert_eq!(
+        vm_memory_diff(&middle, &vm),
+        vec![(100, 0, 21), (2000, 0, 1)]
+    );
+
+    // only the dirty pages are restored, from the checkpoint or from the load
+    vm.restore(&checkpoint).unwrap();
+    assert_eq!(vm, middle);
+    assert_eq!(vm.get_memory(), middle.get_memory());
+    vm.restore(&start).unwrap();
+    assert_eq!(vm.mem_get(600), 0);
+    assert_eq!(vm.dirty_pages(), Some(vec![]));
+
+    // a deserialized VM has no baseline, until cleaned
+    let mut saved: Vm = serde_json::from_str(&serde_json::to_string(&middle).unwrap()).unwrap();
+    assert_eq!(saved.dirty_pages(), None);
+    assert_eq!(saved.differing_pages(&middle), None);
+    assert_eq!(saved, middle);
+    saved.clean_pages();
+    assert_eq!(saved.dirty_pages(), Some(vec![]));
+}
+
+#[test]
+fn delta_saves() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+    use crate::diff::{apply_memory_delta, memory_delta};
+
+    let delta = memory_delta(&[1, 2, 3], &[1, 5, 6, 0, 7]);
+    assert_eq!(delta, vec![(1, vec![5, 6]), (4, vec![7])]);
+    assert_eq!(apply_memory_delta(&[1, 2, 3], 5, &delta)?, [1, 5, 6, 0, 7]);
+    assert!(apply_memory_delta(&[1, 2, 3], 4, &delta).is_err());
+
+    let dir = std::env::temp_dir().join("synacor_delta_saves");
+    std::fs::create_dir_all(&dir)?;
+    let mut cli = Cli::new(Vm::default());
+    cli.snaps_dir = dir.clone();
+    cli.set_binary(Some("challenge.bin".into()));
+    cli.parse_command("run; take tablet; snap take tablet")?;
+    cli.parse_command("snap dump tablet test-delta.json")?;
+    cli.parse_command("snap dump tablet test-delta.zst --compress")?;
+    let size = |name: &str| std::fs::metadata(dir.join(name)).unwrap().len();
+    assert!(size("test-delta.zst") < size("test-delta.json"));
+    assert!(size("test-delta.json") < 100 * 1024);
+    cli.parse_command("state same tablet test-delta.json")?;
+
+    let vm = cli.vm.clone();
+    cli.parse_command("set confirm off; look; snap remove tablet")?;
+    cli.parse_command("snap load test-delta.zst")?;
+    assert_eq!(cli.vm, vm);
+    assert_eq!(cli.vm.get_memory(), vm.get_memory());
+
+    // a delta needs its program
+    cli.set_binary(None);
+    assert!(cli.parse_command("snap load test-delta.json").is_err());
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn print_routine() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::cli::Cli;
+    use crate::report::find_print_routine;
+
+    let mut vm = Vm::default();
+    assert_eq!(find_print_routine(vm.get_memory()), Some(1458));
+
+    vm.set_string_routine(Some(1458));
+    vm.run();
+    vm.feed("look")?;
+    vm.run();
+
+    let calls = vm.get_print_calls();
+    assert_eq!(calls[0].text, "self-test complete, all tests pass\n");
+    assert_eq!(calls[0].words.len(), 35);
+    // the list of exits prints the exits with nested calls
+    let exits = calls
+        .iter()
+        .find(|c| c.text.starts_with("- doorway"))
+        .unwrap();
+    assert_eq!(exits.text, "- doorway\n- south\n");
+    assert!(calls.iter().all(|c| c.text != "doorway"));
+    let printed: String = calls.iter().map(|c| c.text.as_str()).collect();
+    assert!(printed.ends_with("What do you do?\n"));
+    // the output isn't traced
+    assert!(vm.get_trace_buffer().is_empty());
+
+    let mut cli = Cli::new(Vm::default());
+    assert!(cli.parse_command("trace strings").is_err());
+    cli.parse_command("trace start --strings")?;
+    assert_eq!(cli.vm.get_string_routine(), Some(1458));
+    cli.parse_command("trace stop")?;
+    assert_eq!(cli.vm.get_string_routine(), None);
+
+    Ok(())
+}
+
+#[test]
+fn string_decoder() {
+    use crate::report::{Analysis, Decoder, StringDecoder};
+
+    let mut vm = Vm::default();
+    vm.set_string_routine(Some(1458));
+    vm.run();
+
+    let decoder = StringDecoder::from_calls(vm.get_print_calls()).unwrap();
+    assert_eq!(decoder.routine, 1458);
+    assert_eq!(
+        decoder
+            .decoders
+            .iter()
+            .map(|(&k, &v)| (k, v))
+            .collect::<Vec<_>>(),
+        vec![(1528, Decoder::Plain), (1531, Decoder::Xor(2))]
+    );
+
+    // never printed yet
+    let grue = (28204, "You have been eaten by a grue.\n".to_string());
+    assert_eq!(decoder.decode(vm.get_memory()).get(&grue.0), Some(&grue.1));
+    assert!(Analysis::new(&vm).strings.contains(&grue));
+}
+
+#[test]
+fn find_text() {
+    use crate::report::{find_text, TextSource};
+
+    // "Hi!!", then out 'h'; out 'i'; out '!'; out '!'; halt
+    let memory = [72, 105, 33, 33, 0, 19, 104, 19, 105, 19, 33, 19, 33, 0];
+    assert_eq!(
+        find_text(&memory, 4, None),
+        vec![
+            (0, TextSource::Data, "Hi!!".to_string()),
+            (5, TextSource::Out, "hi!!".to_string())
+        ]
+    );
+    assert_eq!(find_text(&memory, 5, None), vec![]);
+}
+
+#[test]
+fn static_call_graph() {
+    use crate::report::StaticCallGraph;
+
+    // 0: call 8; set r0 10; call r0; halt; 8: ret; noop; 10: ret
+    let mut vm = Vm::new();
+    vm.load_program_from_mem(&[17, 8, 1, 32768, 10, 17, 32768, 0, 18, 21, 18]);
+    let graph = StaticCallGraph::new(&vm);
+    let functions: Vec<usize> = graph.functions.iter().map(|f| f.addr).collect();
+    assert_eq!(functions, vec![0, 8]);
+    assert_eq!(graph.calls.len(), 1);
+
+    // the call to r0 is resolved by the trace
+    vm.set_traced_opcodes(Opcode::Call(Val::Invalid).discriminant());
+    vm.run();
+    let graph = StaticCallGraph::new(&vm);
+    let functions: Vec<usize> = graph.functions.iter().map(|f| f.addr).collect();
+    assert_eq!(functions, vec![0, 8, 10]);
+    let indirect = graph.calls.iter().find(|c| c.indirect).unwrap();
+    assert_eq!((indirect.caller, indirect.callee), (0, 10));
+    assert!(indirect.call_sites.contains(&5));
+    assert!(graph
+        .to_dot()
+        .contains("0 -> 10 [label = \"5\", style = dashed];"));
+}
@@ -0,0 +1,124 @@
+use std::str::FromStr;
+
+/// How the values are mapped to `0.0..=1.0`
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Scale {
+    Linear,
+    /// Execution counts span orders of magnitude
+    #[default]
+    Log,
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Scale::Linear),
+            "log" => Ok(Scale::Log),
+            _ => Err(format!("Unknown scale {:?}, expected linear or log", s)),
+        }
+    }
+}
+
+impl Scale {
+    /// Position of `value` between 0 and `max`
+    pub fn map(&self, value: u64, max: u64) -> f64 {
+        if max == 0 {
+            return 0.0;
+        }
+        let ratio = match self {
+            Scale::Linear => value as f64 / max as f64,
+            Scale::Log => (value as f64).ln_1p() / (max as f64).ln_1p(),
+        };
+        ratio.clamp(0.0, 1.0)
+    }
+}
+
+/// Color gradients, from low to high values
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Palette {
+    #[default]
+    Greens,
+    Heat,
+    /// Perceptually uniform and colorblind-safe
+    Viridis,
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "greens" => Ok(Palette::Greens),
+            "heat" => Ok(Palette::Heat),
+            "viridis" => Ok(Palette::Viridis),
+            _ => Err(format!(
+                "Unknown palette {:?}, expected greens, heat or viridis",
+                s
+            )),
+        }
+    }
+}
+
+impl Palette {
+    fn stops(&self) -> &'static [[u8; 3]] {
+        match self {
+            Palette::Greens => &[[0xe5, 0xf5, 0xe0], [0x74, 0xc4, 0x76], [0x00, 0x6d, 0x2c]],
+            Palette::Heat => &[[0xff, 0xff, 0xb2], [0xfd, 0x8d, 0x3c], [0xbd, 0x00, 0x26]],
+            Palette::Viridis => &[
+                [0xfd, 0xe7, 0x25],
+                [0x5e, 0xc9, 0x62],
+                [0x21, 0x91, 0x8c],
+                [0x3b, 0x52, 0x8b],
+                [0x44, 0x01, 0x54],
+            ],
+        }
+    }
+
+    fn rgb(&self, t: f64) -> [u8; 3] {
+        let stops = self.stops();
+        let position = t.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+        let idx = (position as usize).min(stops.len() - 2);
+        let frac = position - idx as f64;
+
+        [0, 1, 2].map(|c| {
+            let (a, b) = (stops[idx][c] as f64, stops[idx + 1][c] as f64);
+            (a + (b - a) * frac).round() as u8
+        })
+    }
+
+    /// `#rrggbb` color at `t` in the gradient, `t` between 0 and 1
+    pub fn color(&self, t: f64) -> String {
+        let [r, g, b] = self.rgb(t);
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+
+    /// Text color readable on `color(t)`
+    pub fn text_color(&self, t: f64) -> &'static str {
+        let [r, g, b] = self.rgb(t);
+        let luma = 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+        if luma < 128.0 {
+            "white"
+        } else {
+            "black"
+        }
+    }
+}
+
+/// Colors of values, for the heatmaps of the reports and graphs
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Gradient {
+    pub scale: Scale,
+    pub palette: Palette,
+}
+
+impl Gradient {
+    pub fn color(&self, value: u64, max: u64) -> String {
+        self.palette.color(self.scale.map(value, max))
+    }
+
+    pub fn text_color(&self, value: u64, max: u64) -> &'static str {
+        self.palette.text_color(self.scale.map(value, max))
+    }
+}
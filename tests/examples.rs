@@ -0,0 +1,62 @@
+//! The examples, run on small inputs: they only use the public API of the library
+
+#[allow(dead_code)]
+#[path = "../examples/play.rs"]
+mod play;
+
+#[allow(dead_code)]
+#[path = "../examples/map_maze.rs"]
+mod map_maze;
+
+#[allow(dead_code)]
+#[path = "../examples/teleporter.rs"]
+mod teleporter;
+
+use synacor_challenge::solver::GameSolver;
+use synacor_challenge::{Game, GameResponse};
+
+#[test]
+fn play() -> Result<(), Box<dyn std::error::Error>> {
+    let mut game = Game::new()?;
+    let mut output = Vec::new();
+    let played = play::play(
+        &mut game,
+        "take tablet\nuse tablet\n".as_bytes(),
+        &mut output,
+    )?;
+
+    let output = String::from_utf8(output)?;
+    assert_eq!(played, 2);
+    assert!(output.starts_with("Welcome to the Synacor Challenge!"));
+    assert!(output.contains("You find yourself writing"));
+    assert_eq!(game.inventory()?, vec!["tablet"]);
+
+    Ok(())
+}
+
+#[test]
+fn map_maze() -> Result<(), Box<dyn std::error::Error>> {
+    let game = Game::new()?;
+    let maze = map_maze::map(&game, 3, None)?;
+
+    assert_eq!(maze.explored.len(), 3);
+    assert!(maze.explored.iter().any(|room| room.name == "Foothills"));
+    assert!(maze.to_graphviz().starts_with("digraph"));
+
+    Ok(())
+}
+
+#[test]
+fn teleporter() -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(teleporter::find_r7(25700..=25800), Some(25734));
+    assert_eq!(teleporter::find_r7(1..=10), None);
+
+    let game = teleporter::use_teleporter(25734)?;
+    match GameResponse::from_vm(game.vm()) {
+        GameResponse::Room(room) => assert_eq!(room.name, "Beach"),
+        response => panic!("not on the beach: {:?}", response),
+    }
+    assert_eq!(GameSolver::find_codes(game.output()).len(), 1);
+
+    Ok(())
+}